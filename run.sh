@@ -4,6 +4,10 @@
 # Usage:
 #   ./run.sh          Build + run in QEMU
 #   ./run.sh build    Build only (no QEMU)
+#
+# The contents of rootfs/ (or $ROOTFS_DIR) are packed into a ustar
+# ramdisk and unpacked into the kernel's filesystem at boot. Set
+# DISK_IMAGE to a raw image file to attach it as a second IDE disk (hdb).
 
 set -euo pipefail
 
@@ -25,6 +29,16 @@ if [ "${1:-}" = "build" ]; then
     exit 0
 fi
 
+ROOTFS_DIR="${ROOTFS_DIR:-$SCRIPT_DIR/rootfs}"
+RAMDISK="$SCRIPT_DIR/target/ramdisk.tar"
+if [ -d "$ROOTFS_DIR" ]; then
+    echo "==> Packing ramdisk from $ROOTFS_DIR..."
+    # COPYFILE_DISABLE keeps macOS tar from adding ._ metadata files
+    COPYFILE_DISABLE=1 tar --format=ustar -cf "$RAMDISK" -C "$ROOTFS_DIR" .
+else
+    RAMDISK=""
+fi
+
 echo "==> Creating disk image..."
 
 # Build the image creator in an isolated directory (outside project tree
@@ -43,7 +57,7 @@ bootloader = "0.11"
 TOML
 
 cat > "$IMGBUILDER_DIR/src/main.rs" << 'RUST'
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
@@ -51,8 +65,12 @@ fn main() {
     let bios_path = kernel_path.with_extension("bios.img");
 
     println!("Creating BIOS disk image...");
-    bootloader::BiosBoot::new(&kernel_path)
-        .create_disk_image(&bios_path)
+    let mut boot = bootloader::BiosBoot::new(&kernel_path);
+    if let Some(ramdisk) = args.get(2).filter(|path| !path.is_empty()) {
+        println!("Including ramdisk: {}", ramdisk);
+        boot.set_ramdisk(Path::new(ramdisk));
+    }
+    boot.create_disk_image(&bios_path)
         .expect("Failed to create BIOS disk image");
     println!("Disk image created: {}", bios_path.display());
 }
@@ -68,9 +86,14 @@ cd "$IMGBUILDER_DIR"
 cargo build 2>&1 | grep -v "^$" | tail -5
 
 IMGBUILDER_BIN="$IMGBUILDER_DIR/target/debug/imgbuilder"
-"$IMGBUILDER_BIN" "$KERNEL_BIN"
+"$IMGBUILDER_BIN" "$KERNEL_BIN" "$RAMDISK"
+
+DRIVES=(-drive "format=raw,file=${KERNEL_BIN}.bios.img,index=0,if=ide")
+if [ -n "${DISK_IMAGE:-}" ]; then
+    DRIVES+=(-drive "format=raw,file=${DISK_IMAGE},index=1,if=ide")
+fi
 
 echo "==> Launching QEMU..."
 exec qemu-system-x86_64 \
-    -drive "format=raw,file=${KERNEL_BIN}.bios.img" \
+    "${DRIVES[@]}" \
     -serial stdio
@@ -5,19 +5,297 @@
 /// free blocks as a linked list within the free memory itself.
 ///
 /// The heap lives at a fixed virtual address range. We map physical
-/// frames to these virtual pages, then initialize the allocator.
+/// frames to these virtual pages, then initialize the allocator. When an
+/// allocation doesn't fit, the heap grows upwards by mapping more pages
+/// through the global mapper and frame allocator, up to a ceiling.
+///
+/// Small allocations (up to 2 KiB) don't walk the free list at all: they
+/// are served from per-size-class slabs, each a singly linked list of
+/// freed blocks of one size. A class only falls back to the linked-list
+/// heap when its list is empty, so the common Box/String/BTreeMap node
+/// allocation is O(1) and doesn't fragment the general heap.
 
-use linked_list_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use linked_list_allocator::Heap;
+use spin::Mutex;
 use x86_64::structures::paging::{
-    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+    mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags,
+    Size4KiB,
 };
 use x86_64::VirtAddr;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
-pub const HEAP_SIZE: usize = 256 * 1024; // 256 KiB
+pub const HEAP_SIZE: usize = 256 * 1024; // 256 KiB initially mapped
+
+/// Default ceiling for heap growth.
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+
+/// Smallest amount the heap grows by, so small allocations don't map
+/// one page at a time.
+const HEAP_GROW_STEP: usize = 64 * 1024;
+
+const PAGE_SIZE: usize = 4096;
+
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+
+/// Block sizes served by the slab allocator. Each class's blocks are aligned
+/// to their own size, so any layout with `align <= size` fits.
+pub const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelAllocator = KernelAllocator {
+    heap: Mutex::new(KernelHeap {
+        slabs: [const { SlabClass::new() }; SIZE_CLASSES.len()],
+        fallback: Heap::empty(),
+    }),
+};
+
+/// Global allocator: size-class slabs in front of a linked-list heap that
+/// can extend itself.
+///
+/// All heap operations run with interrupts disabled so a preempted thread
+/// can never hold the heap lock while the timer ISR (or another thread)
+/// tries to allocate or free.
+pub struct KernelAllocator {
+    heap: Mutex<KernelHeap>,
+}
+
+struct KernelHeap {
+    slabs: [SlabClass; SIZE_CLASSES.len()],
+    fallback: Heap,
+}
+
+/// Free list and counters for one size class.
+struct SlabClass {
+    head: Option<&'static mut FreeBlock>,
+    /// Blocks currently handed out to callers.
+    live: usize,
+    /// Freed blocks waiting on the list for reuse.
+    cached: usize,
+    /// Total allocations served by this class since boot.
+    allocs: u64,
+}
+
+/// Header written into a freed block to link it into its class's list.
+struct FreeBlock {
+    next: Option<&'static mut FreeBlock>,
+}
+
+impl SlabClass {
+    const fn new() -> Self {
+        SlabClass {
+            head: None,
+            live: 0,
+            cached: 0,
+            allocs: 0,
+        }
+    }
+}
+
+/// Index of the smallest size class that fits `layout`, if any.
+fn size_class(layout: &Layout) -> Option<usize> {
+    let required = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&size| size >= required)
+}
+
+impl KernelHeap {
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let Some(class) = size_class(&layout) else {
+            return self.alloc_fallback(layout);
+        };
+
+        let slab = &mut self.slabs[class];
+        let ptr = match slab.head.take() {
+            Some(block) => {
+                slab.head = block.next.take();
+                slab.cached -= 1;
+                block as *mut FreeBlock as *mut u8
+            }
+            None => {
+                let size = SIZE_CLASSES[class];
+                let block_layout = Layout::from_size_align(size, size).unwrap();
+                self.alloc_fallback(block_layout)
+            }
+        };
+
+        if !ptr.is_null() {
+            let slab = &mut self.slabs[class];
+            slab.live += 1;
+            slab.allocs += 1;
+        }
+        ptr
+    }
+
+    fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let Some(class) = size_class(&layout) else {
+            unsafe { self.fallback.deallocate(ptr, layout) };
+            return;
+        };
+
+        let slab = &mut self.slabs[class];
+        let block = ptr.as_ptr() as *mut FreeBlock;
+        unsafe {
+            block.write(FreeBlock {
+                next: slab.head.take(),
+            });
+            slab.head = Some(&mut *block);
+        }
+        slab.live -= 1;
+        slab.cached += 1;
+    }
+
+    fn alloc_fallback(&mut self, layout: Layout) -> *mut u8 {
+        if let Ok(ptr) = self.fallback.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        grow(&mut self.fallback, layout);
+        if let Ok(ptr) = self.fallback.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        // Last resort: give cached slab blocks back so they can coalesce.
+        self.drain_slabs();
+        self.fallback
+            .allocate_first_fit(layout)
+            .map(|ptr| ptr.as_ptr())
+            .unwrap_or_default()
+    }
+
+    /// Return every cached slab block to the linked-list heap.
+    fn drain_slabs(&mut self) {
+        for (class, slab) in self.slabs.iter_mut().enumerate() {
+            let size = SIZE_CLASSES[class];
+            let block_layout = Layout::from_size_align(size, size).unwrap();
+            while let Some(block) = slab.head.take() {
+                slab.head = block.next.take();
+                slab.cached -= 1;
+                let ptr = NonNull::from(block).cast::<u8>();
+                unsafe { self.fallback.deallocate(ptr, block_layout) };
+            }
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        debug_assert!(!crate::memory::in_with_mapper(), "heap allocation inside with_mapper");
+        x86_64::instructions::interrupts::without_interrupts(|| self.heap.lock().alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            if let Some(ptr) = NonNull::new(ptr) {
+                self.heap.lock().dealloc(ptr, layout);
+            }
+        })
+    }
+}
+
+/// Extend the heap by enough pages to satisfy `layout`. Maps as many pages
+/// as it can; a partial extension still helps later smaller allocations.
+fn grow(heap: &mut Heap, layout: Layout) {
+    // Worst case the allocation needs `align` bytes of padding in front.
+    let needed = layout.size() + layout.align();
+    let by = needed.max(HEAP_GROW_STEP).next_multiple_of(PAGE_SIZE);
+    let limit = HEAP_LIMIT.load(Ordering::Relaxed);
+    let by = by.min(limit.saturating_sub(heap.size()) & !(PAGE_SIZE - 1));
+    if by == 0 {
+        return;
+    }
+
+    let top = VirtAddr::from_ptr(heap.top());
+    let start_page: Page<Size4KiB> = Page::containing_address(top);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    // Runs under the heap lock, so it must not wait for the mapper locks:
+    // their holder may be the one allocating. The heap then just doesn't grow.
+    let mapped_pages = crate::memory::try_with_mapper(|mapper, frame_allocator| {
+        let mut mapped = 0;
+        for page in Page::range(start_page, start_page + (by / PAGE_SIZE) as u64) {
+            let Some(frame) = frame_allocator.allocate_frame() else {
+                break;
+            };
+            match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    break;
+                }
+            }
+            mapped += 1;
+        }
+        mapped
+    });
+
+    if let Some(pages) = mapped_pages.filter(|&pages| pages > 0) {
+        unsafe { heap.extend(pages * PAGE_SIZE) };
+    }
+}
+
+/// Snapshot of heap usage, in bytes. `used` counts slab blocks (live or
+/// cached) as used, since the linked-list heap has handed them out.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    pub limit: usize,
+}
+
+/// Counters for one slab size class.
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    pub block_size: usize,
+    pub live: usize,
+    pub cached: usize,
+    pub allocs: u64,
+}
+
+pub fn stats() -> HeapStats {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let heap = ALLOCATOR.heap.lock();
+        HeapStats {
+            size: heap.fallback.size(),
+            used: heap.fallback.used(),
+            free: heap.fallback.free(),
+            limit: HEAP_LIMIT.load(Ordering::Relaxed),
+        }
+    })
+}
+
+/// Per-class slab counters, in `SIZE_CLASSES` order.
+pub fn slab_stats() -> [SlabStats; SIZE_CLASSES.len()] {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let heap = ALLOCATOR.heap.lock();
+        core::array::from_fn(|class| SlabStats {
+            block_size: SIZE_CLASSES[class],
+            live: heap.slabs[class].live,
+            cached: heap.slabs[class].cached,
+            allocs: heap.slabs[class].allocs,
+        })
+    })
+}
+
+/// Set the ceiling the heap may grow to. Values below the current size
+/// only prevent further growth; mapped memory is never given back.
+pub fn set_heap_limit(bytes: usize) {
+    HEAP_LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    let stats = stats();
+    panic!(
+        "allocation error: {} bytes (align {}) failed; heap {} KiB used, {} KiB free, {} KiB mapped, limit {} KiB",
+        layout.size(),
+        layout.align(),
+        stats.used / 1024,
+        stats.free / 1024,
+        stats.size / 1024,
+        stats.limit / 1024
+    );
+}
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -42,7 +320,11 @@ pub fn init_heap(
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+        ALLOCATOR
+            .heap
+            .lock()
+            .fallback
+            .init(HEAP_START as *mut u8, HEAP_SIZE);
     }
 
     Ok(())
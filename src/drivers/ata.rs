@@ -0,0 +1,333 @@
+/// ATA PIO driver for the legacy IDE controller.
+///
+/// Two channels at the standard ports, each with a master and a slave
+/// drive. `init` sends IDENTIFY to all four positions and registers every
+/// ATA disk that answers as `hda`..`hdd` (ATAPI and absent drives are
+/// skipped). Transfers use polled PIO with the channel's interrupt
+/// disabled (nIEN), 28-bit LBA where it reaches and 48-bit LBA beyond
+/// that. Under QEMU the boot image is `hda`; `-drive` adds more.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use super::block::{self, BlockDevice, BlockError, SECTOR_SIZE};
+
+/// (command block base, control port) for the primary and secondary channel.
+const CHANNELS: [(u16, u16); 2] = [(0x1F0, 0x3F6), (0x170, 0x376)];
+
+// Offsets from the command block base.
+const REG_DATA: u16 = 0;
+const REG_ERROR: u16 = 1;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+/// Device control: don't raise IRQ 14/15, we poll.
+const CONTROL_NIEN: u8 = 1 << 1;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_READ_SECTORS_EXT: u8 = 0x24;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_WRITE_SECTORS_EXT: u8 = 0x34;
+const CMD_CACHE_FLUSH: u8 = 0xE7;
+const CMD_CACHE_FLUSH_EXT: u8 = 0xEA;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+/// Highest sector reachable with a 28-bit LBA, plus one.
+const LBA28_LIMIT: u64 = 1 << 28;
+/// Sectors per command; 256 is the most a 28-bit command can move.
+const MAX_SECTORS_PER_COMMAND: u64 = 256;
+/// Status polls before a command is given up on.
+const POLL_LIMIT: u32 = 1_000_000;
+
+/// The I/O ports of one channel. Both drives on a channel share it, so
+/// only one command is in flight per channel.
+struct Channel {
+    base: u16,
+    control: u16,
+}
+
+impl Channel {
+    fn read(&self, reg: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.base + reg).read() }
+    }
+
+    fn write(&self, reg: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.base + reg).write(value) }
+    }
+
+    fn alt_status(&self) -> u8 {
+        unsafe { Port::<u8>::new(self.control).read() }
+    }
+
+    /// About 400ns: long enough for status to reflect a drive select.
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alt_status();
+        }
+    }
+
+    fn select(&self, drive_bits: u8) {
+        self.write(REG_DRIVE, drive_bits);
+        self.delay();
+    }
+
+    fn wait_not_busy(&self) -> Result<u8, BlockError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.alt_status();
+            if status & STATUS_BSY == 0 {
+                return Ok(status);
+            }
+            core::hint::spin_loop();
+        }
+        Err(BlockError::Timeout)
+    }
+
+    /// Wait until the drive has a sector ready (or failed).
+    fn wait_data(&self) -> Result<(), BlockError> {
+        let status = self.wait_not_busy()?;
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::Device(self.read(REG_ERROR)));
+        }
+        if status & STATUS_DRQ == 0 {
+            return Err(BlockError::Device(0));
+        }
+        Ok(())
+    }
+
+    fn read_sector(&self, buf: &mut [u8]) {
+        let mut data = Port::<u16>::new(self.base + REG_DATA);
+        for pair in buf.chunks_exact_mut(2) {
+            let word = unsafe { data.read() };
+            pair.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    fn write_sector(&self, buf: &[u8]) {
+        let mut data = Port::<u16>::new(self.base + REG_DATA);
+        for pair in buf.chunks_exact(2) {
+            unsafe { data.write(u16::from_le_bytes([pair[0], pair[1]])) };
+        }
+    }
+
+    /// Select the drive and load LBA and count for a transfer.
+    fn setup(&self, slave: bool, lba: u64, count: u64, lba48: bool) {
+        let drive = if slave { 0x10 } else { 0 };
+        if lba48 {
+            self.select(0x40 | drive);
+            // High-order bytes first, then the low ones.
+            self.write(REG_SECTOR_COUNT, (count >> 8) as u8);
+            self.write(REG_LBA_LOW, (lba >> 24) as u8);
+            self.write(REG_LBA_MID, (lba >> 32) as u8);
+            self.write(REG_LBA_HIGH, (lba >> 40) as u8);
+        } else {
+            self.select(0xE0 | drive | ((lba >> 24) as u8 & 0x0F));
+        }
+        // A count of 256 is written as 0.
+        self.write(REG_SECTOR_COUNT, count as u8);
+        self.write(REG_LBA_LOW, lba as u8);
+        self.write(REG_LBA_MID, (lba >> 8) as u8);
+        self.write(REG_LBA_HIGH, (lba >> 16) as u8);
+    }
+}
+
+/// What IDENTIFY told us about a drive.
+#[derive(Debug, Clone)]
+pub struct DriveInfo {
+    pub model: String,
+    pub sectors: u64,
+    pub lba48: bool,
+}
+
+/// Send IDENTIFY to one drive. `None` if nothing (or not an ATA disk)
+/// is there.
+fn identify(channel: &Channel, slave: bool) -> Option<DriveInfo> {
+    // A floating bus reads all ones: no controller at all.
+    if channel.alt_status() == 0xFF {
+        return None;
+    }
+    channel.select(if slave { 0xB0 } else { 0xA0 });
+    channel.write(REG_SECTOR_COUNT, 0);
+    channel.write(REG_LBA_LOW, 0);
+    channel.write(REG_LBA_MID, 0);
+    channel.write(REG_LBA_HIGH, 0);
+    channel.write(REG_COMMAND, CMD_IDENTIFY);
+    if channel.read(REG_STATUS) == 0 {
+        return None;
+    }
+    channel.wait_not_busy().ok()?;
+    // ATAPI and SATA devices set a signature here and abort IDENTIFY.
+    if channel.read(REG_LBA_MID) != 0 || channel.read(REG_LBA_HIGH) != 0 {
+        return None;
+    }
+    channel.wait_data().ok()?;
+
+    let mut raw = [0u8; SECTOR_SIZE];
+    channel.read_sector(&mut raw);
+    let word = |i: usize| u16::from_le_bytes([raw[i * 2], raw[i * 2 + 1]]) as u64;
+
+    let lba48 = word(83) & (1 << 10) != 0;
+    let sectors = if lba48 {
+        word(100) | word(101) << 16 | word(102) << 32 | word(103) << 48
+    } else {
+        word(60) | word(61) << 16
+    };
+    // The model string is stored with the bytes of each word swapped.
+    let mut model = String::new();
+    for i in 27..47 {
+        let [hi, lo] = (word(i) as u16).to_be_bytes();
+        model.push(hi as char);
+        model.push(lo as char);
+    }
+    let model = String::from(model.trim());
+    Some(DriveInfo { model, sectors, lba48 })
+}
+
+pub struct AtaDrive {
+    name: String,
+    channel: Arc<Mutex<Channel>>,
+    slave: bool,
+    info: DriveInfo,
+}
+
+impl AtaDrive {
+    pub fn info(&self) -> &DriveInfo {
+        &self.info
+    }
+
+    /// Run one read or write command of at most `MAX_SECTORS_PER_COMMAND`
+    /// sectors, moving data through `transfer` one sector at a time.
+    fn command(
+        &self,
+        lba: u64,
+        count: u64,
+        write: bool,
+        mut transfer: impl FnMut(&Channel, usize),
+    ) -> Result<(), BlockError> {
+        let lba48 = lba + count > LBA28_LIMIT;
+        if lba48 && !self.info.lba48 {
+            return Err(BlockError::OutOfRange);
+        }
+        let channel = self.channel.lock();
+        channel.wait_not_busy()?;
+        channel.setup(self.slave, lba, count, lba48);
+        channel.write(
+            REG_COMMAND,
+            match (write, lba48) {
+                (false, false) => CMD_READ_SECTORS,
+                (false, true) => CMD_READ_SECTORS_EXT,
+                (true, false) => CMD_WRITE_SECTORS,
+                (true, true) => CMD_WRITE_SECTORS_EXT,
+            },
+        );
+        for sector in 0..count as usize {
+            channel.delay();
+            channel.wait_data()?;
+            transfer(&channel, sector);
+        }
+        if write {
+            channel.delay();
+            let status = channel.wait_not_busy()?;
+            if status & (STATUS_ERR | STATUS_DF) != 0 {
+                return Err(BlockError::Device(channel.read(REG_ERROR)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.info.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        block::check_range(lba, buf.len(), self.info.sectors)?;
+        let per_command = MAX_SECTORS_PER_COMMAND as usize * SECTOR_SIZE;
+        for (i, chunk) in buf.chunks_mut(per_command).enumerate() {
+            let start = lba + (i * MAX_SECTORS_PER_COMMAND as usize) as u64;
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+            self.command(start, count, false, |channel, sector| {
+                channel.read_sector(&mut chunk[sector * SECTOR_SIZE..][..SECTOR_SIZE]);
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, data: &[u8]) -> Result<(), BlockError> {
+        block::check_range(lba, data.len(), self.info.sectors)?;
+        let per_command = MAX_SECTORS_PER_COMMAND as usize * SECTOR_SIZE;
+        for (i, chunk) in data.chunks(per_command).enumerate() {
+            let start = lba + (i * MAX_SECTORS_PER_COMMAND as usize) as u64;
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+            self.command(start, count, true, |channel, sector| {
+                channel.write_sector(&chunk[sector * SECTOR_SIZE..][..SECTOR_SIZE]);
+            })?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        let channel = self.channel.lock();
+        channel.wait_not_busy()?;
+        channel.select(if self.slave { 0xB0 } else { 0xA0 });
+        channel.write(
+            REG_COMMAND,
+            if self.info.lba48 { CMD_CACHE_FLUSH_EXT } else { CMD_CACHE_FLUSH },
+        );
+        channel.delay();
+        let status = channel.wait_not_busy()?;
+        if status & (STATUS_ERR | STATUS_DF) != 0 {
+            return Err(BlockError::Device(channel.read(REG_ERROR)));
+        }
+        Ok(())
+    }
+}
+
+/// Detect drives on both channels and register them.
+pub fn init() {
+    let mut letter = b'a';
+    for (base, control) in CHANNELS {
+        let channel = Channel { base, control };
+        unsafe { Port::<u8>::new(control).write(CONTROL_NIEN) };
+        let channel = Arc::new(Mutex::new(channel));
+        for slave in [false, true] {
+            let info = identify(&channel.lock(), slave);
+            let name = format!("hd{}", letter as char);
+            letter += 1;
+            if let Some(info) = info {
+                crate::serial_println!(
+                    "ATA: {} {} ({} sectors, {})",
+                    name,
+                    info.model,
+                    info.sectors,
+                    if info.lba48 { "LBA48" } else { "LBA28" }
+                );
+                block::register(Arc::new(AtaDrive {
+                    name,
+                    channel: channel.clone(),
+                    slave,
+                    info,
+                }));
+            }
+        }
+    }
+}
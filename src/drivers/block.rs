@@ -0,0 +1,132 @@
+/// Block devices: storage addressed in fixed-size sectors.
+///
+/// Drivers implement `BlockDevice` and `register` each device they find;
+/// filesystems look devices up by name (`hda`, `hdb`, ...) and hold an
+/// `Arc` to them. Methods take `&self` so one device can be shared, which
+/// means drivers serialise access internally.
+///
+/// `MemDisk` is a block device backed by a heap buffer, for building
+/// filesystem images in memory.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub const SECTOR_SIZE: usize = 512;
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The request runs past the last sector.
+    OutOfRange,
+    /// The buffer isn't a whole number of sectors.
+    Misaligned,
+    /// The device didn't become ready in time.
+    Timeout,
+    /// The device reported an error (driver-specific code).
+    Device(u8),
+}
+
+impl core::fmt::Display for BlockError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BlockError::OutOfRange => write!(f, "sector out of range"),
+            BlockError::Misaligned => write!(f, "buffer is not a whole number of sectors"),
+            BlockError::Timeout => write!(f, "device timed out"),
+            BlockError::Device(code) => write!(f, "device error {:#04x}", code),
+        }
+    }
+}
+
+pub trait BlockDevice: Send + Sync {
+    /// Short device name, e.g. `hda`.
+    fn name(&self) -> &str;
+
+    /// Size of the device in sectors.
+    fn sector_count(&self) -> u64;
+
+    /// Read `buf.len() / SECTOR_SIZE` sectors starting at `lba`.
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Write `data.len() / SECTOR_SIZE` sectors starting at `lba`.
+    fn write_sectors(&self, lba: u64, data: &[u8]) -> Result<(), BlockError>;
+
+    /// Make earlier writes durable. Devices without a write cache have
+    /// nothing to do.
+    fn flush(&self) -> Result<(), BlockError> {
+        Ok(())
+    }
+}
+
+/// Check that a transfer of `len` bytes at `lba` fits on a device of
+/// `sectors` sectors, returning the sector count.
+pub fn check_range(lba: u64, len: usize, sectors: u64) -> Result<u64, BlockError> {
+    if !len.is_multiple_of(SECTOR_SIZE) {
+        return Err(BlockError::Misaligned);
+    }
+    let count = (len / SECTOR_SIZE) as u64;
+    match lba.checked_add(count) {
+        Some(end) if end <= sectors => Ok(count),
+        _ => Err(BlockError::OutOfRange),
+    }
+}
+
+/// Make a device available by name.
+pub fn register(device: Arc<dyn BlockDevice>) {
+    DEVICES.lock().push(device);
+}
+
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().iter().find(|dev| dev.name() == name).cloned()
+}
+
+/// Every registered device, in detection order.
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().clone()
+}
+
+/// A block device in memory.
+pub struct MemDisk {
+    name: String,
+    data: Mutex<Vec<u8>>,
+}
+
+impl MemDisk {
+    pub fn new(name: &str, sectors: usize) -> MemDisk {
+        MemDisk {
+            name: String::from(name),
+            data: Mutex::new(vec![0; sectors * SECTOR_SIZE]),
+        }
+    }
+}
+
+impl BlockDevice for MemDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        (self.data.lock().len() / SECTOR_SIZE) as u64
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        let data = self.data.lock();
+        check_range(lba, buf.len(), (data.len() / SECTOR_SIZE) as u64)?;
+        let start = lba as usize * SECTOR_SIZE;
+        buf.copy_from_slice(&data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, src: &[u8]) -> Result<(), BlockError> {
+        let mut data = self.data.lock();
+        check_range(lba, src.len(), (data.len() / SECTOR_SIZE) as u64)?;
+        let start = lba as usize * SECTOR_SIZE;
+        data[start..start + src.len()].copy_from_slice(src);
+        Ok(())
+    }
+}
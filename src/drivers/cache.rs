@@ -0,0 +1,354 @@
+/// Block buffer cache.
+///
+/// Holds recently used sectors of every block device, keyed by
+/// (device, sector number), so filesystems don't go to the disk for each
+/// metadata lookup. Filesystems see it through `CachedDevice`, a
+/// `BlockDevice` wrapper returned by `get`. Writes only dirty the cached
+/// copy; dirty sectors reach the device when they are evicted (least
+/// recently used first), when `sync` runs, when the wrapper is dropped
+/// (e.g. on unmount), or from the write-back thread started by
+/// `start_writeback`, which syncs every few seconds.
+///
+/// Misses that fall next to each other are read from the device in one
+/// request. The cache lock is taken with interrupts disabled, so the
+/// write-back thread can't be preempted while it holds it. To keep those
+/// stretches short, `CachedDevice` splits requests so each hold moves at
+/// most `MAX_SECTORS_PER_LOCK` sectors (plus write-backs of evicted ones),
+/// and flushes write dirty sectors back a batch at a time.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use super::block::{self, BlockDevice, BlockError, SECTOR_SIZE};
+
+/// Sectors kept in memory (256 KiB).
+pub const CACHE_SECTORS: usize = 512;
+/// How often the write-back thread syncs dirty sectors.
+pub const WRITEBACK_INTERVAL_MS: u64 = 5000;
+/// Sectors read, written or written back per hold of the cache lock.
+pub const MAX_SECTORS_PER_LOCK: usize = 16;
+
+static CACHE: Mutex<Option<BlockCache>> = Mutex::new(None);
+
+/// Cache entries are keyed by the device's address, which can't be reused
+/// while an entry still holds a reference to the device.
+type Key = (usize, u64);
+
+fn device_id(device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(device) as *const () as usize
+}
+
+struct Entry {
+    device: Arc<dyn BlockDevice>,
+    data: Box<[u8; SECTOR_SIZE]>,
+    dirty: bool,
+    /// Value of the cache's clock at the last access, for LRU eviction.
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Dirty sectors written to their device.
+    pub writebacks: u64,
+    pub cached: usize,
+    pub dirty: usize,
+    pub capacity: usize,
+}
+
+pub struct BlockCache {
+    capacity: usize,
+    entries: BTreeMap<Key, Entry>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    writebacks: u64,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> BlockCache {
+        BlockCache {
+            capacity: capacity.max(1),
+            entries: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            writebacks: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    pub fn read(
+        &mut self,
+        device: &Arc<dyn BlockDevice>,
+        lba: u64,
+        buf: &mut [u8],
+    ) -> Result<(), BlockError> {
+        let count = block::check_range(lba, buf.len(), device.sector_count())? as usize;
+        let id = device_id(device);
+        let mut i = 0;
+        while i < count {
+            let now = self.tick();
+            if let Some(entry) = self.entries.get_mut(&(id, lba + i as u64)) {
+                entry.last_used = now;
+                buf[i * SECTOR_SIZE..][..SECTOR_SIZE].copy_from_slice(&entry.data[..]);
+                self.hits += 1;
+                i += 1;
+                continue;
+            }
+            // Read the whole run of missing sectors at once.
+            let mut end = i + 1;
+            while end < count && !self.entries.contains_key(&(id, lba + end as u64)) {
+                end += 1;
+            }
+            let run = &mut buf[i * SECTOR_SIZE..end * SECTOR_SIZE];
+            device.read_sectors(lba + i as u64, run)?;
+            self.misses += (end - i) as u64;
+            for (n, sector) in run.chunks(SECTOR_SIZE).enumerate() {
+                self.insert(device, lba + (i + n) as u64, sector, false)?;
+            }
+            i = end;
+        }
+        Ok(())
+    }
+
+    pub fn write(
+        &mut self,
+        device: &Arc<dyn BlockDevice>,
+        lba: u64,
+        data: &[u8],
+    ) -> Result<(), BlockError> {
+        block::check_range(lba, data.len(), device.sector_count())?;
+        for (n, sector) in data.chunks(SECTOR_SIZE).enumerate() {
+            self.insert(device, lba + n as u64, sector, true)?;
+        }
+        Ok(())
+    }
+
+    /// Store a sector, evicting the least recently used one if full. A
+    /// clean copy never replaces a dirty one.
+    fn insert(
+        &mut self,
+        device: &Arc<dyn BlockDevice>,
+        lba: u64,
+        data: &[u8],
+        dirty: bool,
+    ) -> Result<(), BlockError> {
+        let key = (device_id(device), lba);
+        let now = self.tick();
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if dirty || !entry.dirty {
+                entry.data.copy_from_slice(data);
+            }
+            entry.dirty |= dirty;
+            entry.last_used = now;
+            return Ok(());
+        }
+        if self.entries.len() >= self.capacity {
+            self.evict()?;
+        }
+        let mut sector = Box::new([0u8; SECTOR_SIZE]);
+        sector.copy_from_slice(data);
+        self.entries.insert(
+            key,
+            Entry { device: device.clone(), data: sector, dirty, last_used: now },
+        );
+        Ok(())
+    }
+
+    fn evict(&mut self) -> Result<(), BlockError> {
+        let Some(key) = self.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(k, _)| *k)
+        else {
+            return Ok(());
+        };
+        let entry = &self.entries[&key];
+        if entry.dirty {
+            entry.device.write_sectors(key.1, &entry.data[..])?;
+            self.writebacks += 1;
+        }
+        self.entries.remove(&key);
+        Ok(())
+    }
+
+    /// Write dirty sectors back, only those of `device` if given, then
+    /// flush the devices written to. Returns the number of sectors written.
+    pub fn flush(&mut self, device: Option<&Arc<dyn BlockDevice>>) -> Result<usize, BlockError> {
+        let mut touched = Vec::new();
+        let written = self.write_back(device, usize::MAX, &mut touched)?;
+        for device in touched {
+            device.flush()?;
+        }
+        Ok(written)
+    }
+
+    /// Write back up to `limit` dirty sectors, only those of `device` if
+    /// given, adding the devices written to to `touched`. Returns the
+    /// number of sectors written.
+    fn write_back(
+        &mut self,
+        device: Option<&Arc<dyn BlockDevice>>,
+        limit: usize,
+        touched: &mut Vec<Arc<dyn BlockDevice>>,
+    ) -> Result<usize, BlockError> {
+        let only = device.map(device_id);
+        let mut written = 0;
+        for (&(id, lba), entry) in self.entries.iter_mut() {
+            if written == limit {
+                break;
+            }
+            if !entry.dirty || only.is_some_and(|only| only != id) {
+                continue;
+            }
+            entry.device.write_sectors(lba, &entry.data[..])?;
+            entry.dirty = false;
+            written += 1;
+            self.writebacks += 1;
+            if !touched.iter().any(|d| device_id(d) == id) {
+                touched.push(entry.device.clone());
+            }
+        }
+        Ok(written)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            writebacks: self.writebacks,
+            cached: self.entries.len(),
+            dirty: self.entries.values().filter(|e| e.dirty).count(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+/// A block device whose reads and writes go through the global cache.
+/// Without a cache (before `init`) it passes requests straight through.
+pub struct CachedDevice {
+    inner: Arc<dyn BlockDevice>,
+}
+
+impl CachedDevice {
+    pub fn new(inner: Arc<dyn BlockDevice>) -> CachedDevice {
+        CachedDevice { inner }
+    }
+}
+
+impl BlockDevice for CachedDevice {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        block::check_range(lba, buf.len(), self.sector_count())?;
+        let chunks = buf.chunks_mut(MAX_SECTORS_PER_LOCK * SECTOR_SIZE);
+        for (n, chunk) in chunks.enumerate() {
+            let lba = lba + (n * MAX_SECTORS_PER_LOCK) as u64;
+            with_cache(|cache| match cache {
+                Some(cache) => cache.read(&self.inner, lba, chunk),
+                None => self.inner.read_sectors(lba, chunk),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, data: &[u8]) -> Result<(), BlockError> {
+        block::check_range(lba, data.len(), self.sector_count())?;
+        for (n, chunk) in data.chunks(MAX_SECTORS_PER_LOCK * SECTOR_SIZE).enumerate() {
+            let lba = lba + (n * MAX_SECTORS_PER_LOCK) as u64;
+            with_cache(|cache| match cache {
+                Some(cache) => cache.write(&self.inner, lba, chunk),
+                None => self.inner.write_sectors(lba, chunk),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), BlockError> {
+        if with_cache(|cache| cache.is_none()) {
+            return self.inner.flush();
+        }
+        flush_cached(Some(&self.inner)).map(|_| ())
+    }
+}
+
+impl Drop for CachedDevice {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            crate::serial_println!("cache: flushing {} failed: {}", self.name(), e);
+        }
+    }
+}
+
+fn with_cache<R>(f: impl FnOnce(Option<&mut BlockCache>) -> R) -> R {
+    interrupts::without_interrupts(|| f(CACHE.lock().as_mut()))
+}
+
+pub fn init() {
+    interrupts::without_interrupts(|| *CACHE.lock() = Some(BlockCache::new(CACHE_SECTORS)));
+}
+
+/// The registered device `name`, with its I/O going through the cache.
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    block::get(name).map(|dev| Arc::new(CachedDevice::new(dev)) as Arc<dyn BlockDevice>)
+}
+
+/// Write every dirty sector back. Returns the number of sectors written.
+pub fn sync() -> Result<usize, BlockError> {
+    flush_cached(None)
+}
+
+/// Write back the dirty sectors of `device` (or all of them), a batch per
+/// hold of the lock, then flush the devices outside it.
+fn flush_cached(device: Option<&Arc<dyn BlockDevice>>) -> Result<usize, BlockError> {
+    let mut touched = Vec::new();
+    let mut written = 0;
+    loop {
+        let batch = with_cache(|cache| match cache {
+            Some(cache) => cache.write_back(device, MAX_SECTORS_PER_LOCK, &mut touched),
+            None => Ok(0),
+        })?;
+        written += batch;
+        if batch < MAX_SECTORS_PER_LOCK {
+            break;
+        }
+    }
+    for device in touched {
+        device.flush()?;
+    }
+    Ok(written)
+}
+
+pub fn stats() -> CacheStats {
+    with_cache(|cache| cache.map(|cache| cache.stats()).unwrap_or_default())
+}
+
+/// Start the kernel thread that periodically writes dirty sectors back.
+pub fn start_writeback() -> u64 {
+    crate::task::scheduler::spawn_thread(String::from("writeback"), writeback_thread_entry, 0, None)
+}
+
+fn writeback_thread_entry(_arg: u64) {
+    loop {
+        crate::task::scheduler::sleep_ms(WRITEBACK_INTERVAL_MS);
+        if let Err(e) = sync() {
+            crate::serial_println!("[writeback] sync failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+/// Character devices: byte streams without sectors or offsets.
+///
+/// Drivers implement `CharDevice` and `register` their devices; devfs
+/// shows every registered device as `/dev/<name>`. Reads and writes
+/// don't block: a device with nothing to read returns 0 bytes.
+///
+/// The memory devices live here: `null` discards writes and reads as
+/// empty, `zero` reads as zeros, and `random` reads from RDRAND when the
+/// CPU has it and from a TSC-seeded xorshift generator otherwise.
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::random::RdRand;
+
+static DEVICES: Mutex<Vec<Arc<dyn CharDevice>>> = Mutex::new(Vec::new());
+
+pub trait CharDevice: Send + Sync {
+    /// Name under `/dev`, e.g. `ttyS0`.
+    fn name(&self) -> &str;
+
+    /// Read what is available into `buf`. Returns bytes read.
+    fn read(&self, buf: &mut [u8]) -> usize;
+
+    /// Write `data`. Returns bytes accepted.
+    fn write(&self, data: &[u8]) -> usize;
+}
+
+/// Make a device available by name. Registering a name twice keeps the
+/// first device.
+pub fn register(device: Arc<dyn CharDevice>) {
+    let mut devices = DEVICES.lock();
+    if !devices.iter().any(|dev| dev.name() == device.name()) {
+        devices.push(device);
+    }
+}
+
+pub fn get(name: &str) -> Option<Arc<dyn CharDevice>> {
+    DEVICES.lock().iter().find(|dev| dev.name() == name).cloned()
+}
+
+/// Every registered device, in registration order.
+pub fn devices() -> Vec<Arc<dyn CharDevice>> {
+    DEVICES.lock().clone()
+}
+
+/// Register the console, the serial port and the memory devices.
+pub fn init() {
+    register(Arc::new(crate::vga_buffer::ConsoleDevice));
+    register(Arc::new(crate::serial::SerialDevice));
+    register(Arc::new(Null));
+    register(Arc::new(Zero));
+    register(Arc::new(Random::new()));
+}
+
+pub struct Null;
+
+impl CharDevice for Null {
+    fn name(&self) -> &str {
+        "null"
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> usize {
+        0
+    }
+
+    fn write(&self, data: &[u8]) -> usize {
+        data.len()
+    }
+}
+
+pub struct Zero;
+
+impl CharDevice for Zero {
+    fn name(&self) -> &str {
+        "zero"
+    }
+
+    fn read(&self, buf: &mut [u8]) -> usize {
+        buf.fill(0);
+        buf.len()
+    }
+
+    fn write(&self, data: &[u8]) -> usize {
+        data.len()
+    }
+}
+
+pub struct Random {
+    state: Mutex<u64>,
+}
+
+impl Random {
+    pub fn new() -> Random {
+        Random { state: Mutex::new(timestamp() | 1) }
+    }
+
+    /// xorshift64*, with the TSC mixed in so separate readers diverge.
+    fn next(&self) -> u64 {
+        if let Some(value) = RdRand::new().and_then(RdRand::get_u64) {
+            return value;
+        }
+        let mut state = self.state.lock();
+        let mut x = *state ^ timestamp().rotate_left(32);
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        if x == 0 {
+            x = 0x9E37_79B9_7F4A_7C15;
+        }
+        *state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::new()
+    }
+}
+
+impl CharDevice for Random {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn read(&self, buf: &mut [u8]) -> usize {
+        for chunk in buf.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next().to_le_bytes()[..chunk.len()]);
+        }
+        buf.len()
+    }
+
+    /// Writes are accepted and dropped, as with `/dev/null`.
+    fn write(&self, data: &[u8]) -> usize {
+        data.len()
+    }
+}
+
+fn timestamp() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
@@ -0,0 +1,19 @@
+/// Hardware device drivers.
+///
+/// `block` defines the `BlockDevice` trait that storage drivers implement
+/// and keeps the list of detected devices; `ata` drives the IDE disks
+/// QEMU emulates; `cache` keeps recently used sectors in memory for the
+/// filesystems mounted on them. `chardev` is the same for byte-stream
+/// devices (console, serial port, null, zero, random).
+
+pub mod ata;
+pub mod block;
+pub mod cache;
+pub mod chardev;
+
+/// Probe for devices and register what is found.
+pub fn init() {
+    ata::init();
+    cache::init();
+    chardev::init();
+}
@@ -0,0 +1,131 @@
+/// Device filesystem (devfs), mounted at `/dev`.
+///
+/// A flat directory with one node per registered character device
+/// (`drivers::chardev`). Reads and writes on a node go straight to its
+/// driver; offsets mean nothing to a device and are ignored. Devices
+/// can't be created or removed through the filesystem.
+///
+/// The registry only grows, so a device's inode is its position in it
+/// (after the root).
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::{DirEntry, FileSystem, FileType, FsError, InodeId, Metadata};
+use crate::drivers::chardev::{self, CharDevice};
+
+const ROOT: InodeId = 1;
+
+/// Permission bits of every device node.
+const DEVICE_MODE: u16 = 0o666;
+
+pub struct DevFs {
+    mounted: u64,
+}
+
+impl DevFs {
+    pub fn new() -> DevFs {
+        DevFs { mounted: crate::time::now() }
+    }
+
+    fn device(&self, inode: InodeId) -> Result<Arc<dyn CharDevice>, FsError> {
+        if inode == ROOT {
+            return Err(FsError::NotAFile);
+        }
+        let index = inode.checked_sub(ROOT + 1).ok_or(FsError::NotFound)?;
+        chardev::devices().get(index as usize).cloned().ok_or(FsError::NotFound)
+    }
+}
+
+impl Default for DevFs {
+    fn default() -> Self {
+        DevFs::new()
+    }
+}
+
+impl FileSystem for DevFs {
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT
+    }
+
+    fn lookup(&self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        if dir != ROOT {
+            self.device(dir)?;
+            return Err(FsError::NotADirectory);
+        }
+        match name {
+            "." | ".." => Ok(ROOT),
+            _ => chardev::devices()
+                .iter()
+                .position(|dev| dev.name() == name)
+                .map(|index| ROOT + 1 + index as InodeId)
+                .ok_or(FsError::NotFound),
+        }
+    }
+
+    fn read(&self, inode: InodeId, _offset: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+        Ok(self.device(inode)?.read(buf))
+    }
+
+    fn write(&mut self, inode: InodeId, _offset: usize, data: &[u8]) -> Result<usize, FsError> {
+        Ok(self.device(inode)?.write(data))
+    }
+
+    /// Devices have no length; truncating one (as opening for writing
+    /// with `TRUNCATE` does) is allowed and does nothing.
+    fn truncate(&mut self, inode: InodeId, _size: usize) -> Result<(), FsError> {
+        self.device(inode).map(|_| ())
+    }
+
+    fn readdir(&self, dir: InodeId) -> Result<Vec<DirEntry>, FsError> {
+        if dir != ROOT {
+            self.device(dir)?;
+            return Err(FsError::NotADirectory);
+        }
+        Ok(chardev::devices()
+            .iter()
+            .enumerate()
+            .map(|(index, dev)| DirEntry {
+                name: String::from(dev.name()),
+                inode: ROOT + 1 + index as InodeId,
+                kind: FileType::CharDevice,
+            })
+            .collect())
+    }
+
+    fn create(&mut self, _dir: InodeId, _name: &str, _kind: FileType) -> Result<InodeId, FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn unlink(&mut self, _dir: InodeId, _name: &str) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+
+    fn stat(&self, inode: InodeId) -> Result<Metadata, FsError> {
+        let (kind, size, mode, nlink) = if inode == ROOT {
+            (FileType::Directory, chardev::devices().len() as u64, 0o755, 2)
+        } else {
+            self.device(inode)?;
+            (FileType::CharDevice, 0, DEVICE_MODE, 1)
+        };
+        Ok(Metadata {
+            inode,
+            kind,
+            size,
+            mode,
+            uid: 0,
+            gid: 0,
+            nlink,
+            created: self.mounted,
+            modified: self.mounted,
+            accessed: self.mounted,
+        })
+    }
+}
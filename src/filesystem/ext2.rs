@@ -0,0 +1,432 @@
+/// Read-only ext2 filesystem on a block device.
+///
+/// Parses the superblock and block group descriptors once at mount, then
+/// reads inodes and data on demand: blocks are found through the direct,
+/// single-, double- and triple-indirect pointers (a zero pointer is a hole
+/// and reads as zeros). Inode numbers are ext2's own, so `.` and `..` come
+/// straight from the directory entries. Symbolic links are supported,
+/// including "fast" ones stored inside the inode.
+///
+/// Volumes using incompatible features other than directory entry file
+/// types (extents, 64-bit, compression, ...) are refused, which rules out
+/// most ext3/ext4 images. Every modification fails with `ReadOnly`.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{DirEntry, FileSystem, FileType, FsError, InodeId, Metadata};
+use crate::drivers::block::{BlockDevice, SECTOR_SIZE};
+
+const SUPERBLOCK_OFFSET: usize = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const EXT2_MAGIC: u16 = 0xEF53;
+const ROOT_INODE: u32 = 2;
+/// Inode size before revision 1 made it configurable.
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GROUP_DESC_SIZE: usize = 32;
+
+/// Incompatible feature: directory entries record the file type.
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+
+const MODE_TYPE_MASK: u16 = 0xF000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_SYMLINK: u16 = 0xA000;
+
+const DIRECT_BLOCKS: usize = 12;
+/// Symlinks shorter than this keep their target in the block pointers.
+const FAST_SYMLINK_MAX: usize = 60;
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+/// The parts of an on-disk inode we use.
+#[derive(Debug, Clone)]
+struct Inode {
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    accessed: u32,
+    changed: u32,
+    modified: u32,
+    links: u16,
+    /// 512-byte sectors allocated, including indirect blocks.
+    sectors: u32,
+    block: [u32; 15],
+}
+
+impl Inode {
+    fn kind(&self) -> FileType {
+        match self.mode & MODE_TYPE_MASK {
+            MODE_DIRECTORY => FileType::Directory,
+            MODE_SYMLINK => FileType::Symlink,
+            // Device nodes, FIFOs and sockets read as (empty) files.
+            _ => FileType::File,
+        }
+    }
+}
+
+pub struct Ext2 {
+    device: Arc<dyn BlockDevice>,
+    block_size: usize,
+    blocks_count: u32,
+    inodes_count: u32,
+    inodes_per_group: u32,
+    inode_size: usize,
+    /// First block of each group's inode table.
+    inode_tables: Vec<u32>,
+    /// Revision 1+ keeps the high half of file sizes in `i_dir_acl`.
+    large_sizes: bool,
+}
+
+impl Ext2 {
+    /// True if `device` has an ext2 superblock, whether or not it would
+    /// mount.
+    pub fn probe(device: &dyn BlockDevice) -> bool {
+        let mut sb = [0u8; SUPERBLOCK_SIZE];
+        device.read_sectors((SUPERBLOCK_OFFSET / SECTOR_SIZE) as u64, &mut sb).is_ok()
+            && u16_at(&sb, 56) == EXT2_MAGIC
+    }
+
+    /// Mount the ext2 volume on `device`.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Ext2, FsError> {
+        let mut sb = [0u8; SUPERBLOCK_SIZE];
+        device.read_sectors((SUPERBLOCK_OFFSET / SECTOR_SIZE) as u64, &mut sb)?;
+        if u16_at(&sb, 56) != EXT2_MAGIC {
+            return Err(FsError::Corrupted);
+        }
+        let log_block_size = u32_at(&sb, 24);
+        if log_block_size > 6 {
+            return Err(FsError::Corrupted);
+        }
+        let block_size = 1024 << log_block_size;
+        let inodes_count = u32_at(&sb, 0);
+        let blocks_count = u32_at(&sb, 4);
+        let first_data_block = u32_at(&sb, 20);
+        let blocks_per_group = u32_at(&sb, 32);
+        let inodes_per_group = u32_at(&sb, 40);
+        let revision = u32_at(&sb, 76);
+        let inode_size = if revision == 0 {
+            GOOD_OLD_INODE_SIZE
+        } else {
+            u16_at(&sb, 88) as usize
+        };
+        if revision > 0 && u32_at(&sb, 96) & !INCOMPAT_FILETYPE != 0 {
+            return Err(FsError::NotSupported);
+        }
+        if blocks_per_group == 0
+            || inodes_per_group == 0
+            || inode_size < GOOD_OLD_INODE_SIZE
+            || inode_size > block_size
+            || !inode_size.is_power_of_two()
+            || blocks_count as u64 * (block_size / SECTOR_SIZE) as u64 > device.sector_count()
+        {
+            return Err(FsError::Corrupted);
+        }
+
+        let groups = blocks_count
+            .checked_sub(first_data_block)
+            .ok_or(FsError::Corrupted)?
+            .div_ceil(blocks_per_group) as usize;
+        let mut fs = Ext2 {
+            device,
+            block_size,
+            blocks_count,
+            inodes_count,
+            inodes_per_group,
+            inode_size,
+            inode_tables: Vec::with_capacity(groups),
+            large_sizes: revision > 0,
+        };
+        // The descriptor table starts in the block after the superblock.
+        let mut table = vec![0u8; (groups * GROUP_DESC_SIZE).div_ceil(block_size) * block_size];
+        fs.read_blocks(first_data_block + 1, &mut table)?;
+        fs.inode_tables = table
+            .chunks_exact(GROUP_DESC_SIZE)
+            .take(groups)
+            .map(|desc| u32_at(desc, 8))
+            .collect();
+        if fs.read_inode(ROOT_INODE)?.kind() != FileType::Directory {
+            return Err(FsError::Corrupted);
+        }
+        Ok(fs)
+    }
+
+    /// Read whole blocks starting at `block` into `buf`.
+    fn read_blocks(&self, block: u32, buf: &mut [u8]) -> Result<(), FsError> {
+        let lba = block as u64 * (self.block_size / SECTOR_SIZE) as u64;
+        self.device.read_sectors(lba, buf)?;
+        Ok(())
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<Inode, FsError> {
+        if ino == 0 || ino > self.inodes_count {
+            return Err(FsError::NotFound);
+        }
+        let group = ((ino - 1) / self.inodes_per_group) as usize;
+        let index = ((ino - 1) % self.inodes_per_group) as usize;
+        let table = *self.inode_tables.get(group).ok_or(FsError::Corrupted)?;
+        let byte = index * self.inode_size;
+        let mut block = vec![0u8; self.block_size];
+        self.read_blocks(table + (byte / self.block_size) as u32, &mut block)?;
+        let raw = &block[byte % self.block_size..][..GOOD_OLD_INODE_SIZE];
+
+        let mode = u16_at(raw, 0);
+        let mut size = u32_at(raw, 4) as u64;
+        if self.large_sizes && mode & MODE_TYPE_MASK != MODE_DIRECTORY {
+            size |= (u32_at(raw, 108) as u64) << 32;
+        }
+        let mut pointers = [0u32; 15];
+        for (i, pointer) in pointers.iter_mut().enumerate() {
+            *pointer = u32_at(raw, 40 + i * 4);
+        }
+        Ok(Inode {
+            mode,
+            uid: u16_at(raw, 2) as u32 | (u16_at(raw, 120) as u32) << 16,
+            gid: u16_at(raw, 24) as u32 | (u16_at(raw, 122) as u32) << 16,
+            size,
+            accessed: u32_at(raw, 8),
+            changed: u32_at(raw, 12),
+            modified: u32_at(raw, 16),
+            links: u16_at(raw, 26),
+            sectors: u32_at(raw, 28),
+            block: pointers,
+        })
+    }
+
+    fn inode_of_kind(&self, ino: InodeId, kind: FileType) -> Result<Inode, FsError> {
+        let inode = self.read_inode(u32::try_from(ino).map_err(|_| FsError::NotFound)?)?;
+        match kind {
+            _ if inode.kind() == kind => Ok(inode),
+            FileType::Directory => Err(FsError::NotADirectory),
+            FileType::File => Err(FsError::NotAFile),
+            FileType::Symlink | FileType::CharDevice => Err(FsError::InvalidPath),
+        }
+    }
+
+    /// Disk block holding logical block `n` of a file (0 for a hole).
+    fn block_for(&self, inode: &Inode, n: u64) -> Result<u32, FsError> {
+        if n < DIRECT_BLOCKS as u64 {
+            return Ok(inode.block[n as usize]);
+        }
+        let per_block = (self.block_size / 4) as u64;
+        let mut n = n - DIRECT_BLOCKS as u64;
+        let mut span = 1;
+        for level in 0..3 {
+            span *= per_block;
+            if n < span {
+                return self.walk_indirect(inode.block[DIRECT_BLOCKS + level], n, span / per_block);
+            }
+            n -= span;
+        }
+        Err(FsError::Corrupted)
+    }
+
+    /// Follow indirect blocks from `block` to entry `n`; `span` is how
+    /// many data blocks each pointer in `block` covers.
+    fn walk_indirect(&self, mut block: u32, mut n: u64, mut span: u64) -> Result<u32, FsError> {
+        let per_block = (self.block_size / 4) as u64;
+        let mut pointers = vec![0u8; self.block_size];
+        loop {
+            if block == 0 {
+                return Ok(0);
+            }
+            self.read_blocks(block, &mut pointers)?;
+            let next = u32_at(&pointers, (n / span) as usize * 4);
+            if span == 1 {
+                return Ok(next);
+            }
+            n %= span;
+            span /= per_block;
+            block = next;
+        }
+    }
+
+    /// Read file data from `offset`, up to the end of the file.
+    fn read_data(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> Result<usize, FsError> {
+        if offset >= inode.size {
+            return Ok(0);
+        }
+        let len = buf.len().min((inode.size - offset) as usize);
+        let block_size = self.block_size as u64;
+        let mut data = vec![0u8; self.block_size];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let within = (pos % block_size) as usize;
+            let n = (self.block_size - within).min(len - done);
+            match self.block_for(inode, pos / block_size)? {
+                0 => buf[done..done + n].fill(0),
+                block => {
+                    self.read_blocks(block, &mut data)?;
+                    buf[done..done + n].copy_from_slice(&data[within..within + n]);
+                }
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// `(name, inode, kind)` for every entry of a directory, including
+    /// `.` and `..`.
+    fn entries(&self, dir: &Inode) -> Result<Vec<(String, u32, FileType)>, FsError> {
+        // The size is read whole, so don't trust one bigger than the volume.
+        if dir.size > self.blocks_count as u64 * self.block_size as u64 {
+            return Err(FsError::Corrupted);
+        }
+        let mut data = vec![0u8; dir.size as usize];
+        self.read_data(dir, 0, &mut data)?;
+        let mut entries = Vec::new();
+        for block in data.chunks(self.block_size) {
+            let mut at = 0;
+            while at + 8 <= block.len() {
+                let ino = u32_at(block, at);
+                let rec_len = u16_at(block, at + 4) as usize;
+                let name_len = block[at + 6] as usize;
+                if rec_len < 8 || at + rec_len > block.len() || 8 + name_len > rec_len {
+                    return Err(FsError::Corrupted);
+                }
+                if ino != 0 {
+                    let name = String::from_utf8_lossy(&block[at + 8..at + 8 + name_len]).into_owned();
+                    // Entry type codes (with the filetype feature): 2 is a
+                    // directory, 7 a symlink. Without it, read the inode.
+                    let kind = match block[at + 7] {
+                        2 => FileType::Directory,
+                        7 => FileType::Symlink,
+                        0 => self.read_inode(ino)?.kind(),
+                        _ => FileType::File,
+                    };
+                    entries.push((name, ino, kind));
+                }
+                at += rec_len;
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn device(&self) -> Option<&str> {
+        Some(self.device.name())
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT_INODE as InodeId
+    }
+
+    fn lookup(&self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        let dir = self.inode_of_kind(dir, FileType::Directory)?;
+        self.entries(&dir)?
+            .into_iter()
+            .find(|(entry, _, _)| entry == name)
+            .map(|(_, ino, _)| ino as InodeId)
+            .ok_or(FsError::NotFound)
+    }
+
+    fn read(&self, inode: InodeId, offset: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+        let inode = self.inode_of_kind(inode, FileType::File)?;
+        self.read_data(&inode, offset as u64, buf)
+    }
+
+    fn write(&mut self, _inode: InodeId, _offset: usize, _data: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&mut self, _inode: InodeId, _size: usize) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn readdir(&self, dir: InodeId) -> Result<Vec<DirEntry>, FsError> {
+        let dir = self.inode_of_kind(dir, FileType::Directory)?;
+        Ok(self
+            .entries(&dir)?
+            .into_iter()
+            .filter(|(name, _, _)| name != "." && name != "..")
+            .map(|(name, ino, kind)| DirEntry {
+                name,
+                inode: ino as InodeId,
+                kind,
+            })
+            .collect())
+    }
+
+    fn create(&mut self, _dir: InodeId, _name: &str, _kind: FileType) -> Result<InodeId, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&mut self, _dir: InodeId, _name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&mut self, _old_dir: InodeId, _old_name: &str, _new_dir: InodeId, _new_name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn stat(&self, inode: InodeId) -> Result<Metadata, FsError> {
+        let ino = u32::try_from(inode).map_err(|_| FsError::NotFound)?;
+        let node = self.read_inode(ino)?;
+        Ok(Metadata {
+            inode,
+            kind: node.kind(),
+            size: node.size,
+            mode: node.mode & 0o7777,
+            uid: node.uid,
+            gid: node.gid,
+            nlink: node.links as u32,
+            // ext2 has no creation time; ctime is the closest.
+            created: node.changed as u64,
+            modified: node.modified as u64,
+            accessed: node.accessed as u64,
+        })
+    }
+
+    fn set_mode(&mut self, _inode: InodeId, _mode: u16) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn symlink(&mut self, _dir: InodeId, _name: &str, _target: &str) -> Result<InodeId, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn readlink(&self, inode: InodeId) -> Result<String, FsError> {
+        let node = self.inode_of_kind(inode, FileType::Symlink)?;
+        let size = node.size as usize;
+        let target = if size < FAST_SYMLINK_MAX && node.sectors == 0 {
+            node.block
+                .iter()
+                .flat_map(|pointer| pointer.to_le_bytes())
+                .take(size)
+                .collect()
+        } else {
+            let mut data = vec![0u8; size];
+            self.read_data(&node, 0, &mut data)?;
+            data
+        };
+        String::from_utf8(target).map_err(|_| FsError::Corrupted)
+    }
+
+    fn link(&mut self, _dir: InodeId, _name: &str, _inode: InodeId) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn set_owner(&mut self, _inode: InodeId, _uid: u32, _gid: u32) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn set_times(&mut self, _inode: InodeId, _accessed: u64, _modified: u64) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+}
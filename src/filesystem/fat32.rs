@@ -0,0 +1,1246 @@
+/// FAT32 filesystem on a block device.
+///
+/// Reads and writes volumes made by `mkfs.fat -F 32` (or `Fat32::format`):
+/// files and directories with long (VFAT) names, cluster chains allocated
+/// and freed through the FAT, and the FSInfo free-cluster hint kept up to
+/// date. Every change is written straight to the device.
+///
+/// FAT has no inodes, so inode numbers are handed out as entries are
+/// looked up and remembered by the position of their directory entry
+/// (directory cluster + slot). They stay valid across renames. A file
+/// unlinked while open loses its entry but keeps its clusters until
+/// `evict`. Names match case-insensitively; modes are synthesised
+/// (`0o755`/`0o644`, without write bits if the read-only attribute is set)
+/// and there are no owners, links or symlinks.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use super::{DirEntry, FileSystem, FileType, FsError, InodeId, Metadata};
+use crate::drivers::block::{BlockDevice, SECTOR_SIZE};
+use crate::time::DateTime;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+/// READ_ONLY | HIDDEN | SYSTEM | VOLUME_ID marks a long-name entry.
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// NT reserved byte: the base / extension of the short name is lowercase.
+const NTRES_LOWER_BASE: u8 = 0x08;
+const NTRES_LOWER_EXT: u8 = 0x10;
+
+const ENTRY_SIZE: usize = 32;
+/// First name byte: this and every later slot are unused.
+const ENTRY_END: u8 = 0x00;
+/// First name byte: deleted slot.
+const ENTRY_FREE: u8 = 0xE5;
+/// Long-name sequence number flag on the entry holding the name's end.
+const LFN_LAST: u8 = 0x40;
+const LFN_CHARS: usize = 13;
+/// Byte offsets of the UCS-2 characters in a long-name entry.
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_NAME_LEN: usize = 255;
+
+const FAT_MASK: u32 = 0x0FFF_FFFF;
+const FAT_EOC: u32 = 0x0FFF_FFFF;
+/// Values from here up end a chain.
+const FAT_EOC_MIN: u32 = 0x0FFF_FFF8;
+const FIRST_CLUSTER: u32 = 2;
+
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIG: u32 = 0xAA55_0000;
+const FSINFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+const ROOT: InodeId = 0;
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn put_u16(data: &mut [u8], at: usize, value: u16) {
+    data[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(data: &mut [u8], at: usize, value: u32) {
+    data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Unix time to FAT `(date, time)`. FAT dates start in 1980.
+fn to_fat_time(timestamp: u64) -> (u16, u16) {
+    let t = DateTime::from_unix(timestamp);
+    if t.year < 1980 {
+        return (1 << 5 | 1, 0); // 1980-01-01
+    }
+    let date = ((t.year - 1980).min(127) << 9 | t.month << 5 | t.day) as u16;
+    let time = (t.hour << 11 | t.minute << 5 | (t.second / 2)) as u16;
+    (date, time)
+}
+
+fn from_fat_time(date: u16, time: u16) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+    let date = date as u32;
+    let time = time as u32;
+    DateTime {
+        year: 1980 + (date >> 9),
+        month: (date >> 5 & 0x0F).max(1),
+        day: (date & 0x1F).max(1),
+        hour: time >> 11,
+        minute: time >> 5 & 0x3F,
+        second: (time & 0x1F) * 2,
+    }
+    .to_unix()
+}
+
+/// Checksum of an 8.3 name, stored in each of its long-name entries.
+fn short_checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&c)
+}
+
+/// Characters FAT doesn't allow in any name.
+fn valid_long_name(name: &str) -> bool {
+    name.len() <= MAX_NAME_LEN
+        && !name.chars().any(|c| c < ' ' || "\"*:<>?\\|".contains(c))
+        && !name.ends_with('.')
+}
+
+/// The 8.3 form of `name`, and whether it holds the name exactly (after
+/// uppercasing), so no long name is needed beyond preserving case.
+fn short_basis(name: &str) -> ([u8; 11], bool) {
+    let mut short = [b' '; 11];
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(dot) => (&trimmed[..dot], &trimmed[dot + 1..]),
+        None => (trimmed, ""),
+    };
+    let mut exact = trimmed.len() == name.len() && base.len() <= 8 && ext.len() <= 3;
+    let mut fill = |field: &mut [u8], part: &str| {
+        let mut n = 0;
+        for c in part.chars() {
+            if c == ' ' || c == '.' {
+                exact = false;
+                continue;
+            }
+            if n == field.len() {
+                exact = false;
+                break;
+            }
+            let upper = c.to_ascii_uppercase();
+            field[n] = if upper.is_ascii() && is_short_char(upper as u8) {
+                upper as u8
+            } else {
+                exact = false;
+                b'_'
+            };
+            n += 1;
+        }
+    };
+    fill(&mut short[..8], base);
+    fill(&mut short[8..], ext);
+    if short[0] == b' ' {
+        short[0] = b'_';
+        exact = false;
+    }
+    (short, exact)
+}
+
+/// Replace the end of the base name with `~n`.
+fn numbered(basis: &[u8; 11], n: u32) -> [u8; 11] {
+    let tail = alloc::format!("~{}", n);
+    let base_len = basis[..8].iter().position(|&b| b == b' ').unwrap_or(8);
+    let keep = base_len.min(8 - tail.len());
+    let mut short = *basis;
+    short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+    for b in &mut short[keep + tail.len()..8] {
+        *b = b' ';
+    }
+    short
+}
+
+/// `NAME.EXT` as stored in the entry, with the lowercase flags applied.
+fn short_display(short: &[u8; 11], ntres: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        let end = bytes.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+        bytes[..end]
+            .iter()
+            .map(|&b| if lower { b.to_ascii_lowercase() as char } else { b as char })
+            .collect()
+    };
+    let mut bytes = *short;
+    if bytes[0] == 0x05 {
+        bytes[0] = ENTRY_FREE; // 0xE5 as a real first character
+    }
+    let mut name = part(&bytes[..8], ntres & NTRES_LOWER_BASE != 0);
+    let ext = part(&bytes[8..], ntres & NTRES_LOWER_EXT != 0);
+    if !ext.is_empty() {
+        name.push('.');
+        name.push_str(&ext);
+    }
+    name
+}
+
+/// Long-name entries for `name`, in on-disk order (last part first).
+fn long_entries(name: &str, checksum: u8) -> Vec<[u8; ENTRY_SIZE]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LFN_CHARS);
+    (1..=count)
+        .rev()
+        .map(|ord| {
+            let mut entry = [0u8; ENTRY_SIZE];
+            entry[0] = ord as u8 | if ord == count { LFN_LAST } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            for (i, &at) in LFN_OFFSETS.iter().enumerate() {
+                let index = (ord - 1) * LFN_CHARS + i;
+                // One NUL after the name, then 0xFFFF padding.
+                let unit = match index.cmp(&units.len()) {
+                    core::cmp::Ordering::Less => units[index],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xFFFF,
+                };
+                put_u16(&mut entry, at, unit);
+            }
+            entry
+        })
+        .collect()
+}
+
+/// Where a directory entry lives: the directory's first cluster and the
+/// index of the entry's short (8.3) slot in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct EntryPos {
+    dir: u32,
+    slot: u32,
+}
+
+/// One name in a directory, as read from disk.
+#[derive(Debug, Clone)]
+struct Slot {
+    name: String,
+    short: [u8; 11],
+    /// First slot of the entry, including its long-name slots.
+    first: u32,
+    /// The 8.3 slot itself.
+    slot: u32,
+    raw: [u8; ENTRY_SIZE],
+}
+
+impl Slot {
+    /// Names match case-insensitively, by long name or 8.3 alias.
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name) || short_display(&self.short, 0).eq_ignore_ascii_case(name)
+    }
+
+    fn attr(&self) -> u8 {
+        self.raw[11]
+    }
+
+    fn cluster(&self) -> u32 {
+        (u16_at(&self.raw, 20) as u32) << 16 | u16_at(&self.raw, 26) as u32
+    }
+
+    fn is_dir(&self) -> bool {
+        self.attr() & ATTR_DIRECTORY != 0
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    kind: FileType,
+    first_cluster: u32,
+    size: u32,
+    attr: u8,
+    created: u64,
+    modified: u64,
+    accessed: u64,
+    /// The directory entry naming this node; `None` for the root and for
+    /// unlinked nodes.
+    entry: Option<EntryPos>,
+    /// Containing directory, for `..`.
+    parent: InodeId,
+}
+
+impl Node {
+    fn from_slot(slot: &Slot, pos: EntryPos, parent: InodeId) -> Node {
+        let raw = &slot.raw;
+        Node {
+            kind: if slot.is_dir() { FileType::Directory } else { FileType::File },
+            first_cluster: slot.cluster(),
+            size: if slot.is_dir() { 0 } else { u32_at(raw, 28) },
+            attr: slot.attr(),
+            created: from_fat_time(u16_at(raw, 16), u16_at(raw, 14)),
+            modified: from_fat_time(u16_at(raw, 24), u16_at(raw, 22)),
+            accessed: from_fat_time(u16_at(raw, 18), 0),
+            entry: Some(pos),
+            parent,
+        }
+    }
+}
+
+/// Inode numbers handed out so far. Behind a `RefCell` because lookups
+/// (which only borrow the filesystem) assign them.
+struct NodeTable {
+    nodes: BTreeMap<InodeId, Node>,
+    by_entry: BTreeMap<EntryPos, InodeId>,
+    next_inode: InodeId,
+}
+
+/// Volume layout from the boot sector.
+#[derive(Debug, Clone, Copy)]
+struct Geometry {
+    sectors_per_cluster: u64,
+    fat_start: u64,
+    fat_sectors: u64,
+    fats: u64,
+    data_start: u64,
+    /// Number of data clusters; valid cluster numbers are `2..clusters + 2`.
+    clusters: u32,
+    root_cluster: u32,
+    info_sector: Option<u64>,
+}
+
+impl Geometry {
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    fn cluster_lba(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster
+    }
+
+    fn is_valid(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..self.clusters + FIRST_CLUSTER).contains(&cluster)
+    }
+}
+
+pub struct Fat32 {
+    device: Arc<dyn BlockDevice>,
+    geometry: Geometry,
+    table: RefCell<NodeTable>,
+    free_clusters: u32,
+    /// Where the next free-cluster search starts.
+    next_free: u32,
+}
+
+impl Fat32 {
+    /// True if `device` starts with a boot sector signature: a FAT volume
+    /// of any kind, or a partition table.
+    pub fn probe(device: &dyn BlockDevice) -> bool {
+        let mut boot = [0u8; SECTOR_SIZE];
+        device.read_sectors(0, &mut boot).is_ok() && u16_at(&boot, 510) == 0xAA55
+    }
+
+    /// Mount the FAT32 volume on `device`.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Fat32, FsError> {
+        let mut boot = [0u8; SECTOR_SIZE];
+        device.read_sectors(0, &mut boot)?;
+        let bytes_per_sector = u16_at(&boot, 11) as usize;
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = u16_at(&boot, 14) as u64;
+        let fats = boot[16] as u64;
+        let total = match u16_at(&boot, 19) {
+            0 => u32_at(&boot, 32) as u64,
+            n => n as u64,
+        };
+        let fat_sectors = u32_at(&boot, 36) as u64;
+        // FAT12/16 have a fixed root directory and a 16-bit FAT size;
+        // FAT32 has neither (this is how Linux tells them apart too).
+        let is_fat32 = u16_at(&boot, 17) == 0 && u16_at(&boot, 22) == 0 && fat_sectors != 0;
+        if u16_at(&boot, 510) != 0xAA55
+            || !is_fat32
+            || bytes_per_sector != SECTOR_SIZE
+            || !sectors_per_cluster.is_power_of_two()
+            || fats == 0
+            || total > device.sector_count()
+        {
+            return Err(FsError::Corrupted);
+        }
+        let data_start = reserved + fats * fat_sectors;
+        let clusters = total
+            .checked_sub(data_start)
+            .ok_or(FsError::Corrupted)?
+            / sectors_per_cluster;
+        // The FAT must have an entry for every cluster.
+        let clusters = clusters.min(fat_sectors * SECTOR_SIZE as u64 / 4 - 2) as u32;
+        let info_sector = match u16_at(&boot, 48) as u64 {
+            0 | 0xFFFF => None,
+            n => Some(n),
+        };
+        let geometry = Geometry {
+            sectors_per_cluster,
+            fat_start: reserved,
+            fat_sectors,
+            fats,
+            data_start,
+            clusters,
+            root_cluster: u32_at(&boot, 44),
+            info_sector,
+        };
+        if !geometry.is_valid(geometry.root_cluster) {
+            return Err(FsError::Corrupted);
+        }
+
+        let root = Node {
+            kind: FileType::Directory,
+            first_cluster: geometry.root_cluster,
+            size: 0,
+            attr: ATTR_DIRECTORY,
+            created: 0,
+            modified: 0,
+            accessed: 0,
+            entry: None,
+            parent: ROOT,
+        };
+        let mut fs = Fat32 {
+            device,
+            geometry,
+            table: RefCell::new(NodeTable {
+                nodes: BTreeMap::from([(ROOT, root)]),
+                by_entry: BTreeMap::new(),
+                next_inode: ROOT + 1,
+            }),
+            free_clusters: 0,
+            next_free: FIRST_CLUSTER,
+        };
+        fs.load_info()?;
+        Ok(fs)
+    }
+
+    /// Free clusters on the volume.
+    pub fn free_clusters(&self) -> u32 {
+        self.free_clusters
+    }
+
+    pub fn cluster_bytes(&self) -> usize {
+        self.geometry.cluster_bytes()
+    }
+
+    /// Read the FSInfo hints, or count free clusters if they're missing.
+    fn load_info(&mut self) -> Result<(), FsError> {
+        let mut free = FSINFO_UNKNOWN;
+        let mut next = FSINFO_UNKNOWN;
+        if let Some(lba) = self.geometry.info_sector {
+            let mut info = [0u8; SECTOR_SIZE];
+            self.device.read_sectors(lba, &mut info)?;
+            if u32_at(&info, 0) == FSINFO_LEAD_SIG && u32_at(&info, 484) == FSINFO_STRUCT_SIG {
+                free = u32_at(&info, 488);
+                next = u32_at(&info, 492);
+            }
+        }
+        self.free_clusters = if free <= self.geometry.clusters {
+            free
+        } else {
+            self.count_free()?
+        };
+        self.next_free = if self.geometry.is_valid(next) { next } else { FIRST_CLUSTER };
+        Ok(())
+    }
+
+    fn count_free(&self) -> Result<u32, FsError> {
+        let mut free = 0;
+        let mut sector = [0u8; SECTOR_SIZE];
+        let last = self.geometry.clusters + FIRST_CLUSTER;
+        for i in 0..self.geometry.fat_sectors {
+            self.device.read_sectors(self.geometry.fat_start + i, &mut sector)?;
+            for (j, entry) in sector.chunks_exact(4).enumerate() {
+                let cluster = (i as usize * SECTOR_SIZE / 4 + j) as u32;
+                if (FIRST_CLUSTER..last).contains(&cluster)
+                    && u32::from_le_bytes(entry.try_into().unwrap()) & FAT_MASK == 0
+                {
+                    free += 1;
+                }
+            }
+        }
+        Ok(free)
+    }
+
+    fn store_info(&self) -> Result<(), FsError> {
+        let Some(lba) = self.geometry.info_sector else {
+            return Ok(());
+        };
+        let mut info = [0u8; SECTOR_SIZE];
+        self.device.read_sectors(lba, &mut info)?;
+        put_u32(&mut info, 0, FSINFO_LEAD_SIG);
+        put_u32(&mut info, 484, FSINFO_STRUCT_SIG);
+        put_u32(&mut info, 488, self.free_clusters);
+        put_u32(&mut info, 492, self.next_free);
+        put_u32(&mut info, 508, FSINFO_TRAIL_SIG);
+        self.device.write_sectors(lba, &info)?;
+        Ok(())
+    }
+
+    // --- FAT ---
+
+    fn fat_location(&self, cluster: u32) -> (u64, usize) {
+        let byte = cluster as usize * 4;
+        (
+            self.geometry.fat_start + (byte / SECTOR_SIZE) as u64,
+            byte % SECTOR_SIZE,
+        )
+    }
+
+    fn fat_get(&self, cluster: u32) -> Result<u32, FsError> {
+        let (lba, offset) = self.fat_location(cluster);
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.device.read_sectors(lba, &mut sector)?;
+        Ok(u32_at(&sector, offset) & FAT_MASK)
+    }
+
+    /// Set a FAT entry in every copy of the FAT.
+    fn fat_set(&self, cluster: u32, value: u32) -> Result<(), FsError> {
+        let (lba, offset) = self.fat_location(cluster);
+        let mut sector = [0u8; SECTOR_SIZE];
+        for copy in 0..self.geometry.fats {
+            let lba = lba + copy * self.geometry.fat_sectors;
+            self.device.read_sectors(lba, &mut sector)?;
+            // The top four bits are reserved and must be preserved.
+            let old = u32_at(&sector, offset);
+            put_u32(&mut sector, offset, old & !FAT_MASK | value & FAT_MASK);
+            self.device.write_sectors(lba, &sector)?;
+        }
+        Ok(())
+    }
+
+    /// Every cluster of the chain starting at `first` (empty for 0).
+    fn chain(&self, first: u32) -> Result<Vec<u32>, FsError> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 && cluster < FAT_EOC_MIN {
+            if !self.geometry.is_valid(cluster) || clusters.len() > self.geometry.clusters as usize {
+                return Err(FsError::Corrupted);
+            }
+            clusters.push(cluster);
+            cluster = self.fat_get(cluster)?;
+        }
+        Ok(clusters)
+    }
+
+    /// Take a free cluster, zero it, and append it after `prev`.
+    fn alloc_cluster(&mut self, prev: Option<u32>) -> Result<u32, FsError> {
+        if self.free_clusters == 0 {
+            return Err(FsError::NoSpace);
+        }
+        let start = self.next_free;
+        let last = self.geometry.clusters + FIRST_CLUSTER;
+        let mut cluster = start;
+        while self.fat_get(cluster)? != 0 {
+            cluster += 1;
+            if cluster == last {
+                cluster = FIRST_CLUSTER;
+            }
+            if cluster == start {
+                return Err(FsError::NoSpace);
+            }
+        }
+        self.fat_set(cluster, FAT_EOC)?;
+        if let Some(prev) = prev {
+            self.fat_set(prev, cluster)?;
+        }
+        let zeros = vec![0u8; self.geometry.cluster_bytes()];
+        self.device.write_sectors(self.geometry.cluster_lba(cluster), &zeros)?;
+        self.free_clusters -= 1;
+        self.next_free = if cluster + 1 == last { FIRST_CLUSTER } else { cluster + 1 };
+        Ok(cluster)
+    }
+
+    fn free_chain(&mut self, clusters: &[u32]) -> Result<(), FsError> {
+        for &cluster in clusters {
+            self.fat_set(cluster, 0)?;
+        }
+        self.free_clusters += clusters.len() as u32;
+        Ok(())
+    }
+
+    // --- Clusters as byte ranges ---
+
+    /// Sector holding byte `offset` of the chain.
+    fn chain_lba(&self, clusters: &[u32], offset: usize) -> u64 {
+        let cluster_bytes = self.geometry.cluster_bytes();
+        self.geometry.cluster_lba(clusters[offset / cluster_bytes])
+            + ((offset % cluster_bytes) / SECTOR_SIZE) as u64
+    }
+
+    fn read_chain(&self, clusters: &[u32], offset: usize, buf: &mut [u8]) -> Result<(), FsError> {
+        let mut done = 0;
+        let mut sector = [0u8; SECTOR_SIZE];
+        while done < buf.len() {
+            let pos = offset + done;
+            let within = pos % SECTOR_SIZE;
+            let n = (SECTOR_SIZE - within).min(buf.len() - done);
+            self.device.read_sectors(self.chain_lba(clusters, pos), &mut sector)?;
+            buf[done..done + n].copy_from_slice(&sector[within..within + n]);
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn write_chain(&self, clusters: &[u32], offset: usize, data: &[u8]) -> Result<(), FsError> {
+        let mut done = 0;
+        let mut sector = [0u8; SECTOR_SIZE];
+        while done < data.len() {
+            let pos = offset + done;
+            let within = pos % SECTOR_SIZE;
+            let n = (SECTOR_SIZE - within).min(data.len() - done);
+            let lba = self.chain_lba(clusters, pos);
+            if n < SECTOR_SIZE {
+                self.device.read_sectors(lba, &mut sector)?;
+            }
+            sector[within..within + n].copy_from_slice(&data[done..done + n]);
+            self.device.write_sectors(lba, &sector)?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    // --- Nodes ---
+
+    fn node(&self, inode: InodeId) -> Result<Node, FsError> {
+        self.table.borrow().nodes.get(&inode).cloned().ok_or(FsError::NotFound)
+    }
+
+    fn dir_node(&self, inode: InodeId) -> Result<Node, FsError> {
+        let node = self.node(inode)?;
+        if node.kind != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+        Ok(node)
+    }
+
+    fn file_node(&self, inode: InodeId) -> Result<Node, FsError> {
+        let node = self.node(inode)?;
+        if node.kind != FileType::File {
+            return Err(FsError::NotAFile);
+        }
+        Ok(node)
+    }
+
+    /// The inode for a slot of directory `dir`, assigning one if needed.
+    fn inode_for(&self, dir: InodeId, dir_cluster: u32, slot: &Slot) -> InodeId {
+        let pos = EntryPos { dir: dir_cluster, slot: slot.slot };
+        let mut table = self.table.borrow_mut();
+        if let Some(&inode) = table.by_entry.get(&pos) {
+            return inode;
+        }
+        let inode = table.next_inode;
+        table.next_inode += 1;
+        table.nodes.insert(inode, Node::from_slot(slot, pos, dir));
+        table.by_entry.insert(pos, inode);
+        inode
+    }
+
+    /// Write a node's cluster, size, attributes and times back to its entry.
+    fn store_node(&mut self, inode: InodeId) -> Result<(), FsError> {
+        let node = self.node(inode)?;
+        let Some(pos) = node.entry else {
+            return Ok(());
+        };
+        let clusters = self.chain(pos.dir)?;
+        let offset = pos.slot as usize * ENTRY_SIZE;
+        let mut raw = [0u8; ENTRY_SIZE];
+        self.read_chain(&clusters, offset, &mut raw)?;
+        raw[11] = node.attr;
+        put_u16(&mut raw, 20, (node.first_cluster >> 16) as u16);
+        put_u16(&mut raw, 26, node.first_cluster as u16);
+        put_u32(&mut raw, 28, node.size);
+        let (date, time) = to_fat_time(node.modified);
+        put_u16(&mut raw, 22, time);
+        put_u16(&mut raw, 24, date);
+        put_u16(&mut raw, 18, to_fat_time(node.accessed).0);
+        self.write_chain(&clusters, offset, &raw)
+    }
+
+    fn update_node(&mut self, inode: InodeId, f: impl FnOnce(&mut Node)) -> Result<(), FsError> {
+        match self.table.get_mut().nodes.get_mut(&inode) {
+            Some(node) => f(node),
+            None => return Err(FsError::NotFound),
+        }
+        self.store_node(inode)
+    }
+
+    // --- Directories ---
+
+    /// Every live entry of the directory starting at `cluster`, except
+    /// `.`, `..` and the volume label.
+    fn slots(&self, cluster: u32) -> Result<Vec<Slot>, FsError> {
+        let clusters = self.chain(cluster)?;
+        let mut data = vec![0u8; clusters.len() * self.geometry.cluster_bytes()];
+        self.read_chain(&clusters, 0, &mut data)?;
+
+        let mut slots = Vec::new();
+        // Long-name parts collected so far: (checksum, parts, first slot).
+        let mut long: Option<(u8, Vec<u16>, u32)> = None;
+        for (index, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+            let index = index as u32;
+            match raw[0] {
+                ENTRY_END => break,
+                ENTRY_FREE => {
+                    long = None;
+                    continue;
+                }
+                _ => {}
+            }
+            if raw[11] & 0x3F == ATTR_LONG_NAME {
+                let ord = (raw[0] & 0x1F) as usize;
+                if raw[0] & LFN_LAST != 0 {
+                    long = Some((raw[13], vec![0xFFFF; ord * LFN_CHARS], index));
+                }
+                if let Some((checksum, units, _)) = &mut long {
+                    if *checksum != raw[13] || ord == 0 || ord * LFN_CHARS > units.len() {
+                        long = None;
+                        continue;
+                    }
+                    for (i, &at) in LFN_OFFSETS.iter().enumerate() {
+                        units[(ord - 1) * LFN_CHARS + i] = u16_at(raw, at);
+                    }
+                }
+                continue;
+            }
+            let short: [u8; 11] = raw[..11].try_into().unwrap();
+            let lfn = long.take();
+            if raw[11] & ATTR_VOLUME_ID != 0 || short[0] == b'.' {
+                continue;
+            }
+            let (name, first) = match lfn {
+                Some((checksum, units, first)) if checksum == short_checksum(&short) => {
+                    let end = units.iter().position(|&u| u == 0 || u == 0xFFFF).unwrap_or(units.len());
+                    let name = char::decode_utf16(units[..end].iter().copied())
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect();
+                    (name, first)
+                }
+                _ => (short_display(&short, raw[12]), index),
+            };
+            slots.push(Slot {
+                name,
+                short,
+                first,
+                slot: index,
+                raw: raw.try_into().unwrap(),
+            });
+        }
+        Ok(slots)
+    }
+
+    fn find_slot(&self, cluster: u32, name: &str) -> Result<Slot, FsError> {
+        self.slots(cluster)?
+            .into_iter()
+            .find(|slot| slot.matches(name))
+            .ok_or(FsError::NotFound)
+    }
+
+    /// Find `count` consecutive unused slots in a directory, growing it
+    /// by a cluster if needed. Returns the index of the first.
+    fn free_slots(&mut self, cluster: u32, count: usize) -> Result<u32, FsError> {
+        let mut clusters = self.chain(cluster)?;
+        let mut data = vec![0u8; clusters.len() * self.geometry.cluster_bytes()];
+        self.read_chain(&clusters, 0, &mut data)?;
+        let mut run = 0;
+        for (index, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+            if raw[0] == ENTRY_END {
+                // Everything from here on is free.
+                let available = data.len() / ENTRY_SIZE - index + run;
+                if available < count {
+                    let per_cluster = self.geometry.cluster_bytes() / ENTRY_SIZE;
+                    for _ in 0..(count - available).div_ceil(per_cluster) {
+                        let last = *clusters.last().unwrap();
+                        clusters.push(self.alloc_cluster(Some(last))?);
+                    }
+                }
+                return Ok((index - run) as u32);
+            }
+            if raw[0] == ENTRY_FREE {
+                run += 1;
+                if run == count {
+                    return Ok((index + 1 - run) as u32);
+                }
+            } else {
+                run = 0;
+            }
+        }
+        // Full to the last slot: the new entry starts in a new cluster.
+        let start = data.len() / ENTRY_SIZE - run;
+        let per_cluster = self.geometry.cluster_bytes() / ENTRY_SIZE;
+        for _ in 0..(count - run).div_ceil(per_cluster) {
+            let last = *clusters.last().unwrap();
+            clusters.push(self.alloc_cluster(Some(last))?);
+        }
+        Ok(start as u32)
+    }
+
+    /// Pick a short name for `name` that no entry in the directory uses.
+    fn unique_short(&self, slots: &[Slot], name: &str) -> Result<([u8; 11], bool), FsError> {
+        let (basis, exact) = short_basis(name);
+        let taken = |short: &[u8; 11]| slots.iter().any(|slot| &slot.short == short);
+        if exact && !taken(&basis) {
+            return Ok((basis, name.bytes().all(|b| !b.is_ascii_lowercase())));
+        }
+        (1..1_000_000)
+            .map(|n| numbered(&basis, n))
+            .find(|short| !taken(short))
+            .map(|short| (short, false))
+            .ok_or(FsError::AlreadyExists)
+    }
+
+    /// Write a new entry for `name` into directory `dir` with the short
+    /// entry `raw` (its name field is filled in here).
+    fn add_entry(&mut self, dir: &Node, name: &str, mut raw: [u8; ENTRY_SIZE]) -> Result<EntryPos, FsError> {
+        if !valid_long_name(name) {
+            return Err(FsError::InvalidPath);
+        }
+        let slots = self.slots(dir.first_cluster)?;
+        if slots.iter().any(|slot| slot.matches(name)) {
+            return Err(FsError::AlreadyExists);
+        }
+        let (short, short_only) = self.unique_short(&slots, name)?;
+        raw[..11].copy_from_slice(&short);
+        raw[12] = 0;
+        let mut entries = if short_only {
+            Vec::new()
+        } else {
+            long_entries(name, short_checksum(&short))
+        };
+        entries.push(raw);
+
+        let first = self.free_slots(dir.first_cluster, entries.len())?;
+        let clusters = self.chain(dir.first_cluster)?;
+        self.write_chain(&clusters, first as usize * ENTRY_SIZE, &entries.concat())?;
+        Ok(EntryPos {
+            dir: dir.first_cluster,
+            slot: first + entries.len() as u32 - 1,
+        })
+    }
+
+    /// Mark a directory entry (and its long-name slots) deleted.
+    fn remove_entry(&mut self, dir_cluster: u32, slot: &Slot) -> Result<(), FsError> {
+        let clusters = self.chain(dir_cluster)?;
+        for index in slot.first..=slot.slot {
+            self.write_chain(&clusters, index as usize * ENTRY_SIZE, &[ENTRY_FREE])?;
+        }
+        let mut table = self.table.borrow_mut();
+        let pos = EntryPos { dir: dir_cluster, slot: slot.slot };
+        if let Some(inode) = table.by_entry.remove(&pos) {
+            if let Some(node) = table.nodes.get_mut(&inode) {
+                node.entry = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill the `.` and `..` entries of a new directory.
+    fn init_dir(&mut self, cluster: u32, parent_cluster: u32, raw: &[u8; ENTRY_SIZE]) -> Result<(), FsError> {
+        let mut dot = *raw;
+        dot[..11].copy_from_slice(b".          ");
+        let mut dotdot = *raw;
+        dotdot[..11].copy_from_slice(b"..         ");
+        // `..` of a directory in the root names cluster 0.
+        let parent = if parent_cluster == self.geometry.root_cluster { 0 } else { parent_cluster };
+        put_u16(&mut dotdot, 20, (parent >> 16) as u16);
+        put_u16(&mut dotdot, 26, parent as u16);
+        self.write_chain(&[cluster], 0, &[dot, dotdot].concat())
+    }
+
+    fn is_empty_dir(&self, cluster: u32) -> Result<bool, FsError> {
+        Ok(self.slots(cluster)?.is_empty())
+    }
+
+    /// Allocate clusters until the chain covers `size` bytes.
+    fn grow(&mut self, inode: InodeId, clusters: &mut Vec<u32>, size: usize) -> Result<(), FsError> {
+        let needed = size.div_ceil(self.geometry.cluster_bytes());
+        while clusters.len() < needed {
+            let cluster = self.alloc_cluster(clusters.last().copied())?;
+            if clusters.is_empty() {
+                self.table.get_mut().nodes.get_mut(&inode).ok_or(FsError::NotFound)?.first_cluster = cluster;
+            }
+            clusters.push(cluster);
+        }
+        Ok(())
+    }
+
+    /// Zero bytes `from..to` that lie in clusters already allocated:
+    /// stale data past the old end of file. New clusters come zeroed.
+    fn zero_tail(&self, clusters: &[u32], from: usize, to: usize) -> Result<(), FsError> {
+        let allocated = clusters.len() * self.geometry.cluster_bytes();
+        let to = to.min(allocated);
+        if from < to {
+            self.write_chain(clusters, from, &vec![0u8; to - from])?;
+        }
+        Ok(())
+    }
+
+    /// Build a FAT32 volume covering all of `device`.
+    pub fn format(device: &dyn BlockDevice, label: &str) -> Result<(), FsError> {
+        const RESERVED: u64 = 32;
+        const FATS: u64 = 2;
+        let total = device.sector_count().min(u32::MAX as u64);
+        if total < 1024 {
+            return Err(FsError::NoSpace);
+        }
+        let sectors_per_cluster: u64 = match total {
+            0..=532_479 => 1,      // up to 260 MiB
+            532_480..=16_777_215 => 8, // up to 8 GiB
+            _ => 32,
+        };
+        // Slightly oversized: counts the FAT's own sectors as clusters.
+        let clusters = (total - RESERVED) / sectors_per_cluster;
+        let fat_sectors = ((clusters + 2) * 4).div_ceil(SECTOR_SIZE as u64);
+
+        let mut boot = [0u8; SECTOR_SIZE];
+        boot[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        boot[3..11].copy_from_slice(b"RUSTKRNL");
+        put_u16(&mut boot, 11, SECTOR_SIZE as u16);
+        boot[13] = sectors_per_cluster as u8;
+        put_u16(&mut boot, 14, RESERVED as u16);
+        boot[16] = FATS as u8;
+        boot[21] = 0xF8; // fixed disk
+        put_u16(&mut boot, 24, 32); // sectors per track
+        put_u16(&mut boot, 26, 64); // heads
+        put_u32(&mut boot, 32, total as u32);
+        put_u32(&mut boot, 36, fat_sectors as u32);
+        put_u32(&mut boot, 44, FIRST_CLUSTER); // root directory
+        put_u16(&mut boot, 48, 1); // FSInfo sector
+        put_u16(&mut boot, 50, 6); // backup boot sector
+        boot[64] = 0x80;
+        boot[66] = 0x29;
+        put_u32(&mut boot, 67, crate::time::now() as u32); // volume id
+        let mut volume_label = [b' '; 11];
+        for (dst, src) in volume_label.iter_mut().zip(label.bytes()) {
+            *dst = src.to_ascii_uppercase();
+        }
+        boot[71..82].copy_from_slice(&volume_label);
+        boot[82..90].copy_from_slice(b"FAT32   ");
+        put_u16(&mut boot, 510, 0xAA55);
+
+        let data_clusters = (total - RESERVED - FATS * fat_sectors) / sectors_per_cluster;
+        let mut info = [0u8; SECTOR_SIZE];
+        put_u32(&mut info, 0, FSINFO_LEAD_SIG);
+        put_u32(&mut info, 484, FSINFO_STRUCT_SIG);
+        put_u32(&mut info, 488, data_clusters as u32 - 1); // all but the root
+        put_u32(&mut info, 492, FIRST_CLUSTER + 1);
+        put_u32(&mut info, 508, FSINFO_TRAIL_SIG);
+
+        let zero = vec![0u8; SECTOR_SIZE * 8];
+        let mut lba = 0;
+        let clear_to = RESERVED + FATS * fat_sectors + sectors_per_cluster;
+        while lba < clear_to {
+            let n = (clear_to - lba).min(8);
+            device.write_sectors(lba, &zero[..n as usize * SECTOR_SIZE])?;
+            lba += n;
+        }
+        for base in [0, 6] {
+            device.write_sectors(base, &boot)?;
+            device.write_sectors(base + 1, &info)?;
+        }
+        let mut fat = [0u8; SECTOR_SIZE];
+        put_u32(&mut fat, 0, 0x0FFF_FFF8); // media descriptor
+        put_u32(&mut fat, 4, FAT_EOC);
+        put_u32(&mut fat, 8, FAT_EOC); // root directory
+        for copy in 0..FATS {
+            device.write_sectors(RESERVED + copy * fat_sectors, &fat)?;
+        }
+        device.flush()?;
+        Ok(())
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn name(&self) -> &'static str {
+        "fat32"
+    }
+
+    fn device(&self) -> Option<&str> {
+        Some(self.device.name())
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT
+    }
+
+    fn lookup(&self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        let node = self.dir_node(dir)?;
+        match name {
+            "." => Ok(dir),
+            ".." => Ok(node.parent),
+            _ => {
+                let slot = self.find_slot(node.first_cluster, name)?;
+                Ok(self.inode_for(dir, node.first_cluster, &slot))
+            }
+        }
+    }
+
+    fn read(&self, inode: InodeId, offset: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+        let node = self.file_node(inode)?;
+        let size = node.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        let clusters = self.chain(node.first_cluster)?;
+        if clusters.len() * self.geometry.cluster_bytes() < size {
+            return Err(FsError::Corrupted);
+        }
+        self.read_chain(&clusters, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write(&mut self, inode: InodeId, offset: usize, data: &[u8]) -> Result<usize, FsError> {
+        let node = self.file_node(inode)?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let end = offset.checked_add(data.len()).ok_or(FsError::NoSpace)?;
+        if end > u32::MAX as usize {
+            return Err(FsError::NoSpace);
+        }
+        let mut clusters = self.chain(node.first_cluster)?;
+        if offset > node.size as usize {
+            self.zero_tail(&clusters, node.size as usize, offset)?;
+        }
+        let result = self.grow(inode, &mut clusters, end);
+        // A full disk still leaves whatever fits written.
+        let capacity = clusters.len() * self.geometry.cluster_bytes();
+        let written = end.min(capacity).saturating_sub(offset);
+        self.write_chain(&clusters, offset, &data[..written])?;
+        let size = node.size.max((offset + written) as u32);
+        self.update_node(inode, |node| {
+            node.size = size;
+            node.attr |= ATTR_ARCHIVE;
+            node.modified = crate::time::now();
+        })?;
+        self.store_info()?;
+        match result {
+            Err(e) if written == 0 => Err(e),
+            _ => Ok(written),
+        }
+    }
+
+    fn truncate(&mut self, inode: InodeId, size: usize) -> Result<(), FsError> {
+        let node = self.file_node(inode)?;
+        if size > u32::MAX as usize {
+            return Err(FsError::NoSpace);
+        }
+        let mut clusters = self.chain(node.first_cluster)?;
+        if size > node.size as usize {
+            self.zero_tail(&clusters, node.size as usize, size)?;
+            self.grow(inode, &mut clusters, size)?;
+        } else {
+            let keep = size.div_ceil(self.geometry.cluster_bytes());
+            if keep < clusters.len() {
+                if keep == 0 {
+                    self.table.get_mut().nodes.get_mut(&inode).ok_or(FsError::NotFound)?.first_cluster = 0;
+                } else {
+                    self.fat_set(clusters[keep - 1], FAT_EOC)?;
+                }
+                self.free_chain(&clusters[keep..])?;
+            }
+        }
+        self.update_node(inode, |node| {
+            node.size = size as u32;
+            node.modified = crate::time::now();
+        })?;
+        self.store_info()
+    }
+
+    fn readdir(&self, dir: InodeId) -> Result<Vec<DirEntry>, FsError> {
+        let node = self.dir_node(dir)?;
+        Ok(self
+            .slots(node.first_cluster)?
+            .iter()
+            .map(|slot| DirEntry {
+                name: slot.name.clone(),
+                inode: self.inode_for(dir, node.first_cluster, slot),
+                kind: if slot.is_dir() { FileType::Directory } else { FileType::File },
+            })
+            .collect())
+    }
+
+    fn create(&mut self, dir: InodeId, name: &str, kind: FileType) -> Result<InodeId, FsError> {
+        super::validate_name(name)?;
+        let parent = self.dir_node(dir)?;
+        let now = crate::time::now();
+        let (date, time) = to_fat_time(now);
+        let mut raw = [0u8; ENTRY_SIZE];
+        put_u16(&mut raw, 14, time);
+        put_u16(&mut raw, 16, date);
+        put_u16(&mut raw, 18, date);
+        put_u16(&mut raw, 22, time);
+        put_u16(&mut raw, 24, date);
+        let cluster = match kind {
+            FileType::File => {
+                raw[11] = ATTR_ARCHIVE;
+                0
+            }
+            FileType::Directory => {
+                raw[11] = ATTR_DIRECTORY;
+                let cluster = self.alloc_cluster(None)?;
+                put_u16(&mut raw, 20, (cluster >> 16) as u16);
+                put_u16(&mut raw, 26, cluster as u16);
+                self.init_dir(cluster, parent.first_cluster, &raw)?;
+                cluster
+            }
+            FileType::Symlink | FileType::CharDevice => return Err(FsError::NotSupported),
+        };
+        let pos = match self.add_entry(&parent, name, raw) {
+            Ok(pos) => pos,
+            Err(e) => {
+                if cluster != 0 {
+                    self.free_chain(&[cluster])?;
+                }
+                return Err(e);
+            }
+        };
+        self.store_info()?;
+        let slot = Slot {
+            name: String::from(name),
+            short: raw[..11].try_into().unwrap(),
+            first: pos.slot,
+            slot: pos.slot,
+            raw,
+        };
+        Ok(self.inode_for(dir, parent.first_cluster, &slot))
+    }
+
+    fn unlink(&mut self, dir: InodeId, name: &str) -> Result<(), FsError> {
+        let parent = self.dir_node(dir)?;
+        let slot = self.find_slot(parent.first_cluster, name)?;
+        if slot.is_dir() && !self.is_empty_dir(slot.cluster())? {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+        self.inode_for(dir, parent.first_cluster, &slot);
+        self.remove_entry(parent.first_cluster, &slot)
+    }
+
+    fn evict(&mut self, inode: InodeId) {
+        let Ok(node) = self.node(inode) else {
+            return;
+        };
+        if inode == ROOT || node.entry.is_some() {
+            return;
+        }
+        self.table.get_mut().nodes.remove(&inode);
+        // Nothing useful to do if the device fails here; the clusters
+        // stay allocated until fsck finds them.
+        if let Ok(clusters) = self.chain(node.first_cluster) {
+            let _ = self.free_chain(&clusters).and_then(|_| self.store_info());
+        }
+    }
+
+    fn rename(&mut self, old_dir: InodeId, old_name: &str, new_dir: InodeId, new_name: &str) -> Result<(), FsError> {
+        super::validate_name(new_name)?;
+        let old_parent = self.dir_node(old_dir)?;
+        let new_parent = self.dir_node(new_dir)?;
+        let slot = self.find_slot(old_parent.first_cluster, old_name)?;
+        let inode = self.inode_for(old_dir, old_parent.first_cluster, &slot);
+        match self.find_slot(new_parent.first_cluster, new_name) {
+            Ok(target) if target.slot == slot.slot => return Ok(()),
+            Ok(target) => {
+                match (slot.is_dir(), target.is_dir()) {
+                    (false, true) => return Err(FsError::NotAFile),
+                    (true, false) => return Err(FsError::NotADirectory),
+                    (true, true) if !self.is_empty_dir(target.cluster())? => {
+                        return Err(FsError::DirectoryNotEmpty)
+                    }
+                    _ => {}
+                }
+                self.inode_for(new_dir, new_parent.first_cluster, &target);
+                self.remove_entry(new_parent.first_cluster, &target)?;
+            }
+            Err(FsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+
+        // Write the new entry first so a failure leaves the old one.
+        let pos = self.add_entry(&new_parent, new_name, slot.raw)?;
+        self.remove_entry(old_parent.first_cluster, &slot)?;
+        {
+            let table = self.table.get_mut();
+            table.by_entry.insert(pos, inode);
+            if let Some(node) = table.nodes.get_mut(&inode) {
+                node.entry = Some(pos);
+                node.parent = new_dir;
+            }
+        }
+        if slot.is_dir() && old_dir != new_dir {
+            let mut dotdot = [0u8; ENTRY_SIZE];
+            let clusters = [slot.cluster()];
+            self.read_chain(&clusters, ENTRY_SIZE, &mut dotdot)?;
+            let parent = match new_parent.first_cluster {
+                c if c == self.geometry.root_cluster => 0,
+                c => c,
+            };
+            put_u16(&mut dotdot, 20, (parent >> 16) as u16);
+            put_u16(&mut dotdot, 26, parent as u16);
+            self.write_chain(&clusters, ENTRY_SIZE, &dotdot)?;
+        }
+        self.store_info()
+    }
+
+    fn stat(&self, inode: InodeId) -> Result<Metadata, FsError> {
+        let node = self.node(inode)?;
+        let mut mode = match node.kind {
+            FileType::Directory => 0o755,
+            _ => 0o644,
+        };
+        if node.attr & ATTR_READ_ONLY != 0 {
+            mode &= !0o222;
+        }
+        Ok(Metadata {
+            inode,
+            kind: node.kind,
+            size: node.size as u64,
+            mode,
+            uid: 0,
+            gid: 0,
+            nlink: match node.kind {
+                FileType::Directory => 2,
+                _ => node.entry.is_some() as u32,
+            },
+            created: node.created,
+            modified: node.modified,
+            accessed: node.accessed,
+        })
+    }
+
+    /// Only the owner write bit is kept, as the read-only attribute.
+    fn set_mode(&mut self, inode: InodeId, mode: u16) -> Result<(), FsError> {
+        if inode == ROOT {
+            return Err(FsError::NotSupported);
+        }
+        self.update_node(inode, |node| {
+            if mode & 0o200 == 0 {
+                node.attr |= ATTR_READ_ONLY;
+            } else {
+                node.attr &= !ATTR_READ_ONLY;
+            }
+        })
+    }
+
+    fn set_times(&mut self, inode: InodeId, accessed: u64, modified: u64) -> Result<(), FsError> {
+        if inode == ROOT {
+            return Err(FsError::NotSupported);
+        }
+        self.update_node(inode, |node| {
+            node.accessed = accessed;
+            node.modified = modified;
+        })
+    }
+}
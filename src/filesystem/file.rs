@@ -0,0 +1,148 @@
+/// Open file handles.
+///
+/// `Vfs::open` returns an `Arc<OpenFile>`: the access mode, a shared
+/// offset, and a reference to the node that keeps it alive. Every handle
+/// on the same node shares one `NodeRef`, and the VFS tracks those with
+/// weak references, so a file unlinked while open keeps its data until the
+/// last handle is dropped. Dropping a handle takes no locks; the VFS frees
+/// orphaned nodes the next time it is used (`Vfs::reap`).
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::ops::BitOr;
+use spin::Mutex;
+
+use super::{FileType, FsError, VNode, Vfs};
+
+/// How a file is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenFlags(u8);
+
+impl OpenFlags {
+    pub const READ: OpenFlags = OpenFlags(1 << 0);
+    pub const WRITE: OpenFlags = OpenFlags(1 << 1);
+    /// Every write goes to the current end of the file.
+    pub const APPEND: OpenFlags = OpenFlags(1 << 2);
+    /// Create the file if it doesn't exist.
+    pub const CREATE: OpenFlags = OpenFlags(1 << 3);
+    /// Truncate to zero length on open (needs `WRITE`).
+    pub const TRUNCATE: OpenFlags = OpenFlags(1 << 4);
+
+    pub fn contains(self, other: OpenFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for OpenFlags {
+    type Output = OpenFlags;
+
+    fn bitor(self, rhs: OpenFlags) -> OpenFlags {
+        OpenFlags(self.0 | rhs.0)
+    }
+}
+
+/// Where `OpenFile::seek` measures from.
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(u64),
+    Current(i64),
+    End(i64),
+}
+
+/// Shared by every handle open on one node.
+#[derive(Debug)]
+pub struct NodeRef {
+    pub vnode: VNode,
+}
+
+#[derive(Debug)]
+pub struct OpenFile {
+    node: Arc<NodeRef>,
+    flags: OpenFlags,
+    offset: Mutex<usize>,
+}
+
+impl OpenFile {
+    pub(super) fn new(node: Arc<NodeRef>, flags: OpenFlags) -> OpenFile {
+        OpenFile {
+            node,
+            flags,
+            offset: Mutex::new(0),
+        }
+    }
+
+    pub fn vnode(&self) -> VNode {
+        self.node.vnode
+    }
+
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    pub fn offset(&self) -> usize {
+        *self.offset.lock()
+    }
+
+    /// Read from the current offset and advance it.
+    pub fn read(&self, vfs: &Vfs, buf: &mut [u8]) -> Result<usize, FsError> {
+        if !self.flags.contains(OpenFlags::READ) {
+            return Err(FsError::AccessMode);
+        }
+        let mut offset = self.offset.lock();
+        let read = vfs.read(self.node.vnode, *offset, buf)?;
+        *offset += read;
+        Ok(read)
+    }
+
+    /// Write at the current offset (or the end, for `APPEND`) and advance.
+    pub fn write(&self, vfs: &mut Vfs, data: &[u8]) -> Result<usize, FsError> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(FsError::AccessMode);
+        }
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = vfs.stat(self.node.vnode)?.size as usize;
+        }
+        let written = vfs.write(self.node.vnode, *offset, data)?;
+        *offset += written;
+        Ok(written)
+    }
+
+    /// Move the offset. Seeking past the end is allowed; a later write
+    /// fills the gap with zeros.
+    pub fn seek(&self, vfs: &Vfs, pos: SeekFrom) -> Result<usize, FsError> {
+        let mut offset = self.offset.lock();
+        let new = match pos {
+            SeekFrom::Start(n) => Some(n as i64),
+            SeekFrom::Current(delta) => (*offset as i64).checked_add(delta),
+            SeekFrom::End(delta) => (vfs.stat(self.node.vnode)?.size as i64).checked_add(delta),
+        };
+        match new {
+            Some(n) if n >= 0 => {
+                *offset = n as usize;
+                Ok(*offset)
+            }
+            _ => Err(FsError::InvalidSeek),
+        }
+    }
+}
+
+impl Vfs {
+    /// Open the file at `path` relative to `cwd`.
+    pub fn open(&mut self, path: &str, flags: OpenFlags, cwd: VNode) -> Result<Arc<OpenFile>, FsError> {
+        self.reap();
+        let vnode = match self.resolve_path(path, cwd) {
+            Ok(vnode) => vnode,
+            Err(FsError::NotFound) if flags.contains(OpenFlags::CREATE) => self.create_file(path, cwd)?,
+            Err(e) => return Err(e),
+        };
+        if !matches!(self.stat(vnode)?.kind, FileType::File | FileType::CharDevice) {
+            return Err(FsError::NotAFile);
+        }
+        if flags.contains(OpenFlags::TRUNCATE) && flags.contains(OpenFlags::WRITE) {
+            self.truncate(vnode, 0)?;
+        }
+        Ok(Arc::new(OpenFile::new(self.node_ref(vnode), flags)))
+    }
+}
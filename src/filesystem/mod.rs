@@ -0,0 +1,670 @@
+/// Virtual filesystem (VFS) layer.
+///
+/// Filesystem drivers implement the `FileSystem` trait, which works on
+/// inode numbers within one filesystem. The `Vfs` keeps a mount table of
+/// driver instances and resolves paths across them: a node is named by a
+/// `VNode` (mount + inode), and looking up a directory that has something
+/// mounted on it continues in the mounted filesystem's root.
+///
+///   /            tmpfs (mounted by `init`)
+///   /proc        procfs (mounted by `init`)
+///   /dev         devfs (mounted by `init`)
+///   /mnt/disk    any other driver instance, via `Vfs::mount`
+///
+/// `..` at the root of a mount goes back to the directory it is mounted
+/// on. Everything goes through the global `VFS` mutex, which parks
+/// waiting threads rather than spinning; it is taken with interrupts
+/// enabled, so disk I/O under it doesn't stop the clock. Files are read and
+/// written through handles from `Vfs::open` (see `file`); renames and
+/// recursive removal and copying are in `tree`, and saving and restoring
+/// the root filesystem's tree in `snapshot`.
+
+pub mod devfs;
+pub mod ext2;
+pub mod fat32;
+pub mod file;
+pub mod procfs;
+pub mod snapshot;
+pub mod tmpfs;
+pub mod tree;
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::drivers::block::BlockError;
+use crate::task::sync::Mutex;
+use file::NodeRef;
+pub use devfs::DevFs;
+pub use ext2::Ext2;
+pub use fat32::Fat32;
+pub use file::{OpenFile, OpenFlags, SeekFrom};
+pub use procfs::ProcFs;
+pub use tmpfs::TmpFs;
+
+pub static VFS: Mutex<Option<Vfs>> = Mutex::new(None);
+
+/// Inode number within a single filesystem.
+pub type InodeId = u64;
+
+/// Permission bits given to new files and directories.
+pub const DEFAULT_FILE_MODE: u16 = 0o644;
+pub const DEFAULT_DIR_MODE: u16 = 0o755;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    NotAFile,
+    DirectoryNotEmpty,
+    InvalidPath,
+    /// The node is in use as a mount point (or has mounts below it).
+    Busy,
+    /// The driver doesn't implement this operation.
+    NotSupported,
+    /// The handle wasn't opened for reading (or writing).
+    AccessMode,
+    /// A seek would move before the start of the file.
+    InvalidSeek,
+    /// A rename or hard link between two mounted filesystems.
+    CrossDevice,
+    /// Too many symbolic links while resolving a path (probably a loop).
+    TooManyLinks,
+    /// The underlying block device failed.
+    Io,
+    /// No free space left on the device.
+    NoSpace,
+    /// On-disk structures are inconsistent, or not this filesystem.
+    Corrupted,
+    /// The filesystem is mounted read-only.
+    ReadOnly,
+}
+
+impl From<BlockError> for FsError {
+    fn from(_: BlockError) -> FsError {
+        FsError::Io
+    }
+}
+
+impl core::fmt::Display for FsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FsError::NotFound => write!(f, "not found"),
+            FsError::AlreadyExists => write!(f, "already exists"),
+            FsError::NotADirectory => write!(f, "not a directory"),
+            FsError::NotAFile => write!(f, "not a file"),
+            FsError::DirectoryNotEmpty => write!(f, "directory not empty"),
+            FsError::InvalidPath => write!(f, "invalid path"),
+            FsError::Busy => write!(f, "resource busy"),
+            FsError::NotSupported => write!(f, "operation not supported"),
+            FsError::AccessMode => write!(f, "file not open for this access"),
+            FsError::InvalidSeek => write!(f, "invalid seek offset"),
+            FsError::CrossDevice => write!(f, "cross-device link"),
+            FsError::TooManyLinks => write!(f, "too many levels of symbolic links"),
+            FsError::Io => write!(f, "input/output error"),
+            FsError::NoSpace => write!(f, "no space left on device"),
+            FsError::Corrupted => write!(f, "filesystem is corrupted"),
+            FsError::ReadOnly => write!(f, "read-only filesystem"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    /// A device node (see `devfs`); reads and writes go to the driver.
+    CharDevice,
+}
+
+/// Most symbolic links followed while resolving one path.
+pub const MAX_SYMLINKS: usize = 40;
+
+/// What `stat` reports about a node. Times are Unix seconds
+/// (see `crate::time`).
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    pub inode: InodeId,
+    pub kind: FileType,
+    /// Bytes for files. For directories it depends on the driver
+    /// (entries in tmpfs, bytes on disk in ext2).
+    pub size: u64,
+    /// Unix permission bits (e.g. `0o644`).
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    /// Directory entries naming this node (`.` and `..` count for
+    /// directories, as in Unix).
+    pub nlink: u32,
+    pub created: u64,
+    pub modified: u64,
+    pub accessed: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: InodeId,
+    pub kind: FileType,
+}
+
+/// A filesystem driver instance. Inode numbers are local to the instance.
+///
+/// `lookup` must resolve `.` and `..` itself (`..` of the root is the
+/// root); the VFS handles `..` across mount points.
+pub trait FileSystem: Send {
+    /// Driver name shown in the mount table.
+    fn name(&self) -> &'static str;
+    /// Name of the block device the filesystem lives on, if any.
+    fn device(&self) -> Option<&str> {
+        None
+    }
+    fn root(&self) -> InodeId;
+    fn lookup(&self, dir: InodeId, name: &str) -> Result<InodeId, FsError>;
+    /// Read into `buf` from byte `offset`. Returns bytes read (0 at end).
+    fn read(&self, inode: InodeId, offset: usize, buf: &mut [u8]) -> Result<usize, FsError>;
+    /// Write `data` at byte `offset`, growing the file as needed.
+    fn write(&mut self, inode: InodeId, offset: usize, data: &[u8]) -> Result<usize, FsError>;
+    fn truncate(&mut self, inode: InodeId, size: usize) -> Result<(), FsError>;
+    fn readdir(&self, dir: InodeId) -> Result<Vec<DirEntry>, FsError>;
+    fn create(&mut self, dir: InodeId, name: &str, kind: FileType) -> Result<InodeId, FsError>;
+    /// Remove the entry for a file or empty directory from `dir`. The
+    /// inode itself stays readable until `evict`.
+    fn unlink(&mut self, dir: InodeId, name: &str) -> Result<(), FsError>;
+    /// Called when a name was removed and nothing has the inode open;
+    /// free it if it has no names left.
+    fn evict(&mut self, _inode: InodeId) {}
+    /// Move `old_name` in `old_dir` to `new_name` in `new_dir`, replacing
+    /// a file or empty directory already there (which is then unlinked,
+    /// as with `unlink`). The caller has checked that a directory isn't
+    /// moved into itself.
+    fn rename(
+        &mut self,
+        _old_dir: InodeId,
+        _old_name: &str,
+        _new_dir: InodeId,
+        _new_name: &str,
+    ) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+    fn stat(&self, inode: InodeId) -> Result<Metadata, FsError>;
+    fn set_mode(&mut self, _inode: InodeId, _mode: u16) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+    /// Create a symbolic link `name` in `dir` containing `target`.
+    fn symlink(&mut self, _dir: InodeId, _name: &str, _target: &str) -> Result<InodeId, FsError> {
+        Err(FsError::NotSupported)
+    }
+    fn readlink(&self, _inode: InodeId) -> Result<String, FsError> {
+        Err(FsError::NotSupported)
+    }
+    /// Add `name` in `dir` as another name for the non-directory `inode`.
+    fn link(&mut self, _dir: InodeId, _name: &str, _inode: InodeId) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+    fn set_owner(&mut self, _inode: InodeId, _uid: u32, _gid: u32) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+    fn set_times(&mut self, _inode: InodeId, _accessed: u64, _modified: u64) -> Result<(), FsError> {
+        Err(FsError::NotSupported)
+    }
+}
+
+/// Reject names that can't be directory entries.
+pub fn validate_name(name: &str) -> Result<(), FsError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        Err(FsError::InvalidPath)
+    } else {
+        Ok(())
+    }
+}
+
+/// Index of a mount in the mount table.
+pub type MountId = usize;
+
+/// A node anywhere in the VFS: an inode within a mounted filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VNode {
+    pub mount: MountId,
+    pub inode: InodeId,
+}
+
+struct Mount {
+    fs: Box<dyn FileSystem>,
+    /// Absolute path of the mount point, for display.
+    path: String,
+    /// The directory this filesystem is mounted on (`None` for `/`).
+    covers: Option<VNode>,
+}
+
+pub struct Vfs {
+    mounts: Vec<Option<Mount>>,
+    /// Nodes with open handles.
+    open: BTreeMap<VNode, Weak<NodeRef>>,
+    /// Unlinked nodes waiting for their last handle to close.
+    orphans: Vec<VNode>,
+}
+
+/// Mount tmpfs at `/`, procfs at `/proc` and devfs at `/dev`.
+pub fn init() {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    let special: [(&str, Box<dyn FileSystem>); 2] =
+        [("/proc", Box::new(ProcFs::new())), ("/dev", Box::new(DevFs::new()))];
+    for (path, fs) in special {
+        vfs.create_dir(path, root)
+            .and_then(|_| vfs.mount(path, fs, root))
+            .expect("mounting a kernel filesystem failed");
+    }
+    *VFS.lock() = Some(vfs);
+}
+
+impl Vfs {
+    /// Create a VFS with `root_fs` mounted at `/`.
+    pub fn new(root_fs: Box<dyn FileSystem>) -> Vfs {
+        Vfs {
+            mounts: vec![Some(Mount {
+                fs: root_fs,
+                path: String::from("/"),
+                covers: None,
+            })],
+            open: BTreeMap::new(),
+            orphans: Vec::new(),
+        }
+    }
+
+    pub fn root(&self) -> VNode {
+        VNode {
+            mount: 0,
+            inode: self.mounts[0].as_ref().map(|m| m.fs.root()).unwrap_or(0),
+        }
+    }
+
+    fn mount_entry(&self, id: MountId) -> Result<&Mount, FsError> {
+        self.mounts.get(id).and_then(Option::as_ref).ok_or(FsError::NotFound)
+    }
+
+    fn fs(&self, node: VNode) -> Result<&dyn FileSystem, FsError> {
+        Ok(self.mount_entry(node.mount)?.fs.as_ref())
+    }
+
+    fn fs_mut(&mut self, node: VNode) -> Result<&mut dyn FileSystem, FsError> {
+        let mount = self.mounts.get_mut(node.mount).and_then(Option::as_mut);
+        Ok(mount.ok_or(FsError::NotFound)?.fs.as_mut())
+    }
+
+    /// The mount whose filesystem is mounted on `node`, if any.
+    fn mounted_on(&self, node: VNode) -> Option<MountId> {
+        self.mounts
+            .iter()
+            .position(|m| m.as_ref().is_some_and(|m| m.covers == Some(node)))
+    }
+
+    /// True if `node` is the root directory of its mount.
+    fn is_mount_root(&self, node: VNode) -> bool {
+        self.fs(node).is_ok_and(|fs| fs.root() == node.inode)
+    }
+
+    /// Follow mounts stacked on `node` down to the topmost root.
+    fn enter_mounts(&self, mut node: VNode) -> VNode {
+        while let Some(id) = self.mounted_on(node) {
+            let root = self.mounts[id].as_ref().map(|m| m.fs.root()).unwrap_or(0);
+            node = VNode { mount: id, inode: root };
+        }
+        node
+    }
+
+    /// Look up `name` in directory `dir`, crossing into mounted filesystems.
+    pub fn lookup(&self, dir: VNode, name: &str) -> Result<VNode, FsError> {
+        if name == ".." {
+            return self.parent(dir);
+        }
+        let inode = self.fs(dir)?.lookup(dir.inode, name)?;
+        Ok(self.enter_mounts(VNode { mount: dir.mount, inode }))
+    }
+
+    /// Parent directory of `dir`, leaving mounts through their mount point.
+    pub fn parent(&self, dir: VNode) -> Result<VNode, FsError> {
+        let mut node = dir;
+        while self.is_mount_root(node) {
+            match self.mount_entry(node.mount)?.covers {
+                Some(covered) => node = covered,
+                None => return Ok(node), // `..` of `/` is `/`
+            }
+        }
+        let inode = self.fs(node)?.lookup(node.inode, "..")?;
+        Ok(VNode { mount: node.mount, inode })
+    }
+
+    /// Resolve a path string to a node, starting from `cwd`.
+    /// Symbolic links are followed, including in the last component.
+    pub fn resolve_path(&self, path: &str, cwd: VNode) -> Result<VNode, FsError> {
+        self.walk(path.trim(), cwd, true, &mut 0)
+    }
+
+    /// Like `resolve_path`, but a symbolic link in the last component is
+    /// returned itself rather than followed.
+    pub fn resolve_path_nofollow(&self, path: &str, cwd: VNode) -> Result<VNode, FsError> {
+        self.walk(path.trim(), cwd, false, &mut 0)
+    }
+
+    /// Walk `path` from `cwd`. `links` counts symlinks followed so far,
+    /// across nested walks, so a loop ends in `TooManyLinks`.
+    fn walk(&self, path: &str, cwd: VNode, follow_last: bool, links: &mut usize) -> Result<VNode, FsError> {
+        let mut current = if path.starts_with('/') { self.root() } else { cwd };
+        let mut components = path.split('/').filter(|c| !c.is_empty() && *c != ".").peekable();
+        while let Some(component) = components.next() {
+            if !self.is_directory(current) {
+                return Err(FsError::NotADirectory);
+            }
+            let next = self.lookup(current, component)?;
+            let is_last = components.peek().is_none();
+            if (!is_last || follow_last) && self.stat(next)?.kind == FileType::Symlink {
+                *links += 1;
+                if *links > MAX_SYMLINKS {
+                    return Err(FsError::TooManyLinks);
+                }
+                // Relative targets are relative to the link's directory.
+                let target = self.fs(next)?.readlink(next.inode)?;
+                current = self.walk(&target, current, true, links)?;
+            } else {
+                current = next;
+            }
+        }
+        Ok(current)
+    }
+
+    /// Resolve the parent directory and return `(parent, child_name)`.
+    pub fn resolve_parent(&self, path: &str, cwd: VNode) -> Result<(VNode, String), FsError> {
+        let path = path.trim().trim_end_matches('/');
+        if path.is_empty() {
+            return Err(FsError::InvalidPath);
+        }
+        let (parent, name) = match path.rfind('/') {
+            Some(0) => (self.root(), &path[1..]),
+            Some(pos) => (self.resolve_path(&path[..pos], cwd)?, &path[pos + 1..]),
+            // No slash — child lives directly under cwd.
+            None => (cwd, path),
+        };
+        validate_name(name)?;
+        if !self.is_directory(parent) {
+            return Err(FsError::NotADirectory);
+        }
+        Ok((parent, String::from(name)))
+    }
+
+    /// Check whether a node is a directory.
+    pub fn is_directory(&self, node: VNode) -> bool {
+        self.stat(node).is_ok_and(|m| m.kind == FileType::Directory)
+    }
+
+    pub fn stat(&self, node: VNode) -> Result<Metadata, FsError> {
+        self.fs(node)?.stat(node.inode)
+    }
+
+    pub fn set_mode(&mut self, node: VNode, mode: u16) -> Result<(), FsError> {
+        self.fs_mut(node)?.set_mode(node.inode, mode)
+    }
+
+    pub fn set_owner(&mut self, node: VNode, uid: u32, gid: u32) -> Result<(), FsError> {
+        self.fs_mut(node)?.set_owner(node.inode, uid, gid)
+    }
+
+    pub fn set_times(&mut self, node: VNode, accessed: u64, modified: u64) -> Result<(), FsError> {
+        self.fs_mut(node)?.set_times(node.inode, accessed, modified)
+    }
+
+    /// List entries of a directory.
+    pub fn list_dir(&self, dir: VNode) -> Result<Vec<DirEntry>, FsError> {
+        self.fs(dir)?.readdir(dir.inode)
+    }
+
+    pub fn read(&self, node: VNode, offset: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+        self.fs(node)?.read(node.inode, offset, buf)
+    }
+
+    pub fn write(&mut self, node: VNode, offset: usize, data: &[u8]) -> Result<usize, FsError> {
+        self.fs_mut(node)?.write(node.inode, offset, data)
+    }
+
+    pub fn truncate(&mut self, node: VNode, size: usize) -> Result<(), FsError> {
+        self.fs_mut(node)?.truncate(node.inode, size)
+    }
+
+    /// Read a whole file.
+    pub fn read_file(&self, node: VNode) -> Result<Vec<u8>, FsError> {
+        let meta = self.stat(node)?;
+        if meta.kind != FileType::File {
+            return Err(FsError::NotAFile);
+        }
+        let mut data = vec![0; meta.size as usize];
+        let len = self.read(node, 0, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
+    fn create(&mut self, path: &str, cwd: VNode, kind: FileType) -> Result<VNode, FsError> {
+        let (parent, name) = self.resolve_parent(path, cwd)?;
+        let inode = self.fs_mut(parent)?.create(parent.inode, &name, kind)?;
+        Ok(VNode { mount: parent.mount, inode })
+    }
+
+    /// Create an empty file at `path` relative to `cwd`.
+    pub fn create_file(&mut self, path: &str, cwd: VNode) -> Result<VNode, FsError> {
+        self.create(path, cwd, FileType::File)
+    }
+
+    /// Create an empty directory at `path` relative to `cwd`.
+    pub fn create_dir(&mut self, path: &str, cwd: VNode) -> Result<VNode, FsError> {
+        self.create(path, cwd, FileType::Directory)
+    }
+
+    /// Create a symbolic link at `path` pointing to `target`. The target
+    /// is stored as given and need not exist.
+    pub fn symlink(&mut self, target: &str, path: &str, cwd: VNode) -> Result<VNode, FsError> {
+        let (parent, name) = self.resolve_parent(path, cwd)?;
+        let inode = self.fs_mut(parent)?.symlink(parent.inode, &name, target)?;
+        Ok(VNode { mount: parent.mount, inode })
+    }
+
+    /// The target of the symbolic link at `path`.
+    pub fn read_link(&self, path: &str, cwd: VNode) -> Result<String, FsError> {
+        let node = self.resolve_path_nofollow(path, cwd)?;
+        self.fs(node)?.readlink(node.inode)
+    }
+
+    /// Add `new_path` as another name for the file at `existing`. A
+    /// symbolic link is linked itself, not its target.
+    pub fn link(&mut self, existing: &str, new_path: &str, cwd: VNode) -> Result<(), FsError> {
+        let node = self.resolve_path_nofollow(existing, cwd)?;
+        let (parent, name) = self.resolve_parent(new_path, cwd)?;
+        if parent.mount != node.mount {
+            return Err(FsError::CrossDevice);
+        }
+        if self.is_directory(node) {
+            return Err(FsError::NotAFile);
+        }
+        self.fs_mut(parent)?.link(parent.inode, &name, node.inode)
+    }
+
+    /// Remove a file or empty directory at `path` relative to `cwd`. A
+    /// file that is still open keeps its data until the last handle closes.
+    pub fn remove(&mut self, path: &str, cwd: VNode) -> Result<(), FsError> {
+        self.reap();
+        let (parent, name) = self.resolve_parent(path, cwd)?;
+        self.remove_entry(parent, &name)
+    }
+
+    /// Unlink `name` from `parent` and release its node.
+    fn remove_entry(&mut self, parent: VNode, name: &str) -> Result<(), FsError> {
+        let target = self.lookup(parent, name)?;
+        if target.mount != parent.mount {
+            return Err(FsError::Busy);
+        }
+        self.fs_mut(parent)?.unlink(parent.inode, name)?;
+        self.release(target)
+    }
+
+    /// Free a node that lost its name, or keep it as an orphan while it
+    /// is still open.
+    fn release(&mut self, node: VNode) -> Result<(), FsError> {
+        if self.is_open(node) {
+            self.orphans.push(node);
+        } else {
+            self.fs_mut(node)?.evict(node.inode);
+        }
+        Ok(())
+    }
+
+    /// The shared reference for handles on `vnode`, created on first open.
+    fn node_ref(&mut self, vnode: VNode) -> Arc<NodeRef> {
+        if let Some(node) = self.open.get(&vnode).and_then(Weak::upgrade) {
+            return node;
+        }
+        let node = Arc::new(NodeRef { vnode });
+        self.open.insert(vnode, Arc::downgrade(&node));
+        node
+    }
+
+    fn is_open(&self, vnode: VNode) -> bool {
+        self.open.get(&vnode).is_some_and(|node| node.strong_count() > 0)
+    }
+
+    /// Forget closed nodes and free orphans whose last handle is gone.
+    /// Called by the operations that open or remove files; handles don't
+    /// lock the VFS when they are dropped.
+    pub fn reap(&mut self) {
+        self.open.retain(|_, node| node.strong_count() > 0);
+        let mut orphans = core::mem::take(&mut self.orphans);
+        orphans.retain(|&vnode| {
+            if self.open.contains_key(&vnode) {
+                return true;
+            }
+            if let Ok(fs) = self.fs_mut(vnode) {
+                fs.evict(vnode.inode);
+            }
+            false
+        });
+        self.orphans = orphans;
+    }
+
+    /// Replace a file's content. Creates the file if it doesn't exist.
+    pub fn write_file(&mut self, path: &str, content: &[u8], cwd: VNode) -> Result<(), FsError> {
+        let node = match self.resolve_path(path, cwd) {
+            Ok(node) => node,
+            Err(FsError::NotFound) => self.create_file(path, cwd)?,
+            Err(e) => return Err(e),
+        };
+        self.truncate(node, 0)?;
+        self.write(node, 0, content)?;
+        Ok(())
+    }
+
+    /// Build the absolute path of a directory by walking `..` up to the
+    /// root and finding each directory's name in its parent. Only
+    /// directories have a single, well-defined path; files may have
+    /// several names (hard links) or none.
+    pub fn get_path(&self, node: VNode) -> Result<String, FsError> {
+        if !self.is_directory(node) {
+            return Err(FsError::NotADirectory);
+        }
+        let mut parts = Vec::new();
+        let mut current = node;
+        loop {
+            // Step out of mount roots onto the directories they cover.
+            while self.is_mount_root(current) {
+                match self.mount_entry(current.mount)?.covers {
+                    Some(covered) => current = covered,
+                    None => break,
+                }
+            }
+            if current == self.root() {
+                break;
+            }
+            let parent = self.parent(current)?;
+            let entries = self.list_dir(parent)?;
+            let entry = entries
+                .into_iter()
+                .find(|e| e.inode == current.inode && e.kind == FileType::Directory)
+                .ok_or(FsError::NotFound)?;
+            parts.push(entry.name);
+            current = parent;
+        }
+
+        parts.reverse();
+        let mut path = String::from("/");
+        path.push_str(&parts.join("/"));
+        Ok(path)
+    }
+
+    /// Mount `fs` on the directory at `path`.
+    pub fn mount(&mut self, path: &str, fs: Box<dyn FileSystem>, cwd: VNode) -> Result<MountId, FsError> {
+        let target = self.resolve_path(path, cwd)?;
+        if !self.is_directory(target) {
+            return Err(FsError::NotADirectory);
+        }
+        // `resolve_path` already entered any mount here, so a mount root
+        // (including `/`) means the directory is taken.
+        if self.is_mount_root(target) {
+            return Err(FsError::Busy);
+        }
+        let mount = Mount {
+            fs,
+            path: self.get_path(target)?,
+            covers: Some(target),
+        };
+        let id = match self.mounts.iter().position(Option::is_none) {
+            Some(id) => id,
+            None => {
+                self.mounts.push(None);
+                self.mounts.len() - 1
+            }
+        };
+        self.mounts[id] = Some(mount);
+        Ok(id)
+    }
+
+    /// Unmount the filesystem mounted at `path`, returning its driver.
+    /// Fails with `Busy` while other mounts sit on it or files are open.
+    pub fn unmount(&mut self, path: &str, cwd: VNode) -> Result<Box<dyn FileSystem>, FsError> {
+        let target = self.resolve_path(path, cwd)?;
+        if !self.is_mount_root(target) {
+            return Err(FsError::InvalidPath);
+        }
+        if self.mount_entry(target.mount)?.covers.is_none() {
+            return Err(FsError::Busy); // can't unmount `/`
+        }
+        let has_children = self
+            .mounts
+            .iter()
+            .flatten()
+            .any(|m| m.covers.is_some_and(|c| c.mount == target.mount));
+        self.reap();
+        if has_children || self.open.keys().any(|node| node.mount == target.mount) {
+            return Err(FsError::Busy);
+        }
+        let mount = self.mounts[target.mount].take().ok_or(FsError::NotFound)?;
+        Ok(mount.fs)
+    }
+
+    /// True if a mounted filesystem lives on the block device `name`.
+    pub fn uses_device(&self, name: &str) -> bool {
+        self.mounts.iter().flatten().any(|m| m.fs.device() == Some(name))
+    }
+
+    /// Mount table entries: `(mount point, driver name)`.
+    pub fn mounts(&self) -> Vec<(String, &'static str)> {
+        self.mounts
+            .iter()
+            .flatten()
+            .map(|m| (m.path.clone(), m.fs.name()))
+            .collect()
+    }
+}
@@ -0,0 +1,280 @@
+/// Process filesystem (procfs), mounted at `/proc`.
+///
+/// Nothing is stored: each file's text is generated from live kernel
+/// state whenever it is read or stat'ed.
+///
+///   /proc/meminfo       physical memory and heap usage
+///   /proc/uptime        seconds since boot
+///   /proc/interrupts    how often each IDT vector was taken
+///   /proc/<pid>/status  one entry of `PROCESS_TABLE`
+///
+/// Inode numbers encode what they name: the fixed files are small
+/// numbers, and a process's directory and files are `pid << PID_SHIFT`
+/// plus a per-file index. Everything is read-only.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use super::{DirEntry, FileSystem, FileType, FsError, InodeId, Metadata};
+use crate::task::process::{Pid, PROCESS_TABLE};
+
+const ROOT: InodeId = 1;
+const PID_SHIFT: u32 = 4;
+const PID_DIR: InodeId = 0;
+const PID_STATUS: InodeId = 1;
+
+/// Builds the text of a top-level file.
+type Generator = fn() -> String;
+/// Builds the text of a per-process file, if the process still exists.
+type PidGenerator = fn(Pid) -> Option<String>;
+
+/// Files at the top of `/proc`: name, inode and generator.
+const FILES: [(&str, InodeId, Generator); 3] = [
+    ("meminfo", 2, meminfo),
+    ("uptime", 3, uptime),
+    ("interrupts", 4, interrupts),
+];
+
+/// Files in each `/proc/<pid>` directory.
+const PID_FILES: [(&str, InodeId, PidGenerator); 1] = [("status", PID_STATUS, status)];
+
+enum Node {
+    Root,
+    File(Generator),
+    PidDir(Pid),
+    PidFile(Pid, PidGenerator),
+}
+
+pub struct ProcFs;
+
+impl ProcFs {
+    pub fn new() -> ProcFs {
+        ProcFs
+    }
+
+    fn node(&self, inode: InodeId) -> Result<Node, FsError> {
+        if inode == ROOT {
+            return Ok(Node::Root);
+        }
+        if let Some(&(_, _, generate)) = FILES.iter().find(|f| f.1 == inode) {
+            return Ok(Node::File(generate));
+        }
+        let pid = inode >> PID_SHIFT;
+        if pid == 0 || !pids().contains(&pid) {
+            return Err(FsError::NotFound);
+        }
+        match inode & ((1 << PID_SHIFT) - 1) {
+            PID_DIR => Ok(Node::PidDir(pid)),
+            index => PID_FILES
+                .iter()
+                .find(|f| f.1 == index)
+                .map(|&(_, _, generate)| Node::PidFile(pid, generate))
+                .ok_or(FsError::NotFound),
+        }
+    }
+
+    /// The current text of a file.
+    fn contents(&self, inode: InodeId) -> Result<String, FsError> {
+        match self.node(inode)? {
+            Node::File(generate) => Ok(generate()),
+            Node::PidFile(pid, generate) => generate(pid).ok_or(FsError::NotFound),
+            Node::Root | Node::PidDir(_) => Err(FsError::NotAFile),
+        }
+    }
+}
+
+impl Default for ProcFs {
+    fn default() -> Self {
+        ProcFs::new()
+    }
+}
+
+fn pid_inode(pid: Pid, index: InodeId) -> InodeId {
+    (pid << PID_SHIFT) | index
+}
+
+fn pids() -> Vec<Pid> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        PROCESS_TABLE
+            .lock()
+            .as_ref()
+            .map(|table| table.list().into_iter().map(|(pid, _)| pid).collect())
+            .unwrap_or_default()
+    })
+}
+
+impl FileSystem for ProcFs {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT
+    }
+
+    fn lookup(&self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        match self.node(dir)? {
+            Node::Root => match name {
+                "." | ".." => Ok(ROOT),
+                _ => {
+                    if let Some(file) = FILES.iter().find(|f| f.0 == name) {
+                        return Ok(file.1);
+                    }
+                    let pid = name
+                        .parse::<Pid>()
+                        .ok()
+                        .filter(|&pid| pid <= Pid::MAX >> PID_SHIFT)
+                        .ok_or(FsError::NotFound)?;
+                    let inode = pid_inode(pid, PID_DIR);
+                    self.node(inode).map(|_| inode)
+                }
+            },
+            Node::PidDir(pid) => match name {
+                "." => Ok(dir),
+                ".." => Ok(ROOT),
+                _ => PID_FILES
+                    .iter()
+                    .find(|f| f.0 == name)
+                    .map(|f| pid_inode(pid, f.1))
+                    .ok_or(FsError::NotFound),
+            },
+            Node::File(_) | Node::PidFile(..) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn read(&self, inode: InodeId, offset: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+        let text = self.contents(inode)?;
+        let bytes = text.as_bytes();
+        if offset >= bytes.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(bytes.len() - offset);
+        buf[..len].copy_from_slice(&bytes[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write(&mut self, _inode: InodeId, _offset: usize, _data: &[u8]) -> Result<usize, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn truncate(&mut self, _inode: InodeId, _size: usize) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn readdir(&self, dir: InodeId) -> Result<Vec<DirEntry>, FsError> {
+        match self.node(dir)? {
+            Node::Root => {
+                let files = FILES.iter().map(|&(name, inode, _)| DirEntry {
+                    name: String::from(name),
+                    inode,
+                    kind: FileType::File,
+                });
+                let processes = pids().into_iter().map(|pid| DirEntry {
+                    name: pid.to_string(),
+                    inode: pid_inode(pid, PID_DIR),
+                    kind: FileType::Directory,
+                });
+                Ok(files.chain(processes).collect())
+            }
+            Node::PidDir(pid) => Ok(PID_FILES
+                .iter()
+                .map(|&(name, index, _)| DirEntry {
+                    name: String::from(name),
+                    inode: pid_inode(pid, index),
+                    kind: FileType::File,
+                })
+                .collect()),
+            Node::File(_) | Node::PidFile(..) => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn create(&mut self, _dir: InodeId, _name: &str, _kind: FileType) -> Result<InodeId, FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&mut self, _dir: InodeId, _name: &str) -> Result<(), FsError> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn stat(&self, inode: InodeId) -> Result<Metadata, FsError> {
+        let (kind, size, mode, nlink) = match self.node(inode)? {
+            Node::Root => (FileType::Directory, FILES.len() + pids().len(), 0o555, 2 + pids().len()),
+            Node::PidDir(_) => (FileType::Directory, PID_FILES.len(), 0o555, 2),
+            Node::File(_) | Node::PidFile(..) => {
+                (FileType::File, self.contents(inode)?.len(), 0o444, 1)
+            }
+        };
+        let now = crate::time::now();
+        Ok(Metadata {
+            inode,
+            kind,
+            size: size as u64,
+            mode,
+            uid: 0,
+            gid: 0,
+            nlink: nlink as u32,
+            created: now,
+            modified: now,
+            accessed: now,
+        })
+    }
+}
+
+fn meminfo() -> String {
+    let heap = crate::allocator::stats();
+    let frames = x86_64::instructions::interrupts::without_interrupts(|| {
+        crate::memory::FRAME_ALLOCATOR
+            .lock()
+            .as_ref()
+            .map(|f| (f.total_frames(), f.free_frames()))
+    });
+    let (total, free) = frames.unwrap_or((0, 0));
+    let mut text = String::new();
+    let mut line = |key: &str, bytes: usize| {
+        let _ = writeln!(text, "{:<12}{:>10} kB", format!("{}:", key), bytes / 1024);
+    };
+    line("MemTotal", total * 4096);
+    line("MemFree", free * 4096);
+    line("MemUsed", (total - free) * 4096);
+    line("HeapSize", heap.size);
+    line("HeapUsed", heap.used);
+    line("HeapFree", heap.free);
+    line("HeapLimit", heap.limit);
+    text
+}
+
+fn uptime() -> String {
+    let ms = crate::time::uptime_ms();
+    format!("{}.{:02}\n", ms / 1000, ms % 1000 / 10)
+}
+
+fn interrupts() -> String {
+    let mut text = String::new();
+    for (vector, name) in crate::interrupts::HANDLED_VECTORS {
+        let count = crate::interrupts::interrupt_count(vector);
+        let _ = writeln!(text, "{:>3}: {:>10}  {}", vector, count, name);
+    }
+    text
+}
+
+fn status(pid: Pid) -> Option<String> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let table = PROCESS_TABLE.lock();
+        let process = table.as_ref()?.get(pid)?;
+        let mut text = String::new();
+        let _ = writeln!(text, "Name:\t{}", process.name);
+        let _ = writeln!(text, "Pid:\t{}", pid);
+        let _ = writeln!(text, "PPid:\t{}", process.parent_pid.unwrap_or(0));
+        let _ = writeln!(text, "State:\t{}", process.state);
+        let _ = writeln!(text, "Type:\t{}", if process.is_thread { "thread" } else { "task" });
+        let _ = writeln!(text, "Files:\t{}", process.files.len());
+        if let Some(code) = process.exit_code {
+            let _ = writeln!(text, "ExitCode:\t{}", code);
+        }
+        Some(text)
+    })
+}
@@ -0,0 +1,535 @@
+/// Filesystem snapshots: the root filesystem's whole tree in one image.
+///
+/// `save` serialises every directory, file and symbolic link of the
+/// filesystem mounted at `/` (names, data, modes, owners and times) and
+/// writes the image to the start of a block device; `load` reads it back
+/// and restores it on top of the current tree, replacing files and links
+/// in the way (but not non-empty directories). `save` only overwrites a
+/// device that holds an earlier snapshot or no filesystem at all, and
+/// never one that is mounted. Other mounts aren't
+/// included: a directory something is mounted on is saved as the
+/// (usually empty) directory underneath. At boot the ramdisk may hold a
+/// snapshot instead of a ustar archive, and the first disk holding one is
+/// restored after it (see `main`).
+///
+/// The image is a one-sector header followed by the records, padded to
+/// whole sectors. All numbers are little-endian.
+///
+///   header:  magic "SNAPSHOT", version u32, record count u32,
+///            payload length u64, save time u64, payload CRC-32 u32,
+///            header CRC-32 u32 (over everything before it)
+///   record:  parent u32, kind u8, name length u16, name, then
+///            - for a hard link: the record it links to, u32
+///            - otherwise: mode u16, uid u32, gid u32, accessed u64,
+///              modified u64, data length u64, data (file contents or
+///              link target; empty for directories)
+///
+/// Record 0 is the root directory. Every record comes after its parent
+/// directory, and a hard link after the file it names, so one pass in
+/// order rebuilds the tree.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{Ext2, Fat32, FileType, FsError, InodeId, Metadata, VNode, Vfs, VFS};
+use crate::drivers::block::{self, BlockDevice, BlockError, SECTOR_SIZE};
+
+const MAGIC: [u8; 8] = *b"SNAPSHOT";
+/// Format version written by `encode`; `decode` accepts only this one.
+pub const VERSION: u32 = 1;
+/// Bytes of the header covered by its checksum.
+const HEADER_LEN: usize = 36;
+
+const KIND_DIRECTORY: u8 = 0;
+const KIND_FILE: u8 = 1;
+const KIND_SYMLINK: u8 = 2;
+const KIND_LINK: u8 = 3;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// The image doesn't start with a snapshot header.
+    NoSnapshot,
+    /// The header was written by a different format version.
+    UnsupportedVersion(u32),
+    /// The header or the records don't match their checksum.
+    BadChecksum,
+    /// The image is shorter than its header says.
+    Truncated,
+    /// A record is malformed (bad kind, parent or link target).
+    Malformed { record: usize },
+    /// The image doesn't fit on the device.
+    TooLarge { sectors: u64 },
+    /// The device backs a mounted filesystem.
+    Mounted,
+    /// The device holds a filesystem (or partition table) rather than a
+    /// snapshot, and saving would destroy it.
+    NotEmpty,
+    Device(BlockError),
+    /// The filesystem rejected a node while saving or restoring.
+    Fs(String, FsError),
+}
+
+impl From<BlockError> for SnapshotError {
+    fn from(e: BlockError) -> SnapshotError {
+        SnapshotError::Device(e)
+    }
+}
+
+impl core::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SnapshotError::NoSnapshot => write!(f, "no snapshot found"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::BadChecksum => write!(f, "checksum mismatch"),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Malformed { record } => write!(f, "record {} is malformed", record),
+            SnapshotError::TooLarge { sectors } => {
+                write!(f, "snapshot needs {} sectors, more than the device has", sectors)
+            }
+            SnapshotError::Mounted => write!(f, "device is mounted"),
+            SnapshotError::NotEmpty => write!(f, "device holds a filesystem"),
+            SnapshotError::Device(e) => write!(f, "{}", e),
+            SnapshotError::Fs(path, e) => write!(f, "{}: {}", path, e),
+        }
+    }
+}
+
+/// What was saved or restored.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SnapshotStats {
+    pub files: usize,
+    pub directories: usize,
+    pub symlinks: usize,
+    /// Extra names for files already counted (hard links).
+    pub links: usize,
+    /// Bytes of file data.
+    pub bytes: usize,
+}
+
+/// A parsed header.
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub version: u32,
+    pub records: u32,
+    pub payload_len: u64,
+    /// Unix time of the save.
+    pub saved: u64,
+    payload_crc: u32,
+}
+
+/// CRC-32 (IEEE 802.3, as used by zip and Ethernet).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Parse and check the header at the start of `image`.
+pub fn parse_header(image: &[u8]) -> Result<Header, SnapshotError> {
+    let bytes = image.get(..HEADER_LEN + 4).ok_or(SnapshotError::NoSnapshot)?;
+    if bytes[..8] != MAGIC {
+        return Err(SnapshotError::NoSnapshot);
+    }
+    let mut reader = Reader { data: bytes, pos: 8 };
+    let header = Header {
+        version: reader.u32()?,
+        records: reader.u32()?,
+        payload_len: reader.u64()?,
+        saved: reader.u64()?,
+        payload_crc: reader.u32()?,
+    };
+    if reader.u32()? != crc32(&bytes[..HEADER_LEN]) {
+        return Err(SnapshotError::BadChecksum);
+    }
+    if header.version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(header.version));
+    }
+    Ok(header)
+}
+
+/// True if `image` starts with a valid snapshot header.
+pub fn is_snapshot(image: &[u8]) -> bool {
+    parse_header(image).is_ok()
+}
+
+/// Serialise the tree of the filesystem mounted at `/`. The image is a
+/// whole number of sectors.
+pub fn encode(fs: &Vfs) -> Result<(Vec<u8>, SnapshotStats), SnapshotError> {
+    let mut encoder = Encoder {
+        payload: Vec::new(),
+        records: 0,
+        seen: BTreeMap::new(),
+        stats: SnapshotStats::default(),
+    };
+    let root = fs.root();
+    let fs_err = |e| SnapshotError::Fs(String::from("/"), e);
+    let meta = fs.stat(root).map_err(fs_err)?;
+    encoder.record(0, "", KIND_DIRECTORY).map_err(fs_err)?;
+    encoder.attributes(&meta, &[]);
+    encoder.directory(fs, root, 0, "")?;
+
+    let mut image = vec![0u8; SECTOR_SIZE];
+    image[..8].copy_from_slice(&MAGIC);
+    image[8..12].copy_from_slice(&VERSION.to_le_bytes());
+    image[12..16].copy_from_slice(&encoder.records.to_le_bytes());
+    image[16..24].copy_from_slice(&(encoder.payload.len() as u64).to_le_bytes());
+    image[24..32].copy_from_slice(&crate::time::now().to_le_bytes());
+    image[32..36].copy_from_slice(&crc32(&encoder.payload).to_le_bytes());
+    let header_crc = crc32(&image[..HEADER_LEN]);
+    image[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&header_crc.to_le_bytes());
+    image.extend_from_slice(&encoder.payload);
+    image.resize(image.len().next_multiple_of(SECTOR_SIZE), 0);
+    Ok((image, encoder.stats))
+}
+
+struct Encoder {
+    payload: Vec<u8>,
+    records: u32,
+    /// Record of each file or link seen that has more than one name.
+    seen: BTreeMap<InodeId, u32>,
+    stats: SnapshotStats,
+}
+
+impl Encoder {
+    /// Start a record and return its index.
+    fn record(&mut self, parent: u32, name: &str, kind: u8) -> Result<u32, FsError> {
+        let name_len = u16::try_from(name.len()).map_err(|_| FsError::InvalidPath)?;
+        self.payload.extend_from_slice(&parent.to_le_bytes());
+        self.payload.push(kind);
+        self.payload.extend_from_slice(&name_len.to_le_bytes());
+        self.payload.extend_from_slice(name.as_bytes());
+        self.records += 1;
+        Ok(self.records - 1)
+    }
+
+    fn attributes(&mut self, meta: &Metadata, data: &[u8]) {
+        self.payload.extend_from_slice(&meta.mode.to_le_bytes());
+        self.payload.extend_from_slice(&meta.uid.to_le_bytes());
+        self.payload.extend_from_slice(&meta.gid.to_le_bytes());
+        self.payload.extend_from_slice(&meta.accessed.to_le_bytes());
+        self.payload.extend_from_slice(&meta.modified.to_le_bytes());
+        self.payload.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        self.payload.extend_from_slice(data);
+    }
+
+    /// Add the records for everything in `dir` (record `index`), which
+    /// is at `path`. Stays in `dir`'s filesystem.
+    fn directory(&mut self, fs: &Vfs, dir: VNode, index: u32, path: &str) -> Result<(), SnapshotError> {
+        let entries = fs.list_dir(dir).map_err(|e| SnapshotError::Fs(String::from(path), e))?;
+        for entry in entries {
+            let child_path = format!("{}/{}", path, entry.name);
+            let fs_err = |e| SnapshotError::Fs(child_path.clone(), e);
+            if let Some(&target) = self.seen.get(&entry.inode) {
+                self.record(index, &entry.name, KIND_LINK).map_err(fs_err)?;
+                self.payload.extend_from_slice(&target.to_le_bytes());
+                self.stats.links += 1;
+                continue;
+            }
+            let node = VNode { mount: dir.mount, inode: entry.inode };
+            let meta = fs.stat(node).map_err(fs_err)?;
+            let child = match meta.kind {
+                FileType::Directory => {
+                    let child = self.record(index, &entry.name, KIND_DIRECTORY).map_err(fs_err)?;
+                    self.attributes(&meta, &[]);
+                    self.stats.directories += 1;
+                    self.directory(fs, node, child, &child_path)?;
+                    continue;
+                }
+                FileType::File => {
+                    let data = fs.read_file(node).map_err(fs_err)?;
+                    let child = self.record(index, &entry.name, KIND_FILE).map_err(fs_err)?;
+                    self.attributes(&meta, &data);
+                    self.stats.files += 1;
+                    self.stats.bytes += data.len();
+                    child
+                }
+                FileType::Symlink => {
+                    let target = fs.fs(node).and_then(|f| f.readlink(node.inode)).map_err(fs_err)?;
+                    let child = self.record(index, &entry.name, KIND_SYMLINK).map_err(fs_err)?;
+                    self.attributes(&meta, target.as_bytes());
+                    self.stats.symlinks += 1;
+                    child
+                }
+                // Device nodes belong to devfs and can't be recreated.
+                FileType::CharDevice => continue,
+            };
+            if meta.nlink > 1 {
+                self.seen.insert(entry.inode, child);
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len());
+        let end = end.ok_or(SnapshotError::Truncated)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+struct Attributes {
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    accessed: u64,
+    modified: u64,
+}
+
+enum Body<'a> {
+    Directory,
+    File(&'a [u8]),
+    Symlink(&'a str),
+    /// Another name for an earlier record.
+    Link(usize),
+}
+
+struct Record<'a> {
+    parent: usize,
+    name: &'a str,
+    body: Body<'a>,
+    attributes: Option<Attributes>,
+}
+
+/// Parse every record, checking that parents are earlier directories and
+/// links name earlier files or symbolic links.
+fn parse_records(payload: &[u8], count: u32) -> Result<Vec<Record<'_>>, SnapshotError> {
+    let mut reader = Reader { data: payload, pos: 0 };
+    let mut records: Vec<Record> = Vec::new();
+    for index in 0..count as usize {
+        let malformed = || SnapshotError::Malformed { record: index };
+        let parent = reader.u32()? as usize;
+        let kind = reader.u8()?;
+        let name_len = reader.u16()? as usize;
+        let name = core::str::from_utf8(reader.bytes(name_len)?).map_err(|_| malformed())?;
+        let (body, attributes) = if kind == KIND_LINK {
+            (Body::Link(reader.u32()? as usize), None)
+        } else {
+            let attributes = Attributes {
+                mode: reader.u16()?,
+                uid: reader.u32()?,
+                gid: reader.u32()?,
+                accessed: reader.u64()?,
+                modified: reader.u64()?,
+            };
+            let len = usize::try_from(reader.u64()?).map_err(|_| SnapshotError::Truncated)?;
+            let data = reader.bytes(len)?;
+            let body = match kind {
+                KIND_DIRECTORY => Body::Directory,
+                KIND_FILE => Body::File(data),
+                KIND_SYMLINK => Body::Symlink(core::str::from_utf8(data).map_err(|_| malformed())?),
+                _ => return Err(malformed()),
+            };
+            (body, Some(attributes))
+        };
+
+        let valid = if index == 0 {
+            matches!(body, Body::Directory)
+        } else {
+            let parent_is_dir =
+                records.get(parent).is_some_and(|p| matches!(p.body, Body::Directory));
+            let target_ok = match body {
+                Body::Link(target) => {
+                    records.get(target).is_some_and(|t| matches!(t.body, Body::File(_) | Body::Symlink(_)))
+                }
+                _ => true,
+            };
+            parent_is_dir && target_ok && super::validate_name(name).is_ok()
+        };
+        if !valid {
+            return Err(malformed());
+        }
+        records.push(Record { parent, name, body, attributes });
+    }
+    Ok(records)
+}
+
+/// Restore a snapshot image onto the filesystem mounted at `/`. Entries
+/// in the snapshot replace what is at their path (directories are merged);
+/// everything else is left alone. The image is checked completely before
+/// anything is changed.
+pub fn decode(fs: &mut Vfs, image: &[u8]) -> Result<SnapshotStats, SnapshotError> {
+    let header = parse_header(image)?;
+    let payload = usize::try_from(header.payload_len)
+        .ok()
+        .and_then(|len| image.get(SECTOR_SIZE..)?.get(..len))
+        .ok_or(SnapshotError::Truncated)?;
+    if crc32(payload) != header.payload_crc {
+        return Err(SnapshotError::BadChecksum);
+    }
+    let records = parse_records(payload, header.records)?;
+
+    fs.reap();
+    let mut stats = SnapshotStats::default();
+    let mut nodes = vec![fs.root()];
+    let mut paths = vec![String::from("/")];
+    for record in &records[1..] {
+        let parent = nodes[record.parent];
+        let path = format!("{}/{}", paths[record.parent].trim_end_matches('/'), record.name);
+        let node = restore_record(fs, parent, record, &nodes, &mut stats)
+            .map_err(|e| SnapshotError::Fs(path.clone(), e))?;
+        nodes.push(node);
+        paths.push(path);
+    }
+    // Attributes go last, since adding entries changes a directory's times.
+    for ((record, &node), path) in records.iter().zip(&nodes).zip(&paths) {
+        if let Some(attrs) = &record.attributes {
+            let fs_err = |e| SnapshotError::Fs(path.clone(), e);
+            fs.set_mode(node, attrs.mode).map_err(fs_err)?;
+            fs.set_owner(node, attrs.uid, attrs.gid).map_err(fs_err)?;
+            fs.set_times(node, attrs.accessed, attrs.modified).map_err(fs_err)?;
+        }
+    }
+    Ok(stats)
+}
+
+/// Create (or reuse) the node for one record under `parent`.
+fn restore_record(
+    fs: &mut Vfs,
+    parent: VNode,
+    record: &Record,
+    nodes: &[VNode],
+    stats: &mut SnapshotStats,
+) -> Result<VNode, FsError> {
+    let name = record.name;
+    // Look in the parent's own filesystem: a mount point is restored as
+    // the directory underneath, as it was saved.
+    let existing = match fs.fs(parent)?.lookup(parent.inode, name) {
+        Ok(inode) => Some(VNode { mount: parent.mount, inode }),
+        Err(FsError::NotFound) => None,
+        Err(e) => return Err(e),
+    };
+    let existing_kind = match existing {
+        Some(node) => Some(fs.stat(node)?.kind),
+        None => None,
+    };
+    let node = match (&record.body, existing) {
+        (Body::Directory, Some(node)) if existing_kind == Some(FileType::Directory) => node,
+        (Body::File(_), Some(node)) if existing_kind == Some(FileType::File) => node,
+        (Body::Link(target), Some(node)) if node == nodes[*target] => node,
+        (body, existing) => {
+            if existing.is_some() {
+                fs.remove_entry(parent, name)?;
+            }
+            let inode = match body {
+                Body::Directory => fs.fs_mut(parent)?.create(parent.inode, name, FileType::Directory)?,
+                Body::File(_) => fs.fs_mut(parent)?.create(parent.inode, name, FileType::File)?,
+                Body::Symlink(target) => fs.fs_mut(parent)?.symlink(parent.inode, name, target)?,
+                Body::Link(target) => {
+                    let target = nodes[*target];
+                    fs.fs_mut(parent)?.link(parent.inode, name, target.inode)?;
+                    target.inode
+                }
+            };
+            VNode { mount: parent.mount, inode }
+        }
+    };
+    match record.body {
+        Body::Directory => stats.directories += 1,
+        Body::File(data) => {
+            fs.truncate(node, 0)?;
+            fs.write(node, 0, data)?;
+            stats.files += 1;
+            stats.bytes += data.len();
+        }
+        Body::Symlink(_) => stats.symlinks += 1,
+        Body::Link(_) => stats.links += 1,
+    }
+    Ok(node)
+}
+
+/// Write a snapshot of the global filesystem to the start of `device`.
+/// Only a device that already holds a snapshot or holds no filesystem
+/// is overwritten, and never one that is mounted.
+pub fn save(device: &Arc<dyn BlockDevice>) -> Result<SnapshotStats, SnapshotError> {
+    // Held until the image is written, so the device can't be mounted
+    // between the check and the write.
+    let fs = VFS.lock();
+    let fs = fs.as_ref().expect("filesystem not initialized");
+    if fs.uses_device(device.name()) {
+        return Err(SnapshotError::Mounted);
+    }
+    let mut header = [0u8; SECTOR_SIZE];
+    device.read_sectors(0, &mut header)?;
+    if !is_snapshot(&header) && (Fat32::probe(device.as_ref()) || Ext2::probe(device.as_ref())) {
+        return Err(SnapshotError::NotEmpty);
+    }
+    let (image, stats) = encode(fs)?;
+    let sectors = (image.len() / SECTOR_SIZE) as u64;
+    if sectors > device.sector_count() {
+        return Err(SnapshotError::TooLarge { sectors });
+    }
+    device.write_sectors(0, &image)?;
+    device.flush()?;
+    Ok(stats)
+}
+
+/// Restore the snapshot at the start of `device` into the global
+/// filesystem.
+pub fn load(device: &Arc<dyn BlockDevice>) -> Result<SnapshotStats, SnapshotError> {
+    let mut header = [0u8; SECTOR_SIZE];
+    device.read_sectors(0, &mut header)?;
+    // The length comes off the disk: check it fits before allocating.
+    let sectors = parse_header(&header)?
+        .payload_len
+        .checked_add(SECTOR_SIZE as u64)
+        .map(|len| len.div_ceil(SECTOR_SIZE as u64))
+        .filter(|&sectors| sectors <= device.sector_count())
+        .and_then(|sectors| usize::try_from(sectors).ok())
+        .ok_or(SnapshotError::Truncated)?;
+    let mut image = vec![0u8; sectors * SECTOR_SIZE];
+    device.read_sectors(0, &mut image)?;
+    load_image(&image)
+}
+
+/// Restore a snapshot image in memory (e.g. the ramdisk) into the global
+/// filesystem.
+pub fn load_image(image: &[u8]) -> Result<SnapshotStats, SnapshotError> {
+    decode(VFS.lock().as_mut().expect("filesystem not initialized"), image)
+}
+
+/// The first block device (through the cache) that starts with a valid
+/// snapshot header.
+pub fn find() -> Option<Arc<dyn BlockDevice>> {
+    block::devices().into_iter().find_map(|dev| {
+        let device = crate::drivers::cache::get(dev.name())?;
+        let mut header = [0u8; SECTOR_SIZE];
+        device.read_sectors(0, &mut header).ok()?;
+        is_snapshot(&header).then_some(device)
+    })
+}
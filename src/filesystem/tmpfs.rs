@@ -0,0 +1,377 @@
+/// In-memory filesystem (tmpfs).
+///
+/// Provides a simple hierarchical filesystem living entirely in the kernel heap.
+/// Supports files, directories and symbolic links with Unix modes, owners
+/// and timestamps. Files can have several names (hard links); directories
+/// have exactly one, so their `..` is stored with them. Mounted at `/` by
+/// `filesystem::init`; further instances can be mounted anywhere.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{
+    validate_name, DirEntry, FileSystem, FileType, FsError, InodeId, Metadata, DEFAULT_DIR_MODE,
+    DEFAULT_FILE_MODE,
+};
+
+#[derive(Debug)]
+pub enum InodeKind {
+    File(Vec<u8>),
+    Directory {
+        entries: BTreeMap<String, InodeId>,
+        parent: InodeId,
+    },
+    Symlink(String),
+}
+
+#[derive(Debug)]
+pub struct Inode {
+    pub kind: InodeKind,
+    /// Unix permission bits (e.g. `0o644`).
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub created: u64,
+    pub modified: u64,
+    /// Atomic so reads (which only borrow the filesystem) can update it.
+    pub accessed: AtomicU64,
+}
+
+impl Inode {
+    fn new(kind: InodeKind, mode: u16) -> Inode {
+        let now = crate::time::now();
+        let nlink = match kind {
+            InodeKind::Directory { .. } => 2,
+            _ => 1,
+        };
+        Inode {
+            kind,
+            mode,
+            uid: 0,
+            gid: 0,
+            nlink,
+            created: now,
+            modified: now,
+            accessed: AtomicU64::new(now),
+        }
+    }
+
+    fn touch(&mut self) {
+        self.modified = crate::time::now();
+    }
+}
+
+const ROOT: InodeId = 0;
+
+pub struct TmpFs {
+    inodes: BTreeMap<InodeId, Inode>,
+    next_inode: InodeId,
+}
+
+impl TmpFs {
+    /// Create a tmpfs with an empty root directory.
+    pub fn new() -> TmpFs {
+        let mut inodes = BTreeMap::new();
+        // Root directory: inode 0, parent points to itself.
+        inodes.insert(ROOT, Inode::new(empty_dir(ROOT), DEFAULT_DIR_MODE));
+        TmpFs {
+            inodes,
+            next_inode: 1,
+        }
+    }
+
+    fn alloc_inode(&mut self) -> InodeId {
+        let id = self.next_inode;
+        self.next_inode += 1;
+        id
+    }
+
+    fn node(&self, inode: InodeId) -> Result<&Inode, FsError> {
+        self.inodes.get(&inode).ok_or(FsError::NotFound)
+    }
+
+    fn node_mut(&mut self, inode: InodeId) -> Result<&mut Inode, FsError> {
+        self.inodes.get_mut(&inode).ok_or(FsError::NotFound)
+    }
+
+    fn entries(&self, dir: InodeId) -> Result<&BTreeMap<String, InodeId>, FsError> {
+        match &self.node(dir)?.kind {
+            InodeKind::Directory { entries, .. } => Ok(entries),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn entries_mut(&mut self, dir: InodeId) -> Result<&mut BTreeMap<String, InodeId>, FsError> {
+        match &mut self.node_mut(dir)?.kind {
+            InodeKind::Directory { entries, .. } => Ok(entries),
+            _ => Err(FsError::NotADirectory),
+        }
+    }
+
+    fn is_dir(&self, inode: InodeId) -> Result<bool, FsError> {
+        Ok(matches!(self.node(inode)?.kind, InodeKind::Directory { .. }))
+    }
+
+    /// Add a new inode under `name` in `dir`.
+    fn insert(&mut self, dir: InodeId, name: &str, kind: InodeKind, mode: u16) -> Result<InodeId, FsError> {
+        validate_name(name)?;
+        if self.entries(dir)?.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        let is_dir = matches!(kind, InodeKind::Directory { .. });
+        let id = self.alloc_inode();
+        self.inodes.insert(id, Inode::new(kind, mode));
+        self.entries_mut(dir)?.insert(String::from(name), id);
+        let parent = self.node_mut(dir)?;
+        parent.touch();
+        if is_dir {
+            parent.nlink += 1; // the new directory's `..`
+        }
+        Ok(id)
+    }
+
+    /// File contents, for modification (updates the modified time).
+    fn data_mut(&mut self, inode: InodeId) -> Result<&mut Vec<u8>, FsError> {
+        let node = self.node_mut(inode)?;
+        node.touch();
+        match &mut node.kind {
+            InodeKind::File(data) => Ok(data),
+            _ => Err(FsError::NotAFile),
+        }
+    }
+}
+
+impl Default for TmpFs {
+    fn default() -> Self {
+        TmpFs::new()
+    }
+}
+
+fn empty_dir(parent: InodeId) -> InodeKind {
+    InodeKind::Directory {
+        entries: BTreeMap::new(),
+        parent,
+    }
+}
+
+fn file_type(kind: &InodeKind) -> FileType {
+    match kind {
+        InodeKind::File(_) => FileType::File,
+        InodeKind::Directory { .. } => FileType::Directory,
+        InodeKind::Symlink(_) => FileType::Symlink,
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> InodeId {
+        ROOT
+    }
+
+    fn lookup(&self, dir: InodeId, name: &str) -> Result<InodeId, FsError> {
+        let InodeKind::Directory { entries, parent } = &self.node(dir)?.kind else {
+            return Err(FsError::NotADirectory);
+        };
+        match name {
+            "." => Ok(dir),
+            ".." => Ok(*parent),
+            _ => entries.get(name).copied().ok_or(FsError::NotFound),
+        }
+    }
+
+    fn read(&self, inode: InodeId, offset: usize, buf: &mut [u8]) -> Result<usize, FsError> {
+        let node = self.node(inode)?;
+        node.accessed.store(crate::time::now(), Ordering::Relaxed);
+        match &node.kind {
+            InodeKind::File(data) => {
+                let start = offset.min(data.len());
+                let len = buf.len().min(data.len() - start);
+                buf[..len].copy_from_slice(&data[start..start + len]);
+                Ok(len)
+            }
+            _ => Err(FsError::NotAFile),
+        }
+    }
+
+    fn write(&mut self, inode: InodeId, offset: usize, data: &[u8]) -> Result<usize, FsError> {
+        let content = self.data_mut(inode)?;
+        let end = offset + data.len();
+        if content.len() < end {
+            content.resize(end, 0);
+        }
+        content[offset..end].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn truncate(&mut self, inode: InodeId, size: usize) -> Result<(), FsError> {
+        self.data_mut(inode)?.resize(size, 0);
+        Ok(())
+    }
+
+    fn readdir(&self, dir: InodeId) -> Result<Vec<DirEntry>, FsError> {
+        let mut result = Vec::new();
+        for (name, &inode) in self.entries(dir)? {
+            result.push(DirEntry {
+                name: name.clone(),
+                inode,
+                kind: file_type(&self.node(inode)?.kind),
+            });
+        }
+        Ok(result)
+    }
+
+    fn create(&mut self, dir: InodeId, name: &str, kind: FileType) -> Result<InodeId, FsError> {
+        match kind {
+            FileType::File => self.insert(dir, name, InodeKind::File(Vec::new()), DEFAULT_FILE_MODE),
+            FileType::Directory => self.insert(dir, name, empty_dir(dir), DEFAULT_DIR_MODE),
+            FileType::Symlink => Err(FsError::NotSupported), // use `symlink`
+            FileType::CharDevice => Err(FsError::NotSupported),
+        }
+    }
+
+    fn symlink(&mut self, dir: InodeId, name: &str, target: &str) -> Result<InodeId, FsError> {
+        self.insert(dir, name, InodeKind::Symlink(String::from(target)), 0o777)
+    }
+
+    fn readlink(&self, inode: InodeId) -> Result<String, FsError> {
+        match &self.node(inode)?.kind {
+            InodeKind::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidPath),
+        }
+    }
+
+    fn link(&mut self, dir: InodeId, name: &str, inode: InodeId) -> Result<(), FsError> {
+        validate_name(name)?;
+        if self.is_dir(inode)? {
+            return Err(FsError::NotAFile);
+        }
+        if self.entries(dir)?.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        self.entries_mut(dir)?.insert(String::from(name), inode);
+        self.node_mut(inode)?.nlink += 1;
+        self.node_mut(dir)?.touch();
+        Ok(())
+    }
+
+    fn unlink(&mut self, dir: InodeId, name: &str) -> Result<(), FsError> {
+        let child_id = self.lookup(dir, name)?;
+        if matches!(name, "." | "..") {
+            return Err(FsError::InvalidPath);
+        }
+        let is_dir = match &self.node(child_id)?.kind {
+            InodeKind::Directory { entries, .. } if !entries.is_empty() => {
+                return Err(FsError::DirectoryNotEmpty);
+            }
+            InodeKind::Directory { .. } => true,
+            _ => false,
+        };
+        self.entries_mut(dir)?.remove(name);
+        let parent = self.node_mut(dir)?;
+        parent.touch();
+        if is_dir {
+            parent.nlink -= 1;
+        }
+        let child = self.node_mut(child_id)?;
+        child.nlink = if is_dir { 0 } else { child.nlink - 1 };
+        Ok(())
+    }
+
+    fn rename(
+        &mut self,
+        old_dir: InodeId,
+        old_name: &str,
+        new_dir: InodeId,
+        new_name: &str,
+    ) -> Result<(), FsError> {
+        validate_name(old_name)?;
+        validate_name(new_name)?;
+        let node = self.lookup(old_dir, old_name)?;
+        let is_dir = self.is_dir(node)?;
+
+        // Unlink whatever `new_name` names now, if the kinds are compatible.
+        // Two names for the same file: nothing to do.
+        if let Some(&existing) = self.entries(new_dir)?.get(new_name) {
+            if existing == node {
+                return Ok(());
+            }
+            match (&self.node(existing)?.kind, is_dir) {
+                (InodeKind::Directory { entries, .. }, true) if !entries.is_empty() => {
+                    return Err(FsError::DirectoryNotEmpty);
+                }
+                (InodeKind::Directory { .. }, false) => return Err(FsError::NotAFile),
+                (_, true) if !self.is_dir(existing)? => return Err(FsError::NotADirectory),
+                _ => {}
+            }
+            self.unlink(new_dir, new_name)?;
+        }
+
+        self.entries_mut(old_dir)?.remove(old_name);
+        self.entries_mut(new_dir)?.insert(String::from(new_name), node);
+        if let InodeKind::Directory { parent, .. } = &mut self.node_mut(node)?.kind {
+            *parent = new_dir;
+        }
+        self.node_mut(old_dir)?.touch();
+        self.node_mut(new_dir)?.touch();
+        if is_dir && old_dir != new_dir {
+            self.node_mut(old_dir)?.nlink -= 1;
+            self.node_mut(new_dir)?.nlink += 1;
+        }
+        Ok(())
+    }
+
+    fn evict(&mut self, inode: InodeId) {
+        let unlinked = self.node(inode).is_ok_and(|node| node.nlink == 0);
+        if inode != ROOT && unlinked {
+            self.inodes.remove(&inode);
+        }
+    }
+
+    fn stat(&self, inode: InodeId) -> Result<Metadata, FsError> {
+        let node = self.node(inode)?;
+        let size = match &node.kind {
+            InodeKind::File(data) => data.len(),
+            InodeKind::Directory { entries, .. } => entries.len(),
+            InodeKind::Symlink(target) => target.len(),
+        };
+        Ok(Metadata {
+            inode,
+            kind: file_type(&node.kind),
+            size: size as u64,
+            mode: node.mode,
+            uid: node.uid,
+            gid: node.gid,
+            nlink: node.nlink,
+            created: node.created,
+            modified: node.modified,
+            accessed: node.accessed.load(Ordering::Relaxed),
+        })
+    }
+
+    fn set_mode(&mut self, inode: InodeId, mode: u16) -> Result<(), FsError> {
+        self.node_mut(inode)?.mode = mode & 0o7777;
+        Ok(())
+    }
+
+    fn set_owner(&mut self, inode: InodeId, uid: u32, gid: u32) -> Result<(), FsError> {
+        let node = self.node_mut(inode)?;
+        node.uid = uid;
+        node.gid = gid;
+        Ok(())
+    }
+
+    fn set_times(&mut self, inode: InodeId, accessed: u64, modified: u64) -> Result<(), FsError> {
+        let node = self.node_mut(inode)?;
+        node.accessed.store(accessed, Ordering::Relaxed);
+        node.modified = modified;
+        Ok(())
+    }
+}
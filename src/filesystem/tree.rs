@@ -0,0 +1,140 @@
+/// Whole-tree operations on the VFS: rename/move, recursive remove and
+/// recursive copy.
+///
+/// Renames stay within one mounted filesystem and are done by the driver
+/// in one step. Removal and copying walk the tree through the VFS, so a
+/// copy can cross mounts; removal stops at a mount point (`Busy`). Neither
+/// follows symbolic links inside the tree.
+
+use super::{FileType, FsError, VNode, Vfs};
+
+impl Vfs {
+    /// True if `node` is `ancestor` or lies somewhere below it.
+    pub fn is_within(&self, node: VNode, ancestor: VNode) -> bool {
+        let root = self.root();
+        let mut current = node;
+        loop {
+            if current == ancestor {
+                return true;
+            }
+            if current == root {
+                return false;
+            }
+            match self.parent(current) {
+                Ok(parent) => current = parent,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Move `old_path` to `new_path` (both relative to `cwd`). An existing
+    /// file, or empty directory, at `new_path` is replaced.
+    pub fn rename(&mut self, old_path: &str, new_path: &str, cwd: VNode) -> Result<(), FsError> {
+        self.reap();
+        let (old_parent, old_name) = self.resolve_parent(old_path, cwd)?;
+        let (new_parent, new_name) = self.resolve_parent(new_path, cwd)?;
+        let source = self.lookup(old_parent, &old_name)?;
+        if source.mount != old_parent.mount {
+            return Err(FsError::Busy); // a mount point
+        }
+        if new_parent.mount != old_parent.mount {
+            return Err(FsError::CrossDevice);
+        }
+        if self.is_directory(source) && self.is_within(new_parent, source) {
+            return Err(FsError::InvalidPath);
+        }
+        let replaced = match self.lookup(new_parent, &new_name) {
+            Ok(node) if node == source => return Ok(()),
+            Ok(node) if node.mount != new_parent.mount => return Err(FsError::Busy),
+            Ok(node) => Some(node),
+            Err(FsError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        self.fs_mut(old_parent)?
+            .rename(old_parent.inode, &old_name, new_parent.inode, &new_name)?;
+        match replaced {
+            Some(node) => self.release(node),
+            None => Ok(()),
+        }
+    }
+
+    /// Remove `path` and, if it is a directory, everything below it.
+    pub fn remove_all(&mut self, path: &str, cwd: VNode) -> Result<(), FsError> {
+        self.reap();
+        let (parent, name) = self.resolve_parent(path, cwd)?;
+        self.remove_tree(parent, &name)
+    }
+
+    fn remove_tree(&mut self, parent: VNode, name: &str) -> Result<(), FsError> {
+        let target = self.lookup(parent, name)?;
+        if target.mount != parent.mount {
+            return Err(FsError::Busy);
+        }
+        if self.is_directory(target) {
+            for entry in self.list_dir(target)? {
+                self.remove_tree(target, &entry.name)?;
+            }
+        }
+        self.remove_entry(parent, name)
+    }
+
+    /// Copy the file or directory tree at `src` to `dst` (both relative
+    /// to `cwd`). Modes are copied; an existing file at `dst` is
+    /// overwritten, anything else there is an error.
+    pub fn copy(&mut self, src: &str, dst: &str, cwd: VNode) -> Result<(), FsError> {
+        let source = self.resolve_path(src, cwd)?;
+        let (parent, name) = self.resolve_parent(dst, cwd)?;
+        if self.is_directory(source) && self.is_within(parent, source) {
+            return Err(FsError::InvalidPath);
+        }
+        self.copy_tree(source, parent, &name)
+    }
+
+    fn copy_tree(&mut self, source: VNode, parent: VNode, name: &str) -> Result<(), FsError> {
+        let meta = self.stat(source)?;
+        let existing = match self.lookup(parent, name) {
+            Ok(node) => Some(node),
+            Err(FsError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        let copy = match (meta.kind, existing) {
+            (FileType::File, Some(node)) if self.stat(node)?.kind == FileType::File => {
+                if node == source {
+                    return Err(FsError::AlreadyExists);
+                }
+                self.truncate(node, 0)?;
+                node
+            }
+            (_, Some(_)) => return Err(FsError::AlreadyExists),
+            // Links are copied as links, not followed.
+            (FileType::Symlink, None) => {
+                let target = self.fs(source)?.readlink(source.inode)?;
+                self.fs_mut(parent)?.symlink(parent.inode, name, &target)?;
+                return Ok(());
+            }
+            (kind, None) => {
+                let inode = self.fs_mut(parent)?.create(parent.inode, name, kind)?;
+                VNode { mount: parent.mount, inode }
+            }
+        };
+        // Not every driver keeps modes; the copy is still useful without.
+        match self.set_mode(copy, meta.mode) {
+            Ok(()) | Err(FsError::NotSupported) => {}
+            Err(e) => return Err(e),
+        }
+        match meta.kind {
+            FileType::File => {
+                let data = self.read_file(source)?;
+                self.write(copy, 0, &data)?;
+            }
+            FileType::Directory => {
+                for entry in self.list_dir(source)? {
+                    let child = self.lookup(source, &entry.name)?;
+                    self.copy_tree(child, copy, &entry.name)?;
+                }
+            }
+            FileType::Symlink | FileType::CharDevice => {}
+        }
+        Ok(())
+    }
+}
@@ -7,13 +7,32 @@
 /// The IST lets us define separate stacks for specific interrupts.
 /// This is critical for double faults — if a stack overflow causes a
 /// page fault that then causes a double fault, we need a known-good stack.
+///
+/// User code runs at CPL 3 with the user code/data segments. When an
+/// interrupt arrives in ring 3 the CPU switches to the stack in the TSS's
+/// `privilege_stack_table[0]`, which the scheduler points at the running
+/// thread's kernel stack on every switch.
+///
+/// Descriptor order (selectors are hard-coded in the scheduler):
+///   0x08 kernel code, 0x10 kernel data, 0x18 TSS (two slots),
+///   0x28 user data, 0x30 user code
 
 use spin::Once;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
-use x86_64::VirtAddr;
+use x86_64::{PrivilegeLevel, VirtAddr};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// Page faults get their own stack so a thread overflowing into its guard
+/// page can still be reported (the CPU can't push onto the faulting stack).
+pub const PAGE_FAULT_IST_INDEX: u16 = 1;
+
+/// Segment selectors, fixed by the descriptor order in `init`.
+pub const KERNEL_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+pub const KERNEL_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(2, PrivilegeLevel::Ring0);
+/// User segment selectors (RPL 3). `sysret` requires data right below code.
+pub const USER_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(5, PrivilegeLevel::Ring3);
+pub const USER_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(6, PrivilegeLevel::Ring3);
 
 const STACK_SIZE: usize = 4096 * 5;
 
@@ -21,8 +40,11 @@ const STACK_SIZE: usize = 4096 * 5;
 struct Stack(#[allow(dead_code)] [u8; STACK_SIZE]);
 
 static mut DOUBLE_FAULT_STACK: Stack = Stack([0; STACK_SIZE]);
+static mut PAGE_FAULT_STACK: Stack = Stack([0; STACK_SIZE]);
 
-static TSS: Once<TaskStateSegment> = Once::new();
+// Mutable because the ring-0 stack pointer changes on every thread switch.
+// Only written by `init` and `set_kernel_stack` with interrupts disabled.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 static GDT: Once<(GlobalDescriptorTable, Selectors)> = Once::new();
 
 struct Selectors {
@@ -32,20 +54,26 @@ struct Selectors {
 }
 
 pub fn init() {
-    let tss = TSS.call_once(|| {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+    let tss = &raw mut TSS;
+    unsafe {
+        (*tss).interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
             let stack_start = VirtAddr::from_ptr(&raw const DOUBLE_FAULT_STACK);
             stack_start + STACK_SIZE as u64
         };
-        tss
-    });
+        (*tss).interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+            let stack_start = VirtAddr::from_ptr(&raw const PAGE_FAULT_STACK);
+            stack_start + STACK_SIZE as u64
+        };
+    }
 
     let (gdt, selectors) = GDT.call_once(|| {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.append(Descriptor::kernel_code_segment());
         let data_selector = gdt.append(Descriptor::kernel_data_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(tss));
+        let tss_selector = gdt.append(unsafe { Descriptor::tss_segment_unchecked(tss) });
+        // User data must directly precede user code for `sysret`.
+        gdt.append(Descriptor::user_data_segment());
+        gdt.append(Descriptor::user_code_segment());
         (
             gdt,
             Selectors {
@@ -68,3 +96,11 @@ pub fn init() {
         load_tss(selectors.tss_selector);
     }
 }
+
+/// Set the stack the CPU switches to when an interrupt or exception
+/// arrives while running in ring 3.
+pub fn set_kernel_stack(top: VirtAddr) {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        TSS.privilege_stack_table[0] = top;
+    });
+}
@@ -8,19 +8,24 @@
 /// IDT entries 8-15 to 32-47 to avoid colliding with CPU exceptions.
 
 use crate::gdt;
-use crate::hlt_loop;
+use crate::memory::address_space::is_user_range;
+use crate::memory::vma::FaultOutcome;
 use core::sync::atomic::{AtomicU64, Ordering};
 use pic8259::ChainedPics;
 use spin::Mutex;
 use x86_64::instructions::port::Port;
+use x86_64::registers::rflags::RFlags;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
-use x86_64::VirtAddr;
+use x86_64::{PrivilegeLevel, VirtAddr};
 
 pub static TICK_COUNT: AtomicU64 = AtomicU64::new(0);
 
+/// Times each IDT vector has been taken, for `/proc/interrupts`.
+static INTERRUPT_COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
 // 8254 PIT constants
 const PIT_OSCILLATOR_HZ: u32 = 1_193_182;
-const PIT_TARGET_HZ: u32 = 100; // 10ms timeslice
+pub const PIT_TARGET_HZ: u32 = 100; // 10ms timeslice
 const PIT_COMMAND_PORT: u16 = 0x43;
 const PIT_CHANNEL0_PORT: u16 = 0x40;
 const PS2_DATA_PORT: u16 = 0x60;
@@ -48,6 +53,28 @@ pub enum InterruptIndex {
     Keyboard,
 }
 
+/// Software interrupt a thread raises to give up the CPU at once.
+pub const YIELD_VECTOR: u8 = 0x81;
+
+/// Vectors with a handler installed, and what they are.
+pub const HANDLED_VECTORS: [(u8, &str); 6] = [
+    (3, "breakpoint"),
+    (8, "double fault"),
+    (14, "page fault"),
+    (InterruptIndex::Timer as u8, "timer"),
+    (InterruptIndex::Keyboard as u8, "keyboard"),
+    (YIELD_VECTOR, "yield"),
+];
+
+fn count_interrupt(vector: u8) {
+    INTERRUPT_COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// How many times `vector` has been taken since boot.
+pub fn interrupt_count(vector: u8) -> u64 {
+    INTERRUPT_COUNTS[vector as usize].load(Ordering::Relaxed)
+}
+
 // --- Scancode queue for keyboard input ---
 
 pub static SCANCODE_QUEUE: Mutex<ScancodeQueue> = Mutex::new(ScancodeQueue::new());
@@ -101,12 +128,20 @@ pub fn init_idt() {
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        unsafe {
+            idt.page_fault
+                .set_handler_fn(page_fault_handler)
+                .set_stack_index(gdt::PAGE_FAULT_IST_INDEX);
+        }
         unsafe {
             idt[InterruptIndex::Timer as u8]
                 .set_handler_addr(VirtAddr::new(crate::task::context::timer_isr_addr()));
         }
         idt[InterruptIndex::Keyboard as u8].set_handler_fn(keyboard_interrupt_handler);
+        unsafe {
+            idt[YIELD_VECTOR]
+                .set_handler_addr(VirtAddr::new(crate::task::context::yield_isr_addr()));
+        }
         idt
     });
     idt.load();
@@ -115,6 +150,7 @@ pub fn init_idt() {
 // --- CPU Exception Handlers ---
 
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    count_interrupt(3);
     crate::println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
@@ -122,20 +158,85 @@ extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    count_interrupt(8);
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2;
 
-    crate::println!("EXCEPTION: PAGE FAULT");
-    crate::println!("Accessed Address: {:?}", Cr2::read());
-    crate::println!("Error Code: {:?}", error_code);
-    crate::println!("{:#?}", stack_frame);
-    hlt_loop();
+    count_interrupt(14);
+    let addr = Cr2::read();
+
+    if let Ok(fault_addr) = addr {
+        let from_user = stack_frame.code_segment.rpl() == PrivilegeLevel::Ring3;
+        let guard_hit = crate::task::stack::is_guard_hit(fault_addr);
+        let reason = if guard_hit {
+            "stack overflow (guard page hit)"
+        } else if is_user_range(fault_addr.as_u64(), 1) {
+            match crate::task::scheduler::current_address_space() {
+                Some(space) => match space.handle_fault(fault_addr, error_code) {
+                    FaultOutcome::Resolved => return,
+                    FaultOutcome::Violation(reason) => reason,
+                    FaultOutcome::Unmapped => "access to unmapped user address",
+                },
+                None => "user address without an address space",
+            }
+        } else {
+            match crate::memory::vma::handle_page_fault(fault_addr, error_code) {
+                FaultOutcome::Resolved => return,
+                FaultOutcome::Violation(reason) => reason,
+                FaultOutcome::Unmapped => "access to unmapped address",
+            }
+        };
+
+        // Kill just the faulting thread by resuming it at `thread_fault_exit`
+        // on the top of its own stack. A kernel fault with interrupts off
+        // came from an interrupt handler or a critical section holding spin
+        // locks, and one outside any thread has nothing to kill; panic then.
+        let interrupts_on = stack_frame.cpu_flags.contains(RFlags::INTERRUPT_FLAG);
+        let thread = (from_user || guard_hit || interrupts_on)
+            .then(crate::task::scheduler::current_thread_for_fault)
+            .flatten();
+        if let Some(thread) = thread {
+            crate::println!(
+                "[T:{}] PID {} killed: {} at {:#x}",
+                thread.name(),
+                thread.pid,
+                reason,
+                fault_addr.as_u64()
+            );
+            unsafe {
+                stack_frame.as_mut().update(|frame| {
+                    frame.instruction_pointer = VirtAddr::new(
+                        crate::task::scheduler::thread_fault_exit as *const () as u64,
+                    );
+                    // Entered as if called: RSP + 8 must be 16-byte aligned.
+                    frame.stack_pointer = VirtAddr::new(thread.stack_top - 8);
+                    frame.cpu_flags |= RFlags::INTERRUPT_FLAG;
+                    // The fault may have come from ring 3; exit in ring 0.
+                    frame.code_segment = gdt::KERNEL_CODE_SELECTOR;
+                    frame.stack_segment = gdt::KERNEL_DATA_SELECTOR;
+                });
+            }
+            return;
+        }
+        panic!(
+            "EXCEPTION: PAGE FAULT: {} at {:#x}\nError Code: {:?}\n{:#?}",
+            reason,
+            fault_addr.as_u64(),
+            error_code,
+            stack_frame
+        );
+    }
+
+    panic!(
+        "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
+        addr, error_code, stack_frame
+    );
 }
 
 // --- Hardware Interrupt Handlers ---
@@ -145,6 +246,7 @@ extern "x86-interrupt" fn page_fault_handler(
 #[no_mangle]
 extern "C" fn timer_tick_handler(frame: *mut crate::task::context::InterruptFrame) -> *mut crate::task::context::InterruptFrame {
     TICK_COUNT.fetch_add(1, Ordering::Relaxed);
+    count_interrupt(InterruptIndex::Timer as u8);
 
     unsafe {
         PICS.lock()
@@ -161,7 +263,23 @@ extern "C" fn timer_tick_handler(frame: *mut crate::task::context::InterruptFram
     frame
 }
 
+/// Called from the raw yield ISR assembly stub when a thread gives up the
+/// CPU. Like the timer handler, but no tick has passed.
+#[no_mangle]
+extern "C" fn yield_handler(frame: *mut crate::task::context::InterruptFrame) -> *mut crate::task::context::InterruptFrame {
+    count_interrupt(YIELD_VECTOR);
+
+    if crate::task::scheduler::is_enabled() {
+        if let Some(new_frame) = crate::task::scheduler::try_switch(frame) {
+            return new_frame;
+        }
+    }
+
+    frame
+}
+
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    count_interrupt(InterruptIndex::Keyboard as u8);
     let mut port = Port::new(PS2_DATA_PORT);
     let scancode: u8 = unsafe { port.read() };
 
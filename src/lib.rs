@@ -1,5 +1,6 @@
 #![no_std]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
 #![test_runner(test_runner)]
@@ -9,21 +10,26 @@ extern crate alloc;
 
 pub mod allocator;
 pub mod console;
+pub mod drivers;
 pub mod filesystem;
 pub mod font;
 pub mod framebuffer;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
+pub mod ramdisk;
 pub mod serial;
 pub mod shell;
+pub mod syscall;
 pub mod task;
+pub mod time;
 pub mod vga_buffer;
 
 /// Initialize GDT, IDT, PICs, and enable hardware interrupts.
 pub fn init() {
     gdt::init();
     interrupts::init_idt();
+    syscall::init();
     unsafe { interrupts::PICS.lock().initialize() };
     x86_64::instructions::interrupts::enable();
 }
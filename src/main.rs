@@ -82,16 +82,72 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     );
 
     let mut mapper = unsafe { kernel::memory::init(phys_mem_offset) };
-    let mut frame_allocator =
-        unsafe { kernel::memory::BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    let mut frame_allocator = unsafe {
+        kernel::memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    kernel::serial_println!(
+        "Frame allocator: {} of {} frames free",
+        frame_allocator.free_frames(),
+        frame_allocator.total_frames()
+    );
 
     kernel::allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap initialization failed");
+    kernel::memory::install(mapper, frame_allocator);
+    kernel::memory::vma::init();
+    kernel::memory::address_space::init();
     kernel::serial_println!("Heap initialized");
 
+    kernel::time::init();
+    kernel::serial_println!("Time: {}", kernel::time::DateTime::from_unix(kernel::time::now()));
+
+    kernel::drivers::init();
+    kernel::serial_println!("Block devices: {}", kernel::drivers::block::devices().len());
+
     kernel::filesystem::init();
+    kernel::task::user::install_programs();
     kernel::serial_println!("Filesystem initialized");
 
+    if let Some(addr) = boot_info.ramdisk_addr.into_option() {
+        let image = unsafe {
+            core::slice::from_raw_parts(addr as *const u8, boot_info.ramdisk_len as usize)
+        };
+        if kernel::filesystem::snapshot::is_snapshot(image) {
+            match kernel::filesystem::snapshot::load_image(image) {
+                Ok(stats) => kernel::serial_println!(
+                    "Ramdisk snapshot: {} files, {} directories, {} bytes",
+                    stats.files,
+                    stats.directories,
+                    stats.bytes
+                ),
+                Err(e) => kernel::println!("WARNING: ramdisk snapshot not restored: {}", e),
+            }
+        } else {
+            match kernel::ramdisk::load(image) {
+                Ok(stats) => kernel::serial_println!(
+                    "Ramdisk: {} files, {} directories, {} bytes",
+                    stats.files,
+                    stats.directories,
+                    stats.bytes
+                ),
+                Err(e) => kernel::println!("WARNING: ramdisk not loaded: {}", e),
+            }
+        }
+    }
+
+    if let Some(device) = kernel::filesystem::snapshot::find() {
+        match kernel::filesystem::snapshot::load(&device) {
+            Ok(stats) => kernel::serial_println!(
+                "Snapshot on {}: {} files, {} directories, {} bytes",
+                device.name(),
+                stats.files,
+                stats.directories,
+                stats.bytes
+            ),
+            Err(e) => kernel::println!("WARNING: snapshot on {} not restored: {}", device.name(), e),
+        }
+    }
+
     kernel::interrupts::init_pit();
     kernel::serial_println!("PIT configured at 100 Hz");
 
@@ -99,6 +155,7 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     kernel::serial_println!("Process table initialized");
 
     kernel::task::scheduler::init();
+    kernel::drivers::cache::start_writeback();
 
     kernel::println!("All subsystems initialized.");
 
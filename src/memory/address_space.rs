@@ -0,0 +1,374 @@
+/// Per-process address spaces.
+///
+/// Every address space has its own PML4. The kernel's PML4 entries are
+/// copied into it, so all kernel mappings (code, heap, thread stacks,
+/// physical memory window) are shared through the same lower-level
+/// tables, while the user window below gets private page tables:
+///
+///   PML4[128..136]  user window (0x4000_0000_0000 .. 0x4400_0000_0000)
+///   everything else shared with the kernel
+///
+/// Kernel regions that may grow after an address space is created get
+/// their PML4 entries pre-allocated by `init`, otherwise a later kernel
+/// mapping would only show up in the kernel's own PML4.
+///
+/// The scheduler switches CR3 to a thread's address space when it runs
+/// the thread. Dropping the last reference unmaps every user page and
+/// returns the frames and page tables to the frame allocator.
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use spin::Mutex;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame,
+    Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::vma::{FaultOutcome, VmaError, VmaFlags, VmaKind, VmaSet};
+
+/// Lowest user-space address.
+pub const USER_SPACE_START: u64 = 0x_4000_0000_0000;
+/// End (exclusive) of user space.
+pub const USER_SPACE_END: u64 = 0x_4400_0000_0000;
+
+const USER_PML4_FIRST: usize = (USER_SPACE_START >> 39) as usize;
+const USER_PML4_END: usize = (USER_SPACE_END >> 39) as usize;
+
+const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug)]
+pub enum AddressSpaceError {
+    OutOfMemory,
+    NotUserRange,
+    Fault(&'static str),
+    Vma(VmaError),
+}
+
+impl core::fmt::Display for AddressSpaceError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            AddressSpaceError::OutOfMemory => write!(f, "out of physical memory"),
+            AddressSpaceError::NotUserRange => write!(f, "range is outside user space"),
+            AddressSpaceError::Fault(reason) => write!(f, "{}", reason),
+            AddressSpaceError::Vma(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<VmaError> for AddressSpaceError {
+    fn from(e: VmaError) -> Self {
+        AddressSpaceError::Vma(e)
+    }
+}
+
+/// True if `[start, start + len)` lies entirely in the user window.
+pub fn is_user_range(start: u64, len: u64) -> bool {
+    start >= USER_SPACE_START
+        && start
+            .checked_add(len)
+            .is_some_and(|end| end <= USER_SPACE_END)
+}
+
+pub struct AddressSpace {
+    pml4: PhysFrame,
+    /// User areas. The lock also serializes changes to the private tables.
+    vmas: Mutex<VmaSet>,
+}
+
+impl AddressSpace {
+    /// Create an address space sharing the kernel half of the active PML4.
+    pub fn new() -> Result<Arc<AddressSpace>, AddressSpaceError> {
+        let offset = super::physical_memory_offset();
+        let kernel_pml4 = super::kernel_pml4();
+        let pml4 = super::with_mapper(|_, frames| frames.allocate_frame())
+            .flatten()
+            .ok_or(AddressSpaceError::OutOfMemory)?;
+
+        unsafe {
+            let kernel: &PageTable = &*(offset + kernel_pml4.start_address().as_u64()).as_ptr();
+            let table: &mut PageTable = &mut *(offset + pml4.start_address().as_u64()).as_mut_ptr();
+            table.zero();
+            for (index, entry) in kernel.iter().enumerate() {
+                if !(USER_PML4_FIRST..USER_PML4_END).contains(&index) {
+                    table[index] = entry.clone();
+                } else {
+                    // Not copied, so a kernel mapping here would be missing
+                    // from every address space.
+                    debug_assert!(entry.is_unused(), "kernel mapping in user PML4 entry {}", index);
+                }
+            }
+        }
+
+        Ok(Arc::new(AddressSpace {
+            pml4,
+            vmas: Mutex::new(VmaSet::new()),
+        }))
+    }
+
+    /// Physical frame of this address space's PML4 (the CR3 value).
+    pub fn pml4_frame(&self) -> PhysFrame {
+        self.pml4
+    }
+
+    /// Load this address space into CR3.
+    ///
+    /// # Safety
+    /// The caller must keep the address space alive while it's active.
+    pub unsafe fn activate(&self) {
+        unsafe { Cr3::write(self.pml4, Cr3Flags::empty()) };
+    }
+
+    /// Build a mapper over this address space's page tables.
+    ///
+    /// # Safety
+    /// The caller must hold `self.vmas` so no one else edits the tables.
+    unsafe fn mapper(&self) -> OffsetPageTable<'_> {
+        let offset = super::physical_memory_offset();
+        let table: &mut PageTable =
+            unsafe { &mut *(offset + self.pml4.start_address().as_u64()).as_mut_ptr() };
+        unsafe { OffsetPageTable::new(table, offset) }
+    }
+
+    /// Reserve a demand-paged user area. Nothing is mapped until touched.
+    pub fn reserve(
+        &self,
+        start: VirtAddr,
+        len: u64,
+        flags: VmaFlags,
+        name: &str,
+    ) -> Result<(), AddressSpaceError> {
+        if !is_user_range(start.as_u64(), len) {
+            return Err(AddressSpaceError::NotUserRange);
+        }
+        let flags = flags | VmaFlags::USER;
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            self.vmas
+                .lock()
+                .reserve(start, len, flags, VmaKind::Anonymous, name)
+        })?;
+        Ok(())
+    }
+
+    /// Reserve a user area and back every page immediately with a zeroed
+    /// frame.
+    pub fn map_user(
+        &self,
+        start: VirtAddr,
+        len: u64,
+        flags: VmaFlags,
+        name: &str,
+    ) -> Result<(), AddressSpaceError> {
+        self.reserve(start, len, flags, name)?;
+        let first = Page::<Size4KiB>::containing_address(start);
+        for page in Page::range(first, first + len.div_ceil(PAGE_SIZE)) {
+            if self.populate(page.start_address()) != FaultOutcome::Resolved {
+                let _ = self.unmap(start);
+                return Err(AddressSpaceError::OutOfMemory);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the area starting at `start`, freeing its frames.
+    pub fn unmap(&self, start: VirtAddr) -> Result<(), AddressSpaceError> {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut vmas = self.vmas.lock();
+            let vma = vmas.remove(start).ok_or(VmaError::NotFound)?;
+            let mut mapper = unsafe { self.mapper() };
+            let mut frames = super::FRAME_ALLOCATOR.lock();
+            let frames = frames.as_mut().ok_or(AddressSpaceError::OutOfMemory)?;
+            VmaSet::release_pages(&vma, &mut mapper, frames);
+            Ok(())
+        })
+    }
+
+    /// Resolve a fault on a user address against this address space's
+    /// areas. Only try-locks, since it runs in the page fault handler.
+    pub fn handle_fault(&self, addr: VirtAddr, error_code: PageFaultErrorCode) -> FaultOutcome {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let Some(mut vmas) = self.vmas.try_lock() else {
+                return FaultOutcome::Unmapped;
+            };
+            let Some(mut frames) = super::FRAME_ALLOCATOR.try_lock() else {
+                return FaultOutcome::Unmapped;
+            };
+            let Some(frames) = frames.as_mut() else {
+                return FaultOutcome::Unmapped;
+            };
+            let mut mapper = unsafe { self.mapper() };
+            let offset = super::physical_memory_offset();
+            vmas.handle_fault(addr, error_code, &mut mapper, frames, offset)
+        })
+    }
+
+    /// Back the page containing `addr` regardless of the area's
+    /// permissions. Unlike `handle_fault` this waits for the locks, so it
+    /// is not for the fault handler.
+    fn populate(&self, addr: VirtAddr) -> FaultOutcome {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut vmas = self.vmas.lock();
+            let mut frames = super::FRAME_ALLOCATOR.lock();
+            let Some(frames) = frames.as_mut() else {
+                return FaultOutcome::Unmapped;
+            };
+            let mut mapper = unsafe { self.mapper() };
+            let offset = super::physical_memory_offset();
+            vmas.populate(addr, &mut mapper, frames, offset)
+        })
+    }
+
+    /// Copy `data` into this address space at `addr`, backing pages as
+    /// needed. Writes through the physical memory mapping, so it works
+    /// without activating the space and ignores the area's permissions
+    /// (used to fill read-only code pages).
+    pub fn write_user(&self, addr: VirtAddr, data: &[u8]) -> Result<(), AddressSpaceError> {
+        if !is_user_range(addr.as_u64(), data.len() as u64) {
+            return Err(AddressSpaceError::NotUserRange);
+        }
+        let offset = super::physical_memory_offset();
+        let mut done = 0;
+        while done < data.len() {
+            let at = addr + done as u64;
+            let chunk = (PAGE_SIZE - at.as_u64() % PAGE_SIZE).min((data.len() - done) as u64);
+            let phys = match self.translate(at) {
+                Some(phys) => phys,
+                None => match self.populate(at) {
+                    FaultOutcome::Resolved => self.translate(at).ok_or(AddressSpaceError::OutOfMemory)?,
+                    FaultOutcome::Violation(reason) => return Err(AddressSpaceError::Fault(reason)),
+                    FaultOutcome::Unmapped => return Err(VmaError::NotFound.into()),
+                },
+            };
+            unsafe {
+                let dst: *mut u8 = (offset + phys.as_u64()).as_mut_ptr();
+                core::ptr::copy_nonoverlapping(data[done..].as_ptr(), dst, chunk as usize);
+            }
+            done += chunk as usize;
+        }
+        Ok(())
+    }
+
+    fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let _vmas = self.vmas.lock();
+            unsafe { self.mapper() }.translate_addr(addr)
+        })
+    }
+
+    /// Page table flags of the page containing `addr`, if it is mapped.
+    pub fn translate_flags(&self, addr: VirtAddr) -> Option<PageTableFlags> {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let _vmas = self.vmas.lock();
+            let mapper = unsafe { self.mapper() };
+            match mapper.translate(addr) {
+                TranslateResult::Mapped { flags, .. } => Some(flags),
+                _ => None,
+            }
+        })
+    }
+
+    /// True if every byte of `[addr, addr + len)` lies in user areas that
+    /// allow `flags`. Used to validate pointers passed in by user code.
+    pub fn check_access(&self, addr: VirtAddr, len: u64, flags: VmaFlags) -> bool {
+        if !is_user_range(addr.as_u64(), len) {
+            return false;
+        }
+        let end = addr + len;
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let vmas = self.vmas.lock();
+            let mut at = addr;
+            while at < end {
+                match vmas.find(at) {
+                    Some(vma) if vma.flags.contains(flags) => at = vma.end,
+                    _ => return false,
+                }
+            }
+            true
+        })
+    }
+
+    /// Number of user areas and pages currently backed by frames.
+    pub fn usage(&self) -> (usize, usize) {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let vmas = self.vmas.lock();
+            (
+                vmas.iter().count(),
+                vmas.iter().map(|vma| vma.resident).sum(),
+            )
+        })
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let offset = super::physical_memory_offset();
+        let table = |frame: PhysFrame| -> &mut PageTable {
+            unsafe { &mut *(offset + frame.start_address().as_u64()).as_mut_ptr() }
+        };
+
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut frames = super::FRAME_ALLOCATOR.lock();
+            let Some(frames) = frames.as_mut() else {
+                return;
+            };
+            let pml4 = table(self.pml4);
+            for pml4_entry in pml4.iter_mut().take(USER_PML4_END).skip(USER_PML4_FIRST) {
+                let Ok(pdpt_frame) = pml4_entry.frame() else {
+                    continue;
+                };
+                for pdpt_entry in table(pdpt_frame).iter_mut() {
+                    let Ok(pd_frame) = pdpt_entry.frame() else {
+                        continue;
+                    };
+                    for pd_entry in table(pd_frame).iter_mut() {
+                        let Ok(pt_frame) = pd_entry.frame() else {
+                            continue;
+                        };
+                        for pt_entry in table(pt_frame).iter_mut() {
+                            if let Ok(frame) = pt_entry.frame() {
+                                unsafe { frames.deallocate_frame(frame) };
+                            }
+                        }
+                        unsafe { frames.deallocate_frame(pt_frame) };
+                    }
+                    unsafe { frames.deallocate_frame(pd_frame) };
+                }
+                unsafe { frames.deallocate_frame(pdpt_frame) };
+            }
+            unsafe { frames.deallocate_frame(self.pml4) };
+        });
+    }
+}
+
+/// Make sure the kernel PML4 has entries for every kernel region that may
+/// get new mappings later, so address spaces created now share them.
+pub fn init() {
+    let regions = [
+        crate::allocator::HEAP_START as u64,
+        crate::task::stack::STACK_REGION_START,
+        super::vma::VMA_REGION_START,
+    ];
+    super::with_mapper(|mapper, frames| {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let offset = mapper.phys_offset();
+        for start in regions {
+            let index = ((start >> 39) & 0x1FF) as usize;
+            let entry = &mut mapper.level_4_table_mut()[index];
+            if entry.is_unused() {
+                let frame = frames
+                    .allocate_frame()
+                    .expect("out of memory for kernel PDPT");
+                unsafe {
+                    let pdpt: &mut PageTable =
+                        &mut *(offset + frame.start_address().as_u64()).as_mut_ptr();
+                    pdpt.zero();
+                }
+                entry.set_frame(frame, flags);
+            }
+        }
+    });
+}
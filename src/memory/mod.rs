@@ -0,0 +1,328 @@
+/// Virtual memory management: page tables and frame allocation.
+///
+/// x86_64 uses 4-level page tables to translate virtual to physical addresses:
+///   PML4 → PDPT → PD → PT → Physical Frame
+///
+/// Each table has 512 entries covering progressively smaller regions:
+///   PML4: 512 GiB, PDPT: 1 GiB, PD: 2 MiB, PT: 4 KiB
+///
+/// CR3 register points to the physical address of the PML4 table.
+/// The bootloader maps all physical memory at a known virtual offset.
+///
+/// Reserved virtual ranges and demand paging live in `vma`; per-process
+/// page tables live in `address_space`.
+
+pub mod address_space;
+pub mod vma;
+
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, OffsetPageTable, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Kernel page table mapper, available once `install` has been called.
+pub static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+
+/// Physical frame allocator, available once `install` has been called.
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+static KERNEL_PML4: AtomicU64 = AtomicU64::new(0);
+/// Set while a `with_mapper` closure runs.
+static IN_MAPPER: AtomicBool = AtomicBool::new(false);
+
+/// # Safety
+/// The complete physical memory must be mapped at `physical_memory_offset`.
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let level_4_table = unsafe { active_level_4_table(physical_memory_offset) };
+    unsafe { OffsetPageTable::new(level_4_table, physical_memory_offset) }
+}
+
+/// Hand the mapper and frame allocator over to the kernel-wide globals so
+/// the heap and later subsystems can map memory on demand.
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BitmapFrameAllocator) {
+    PHYSICAL_MEMORY_OFFSET.store(mapper.phys_offset().as_u64(), Ordering::Relaxed);
+    let (level_4_table_frame, _) = x86_64::registers::control::Cr3::read();
+    KERNEL_PML4.store(level_4_table_frame.start_address().as_u64(), Ordering::Relaxed);
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        *MAPPER.lock() = Some(mapper);
+        *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    });
+}
+
+/// Virtual address where the bootloader mapped all of physical memory.
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed))
+}
+
+/// The kernel's own PML4, active whenever no process address space is.
+pub fn kernel_pml4() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_PML4.load(Ordering::Relaxed)))
+}
+
+/// Run `f` with the global mapper and frame allocator locked, interrupts
+/// disabled. Returns `None` if `install` hasn't been called yet.
+///
+/// `f` must not allocate on the heap: heap growth itself takes these locks.
+/// The allocator asserts this in debug builds, and grows the heap through
+/// `try_with_mapper`, so in release builds such an allocation fails rather
+/// than deadlocking.
+pub fn with_mapper<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BitmapFrameAllocator) -> R,
+) -> Option<R> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frames = FRAME_ALLOCATOR.lock();
+        let (mapper, frames) = (mapper.as_mut()?, frames.as_mut()?);
+        IN_MAPPER.store(true, Ordering::Relaxed);
+        let result = f(mapper, frames);
+        IN_MAPPER.store(false, Ordering::Relaxed);
+        Some(result)
+    })
+}
+
+/// Like `with_mapper`, but returns `None` at once if either lock is
+/// already held instead of waiting for it.
+pub fn try_with_mapper<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BitmapFrameAllocator) -> R,
+) -> Option<R> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.try_lock()?;
+        let mut frames = FRAME_ALLOCATOR.try_lock()?;
+        Some(f(mapper.as_mut()?, frames.as_mut()?))
+    })
+}
+
+/// True while a `with_mapper` closure runs.
+pub fn in_with_mapper() -> bool {
+    IN_MAPPER.load(Ordering::Relaxed)
+}
+
+unsafe fn active_level_4_table(
+    physical_memory_offset: VirtAddr,
+) -> &'static mut x86_64::structures::paging::PageTable {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut x86_64::structures::paging::PageTable = virt.as_mut_ptr();
+
+    unsafe { &mut *page_table_ptr }
+}
+
+/// Physical frame allocator backed by a bitmap with one bit per 4 KiB frame.
+///
+/// A set bit means the frame is in use (allocated, reserved by firmware, or
+/// holding the bitmap itself). The bitmap lives in the first usable region
+/// large enough to hold it and is accessed through the physical memory
+/// mapping, so the allocator works before the heap exists.
+///
+/// Allocation scans one 64-bit word at a time starting from a hint, so the
+/// common case skips fully used words quickly. Frames can be returned with
+/// `FrameDeallocator`, and runs of contiguous frames can be requested for
+/// things like DMA buffers or multi-page kernel stacks.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    /// Number of frames covered by the bitmap (highest usable frame + 1).
+    frame_count: usize,
+    /// Number of frames the memory map reported as usable.
+    usable_frames: usize,
+    free_frames: usize,
+    /// Word index where the next single-frame search starts.
+    next_word: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// # Safety
+    /// The memory map must be valid, usable frames must be truly unused, and
+    /// the complete physical memory must be mapped at `physical_memory_offset`.
+    pub unsafe fn init(
+        memory_regions: &'static MemoryRegions,
+        physical_memory_offset: VirtAddr,
+    ) -> Self {
+        let usable = || {
+            memory_regions
+                .iter()
+                .filter(|r| r.kind == MemoryRegionKind::Usable)
+                .map(|r| (align_up(r.start), r.end & !(FRAME_SIZE - 1)))
+                .filter(|(start, end)| start < end)
+        };
+
+        let frame_count = usable()
+            .map(|(_, end)| (end / FRAME_SIZE) as usize)
+            .max()
+            .unwrap_or(0);
+        let words = frame_count.div_ceil(64);
+        let bitmap_bytes = (words * 8) as u64;
+
+        // Place the bitmap at the start of the first region that fits it.
+        let bitmap_phys = usable()
+            .find(|(start, end)| end - start >= bitmap_bytes)
+            .map(|(start, _)| start)
+            .expect("no usable region large enough for the frame bitmap");
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_phys).as_mut_ptr();
+        let bitmap = unsafe { core::slice::from_raw_parts_mut(bitmap_ptr, words) };
+
+        // Everything starts out used; usable frames are then released.
+        bitmap.fill(u64::MAX);
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            frame_count,
+            usable_frames: 0,
+            free_frames: 0,
+            next_word: 0,
+        };
+        for (start, end) in usable() {
+            for index in (start / FRAME_SIZE) as usize..(end / FRAME_SIZE) as usize {
+                allocator.clear_bit(index);
+                allocator.usable_frames += 1;
+                allocator.free_frames += 1;
+            }
+        }
+
+        // Reserve the frames holding the bitmap itself.
+        let first = (bitmap_phys / FRAME_SIZE) as usize;
+        let count = bitmap_bytes.div_ceil(FRAME_SIZE) as usize;
+        for index in first..first + count {
+            allocator.set_bit(index);
+            allocator.free_frames -= 1;
+        }
+
+        allocator
+    }
+
+    /// Number of frames currently available for allocation.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Number of usable frames that are allocated (including the bitmap).
+    pub fn used_frames(&self) -> usize {
+        self.usable_frames - self.free_frames
+    }
+
+    /// Total number of usable frames reported by the bootloader.
+    pub fn total_frames(&self) -> usize {
+        self.usable_frames
+    }
+
+    /// Allocate `count` physically contiguous frames. Returns the first frame.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        if count == 0 || count > self.free_frames {
+            return None;
+        }
+        if count == 1 {
+            return self.allocate_frame();
+        }
+
+        let mut run_start = 0;
+        let mut run_len = 0;
+        let mut index = 0;
+        while index < self.frame_count {
+            // Skip whole used words while no run is in progress.
+            if run_len == 0 && index % 64 == 0 && self.bitmap[index / 64] == u64::MAX {
+                index += 64;
+                continue;
+            }
+            if self.is_used(index) {
+                run_len = 0;
+            } else {
+                if run_len == 0 {
+                    run_start = index;
+                }
+                run_len += 1;
+                if run_len == count {
+                    for i in run_start..run_start + count {
+                        self.set_bit(i);
+                    }
+                    self.free_frames -= count;
+                    return Some(frame_at(run_start));
+                }
+            }
+            index += 1;
+        }
+        None
+    }
+
+    /// Return `count` contiguous frames starting at `start` to the allocator.
+    ///
+    /// # Safety
+    /// The frames must have been allocated by this allocator and must no
+    /// longer be mapped or otherwise in use.
+    pub unsafe fn deallocate_contiguous(&mut self, start: PhysFrame, count: usize) {
+        let first = (start.start_address().as_u64() / FRAME_SIZE) as usize;
+        for index in first..first + count {
+            self.release(index);
+        }
+    }
+
+    fn release(&mut self, index: usize) {
+        assert!(
+            index < self.frame_count && self.is_used(index),
+            "freeing frame {:#x} that is not allocated",
+            index as u64 * FRAME_SIZE
+        );
+        self.clear_bit(index);
+        self.free_frames += 1;
+        if index / 64 < self.next_word {
+            self.next_word = index / 64;
+        }
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn set_bit(&mut self, index: usize) {
+        self.bitmap[index / 64] |= 1 << (index % 64);
+    }
+
+    fn clear_bit(&mut self, index: usize) {
+        self.bitmap[index / 64] &= !(1 << (index % 64));
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if self.free_frames == 0 {
+            return None;
+        }
+        let words = self.bitmap.len();
+        for offset in 0..words {
+            let word_index = (self.next_word + offset) % words;
+            let word = self.bitmap[word_index];
+            if word == u64::MAX {
+                continue;
+            }
+            let index = word_index * 64 + word.trailing_ones() as usize;
+            if index >= self.frame_count {
+                continue;
+            }
+            self.set_bit(index);
+            self.free_frames -= 1;
+            self.next_word = word_index;
+            return Some(frame_at(index));
+        }
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        self.release((frame.start_address().as_u64() / FRAME_SIZE) as usize);
+    }
+}
+
+const FRAME_SIZE: u64 = 4096;
+
+fn align_up(addr: u64) -> u64 {
+    (addr + FRAME_SIZE - 1) & !(FRAME_SIZE - 1)
+}
+
+fn frame_at(index: usize) -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+}
@@ -0,0 +1,436 @@
+/// Virtual memory areas (VMAs) and demand paging.
+///
+/// A VMA records a reserved range of virtual addresses together with the
+/// access it permits. Reserving a range doesn't map anything: anonymous
+/// areas are backed lazily, one zeroed frame at a time, when the page
+/// fault handler sees an access to a valid-but-unmapped page. Accesses
+/// outside any area, or that the area's permissions forbid, are true
+/// faults and are reported to the page fault handler as such.
+///
+/// `Reserved` areas describe ranges whose owner maps them eagerly (the
+/// heap, thread stacks); they only exist so the layout is visible and
+/// new areas can't overlap them.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::BitOr;
+use spin::Mutex;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+/// Start of the region handed out by `map_anonymous`.
+pub const VMA_REGION_START: u64 = 0x_6666_0000_0000;
+/// End (exclusive) of the region handed out by `map_anonymous`.
+pub const VMA_REGION_END: u64 = VMA_REGION_START + 0x10_0000_0000; // 64 GiB
+
+const PAGE_SIZE: u64 = 4096;
+
+/// Areas of the kernel address space.
+pub static KERNEL_VMAS: Mutex<VmaSet> = Mutex::new(VmaSet::new());
+
+/// Access permissions of an area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmaFlags(u8);
+
+impl VmaFlags {
+    pub const READ: VmaFlags = VmaFlags(1 << 0);
+    pub const WRITE: VmaFlags = VmaFlags(1 << 1);
+    pub const EXEC: VmaFlags = VmaFlags(1 << 2);
+    pub const USER: VmaFlags = VmaFlags(1 << 3);
+
+    pub fn contains(self, other: VmaFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Page table flags for a page backing an area with these permissions.
+    pub fn page_table_flags(self) -> PageTableFlags {
+        let mut flags = PageTableFlags::PRESENT;
+        if self.contains(VmaFlags::WRITE) {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if self.contains(VmaFlags::USER) {
+            flags |= PageTableFlags::USER_ACCESSIBLE;
+        }
+        if !self.contains(VmaFlags::EXEC) && Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }
+}
+
+impl BitOr for VmaFlags {
+    type Output = VmaFlags;
+
+    fn bitor(self, rhs: VmaFlags) -> VmaFlags {
+        VmaFlags(self.0 | rhs.0)
+    }
+}
+
+impl core::fmt::Display for VmaFlags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let bit = |flag, c| if self.contains(flag) { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}{}",
+            bit(VmaFlags::READ, 'r'),
+            bit(VmaFlags::WRITE, 'w'),
+            bit(VmaFlags::EXEC, 'x'),
+            bit(VmaFlags::USER, 'u')
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    /// Zero-filled on first touch.
+    Anonymous,
+    /// Mapped eagerly by its owner; faults inside are never resolved.
+    Reserved,
+}
+
+#[derive(Debug, Clone)]
+pub struct Vma {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub flags: VmaFlags,
+    pub kind: VmaKind,
+    pub name: String,
+    /// Pages currently backed by a frame (demand-paged areas only).
+    pub resident: usize,
+}
+
+impl Vma {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+#[derive(Debug)]
+pub enum VmaError {
+    Overlap,
+    Unaligned,
+    NoSpace,
+    NotFound,
+}
+
+impl core::fmt::Display for VmaError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            VmaError::Overlap => write!(f, "range overlaps an existing area"),
+            VmaError::Unaligned => write!(f, "range is not page aligned"),
+            VmaError::NoSpace => write!(f, "no free virtual range large enough"),
+            VmaError::NotFound => write!(f, "no area starts at that address"),
+        }
+    }
+}
+
+/// Outcome of offering a page fault to the VMA layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultOutcome {
+    /// A frame was mapped; the faulting instruction can be retried.
+    Resolved,
+    /// The address is inside an area but the access isn't allowed.
+    Violation(&'static str),
+    /// No area covers the address.
+    Unmapped,
+}
+
+/// A set of non-overlapping areas, keyed by start address.
+pub struct VmaSet {
+    areas: BTreeMap<u64, Vma>,
+}
+
+impl VmaSet {
+    pub const fn new() -> Self {
+        VmaSet {
+            areas: BTreeMap::new(),
+        }
+    }
+
+    /// Record `[start, start + len)` as an area. Nothing is mapped.
+    pub fn reserve(
+        &mut self,
+        start: VirtAddr,
+        len: u64,
+        flags: VmaFlags,
+        kind: VmaKind,
+        name: &str,
+    ) -> Result<(), VmaError> {
+        if !start.is_aligned(PAGE_SIZE) || len == 0 || !len.is_multiple_of(PAGE_SIZE) {
+            return Err(VmaError::Unaligned);
+        }
+        let end = start + len;
+        if self.overlaps(start, end) {
+            return Err(VmaError::Overlap);
+        }
+        self.areas.insert(
+            start.as_u64(),
+            Vma {
+                start,
+                end,
+                flags,
+                kind,
+                name: String::from(name),
+                resident: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Find a free, page-aligned range of `len` bytes in `[lo, hi)`.
+    pub fn find_free(&self, len: u64, lo: u64, hi: u64) -> Option<VirtAddr> {
+        let mut candidate = lo;
+        for vma in self.areas.range(..hi).map(|(_, vma)| vma) {
+            if vma.end.as_u64() <= candidate {
+                continue;
+            }
+            if vma.start.as_u64() >= candidate + len {
+                break;
+            }
+            candidate = candidate.max(vma.end.as_u64());
+        }
+        (candidate + len <= hi).then(|| VirtAddr::new(candidate))
+    }
+
+    /// Remove the area starting at `start`, returning it.
+    pub fn remove(&mut self, start: VirtAddr) -> Option<Vma> {
+        self.areas.remove(&start.as_u64())
+    }
+
+    pub fn find(&self, addr: VirtAddr) -> Option<&Vma> {
+        self.areas
+            .range(..=addr.as_u64())
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    fn find_mut(&mut self, addr: VirtAddr) -> Option<&mut Vma> {
+        self.areas
+            .range_mut(..=addr.as_u64())
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    fn overlaps(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        // The only candidates are the last area starting before `end`.
+        self.areas
+            .range(..end.as_u64())
+            .next_back()
+            .is_some_and(|(_, vma)| vma.end > start)
+    }
+
+    /// Try to resolve a fault at `addr` by backing the page with a zeroed
+    /// frame through `mapper`.
+    pub fn handle_fault(
+        &mut self,
+        addr: VirtAddr,
+        error_code: PageFaultErrorCode,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+        physical_memory_offset: VirtAddr,
+    ) -> FaultOutcome {
+        let Some(vma) = self.find_mut(addr) else {
+            return FaultOutcome::Unmapped;
+        };
+        if let Some(reason) = check_access(vma.flags, error_code) {
+            return FaultOutcome::Violation(reason);
+        }
+        if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            // The page is present, so the permissions above should have
+            // allowed the access; the mapping disagrees with the area.
+            return FaultOutcome::Violation("protection violation");
+        }
+        self.populate(addr, mapper, frame_allocator, physical_memory_offset)
+    }
+
+    /// Back the page containing `addr` with a zeroed frame whatever the
+    /// area allows, for the kernel filling pages (e.g. loading a segment
+    /// that user code can't read).
+    pub fn populate(
+        &mut self,
+        addr: VirtAddr,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+        physical_memory_offset: VirtAddr,
+    ) -> FaultOutcome {
+        let Some(vma) = self.find_mut(addr) else {
+            return FaultOutcome::Unmapped;
+        };
+        if vma.kind != VmaKind::Anonymous {
+            return FaultOutcome::Violation("access to unbacked reserved area");
+        }
+
+        let page: Page<Size4KiB> = Page::containing_address(addr);
+        let Some(frame) = frame_allocator.allocate_frame() else {
+            return FaultOutcome::Violation("out of physical memory");
+        };
+        // Zero through the physical memory mapping before the page becomes
+        // visible, so user mappings never see stale data.
+        unsafe {
+            let ptr: *mut u8 = (physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr();
+            core::ptr::write_bytes(ptr, 0, PAGE_SIZE as usize);
+        }
+        let table_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | (vma.flags.page_table_flags() & PageTableFlags::USER_ACCESSIBLE);
+        match unsafe {
+            mapper.map_to_with_table_flags(
+                page,
+                frame,
+                vma.flags.page_table_flags(),
+                table_flags,
+                frame_allocator,
+            )
+        } {
+            Ok(flush) => flush.flush(),
+            Err(_) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                return FaultOutcome::Violation("failed to map page");
+            }
+        }
+        vma.resident += 1;
+        FaultOutcome::Resolved
+    }
+
+    /// Unmap every backed page of `vma` and free its frames.
+    pub fn release_pages(
+        vma: &Vma,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+    ) {
+        let first = Page::<Size4KiB>::containing_address(vma.start);
+        let last = Page::<Size4KiB>::containing_address(vma.end - 1u64);
+        for page in Page::range_inclusive(first, last) {
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                flush.flush();
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    }
+}
+
+impl Default for VmaSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns why an access described by `error_code` is not allowed by
+/// `flags`, or `None` if it is.
+fn check_access(flags: VmaFlags, error_code: PageFaultErrorCode) -> Option<&'static str> {
+    if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) && !flags.contains(VmaFlags::WRITE) {
+        return Some("write to read-only area");
+    }
+    if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) && !flags.contains(VmaFlags::EXEC) {
+        return Some("execute from non-executable area");
+    }
+    if error_code.contains(PageFaultErrorCode::USER_MODE) && !flags.contains(VmaFlags::USER) {
+        return Some("user access to kernel area");
+    }
+    if !flags.contains(VmaFlags::READ) {
+        return Some("access to inaccessible area");
+    }
+    None
+}
+
+/// Record the kernel's eagerly mapped regions so the layout is visible and
+/// anonymous mappings can't overlap them. Call after the heap is up.
+pub fn init() {
+    let mut vmas = KERNEL_VMAS.lock();
+    let rw = VmaFlags::READ | VmaFlags::WRITE;
+    vmas.reserve(
+        VirtAddr::new(crate::allocator::HEAP_START as u64),
+        crate::allocator::HEAP_MAX_SIZE as u64,
+        rw,
+        VmaKind::Reserved,
+        "[heap]",
+    )
+    .expect("heap area overlaps");
+    vmas.reserve(
+        VirtAddr::new(crate::task::stack::STACK_REGION_START),
+        crate::task::stack::STACK_REGION_SIZE,
+        rw,
+        VmaKind::Reserved,
+        "[thread stacks]",
+    )
+    .expect("stack area overlaps");
+}
+
+/// Reserve `len` bytes (rounded up to pages) of demand-paged, zero-filled
+/// kernel memory. Returns the start address.
+pub fn map_anonymous(len: u64, flags: VmaFlags, name: &str) -> Result<VirtAddr, VmaError> {
+    let len = len.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut vmas = KERNEL_VMAS.lock();
+        let start = vmas
+            .find_free(len, VMA_REGION_START, VMA_REGION_END)
+            .ok_or(VmaError::NoSpace)?;
+        vmas.reserve(start, len, flags, VmaKind::Anonymous, name)?;
+        Ok(start)
+    })
+}
+
+/// Remove the anonymous area starting at `start`, freeing its frames.
+pub fn unmap(start: VirtAddr) -> Result<(), VmaError> {
+    let vma = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut vmas = KERNEL_VMAS.lock();
+        match vmas.find(start) {
+            Some(vma) if vma.start == start && vma.kind == VmaKind::Anonymous => {}
+            _ => return Err(VmaError::NotFound),
+        }
+        Ok(vmas.remove(start).unwrap())
+    })?;
+    super::with_mapper(|mapper, frame_allocator| {
+        VmaSet::release_pages(&vma, mapper, frame_allocator);
+    });
+    Ok(())
+}
+
+/// Offer a kernel-space page fault to the VMA layer. Called from the page
+/// fault handler, so it only try-locks: a fault taken while one of these
+/// locks is held can't be resolved and is reported as a true fault.
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> FaultOutcome {
+    let Some(mut vmas) = KERNEL_VMAS.try_lock() else {
+        return FaultOutcome::Unmapped;
+    };
+    let (Some(mut mapper), Some(mut frames)) =
+        (super::MAPPER.try_lock(), super::FRAME_ALLOCATOR.try_lock())
+    else {
+        return FaultOutcome::Unmapped;
+    };
+    match (mapper.as_mut(), frames.as_mut()) {
+        (Some(mapper), Some(frames)) => {
+            let offset = mapper.phys_offset();
+            vmas.handle_fault(addr, error_code, mapper, frames, offset)
+        }
+        _ => FaultOutcome::Unmapped,
+    }
+}
+
+/// Snapshot of the kernel's areas, in address order.
+pub fn kernel_areas() -> Vec<Vma> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        KERNEL_VMAS.lock().iter().cloned().collect()
+    })
+}
@@ -0,0 +1,181 @@
+/// Initial ramdisk.
+///
+/// The bootloader can load a file next to the kernel and hands us its
+/// address in `BootInfo::ramdisk_addr`. `run.sh` packs the `rootfs/`
+/// directory into a ustar archive for it, and `load` unpacks that archive
+/// into the filesystem before the shell starts.
+///
+/// Each archive member is a 512-byte header followed by its data, padded
+/// to 512 bytes. The archive ends with a zero block. Regular files and
+/// directories are created with their modes, owners and modification
+/// times; other member types (links, devices) are skipped.
+
+extern crate alloc;
+
+use alloc::string::String;
+
+use crate::filesystem::{FsError, VNode, Vfs, VFS};
+
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug)]
+pub enum RamdiskError {
+    /// A header's checksum doesn't match its contents.
+    BadChecksum { offset: usize },
+    /// A header isn't a ustar header.
+    BadHeader { offset: usize },
+    /// A member's data runs past the end of the image.
+    Truncated { offset: usize },
+    /// The filesystem rejected a member.
+    Fs(String, FsError),
+}
+
+impl core::fmt::Display for RamdiskError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RamdiskError::BadChecksum { offset } => write!(f, "bad header checksum at {:#x}", offset),
+            RamdiskError::BadHeader { offset } => write!(f, "not a ustar header at {:#x}", offset),
+            RamdiskError::Truncated { offset } => write!(f, "member at {:#x} is truncated", offset),
+            RamdiskError::Fs(path, e) => write!(f, "{}: {}", path, e),
+        }
+    }
+}
+
+/// What `load` unpacked.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RamdiskStats {
+    pub files: usize,
+    pub directories: usize,
+    pub bytes: usize,
+    pub skipped: usize,
+}
+
+/// Parse an octal number field (NUL/space terminated).
+fn octal(field: &[u8]) -> Option<usize> {
+    let mut value = 0usize;
+    for &b in field.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => value = value.checked_mul(8)?.checked_add((b - b'0') as usize)?,
+            0 | b' ' => break,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+/// A NUL-terminated string field.
+fn field_str(field: &[u8]) -> &str {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..len]).unwrap_or("")
+}
+
+fn checksum_ok(header: &[u8]) -> bool {
+    let Some(expected) = octal(&header[148..156]) else {
+        return false;
+    };
+    // The checksum field itself counts as eight spaces.
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as usize } else { b as usize })
+        .sum();
+    sum == expected
+}
+
+/// Create every missing directory along `path` (like `mkdir -p`).
+fn create_dirs(fs: &mut Vfs, path: &str) -> Result<(), FsError> {
+    let root = fs.root();
+    let mut prefix = String::new();
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        prefix.push('/');
+        prefix.push_str(component);
+        match fs.create_dir(&prefix, root) {
+            Ok(_) | Err(FsError::AlreadyExists) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn set_attributes(
+    fs: &mut Vfs,
+    node: VNode,
+    mode: usize,
+    uid: usize,
+    gid: usize,
+    mtime: usize,
+) -> Result<(), FsError> {
+    fs.set_mode(node, mode as u16)?;
+    fs.set_owner(node, uid as u32, gid as u32)?;
+    fs.set_times(node, mtime as u64, mtime as u64)
+}
+
+/// Unpack a ustar archive into the global filesystem. Existing files with
+/// the same path are overwritten.
+pub fn load(image: &[u8]) -> Result<RamdiskStats, RamdiskError> {
+    let mut fs = VFS.lock();
+    unpack(fs.as_mut().expect("filesystem not initialized"), image)
+}
+
+fn unpack(fs: &mut Vfs, image: &[u8]) -> Result<RamdiskStats, RamdiskError> {
+    let mut stats = RamdiskStats::default();
+    let mut offset = 0;
+    let root = fs.root();
+
+    while offset + BLOCK_SIZE <= image.len() {
+        let header = &image[offset..offset + BLOCK_SIZE];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if &header[257..262] != b"ustar" {
+            return Err(RamdiskError::BadHeader { offset });
+        }
+        if !checksum_ok(header) {
+            return Err(RamdiskError::BadChecksum { offset });
+        }
+
+        let size = octal(&header[124..136]).ok_or(RamdiskError::BadHeader { offset })?;
+        let mode = octal(&header[100..108]).ok_or(RamdiskError::BadHeader { offset })?;
+        let uid = octal(&header[108..116]).ok_or(RamdiskError::BadHeader { offset })?;
+        let gid = octal(&header[116..124]).ok_or(RamdiskError::BadHeader { offset })?;
+        let mtime = octal(&header[136..148]).ok_or(RamdiskError::BadHeader { offset })?;
+        let data_start = offset + BLOCK_SIZE;
+        let data_end = data_start.checked_add(size).filter(|&end| end <= image.len());
+        let data_end = data_end.ok_or(RamdiskError::Truncated { offset })?;
+
+        let mut path = String::new();
+        let prefix = field_str(&header[345..500]);
+        if !prefix.is_empty() {
+            path.push_str(prefix);
+            path.push('/');
+        }
+        path.push_str(field_str(&header[0..100]));
+        let path = path.trim_start_matches("./").trim_matches('/');
+
+        if !path.is_empty() && path != "." {
+            let absolute = alloc::format!("/{}", path);
+            let parent = absolute.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+            let fs_err = |e| RamdiskError::Fs(absolute.clone(), e);
+            match header[156] {
+                b'0' | 0 => {
+                    create_dirs(fs, parent).map_err(fs_err)?;
+                    fs.write_file(&absolute, &image[data_start..data_end], root).map_err(fs_err)?;
+                    let node = fs.resolve_path(&absolute, root).map_err(fs_err)?;
+                    set_attributes(fs, node, mode, uid, gid, mtime).map_err(fs_err)?;
+                    stats.files += 1;
+                    stats.bytes += size;
+                }
+                b'5' => {
+                    create_dirs(fs, &absolute).map_err(fs_err)?;
+                    let node = fs.resolve_path(&absolute, root).map_err(fs_err)?;
+                    set_attributes(fs, node, mode, uid, gid, mtime).map_err(fs_err)?;
+                    stats.directories += 1;
+                }
+                _ => stats.skipped += 1,
+            }
+        }
+
+        offset = data_start + size.next_multiple_of(BLOCK_SIZE);
+    }
+    Ok(stats)
+}
@@ -6,6 +6,9 @@
 
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::interrupts;
+
+use crate::drivers::chardev::CharDevice;
 
 pub static SERIAL1: Mutex<SerialPort> = Mutex::new(unsafe { SerialPort::new(0x3F8) });
 
@@ -13,6 +16,41 @@ pub fn init() {
     SERIAL1.lock().init();
 }
 
+/// `/dev/ttyS0`: raw bytes to and from COM1. Reads return whatever the
+/// UART has received so far.
+pub struct SerialDevice;
+
+impl CharDevice for SerialDevice {
+    fn name(&self) -> &str {
+        "ttyS0"
+    }
+
+    fn read(&self, buf: &mut [u8]) -> usize {
+        interrupts::without_interrupts(|| {
+            let mut port = SERIAL1.lock();
+            let mut read = 0;
+            while read < buf.len() {
+                match port.try_receive() {
+                    Ok(byte) => buf[read] = byte,
+                    Err(_) => break,
+                }
+                read += 1;
+            }
+            read
+        })
+    }
+
+    fn write(&self, data: &[u8]) -> usize {
+        interrupts::without_interrupts(|| {
+            let mut port = SERIAL1.lock();
+            for &byte in data {
+                port.send_raw(byte);
+            }
+        });
+        data.len()
+    }
+}
+
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => {
@@ -29,7 +67,6 @@ macro_rules! serial_println {
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
         SERIAL1.lock().write_fmt(args).unwrap();
@@ -10,8 +10,12 @@ use alloc::string::String;
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 
 use crate::console::CONSOLE;
-use crate::filesystem::FILESYSTEM;
+use crate::drivers::block::{self, SECTOR_SIZE};
+use crate::drivers::cache;
+use crate::filesystem::snapshot;
+use crate::filesystem::{Ext2, Fat32, FileSystem, FileType, FsError, Metadata, OpenFlags, TmpFs, VNode, VFS};
 use crate::framebuffer::FRAMEBUFFER;
+use crate::time::DateTime;
 use crate::task::keyboard::ScancodeStream;
 use crate::task::process::{PROCESS_TABLE, SHELL_PID};
 use crate::vga_buffer::WRITER;
@@ -25,7 +29,11 @@ pub async fn run() {
         HandleControl::Ignore,
     );
     let mut input = String::with_capacity(MAX_CMD_LEN);
-    let mut cwd: u64 = 0;
+    let mut cwd = VFS
+        .lock()
+        .as_ref()
+        .map(|fs| fs.root())
+        .unwrap_or(VNode { mount: 0, inode: 0 });
     let scancode_stream = ScancodeStream::new();
 
     crate::println!();
@@ -75,9 +83,9 @@ pub async fn run() {
     }
 }
 
-fn print_prompt(cwd: u64) {
+fn print_prompt(cwd: VNode) {
     let path = {
-        let fs = FILESYSTEM.lock();
+        let fs = VFS.lock();
         if let Some(fs) = fs.as_ref() {
             fs.get_path(cwd).unwrap_or_else(|_| String::from("/"))
         } else {
@@ -87,7 +95,57 @@ fn print_prompt(cwd: u64) {
     crate::print!("{}> ", path);
 }
 
-fn execute_command(cmd: &str, cwd: &mut u64) {
+/// Where `mv`/`cp` put `src`: into `dst` if that is an existing
+/// directory, otherwise at `dst` itself.
+fn destination(fs: &crate::filesystem::Vfs, src: &str, dst: &str, cwd: VNode) -> String {
+    match fs.resolve_path(dst, cwd) {
+        Ok(node) if fs.is_directory(node) => {
+            let name = src.trim_end_matches('/').rsplit('/').next().unwrap_or(src);
+            alloc::format!("{}/{}", dst.trim_end_matches('/'), name)
+        }
+        _ => String::from(dst),
+    }
+}
+
+/// `drwxr-xr-x`-style type and permission string.
+fn mode_string(meta: &Metadata) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match meta.kind {
+        FileType::Directory => 'd',
+        FileType::File => '-',
+        FileType::Symlink => 'l',
+        FileType::CharDevice => 'c',
+    });
+    for shift in [6, 3, 0] {
+        let bits = meta.mode >> shift;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+/// One `ls -l` line: mode, links, owner, size, modification time, name.
+fn print_long_entry(name: &str, meta: &Metadata) {
+    let modified = DateTime::from_unix(meta.modified);
+    crate::println!(
+        "{} {:>2} {:<4} {:<4} {:>8} {:04}-{:02}-{:02} {:02}:{:02} {}{}",
+        mode_string(meta),
+        meta.nlink,
+        meta.uid,
+        meta.gid,
+        meta.size,
+        modified.year,
+        modified.month,
+        modified.day,
+        modified.hour,
+        modified.minute,
+        name,
+        if meta.kind == FileType::Directory { "/" } else { "" }
+    );
+}
+
+fn execute_command(cmd: &str, cwd: &mut VNode) {
     let cmd = cmd.trim();
     if cmd.is_empty() {
         return;
@@ -105,23 +163,49 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
             crate::println!("  echo <text>       - Print text to screen");
             crate::println!("  clear             - Clear the screen");
             crate::println!("  info              - Show system information");
+            crate::println!("  date              - Show the current time and uptime");
+            crate::println!("  heap              - Show heap usage per allocation size class");
             crate::println!("  halt              - Halt the CPU");
             crate::println!("  panic             - Trigger a kernel panic");
             crate::println!("  page <addr>       - Show page table info for hex address");
+            crate::println!("  vmas               - List kernel virtual memory areas");
+            crate::println!("  mmap <KiB>         - Reserve demand-paged kernel memory");
+            crate::println!("  munmap <addr>      - Release a region created by mmap");
+            crate::println!("  peek <addr>        - Read a byte in a region created by mmap");
+            crate::println!("  poke <addr> <byte> - Write a byte in a region created by mmap");
             crate::println!("  color <name>      - Set text color (white/red/green/blue/cyan/yellow/magenta)");
-            crate::println!("  ls [path]          - List directory contents");
+            crate::println!("  ls [-l] [path]     - List directory contents (-l: long format)");
+            crate::println!("  stat <path>        - Show size, mode, owner, links and times");
+            crate::println!("  chmod <mode> <path> - Set permission bits (octal)");
             crate::println!("  cat <path>         - Print file contents");
             crate::println!("  touch <path>       - Create empty file");
             crate::println!("  mkdir <path>       - Create directory");
-            crate::println!("  rm <path>          - Remove file or empty directory");
+            crate::println!("  rm [-r] <path>     - Remove file or empty directory (-r: whole tree)");
+            crate::println!("  mv <src> <dst>     - Move or rename a file or directory");
+            crate::println!("  cp [-r] <src> <dst> - Copy a file (-r: directory tree)");
+            crate::println!("  ln [-s] <tgt> <link> - Create a hard link (-s: symbolic link)");
+            crate::println!("  readlink <path>    - Print the target of a symbolic link");
             crate::println!("  cd [path]          - Change directory (no args = root)");
             crate::println!("  pwd                - Print working directory");
             crate::println!("  write <path> <txt> - Write text to file");
+            crate::println!("  append <path> <txt> - Append a line of text to file");
+            crate::println!("  mount [tmpfs <path>] - List mounts or mount a new tmpfs");
+            crate::println!("  mount fat32|ext2 <dev> <path> - Mount a FAT32 or (read-only) ext2 volume");
+            crate::println!("  umount <path>      - Unmount the filesystem at path");
+            crate::println!("  disks              - List detected block devices");
+            crate::println!("  sector <dev> <lba> - Hex dump one sector of a block device");
+            crate::println!("  sync               - Write cached disk sectors back");
+            crate::println!("  cache              - Show block cache hits, misses and dirty sectors");
+            crate::println!("  fs save|load <dev> - Save the root filesystem to a device / restore it");
             crate::println!("  ps                 - List running processes");
             crate::println!("  spawn <name> [n]   - Spawn async demo counter (n ticks, default 5)");
             crate::println!("  tspawn <name> [n]  - Spawn preemptible thread (n ticks, default 5)");
             crate::println!("  sleep <ms>         - Sleep for given milliseconds (spawns a thread)");
+            crate::println!("  overflow           - Spawn a thread that overflows its stack");
+            crate::println!("  ring3 [hello|spin|evil] - Run a user-mode demo program");
+            crate::println!("  run <path> [args]  - Run an ELF program from the filesystem");
             crate::println!("  kill <pid>         - Kill a process or thread by PID");
+            crate::println!("  nice <pid> <n>     - Set a thread's nice value (-20..19, lower runs first)");
             crate::println!("  draw rect <x> <y> <w> <h> <color>");
             crate::println!("  draw line <x1> <y1> <x2> <y2> <color>");
             crate::println!("  draw circle <cx> <cy> <r> <color>");
@@ -138,11 +222,27 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
         "info" => {
             crate::println!("RustKernel v0.1");
             crate::println!("Architecture: x86_64");
+            let heap = crate::allocator::stats();
             crate::println!(
-                "Heap: {} KiB at {:#x}",
-                crate::allocator::HEAP_SIZE / 1024,
-                crate::allocator::HEAP_START
+                "Heap: {} KiB used / {} KiB mapped at {:#x} (limit {} KiB)",
+                heap.used / 1024,
+                heap.size / 1024,
+                crate::allocator::HEAP_START,
+                heap.limit / 1024
             );
+            let frames = x86_64::instructions::interrupts::without_interrupts(|| {
+                crate::memory::FRAME_ALLOCATOR
+                    .lock()
+                    .as_ref()
+                    .map(|f| (f.free_frames(), f.total_frames()))
+            });
+            if let Some((free, total)) = frames {
+                crate::println!(
+                    "Physical memory: {} KiB free / {} KiB usable",
+                    free * 4,
+                    total * 4
+                );
+            }
             let fb = FRAMEBUFFER.lock();
             if let Some(f) = fb.as_ref() {
                 crate::println!(
@@ -153,6 +253,32 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
                 );
             }
         }
+        "heap" => {
+            let heap = crate::allocator::stats();
+            crate::println!(
+                "Heap: {} KiB used, {} KiB free, {} KiB mapped (limit {} KiB)",
+                heap.used / 1024,
+                heap.free / 1024,
+                heap.size / 1024,
+                heap.limit / 1024
+            );
+            crate::println!("{:<8} {:<8} {:<8} {:<10} {}", "CLASS", "LIVE", "CACHED", "BYTES", "ALLOCS");
+            for class in crate::allocator::slab_stats() {
+                crate::println!(
+                    "{:<8} {:<8} {:<8} {:<10} {}",
+                    class.block_size,
+                    class.live,
+                    class.cached,
+                    class.block_size * (class.live + class.cached),
+                    class.allocs
+                );
+            }
+        }
+        "date" => {
+            let uptime = crate::time::uptime_ms() / 1000;
+            crate::println!("{} UTC", DateTime::from_unix(crate::time::now()));
+            crate::println!("up {}:{:02}:{:02}", uptime / 3600, uptime / 60 % 60, uptime % 60);
+        }
         "halt" => {
             crate::println!("Halting CPU...");
             crate::hlt_loop();
@@ -180,6 +306,72 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
                 }
             }
         }
+        "vmas" => {
+            crate::println!("{:<18} {:<18} {:<5} {:<9} {}", "START", "END", "PERM", "RESIDENT", "NAME");
+            for vma in crate::memory::vma::kernel_areas() {
+                let resident = match vma.kind {
+                    crate::memory::vma::VmaKind::Anonymous => alloc::format!("{}", vma.resident),
+                    crate::memory::vma::VmaKind::Reserved => String::from("-"),
+                };
+                crate::println!(
+                    "{:<#18x} {:<#18x} {:<5} {:<9} {}",
+                    vma.start.as_u64(),
+                    vma.end.as_u64(),
+                    alloc::format!("{}", vma.flags),
+                    resident,
+                    vma.name
+                );
+            }
+        }
+        "mmap" => {
+            let kib: u64 = match args.parse() {
+                Ok(k) if k > 0 => k,
+                _ => {
+                    crate::println!("Usage: mmap <KiB>");
+                    return;
+                }
+            };
+            use crate::memory::vma::VmaFlags;
+            match crate::memory::vma::map_anonymous(kib * 1024, VmaFlags::READ | VmaFlags::WRITE, "mmap") {
+                Ok(addr) => crate::println!("Reserved {} KiB at {:#x}", kib, addr.as_u64()),
+                Err(e) => crate::println!("mmap: {}", e),
+            }
+        }
+        "munmap" => {
+            match parse_hex_addr(args) {
+                Some(addr) => match crate::memory::vma::unmap(x86_64::VirtAddr::new(addr)) {
+                    Ok(()) => {}
+                    Err(e) => crate::println!("munmap: {}", e),
+                },
+                None => crate::println!("Usage: munmap <hex_address>"),
+            }
+        }
+        "peek" => {
+            use crate::memory::vma::VmaFlags;
+            match parse_hex_addr(args) {
+                Some(addr) if in_mmap_area(addr, VmaFlags::READ) => {
+                    let value = unsafe { core::ptr::read_volatile(addr as *const u8) };
+                    crate::println!("{:#x}: {:#04x}", addr, value);
+                }
+                Some(addr) => crate::println!("peek: {:#x} is not in a region created by mmap", addr),
+                None => crate::println!("Usage: peek <hex_address>"),
+            }
+        }
+        "poke" => {
+            use crate::memory::vma::VmaFlags;
+            let parsed = args.split_once(' ').and_then(|(addr, value)| {
+                Some((parse_hex_addr(addr)?, value.trim().parse::<u8>().ok()?))
+            });
+            match parsed {
+                Some((addr, value)) if in_mmap_area(addr, VmaFlags::WRITE) => unsafe {
+                    core::ptr::write_volatile(addr as *mut u8, value);
+                },
+                Some((addr, _)) => {
+                    crate::println!("poke: {:#x} is not in a region created by mmap", addr)
+                }
+                None => crate::println!("Usage: poke <hex_address> <byte>"),
+            }
+        }
         "color" => {
             if args.is_empty() {
                 crate::println!("Usage: color <name>");
@@ -197,18 +389,35 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
             cmd_draw(args);
         }
         "ls" => {
-            let target = if args.is_empty() { "." } else { args };
-            let mut fs = FILESYSTEM.lock();
-            if let Some(fs) = fs.as_mut() {
+            let (long, target) = match args.strip_prefix("-l") {
+                Some(rest) => (true, rest.trim()),
+                None => (false, args),
+            };
+            let target = if target.is_empty() { "." } else { target };
+            let fs = VFS.lock();
+            if let Some(fs) = fs.as_ref() {
                 match fs.resolve_path(target, *cwd) {
-                    Ok(dir_id) => match fs.list_dir(dir_id) {
+                    Ok(dir) => match fs.list_dir(dir) {
+                        Ok(entries) if long => {
+                            for entry in entries {
+                                match fs.lookup(dir, &entry.name).and_then(|node| fs.stat(node)) {
+                                    Ok(meta) if meta.kind == FileType::Symlink => {
+                                        let target = fs.read_link(&entry.name, dir).unwrap_or_default();
+                                        print_long_entry(&alloc::format!("{} -> {}", entry.name, target), &meta)
+                                    }
+                                    Ok(meta) => print_long_entry(&entry.name, &meta),
+                                    Err(e) => crate::println!("ls: {}: {}", entry.name, e),
+                                }
+                            }
+                        }
                         Ok(entries) => {
                             crate::println!(".   ../");
-                            for (name, is_dir) in entries {
-                                if is_dir {
-                                    crate::println!("{}/", name);
-                                } else {
-                                    crate::println!("{}", name);
+                            for entry in entries {
+                                match entry.kind {
+                                    FileType::Directory => crate::println!("{}/", entry.name),
+                                    FileType::File => crate::println!("{}", entry.name),
+                                    FileType::Symlink => crate::println!("{}@", entry.name),
+                                    FileType::CharDevice => crate::println!("{}", entry.name),
                                 }
                             }
                         }
@@ -223,12 +432,12 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
                 crate::println!("Usage: cat <path>");
                 return;
             }
-            let fs = FILESYSTEM.lock();
+            let fs = VFS.lock();
             if let Some(fs) = fs.as_ref() {
                 match fs.resolve_path(args, *cwd) {
-                    Ok(inode_id) => match fs.read_file(inode_id) {
+                    Ok(node) => match fs.read_file(node) {
                         Ok(data) => {
-                            let text = core::str::from_utf8(data).unwrap_or("<binary data>");
+                            let text = core::str::from_utf8(&data).unwrap_or("<binary data>");
                             crate::println!("{}", text);
                         }
                         Err(e) => crate::println!("cat: {}", e),
@@ -237,12 +446,54 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
                 }
             }
         }
+        "stat" => {
+            if args.is_empty() {
+                crate::println!("Usage: stat <path>");
+                return;
+            }
+            let fs = VFS.lock();
+            if let Some(fs) = fs.as_ref() {
+                match fs.resolve_path(args, *cwd).and_then(|node| fs.stat(node)) {
+                    Ok(meta) => {
+                        let kind = match meta.kind {
+                            FileType::File => "regular file",
+                            FileType::Directory => "directory",
+                            FileType::Symlink => "symbolic link",
+                            FileType::CharDevice => "character device",
+                        };
+                        crate::println!("  File: {}", args);
+                        crate::println!("  Size: {:<10} Inode: {:<8} Links: {}  {}", meta.size, meta.inode, meta.nlink, kind);
+                        crate::println!("Access: ({:04o}/{})  Uid: {}  Gid: {}", meta.mode, mode_string(&meta), meta.uid, meta.gid);
+                        crate::println!("Access: {}", DateTime::from_unix(meta.accessed));
+                        crate::println!("Modify: {}", DateTime::from_unix(meta.modified));
+                        crate::println!(" Birth: {}", DateTime::from_unix(meta.created));
+                    }
+                    Err(e) => crate::println!("stat: {}", e),
+                }
+            }
+        }
+        "chmod" => {
+            let parsed = args
+                .split_once(' ')
+                .and_then(|(mode, path)| Some((u16::from_str_radix(mode, 8).ok()?, path.trim())));
+            let Some((mode, path)) = parsed else {
+                crate::println!("Usage: chmod <octal mode> <path>");
+                return;
+            };
+            let mut fs = VFS.lock();
+            if let Some(fs) = fs.as_mut() {
+                let result = fs.resolve_path(path, *cwd).and_then(|node| fs.set_mode(node, mode));
+                if let Err(e) = result {
+                    crate::println!("chmod: {}", e);
+                }
+            }
+        }
         "touch" => {
             if args.is_empty() {
                 crate::println!("Usage: touch <path>");
                 return;
             }
-            let mut fs = FILESYSTEM.lock();
+            let mut fs = VFS.lock();
             if let Some(fs) = fs.as_mut() {
                 match fs.create_file(args, *cwd) {
                     Ok(_) => {}
@@ -256,7 +507,7 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
                 crate::println!("Usage: mkdir <path>");
                 return;
             }
-            let mut fs = FILESYSTEM.lock();
+            let mut fs = VFS.lock();
             if let Some(fs) = fs.as_mut() {
                 match fs.create_dir(args, *cwd) {
                     Ok(_) => {}
@@ -265,26 +516,180 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
             }
         }
         "rm" => {
-            if args.is_empty() {
-                crate::println!("Usage: rm <path>");
+            let (recursive, path) = match args.strip_prefix("-r") {
+                Some(rest) => (true, rest.trim()),
+                None => (false, args),
+            };
+            if path.is_empty() {
+                crate::println!("Usage: rm [-r] <path>");
                 return;
             }
-            let mut fs = FILESYSTEM.lock();
+            let mut fs = VFS.lock();
             if let Some(fs) = fs.as_mut() {
-                match fs.remove(args, *cwd) {
-                    Ok(()) => {}
-                    Err(e) => crate::println!("rm: {}", e),
+                let result = if recursive { fs.remove_all(path, *cwd) } else { fs.remove(path, *cwd) };
+                if let Err(e) = result {
+                    crate::println!("rm: {}", e);
+                }
+                // The working directory may have been inside the removed tree.
+                if fs.stat(*cwd).is_err() {
+                    *cwd = fs.root();
+                }
+            }
+        }
+        "mv" | "cp" => {
+            let (recursive, rest) = match args.strip_prefix("-r") {
+                Some(rest) if command == "cp" => (true, rest.trim()),
+                _ => (false, args),
+            };
+            let Some((src, dst)) = rest.split_once(' ') else {
+                crate::println!("Usage: mv <src> <dst> | cp [-r] <src> <dst>");
+                return;
+            };
+            let (src, dst) = (src.trim(), dst.trim());
+            let mut fs = VFS.lock();
+            if let Some(fs) = fs.as_mut() {
+                let dst = destination(fs, src, dst, *cwd);
+                let result = if command == "mv" {
+                    fs.rename(src, &dst, *cwd)
+                } else if !recursive && fs.resolve_path(src, *cwd).is_ok_and(|node| fs.is_directory(node)) {
+                    crate::println!("cp: {} is a directory (use -r)", src);
+                    return;
+                } else {
+                    fs.copy(src, &dst, *cwd)
+                };
+                if let Err(e) = result {
+                    crate::println!("{}: {}", command, e);
+                }
+            }
+        }
+        "ln" => {
+            let (symbolic, rest) = match args.strip_prefix("-s") {
+                Some(rest) => (true, rest.trim()),
+                None => (false, args),
+            };
+            let Some((target, link)) = rest.split_once(' ') else {
+                crate::println!("Usage: ln [-s] <target> <link>");
+                return;
+            };
+            let (target, link) = (target.trim(), link.trim());
+            let mut fs = VFS.lock();
+            if let Some(fs) = fs.as_mut() {
+                let result = if symbolic {
+                    fs.symlink(target, link, *cwd).map(|_| ())
+                } else {
+                    let link = destination(fs, target, link, *cwd);
+                    fs.link(target, &link, *cwd)
+                };
+                if let Err(e) = result {
+                    crate::println!("ln: {}", e);
+                }
+            }
+        }
+        "readlink" => {
+            let fs = VFS.lock();
+            if let Some(fs) = fs.as_ref() {
+                match fs.read_link(args, *cwd) {
+                    Ok(target) => crate::println!("{}", target),
+                    Err(e) => crate::println!("readlink: {}", e),
                 }
             }
         }
+        "disks" => {
+            for dev in block::devices() {
+                let sectors = dev.sector_count();
+                crate::println!(
+                    "{:<6} {:>12} sectors {:>8} MiB",
+                    dev.name(),
+                    sectors,
+                    sectors * SECTOR_SIZE as u64 / (1024 * 1024)
+                );
+            }
+        }
+        "sector" => {
+            let parsed = args.split_once(' ').and_then(|(name, lba)| {
+                Some((cache::get(name)?, lba.trim().parse::<u64>().ok()?))
+            });
+            let Some((dev, lba)) = parsed else {
+                crate::println!("Usage: sector <device> <lba>");
+                return;
+            };
+            let mut buf = [0u8; SECTOR_SIZE];
+            if let Err(e) = dev.read_sectors(lba, &mut buf) {
+                crate::println!("sector: {}", e);
+                return;
+            }
+            for (i, row) in buf.chunks(16).enumerate() {
+                crate::print!("{:03x}:", i * 16);
+                for byte in row {
+                    crate::print!(" {:02x}", byte);
+                }
+                crate::print!("  ");
+                for &byte in row {
+                    let c = if byte.is_ascii_graphic() { byte as char } else { '.' };
+                    crate::print!("{}", c);
+                }
+                crate::println!();
+            }
+        }
+        "sync" => match cache::sync() {
+            Ok(written) => crate::println!("sync: {} sectors written", written),
+            Err(e) => crate::println!("sync: {}", e),
+        },
+        "cache" => {
+            let stats = cache::stats();
+            let lookups = stats.hits + stats.misses;
+            crate::println!(
+                "{} hits, {} misses ({}% hit rate)",
+                stats.hits,
+                stats.misses,
+                (stats.hits * 100).checked_div(lookups).unwrap_or(0)
+            );
+            crate::println!(
+                "{}/{} sectors cached, {} dirty, {} written back",
+                stats.cached,
+                stats.capacity,
+                stats.dirty,
+                stats.writebacks
+            );
+        }
+        "fs" => {
+            let Some((action, device)) = args.split_once(' ') else {
+                crate::println!("Usage: fs save|load <device>");
+                return;
+            };
+            let Some(dev) = cache::get(device.trim()) else {
+                crate::println!("fs: no such device: {}", device.trim());
+                return;
+            };
+            let result = match action {
+                "save" => snapshot::save(&dev),
+                "load" => snapshot::load(&dev),
+                _ => {
+                    crate::println!("Usage: fs save|load <device>");
+                    return;
+                }
+            };
+            match result {
+                Ok(stats) => crate::println!(
+                    "fs {}: {} files, {} directories, {} symlinks, {} links, {} bytes",
+                    action,
+                    stats.files,
+                    stats.directories,
+                    stats.symlinks,
+                    stats.links,
+                    stats.bytes
+                ),
+                Err(e) => crate::println!("fs {}: {}", action, e),
+            }
+        }
         "cd" => {
             let target = if args.is_empty() { "/" } else { args };
-            let fs = FILESYSTEM.lock();
+            let fs = VFS.lock();
             if let Some(fs) = fs.as_ref() {
                 match fs.resolve_path(target, *cwd) {
-                    Ok(inode_id) => {
-                        if fs.is_directory(inode_id) {
-                            *cwd = inode_id;
+                    Ok(node) => {
+                        if fs.is_directory(node) {
+                            *cwd = node;
                         } else {
                             crate::println!("cd: not a directory");
                         }
@@ -294,7 +699,7 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
             }
         }
         "pwd" => {
-            let fs = FILESYSTEM.lock();
+            let fs = VFS.lock();
             if let Some(fs) = fs.as_ref() {
                 match fs.get_path(*cwd) {
                     Ok(path) => crate::println!("{}", path),
@@ -314,7 +719,7 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
                     return;
                 }
             };
-            let mut fs = FILESYSTEM.lock();
+            let mut fs = VFS.lock();
             if let Some(fs) = fs.as_mut() {
                 match fs.write_file(path, text.as_bytes(), *cwd) {
                     Ok(()) => {}
@@ -322,10 +727,81 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
                 }
             }
         }
+        "append" => {
+            let Some((path, text)) = args.split_once(' ') else {
+                crate::println!("Usage: append <path> <text>");
+                return;
+            };
+            let mut fs = VFS.lock();
+            if let Some(fs) = fs.as_mut() {
+                let flags = OpenFlags::WRITE | OpenFlags::APPEND | OpenFlags::CREATE;
+                let result = fs.open(path, flags, *cwd).and_then(|file| {
+                    file.write(fs, text.as_bytes())?;
+                    file.write(fs, b"\n")
+                });
+                if let Err(e) = result {
+                    crate::println!("append: {}", e);
+                }
+            }
+        }
+        "mount" => {
+            let mut fs = VFS.lock();
+            let Some(fs) = fs.as_mut() else {
+                return;
+            };
+            if args.is_empty() {
+                for (path, driver) in fs.mounts() {
+                    crate::println!("{} on {}", driver, path);
+                }
+                return;
+            }
+            let words: alloc::vec::Vec<&str> = args.split_whitespace().collect();
+            let driver: Result<(alloc::boxed::Box<dyn FileSystem>, &str), FsError> = match words[..] {
+                ["tmpfs", path] => Ok((alloc::boxed::Box::new(TmpFs::new()), path)),
+                [kind @ ("fat32" | "ext2"), device, path] => match cache::get(device) {
+                    Some(dev) if kind == "ext2" => {
+                        Ext2::new(dev).map(|ext2| (alloc::boxed::Box::new(ext2) as _, path))
+                    }
+                    Some(dev) => Fat32::new(dev).map(|fat| (alloc::boxed::Box::new(fat) as _, path)),
+                    None => {
+                        crate::println!("mount: no such device: {}", device);
+                        return;
+                    }
+                },
+                _ => {
+                    crate::println!("Usage: mount [tmpfs <path> | fat32|ext2 <device> <path>]");
+                    return;
+                }
+            };
+            if let Err(e) = driver.and_then(|(driver, path)| fs.mount(path, driver, *cwd)) {
+                crate::println!("mount: {}", e);
+            }
+        }
+        "umount" => {
+            if args.is_empty() {
+                crate::println!("Usage: umount <path>");
+                return;
+            }
+            let mut fs = VFS.lock();
+            if let Some(fs) = fs.as_mut() {
+                let target = fs.resolve_path(args, *cwd).map(|node| node.mount);
+                match fs.unmount(args, *cwd) {
+                    // Don't leave the shell inside the removed filesystem.
+                    Ok(_) if target == Ok(cwd.mount) => *cwd = fs.root(),
+                    Ok(_) => {}
+                    Err(e) => crate::println!("umount: {}", e),
+                }
+            }
+        }
         "ps" => {
+            // Read the scheduler before locking the process table.
+            let threads = crate::task::scheduler::threads();
             let table = PROCESS_TABLE.lock();
             if let Some(table) = table.as_ref() {
-                crate::println!("{:<6} {:<4} {:<6} {:<12} {}", "PID", "TYPE", "PPID", "STATE", "NAME");
+                crate::println!(
+                    "{:<6} {:<4} {:<6} {:<12} {:>3} {:>3} {}",
+                    "PID", "TYPE", "PPID", "STATE", "PRI", "NI", "NAME"
+                );
                 for (pid, proc) in table.list() {
                     let ppid = match proc.parent_pid {
                         Some(p) => alloc::format!("{}", p),
@@ -339,7 +815,14 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
                         }
                         ref s => alloc::format!("{}", s),
                     };
-                    crate::println!("{:<6} {:<4} {:<6} {:<12} {}", pid, type_str, ppid, state_str, proc.name);
+                    let (pri, ni) = match threads.iter().find(|t| t.pid == pid) {
+                        Some(t) => (alloc::format!("{}", t.priority), alloc::format!("{}", t.nice)),
+                        None => (String::from("-"), String::from("-")),
+                    };
+                    crate::println!(
+                        "{:<6} {:<4} {:<6} {:<12} {:>3} {:>3} {}",
+                        pid, type_str, ppid, state_str, pri, ni, proc.name
+                    );
                 }
             }
         }
@@ -385,6 +868,49 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
             );
             crate::println!("Sleeping for {}ms (PID {})", ms, pid);
         }
+        "overflow" => {
+            let pid = crate::task::scheduler::spawn_thread(
+                String::from("overflow"),
+                crate::task::scheduler::overflow_thread_entry,
+                0,
+                Some(SHELL_PID),
+            );
+            crate::println!("Spawned stack overflow thread as PID {}", pid);
+        }
+        "ring3" => {
+            let (name, program) = match args {
+                "" | "hello" => ("hello", crate::task::user::HELLO_PROGRAM),
+                "spin" => ("spin", crate::task::user::SPIN_PROGRAM),
+                "evil" => ("evil", crate::task::user::KERNEL_WRITE_PROGRAM),
+                _ => {
+                    crate::println!("Usage: ring3 [hello|spin|evil]");
+                    return;
+                }
+            };
+            match crate::task::user::spawn_flat(String::from(name), program, 0, Some(SHELL_PID)) {
+                Ok(pid) => crate::println!("Started user program '{}' as PID {}", name, pid),
+                Err(e) => crate::println!("ring3: {}", e),
+            }
+        }
+        "run" => {
+            let argv: alloc::vec::Vec<&str> = args.split_whitespace().collect();
+            let Some(&path) = argv.first() else {
+                crate::println!("Usage: run <path> [args]");
+                return;
+            };
+            let pwd = {
+                let fs = VFS.lock();
+                fs.as_ref()
+                    .and_then(|fs| fs.get_path(*cwd).ok())
+                    .unwrap_or_else(|| String::from("/"))
+            };
+            let pwd = alloc::format!("PWD={}", pwd);
+            let envp = [pwd.as_str()];
+            match crate::task::user::exec(path, *cwd, &argv, &envp, Some(SHELL_PID)) {
+                Ok(pid) => crate::println!("Started '{}' as PID {}", path, pid),
+                Err(e) => crate::println!("run: {}: {}", path, e),
+            }
+        }
         "kill" => {
             if args.is_empty() {
                 crate::println!("Usage: kill <pid>");
@@ -412,6 +938,22 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
             crate::task::process::kill_process(pid);
             crate::println!("Killed PID {}", pid);
         }
+        "nice" => {
+            let parsed = args
+                .split_once(' ')
+                .and_then(|(pid, nice)| Some((pid.parse::<u64>().ok()?, nice.trim().parse::<i8>().ok()?)));
+            let Some((pid, nice)) = parsed else {
+                crate::println!("Usage: nice <pid> <-20..19>");
+                return;
+            };
+            if !crate::task::scheduler::set_nice(pid, nice) {
+                crate::println!("nice: no such thread (PID {})", pid);
+                return;
+            }
+            if let Some(info) = crate::task::scheduler::thread_info(pid) {
+                crate::println!("PID {}: nice {}, priority {}", pid, info.nice, info.priority);
+            }
+        }
         "screenfill" => {
             if args.is_empty() {
                 crate::println!("Usage: screenfill <color>");
@@ -435,6 +977,23 @@ fn execute_command(cmd: &str, cwd: &mut u64) {
     }
 }
 
+/// True if `addr` lies in an area reserved by `mmap` that allows `flags`,
+/// so `peek` and `poke` only touch demand-paged memory no one else uses.
+fn in_mmap_area(addr: u64, flags: crate::memory::vma::VmaFlags) -> bool {
+    use crate::memory::vma::{self, VmaKind};
+    vma::kernel_areas().iter().any(|area| {
+        area.name == "mmap"
+            && area.kind == VmaKind::Anonymous
+            && area.flags.contains(flags)
+            && area.contains(x86_64::VirtAddr::new(addr))
+    })
+}
+
+fn parse_hex_addr(s: &str) -> Option<u64> {
+    let addr = u64::from_str_radix(s.trim().trim_start_matches("0x"), 16).ok()?;
+    x86_64::VirtAddr::try_new(addr).ok().map(|a| a.as_u64())
+}
+
 fn parse_spawn_args<'a>(args: &'a str, cmd_name: &str) -> Option<(&'a str, u32)> {
     if args.is_empty() {
         crate::println!("Usage: {} <name> [count]", cmd_name);
@@ -0,0 +1,448 @@
+/// System call interface (SYSCALL/SYSRET).
+///
+/// User programs enter the kernel with the `syscall` instruction. The CPU
+/// jumps to `syscall_entry` (LSTAR) with interrupts masked (FMASK), the
+/// user RIP in RCX and RFLAGS in R11, but without switching stacks. The
+/// stub swaps to the running thread's kernel stack, saves the user
+/// context as a `SyscallFrame`, and calls `syscall_dispatch`.
+///
+/// Calling convention (Linux-style):
+///   RAX = syscall number, args in RDI, RSI, RDX, R10, R8, R9
+///   RAX = return value; negative values are `-errno`
+///
+/// Every pointer a program passes in is checked against the areas of the
+/// caller's address space before the kernel touches it.
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+
+use crate::filesystem::{FsError, OpenFile, OpenFlags, SeekFrom, VFS};
+use crate::memory::vma::VmaFlags;
+use crate::task::process::{FIRST_FILE_FD, PROCESS_TABLE};
+use crate::task::scheduler;
+
+pub const SYS_READ: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_OPEN: u64 = 2;
+pub const SYS_CLOSE: u64 = 3;
+pub const SYS_LSEEK: u64 = 8;
+pub const SYS_YIELD: u64 = 24;
+pub const SYS_SLEEP: u64 = 35;
+pub const SYS_GETPID: u64 = 39;
+pub const SYS_SPAWN: u64 = 56;
+pub const SYS_EXIT: u64 = 60;
+
+/// Flags for `open`.
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+pub const O_RDWR: u64 = 2;
+pub const O_CREAT: u64 = 0x40;
+pub const O_TRUNC: u64 = 0x200;
+pub const O_APPEND: u64 = 0x400;
+
+/// `whence` values for `lseek`.
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+/// Error numbers (returned negated), matching Linux values.
+pub mod errno {
+    pub const ENOENT: i64 = 2;
+    pub const EIO: i64 = 5;
+    pub const EBADF: i64 = 9;
+    pub const EFAULT: i64 = 14;
+    pub const EBUSY: i64 = 16;
+    pub const EEXIST: i64 = 17;
+    pub const EXDEV: i64 = 18;
+    pub const ENOTDIR: i64 = 20;
+    pub const EISDIR: i64 = 21;
+    pub const EINVAL: i64 = 22;
+    pub const EMFILE: i64 = 24;
+    pub const ENOSPC: i64 = 28;
+    pub const ESPIPE: i64 = 29;
+    pub const EROFS: i64 = 30;
+    pub const ENOSYS: i64 = 38;
+    pub const ENOTEMPTY: i64 = 39;
+    pub const ELOOP: i64 = 40;
+    pub const EOPNOTSUPP: i64 = 95;
+}
+
+/// Longest path accepted by `open`.
+const MAX_PATH_LEN: u64 = 4096;
+
+/// Largest transfer a single read or write performs; longer requests
+/// complete partially, as with a short read.
+const MAX_IO_LEN: u64 = 1024 * 1024;
+
+/// Longest sleep accepted by `sleep` (a day); longer ones are EINVAL.
+pub const MAX_SLEEP_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// User context saved by `syscall_entry`. Field order matches the pushes
+/// in the stub (last pushed first).
+#[repr(C)]
+pub struct SyscallFrame {
+    pub r9: u64,
+    pub r8: u64,
+    pub r10: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rax: u64,
+    pub rflags: u64,
+    pub rip: u64,
+    pub rsp: u64,
+}
+
+type SysResult = Result<u64, i64>;
+type Handler = fn(&SyscallFrame) -> SysResult;
+
+const TABLE_SIZE: usize = 64;
+
+/// Handlers indexed by syscall number.
+static SYSCALL_TABLE: [Option<Handler>; TABLE_SIZE] = {
+    let mut table: [Option<Handler>; TABLE_SIZE] = [None; TABLE_SIZE];
+    table[SYS_READ as usize] = Some(sys_read);
+    table[SYS_WRITE as usize] = Some(sys_write);
+    table[SYS_OPEN as usize] = Some(sys_open);
+    table[SYS_CLOSE as usize] = Some(sys_close);
+    table[SYS_LSEEK as usize] = Some(sys_lseek);
+    table[SYS_YIELD as usize] = Some(sys_yield);
+    table[SYS_SLEEP as usize] = Some(sys_sleep);
+    table[SYS_GETPID as usize] = Some(sys_getpid);
+    table[SYS_SPAWN as usize] = Some(sys_spawn);
+    table[SYS_EXIT as usize] = Some(sys_exit);
+    table
+};
+
+// Scratch slots used by the entry stub (single CPU). The user RSP is only
+// live between `syscall` and the first push, with interrupts masked.
+#[no_mangle]
+static mut SYSCALL_USER_RSP: u64 = 0;
+#[no_mangle]
+static mut SYSCALL_KERNEL_RSP: u64 = 0;
+
+/// Enable `syscall`/`sysret` and point LSTAR at the entry stub.
+/// Call after `gdt::init` (STAR relies on the descriptor order there).
+pub fn init() {
+    use crate::gdt::{KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR, USER_CODE_SELECTOR, USER_DATA_SELECTOR};
+    Star::write(USER_CODE_SELECTOR, USER_DATA_SELECTOR, KERNEL_CODE_SELECTOR, KERNEL_DATA_SELECTOR)
+        .expect("GDT layout incompatible with sysret");
+    LStar::write(VirtAddr::new(syscall_entry_addr()));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// Set the stack `syscall_entry` switches to. The scheduler calls this
+/// with the thread's kernel stack top on every switch.
+pub fn set_kernel_stack(top: VirtAddr) {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        SYSCALL_KERNEL_RSP = top.as_u64();
+    });
+}
+
+fn syscall_entry_addr() -> u64 {
+    extern "C" {
+        fn syscall_entry();
+    }
+    syscall_entry as *const () as u64
+}
+
+core::arch::global_asm!(
+    ".global syscall_entry",
+    "syscall_entry:",
+    // Switch to the thread's kernel stack
+    "mov [rip + {user_rsp}], rsp",
+    "mov rsp, [rip + {kernel_rsp}]",
+    // Build a SyscallFrame
+    "push qword ptr [rip + {user_rsp}]",
+    "push rcx",
+    "push r11",
+    "push rax",
+    "push rdi",
+    "push rsi",
+    "push rdx",
+    "push r10",
+    "push r8",
+    "push r9",
+    // The user context is safe on this thread's stack; allow preemption
+    "sti",
+    "mov rdi, rsp",
+    "cld",
+    "call {dispatch}",
+    // Restore the user context and return to ring 3 (result in rax)
+    "cli",
+    "pop r9",
+    "pop r8",
+    "pop r10",
+    "pop rdx",
+    "pop rsi",
+    "pop rdi",
+    "add rsp, 8",
+    "pop r11",
+    "pop rcx",
+    "pop rsp",
+    "sysretq",
+    user_rsp = sym SYSCALL_USER_RSP,
+    kernel_rsp = sym SYSCALL_KERNEL_RSP,
+    dispatch = sym syscall_dispatch,
+);
+
+/// Look up and run the handler for `frame.rax`. Returns the value for RAX.
+extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) -> u64 {
+    let handler = SYSCALL_TABLE.get(frame.rax as usize).copied().flatten();
+    let result = match handler {
+        Some(handler) => handler(frame),
+        None => Err(errno::ENOSYS),
+    };
+    // On the way back to ring 3 the thread holds nothing in the kernel,
+    // so a kill that came in during the call takes effect here.
+    scheduler::exit_if_killed();
+    match result {
+        Ok(value) => value,
+        Err(errno) => (-errno) as u64,
+    }
+}
+
+fn fs_errno(e: FsError) -> i64 {
+    match e {
+        FsError::NotFound => errno::ENOENT,
+        FsError::AlreadyExists => errno::EEXIST,
+        FsError::NotADirectory => errno::ENOTDIR,
+        FsError::NotAFile => errno::EISDIR,
+        FsError::DirectoryNotEmpty => errno::ENOTEMPTY,
+        FsError::InvalidPath => errno::EINVAL,
+        FsError::Busy => errno::EBUSY,
+        FsError::NotSupported => errno::EOPNOTSUPP,
+        FsError::AccessMode => errno::EBADF,
+        FsError::InvalidSeek => errno::EINVAL,
+        FsError::CrossDevice => errno::EXDEV,
+        FsError::TooManyLinks => errno::ELOOP,
+        FsError::Io | FsError::Corrupted => errno::EIO,
+        FsError::NoSpace => errno::ENOSPC,
+        FsError::ReadOnly => errno::EROFS,
+    }
+}
+
+/// Check that `[addr, addr + len)` is accessible with `flags` in the
+/// caller's address space.
+fn check_user(addr: u64, len: u64, flags: VmaFlags) -> Result<(), i64> {
+    let space = scheduler::current_address_space().ok_or(errno::EFAULT)?;
+    let addr = VirtAddr::try_new(addr).map_err(|_| errno::EFAULT)?;
+    if space.check_access(addr, len, flags) {
+        Ok(())
+    } else {
+        Err(errno::EFAULT)
+    }
+}
+
+/// Copy a validated user buffer into the kernel.
+fn copy_from_user(addr: u64, len: u64) -> Result<Vec<u8>, i64> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    check_user(addr, len, VmaFlags::READ)?;
+    let mut buf = vec![0u8; len as usize];
+    unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), buf.len()) };
+    Ok(buf)
+}
+
+/// Copy `data` out to a validated, writable user buffer.
+fn copy_to_user(addr: u64, data: &[u8]) -> Result<(), i64> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    check_user(addr, data.len() as u64, VmaFlags::READ | VmaFlags::WRITE)?;
+    unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), addr as *mut u8, data.len()) };
+    Ok(())
+}
+
+fn current_pid() -> Result<u64, i64> {
+    scheduler::current_pid().ok_or(errno::EINVAL)
+}
+
+/// The calling process's open file for `fd`.
+fn get_file(fd: u64) -> Result<Arc<OpenFile>, i64> {
+    let pid = current_pid()?;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let table = PROCESS_TABLE.lock();
+        let process = table.as_ref().and_then(|t| t.get(pid)).ok_or(errno::EBADF)?;
+        process.files.get(fd as usize).ok_or(errno::EBADF)
+    })
+}
+
+// --- Handlers ---
+
+/// read(fd, buf, len) -> bytes read
+fn sys_read(frame: &SyscallFrame) -> SysResult {
+    let (fd, buf, len) = (frame.rdi, frame.rsi, frame.rdx.min(MAX_IO_LEN));
+    match fd {
+        // Console input belongs to the shell; programs see end-of-file.
+        0 => return Ok(0),
+        1 | 2 => return Err(errno::EBADF),
+        _ => {}
+    }
+    check_user(buf, len, VmaFlags::READ | VmaFlags::WRITE)?;
+    let file = get_file(fd)?;
+    let mut data = vec![0; len as usize];
+    let read = file
+        .read(VFS.lock().as_ref().ok_or(errno::ENOENT)?, &mut data)
+        .map_err(fs_errno)?;
+    data.truncate(read);
+    copy_to_user(buf, &data)?;
+    Ok(data.len() as u64)
+}
+
+/// write(fd, buf, len) -> bytes written
+fn sys_write(frame: &SyscallFrame) -> SysResult {
+    let (fd, buf, len) = (frame.rdi, frame.rsi, frame.rdx.min(MAX_IO_LEN));
+    let data = copy_from_user(buf, len)?;
+    match fd {
+        0 => Err(errno::EBADF),
+        1 | 2 => {
+            crate::print!("{}", String::from_utf8_lossy(&data));
+            Ok(data.len() as u64)
+        }
+        _ => {
+            let file = get_file(fd)?;
+            let written = file
+                .write(VFS.lock().as_mut().ok_or(errno::ENOENT)?, &data)
+                .map_err(fs_errno)?;
+            Ok(written as u64)
+        }
+    }
+}
+
+/// open(path, path_len, flags) -> fd. Paths are resolved from the root.
+fn sys_open(frame: &SyscallFrame) -> SysResult {
+    let (path, path_len, flags) = (frame.rdi, frame.rsi, frame.rdx);
+    if path_len == 0 || path_len > MAX_PATH_LEN {
+        return Err(errno::EINVAL);
+    }
+    let mut open_flags = match flags & 0b11 {
+        O_RDONLY => OpenFlags::READ,
+        O_WRONLY => OpenFlags::WRITE,
+        O_RDWR => OpenFlags::READ | OpenFlags::WRITE,
+        _ => return Err(errno::EINVAL),
+    };
+    for (bit, flag) in [
+        (O_CREAT, OpenFlags::CREATE),
+        (O_TRUNC, OpenFlags::TRUNCATE),
+        (O_APPEND, OpenFlags::APPEND),
+    ] {
+        if flags & bit != 0 {
+            open_flags = open_flags | flag;
+        }
+    }
+    let path = copy_from_user(path, path_len)?;
+    let path = core::str::from_utf8(&path).map_err(|_| errno::EINVAL)?;
+
+    let file = {
+        let mut fs = VFS.lock();
+        let fs = fs.as_mut().ok_or(errno::ENOENT)?;
+        let root = fs.root();
+        fs.open(path, open_flags, root).map_err(fs_errno)?
+    };
+
+    let pid = current_pid()?;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut table = PROCESS_TABLE.lock();
+        let process = table.as_mut().and_then(|t| t.get_mut(pid)).ok_or(errno::EINVAL)?;
+        let fd = process.files.insert(file).ok_or(errno::EMFILE)?;
+        Ok(fd as u64)
+    })
+}
+
+/// close(fd) -> 0
+fn sys_close(frame: &SyscallFrame) -> SysResult {
+    let pid = current_pid()?;
+    let file = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut table = PROCESS_TABLE.lock();
+        let process = table.as_mut().and_then(|t| t.get_mut(pid)).ok_or(errno::EBADF)?;
+        process.files.remove(frame.rdi as usize).ok_or(errno::EBADF)
+    })?;
+    drop(file);
+    // Free the file now if this was the last handle on an unlinked node.
+    if let Some(fs) = VFS.lock().as_mut() {
+        fs.reap();
+    }
+    Ok(0)
+}
+
+/// lseek(fd, offset, whence) -> new offset
+fn sys_lseek(frame: &SyscallFrame) -> SysResult {
+    let (fd, offset, whence) = (frame.rdi, frame.rsi as i64, frame.rdx);
+    let pos = match whence {
+        SEEK_SET => SeekFrom::Start(u64::try_from(offset).map_err(|_| errno::EINVAL)?),
+        SEEK_CUR => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return Err(errno::EINVAL),
+    };
+    if fd < FIRST_FILE_FD as u64 {
+        return Err(errno::ESPIPE);
+    }
+    let file = get_file(fd)?;
+    file.seek(VFS.lock().as_ref().ok_or(errno::ENOENT)?, pos)
+        .map(|offset| offset as u64)
+        .map_err(fs_errno)
+}
+
+/// yield() -> 0
+fn sys_yield(_frame: &SyscallFrame) -> SysResult {
+    scheduler::yield_now();
+    Ok(0)
+}
+
+/// sleep(ms) -> 0
+fn sys_sleep(frame: &SyscallFrame) -> SysResult {
+    if frame.rdi > MAX_SLEEP_MS {
+        return Err(errno::EINVAL);
+    }
+    scheduler::sleep_ms(frame.rdi);
+    Ok(0)
+}
+
+/// getpid() -> pid
+fn sys_getpid(_frame: &SyscallFrame) -> SysResult {
+    current_pid()
+}
+
+/// spawn(entry, stack_top, arg) -> pid of a new thread in the caller's
+/// address space, starting at `entry` with RSP = `stack_top`, RDI = `arg`.
+fn sys_spawn(frame: &SyscallFrame) -> SysResult {
+    let (entry, stack_top, arg) = (frame.rdi, frame.rsi, frame.rdx);
+    check_user(entry, 1, VmaFlags::READ | VmaFlags::EXEC)?;
+    let stack_last = stack_top.checked_sub(8).ok_or(errno::EFAULT)?;
+    check_user(stack_last, 8, VmaFlags::READ | VmaFlags::WRITE)?;
+
+    let space = scheduler::current_address_space().ok_or(errno::EFAULT)?;
+    let parent = current_pid()?;
+    let name = x86_64::instructions::interrupts::without_interrupts(|| {
+        let table = PROCESS_TABLE.lock();
+        table
+            .as_ref()
+            .and_then(|t| t.get(parent))
+            .map(|p| p.name.clone())
+            .unwrap_or_else(|| String::from("user"))
+    });
+    let pid = scheduler::spawn_user_thread(
+        name,
+        space,
+        VirtAddr::new(entry),
+        VirtAddr::new(stack_top),
+        arg,
+        Some(parent),
+    );
+    Ok(pid)
+}
+
+/// exit(code) -> never returns
+fn sys_exit(frame: &SyscallFrame) -> SysResult {
+    scheduler::terminate_current_thread(frame.rdi as i32);
+}
@@ -1,11 +1,14 @@
 /// CPU state saved/restored on timer interrupt for preemptive context switching.
 ///
+/// The same stub layout serves the yield interrupt, which a thread raises
+/// to switch away without waiting for the next tick.
+///
 /// The raw ISR stub pushes all 15 GP registers onto the current stack,
 /// then calls the Rust handler with RSP as the argument. The handler
 /// returns a (possibly different) RSP, and the stub pops registers from
 /// the new frame and does `iretq`.
 
-/// Saved CPU state on the stack during a timer interrupt.
+/// Saved CPU state on the stack during a timer or yield interrupt.
 /// Field order matches the push/pop order in the assembly ISR.
 #[repr(C)]
 pub struct InterruptFrame {
@@ -42,11 +45,28 @@ pub fn timer_isr_addr() -> u64 {
     timer_isr as *const () as u64
 }
 
-// Raw timer ISR: save all GP registers, call Rust handler, restore (possibly different) frame.
+/// Returns the address of the raw yield ISR assembly stub for IDT registration.
+pub fn yield_isr_addr() -> u64 {
+    extern "C" {
+        fn yield_isr();
+    }
+    yield_isr as *const () as u64
+}
+
+// Raw ISR stubs: save all GP registers, call the Rust handler whose address
+// the stub put in rax, restore the (possibly different) frame it returns.
 core::arch::global_asm!(
     ".global timer_isr",
     "timer_isr:",
     "push rax",
+    "lea rax, [rip + timer_tick_handler]",
+    "jmp isr_common",
+    ".global yield_isr",
+    "yield_isr:",
+    "push rax",
+    "lea rax, [rip + yield_handler]",
+    "jmp isr_common",
+    "isr_common:",
     "push rbx",
     "push rcx",
     "push rdx",
@@ -66,7 +86,7 @@ core::arch::global_asm!(
     // Clear direction flag (SysV ABI requires DF=0 on function entry)
     "cld",
     // Call Rust handler — returns new RSP in rax
-    "call timer_tick_handler",
+    "call rax",
     // Switch to returned stack frame (may be a different thread's stack)
     "mov rsp, rax",
     // Restore registers from the (possibly new) frame
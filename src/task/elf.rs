@@ -0,0 +1,198 @@
+/// ELF64 executable parsing.
+///
+/// Only what the loader needs: the file header, and the program headers of
+/// PT_LOAD segments of statically linked x86_64 executables (ET_EXEC).
+/// Section headers, dynamic linking and relocation are not supported.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::memory::vma::VmaFlags;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 0x3E;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const HEADER_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+#[derive(Debug)]
+pub enum ElfError {
+    BadMagic,
+    Truncated,
+    Unsupported(&'static str),
+    BadSegment,
+    BadEntry,
+}
+
+impl core::fmt::Display for ElfError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ElfError::BadMagic => write!(f, "not an ELF file"),
+            ElfError::Truncated => write!(f, "file is truncated"),
+            ElfError::Unsupported(what) => write!(f, "unsupported ELF: {}", what),
+            ElfError::BadSegment => write!(f, "invalid program segment"),
+            ElfError::BadEntry => write!(f, "entry point is not in an executable segment"),
+        }
+    }
+}
+
+/// A PT_LOAD segment: `file_size` bytes from `offset` in the file are
+/// loaded at `vaddr`; the rest up to `mem_size` is zero-filled.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub vaddr: u64,
+    pub offset: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+    pub flags: VmaFlags,
+}
+
+#[derive(Debug)]
+pub struct ElfImage {
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+}
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+/// Parse the headers of an ELF64 executable.
+pub fn parse(data: &[u8]) -> Result<ElfImage, ElfError> {
+    if data.len() < HEADER_SIZE {
+        return Err(if data.starts_with(&ELF_MAGIC) { ElfError::Truncated } else { ElfError::BadMagic });
+    }
+    if data[0..4] != ELF_MAGIC {
+        return Err(ElfError::BadMagic);
+    }
+    if data[4] != ELFCLASS64 {
+        return Err(ElfError::Unsupported("not 64-bit"));
+    }
+    if data[5] != ELFDATA2LSB {
+        return Err(ElfError::Unsupported("not little-endian"));
+    }
+    if u16_at(data, 16) != ET_EXEC {
+        return Err(ElfError::Unsupported("not a static executable"));
+    }
+    if u16_at(data, 18) != EM_X86_64 {
+        return Err(ElfError::Unsupported("not x86_64"));
+    }
+
+    let entry = u64_at(data, 24);
+    let phoff = u64_at(data, 32) as usize;
+    let phentsize = u16_at(data, 54) as usize;
+    let phnum = u16_at(data, 56) as usize;
+    if phentsize < PHDR_SIZE {
+        return Err(ElfError::Unsupported("program header size"));
+    }
+    let table_end = phnum
+        .checked_mul(phentsize)
+        .and_then(|len| len.checked_add(phoff))
+        .ok_or(ElfError::Truncated)?;
+    if table_end > data.len() {
+        return Err(ElfError::Truncated);
+    }
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        if u32_at(data, ph) != PT_LOAD {
+            continue;
+        }
+        let p_flags = u32_at(data, ph + 4);
+        let segment = Segment {
+            offset: u64_at(data, ph + 8),
+            vaddr: u64_at(data, ph + 16),
+            file_size: u64_at(data, ph + 32),
+            mem_size: u64_at(data, ph + 40),
+            flags: segment_flags(p_flags),
+        };
+        let file_end = segment.offset.checked_add(segment.file_size).ok_or(ElfError::BadSegment)?;
+        if file_end > data.len() as u64 || segment.file_size > segment.mem_size {
+            return Err(ElfError::BadSegment);
+        }
+        segments.push(segment);
+    }
+    if segments.is_empty() {
+        return Err(ElfError::Unsupported("no loadable segments"));
+    }
+    let executes_entry = |s: &Segment| {
+        s.flags.contains(VmaFlags::EXEC)
+            && s.vaddr <= entry
+            && s.vaddr.checked_add(s.mem_size).is_some_and(|end| entry < end)
+    };
+    if !segments.iter().any(executes_entry) {
+        return Err(ElfError::BadEntry);
+    }
+    Ok(ElfImage { entry, segments })
+}
+
+fn segment_flags(p_flags: u32) -> VmaFlags {
+    let mut flags = VmaFlags::USER;
+    if p_flags & PF_R != 0 {
+        flags = flags | VmaFlags::READ;
+    }
+    if p_flags & PF_W != 0 {
+        flags = flags | VmaFlags::WRITE;
+    }
+    if p_flags & PF_X != 0 {
+        flags = flags | VmaFlags::EXEC;
+    }
+    flags
+}
+
+/// Wrap raw machine code in a minimal executable with a single read/execute
+/// segment at `vaddr`, entered at its first byte. Used to install the
+/// built-in demo programs as files.
+pub fn wrap_code(code: &[u8], vaddr: u64) -> Vec<u8> {
+    let offset = (HEADER_SIZE + PHDR_SIZE) as u64;
+    let mut elf = Vec::with_capacity(offset as usize + code.len());
+
+    // File header
+    elf.extend_from_slice(&ELF_MAGIC);
+    elf.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&ET_EXEC.to_le_bytes());
+    elf.extend_from_slice(&EM_X86_64.to_le_bytes());
+    elf.extend_from_slice(&1u32.to_le_bytes()); // e_version
+    elf.extend_from_slice(&(vaddr + offset).to_le_bytes()); // e_entry
+    elf.extend_from_slice(&(HEADER_SIZE as u64).to_le_bytes()); // e_phoff
+    elf.extend_from_slice(&0u64.to_le_bytes()); // e_shoff
+    elf.extend_from_slice(&0u32.to_le_bytes()); // e_flags
+    elf.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes()); // e_ehsize
+    elf.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes()); // e_phentsize
+    elf.extend_from_slice(&1u16.to_le_bytes()); // e_phnum
+    elf.extend_from_slice(&[0; 6]); // e_shentsize, e_shnum, e_shstrndx
+
+    // Program header: the whole file is mapped, so the code lands at
+    // `vaddr + offset` just like the headers in front of it.
+    let size = offset + code.len() as u64;
+    elf.extend_from_slice(&PT_LOAD.to_le_bytes());
+    elf.extend_from_slice(&(PF_R | PF_X).to_le_bytes());
+    elf.extend_from_slice(&0u64.to_le_bytes()); // p_offset
+    elf.extend_from_slice(&vaddr.to_le_bytes()); // p_vaddr
+    elf.extend_from_slice(&vaddr.to_le_bytes()); // p_paddr
+    elf.extend_from_slice(&size.to_le_bytes()); // p_filesz
+    elf.extend_from_slice(&size.to_le_bytes()); // p_memsz
+    elf.extend_from_slice(&0x1000u64.to_le_bytes()); // p_align
+
+    elf.extend_from_slice(code);
+    elf
+}
@@ -1,8 +1,12 @@
 pub mod context;
+pub mod elf;
 pub mod executor;
 pub mod keyboard;
 pub mod process;
 pub mod scheduler;
+pub mod stack;
+pub mod sync;
+pub mod user;
 
 extern crate alloc;
 
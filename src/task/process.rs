@@ -2,10 +2,12 @@ extern crate alloc;
 
 use alloc::collections::BTreeMap;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use spin::Mutex;
 
 use super::TaskId;
+use crate::filesystem::OpenFile;
 
 pub type Pid = u64;
 
@@ -40,6 +42,63 @@ pub struct Process {
     pub parent_pid: Option<Pid>,
     pub exit_code: Option<i32>,
     pub is_thread: bool,
+    /// Files opened through the `open` syscall.
+    pub files: FdTable,
+}
+
+/// Descriptors below this are the console (stdin, stdout, stderr).
+pub const FIRST_FILE_FD: usize = 3;
+
+/// Most files a process may have open at once.
+pub const MAX_OPEN_FILES: usize = 64;
+
+/// A process's file descriptors. Descriptors are handed out lowest-free
+/// first, starting at `FIRST_FILE_FD`.
+#[derive(Default)]
+pub struct FdTable {
+    files: Vec<Option<Arc<OpenFile>>>,
+}
+
+impl FdTable {
+    /// Install `file` and return its descriptor, or `None` if the table
+    /// is full.
+    pub fn insert(&mut self, file: Arc<OpenFile>) -> Option<usize> {
+        let index = match self.files.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if self.files.len() < MAX_OPEN_FILES => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+            None => return None,
+        };
+        self.files[index] = Some(file);
+        Some(index + FIRST_FILE_FD)
+    }
+
+    pub fn get(&self, fd: usize) -> Option<Arc<OpenFile>> {
+        let index = fd.checked_sub(FIRST_FILE_FD)?;
+        self.files.get(index)?.clone()
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Option<Arc<OpenFile>> {
+        let index = fd.checked_sub(FIRST_FILE_FD)?;
+        self.files.get_mut(index)?.take()
+    }
+
+    /// Number of open descriptors.
+    pub fn len(&self) -> usize {
+        self.files.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every handle. Nothing is locked here; the VFS frees unlinked
+    /// files on its next operation.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
 }
 
 pub struct ProcessTable {
@@ -64,6 +123,7 @@ impl ProcessTable {
                 parent_pid,
                 exit_code: None,
                 is_thread,
+                files: FdTable::default(),
             },
         );
     }
@@ -72,6 +132,7 @@ impl ProcessTable {
         if let Some(proc) = self.processes.get_mut(&pid) {
             proc.state = ProcessState::Terminated;
             proc.exit_code = Some(exit_code);
+            proc.files.clear();
         }
     }
 
@@ -91,6 +152,10 @@ impl ProcessTable {
         self.processes.get(&pid)
     }
 
+    pub fn get_mut(&mut self, pid: Pid) -> Option<&mut Process> {
+        self.processes.get_mut(&pid)
+    }
+
     pub fn is_alive(&self, pid: Pid) -> bool {
         self.processes
             .get(&pid)
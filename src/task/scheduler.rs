@@ -4,32 +4,93 @@
 /// preempted by the timer interrupt. The executor's main loop is the
 /// "idle context" — when no threads are ready, control returns there
 /// to poll async futures as before.
+///
+/// Threads are picked from a multi-level feedback queue. A thread's
+/// priority (0 runs first) is its feedback level plus an offset from its
+/// nice value. Each level has a longer time slice than the one above; a
+/// thread that uses up its slice drops a level, and one that goes to
+/// sleep before it does climbs one, so interactive threads stay ahead of
+/// CPU-bound ones. The running thread is preempted when its slice ends or
+/// a higher-priority thread is ready. A ready thread that has waited
+/// `AGING_TICKS` moves up one queue, so nothing starves.
+///
+/// Sleeping threads leave the run queues for a sleep queue ordered by
+/// wake tick, so each tick only looks at the sleepers that are due.
+/// Sleeping, yielding and exiting switch away at once through the yield
+/// interrupt instead of halting until the next tick.
+///
+/// Threads waiting for a lock or an event (see `task::sync`) are parked
+/// on a wait queue key in the blocked list until something wakes them.
+///
+/// A killed thread is only torn down where it holds nothing in the kernel.
+/// One that hasn't run yet or was preempted in ring 3 goes at once; any
+/// other exits by itself at its next kill point (returning from a syscall,
+/// sleeping, yielding or waiting in `task::sync`) once it holds no
+/// blocking lock. Sleepers are woken early and parked threads have their
+/// wait cancelled so they get there.
+///
+/// User threads start in ring 3 through the same synthetic frame, just
+/// with user selectors. Interrupts taken in ring 3 land on the thread's
+/// kernel stack, which is published in the TSS whenever a thread is
+/// switched in.
 
 extern crate alloc;
 
 use alloc::collections::VecDeque;
 use alloc::string::String;
+use alloc::sync::Arc;
 use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
 
+use x86_64::VirtAddr;
+
 use super::context::InterruptFrame;
+use crate::memory::address_space::AddressSpace;
 use super::process::PROCESS_TABLE;
+use super::stack::{ThreadStack, DEFAULT_STACK_SIZE};
 use super::TaskId;
 
-const THREAD_STACK_SIZE: usize = 16 * 1024; // 16 KiB per thread
+/// Exit code recorded for a thread killed by a fatal fault (128 + SIGSEGV).
+pub const FAULT_EXIT_CODE: i32 = 139;
+/// Exit code recorded for a thread killed with `kill_thread`.
+pub const KILLED_EXIT_CODE: i32 = 1;
 
 // Kernel segment selectors (must match gdt.rs init order)
 const KERNEL_CS: u64 = 0x08;
 const KERNEL_SS: u64 = 0x10;
+// User segment selectors with RPL 3
+const USER_SS: u64 = 0x28 | 3;
+const USER_CS: u64 = 0x30 | 3;
 
 // IF (interrupts enabled) + reserved bit 1 — required for iretq
 const RFLAGS_IF: u64 = 0x202;
 
+/// Feedback levels a thread moves between.
+pub const LEVELS: usize = 4;
+/// Time slice at each level, in timer ticks (10 ms).
+const QUANTUM_TICKS: [u32; LEVELS] = [1, 2, 4, 8];
+
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+/// Nice values per priority step.
+const NICE_STEP: usize = 5;
+
+/// Number of run queues: every level shifted by every nice offset.
+pub const PRIORITIES: usize = LEVELS + (NICE_MAX - NICE_MIN) as usize / NICE_STEP;
+/// Ticks a ready thread waits before it moves up a queue.
+pub const AGING_TICKS: u64 = 10;
+
+/// Priority of a thread at `level` with `nice`; 0 runs first.
+fn priority(level: usize, nice: i8) -> usize {
+    level + (nice - NICE_MIN) as usize / NICE_STEP
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ThreadState {
     Ready,
     Running,
     Sleeping(u64), // absolute tick count at which to wake
+    Blocked(u64),  // wait queue key, see `park`
     Terminated,
 }
 
@@ -38,21 +99,91 @@ pub struct Thread {
     pub name: String,
     pub state: ThreadState,
     pub parent_pid: Option<u64>,
-    stack_bottom: *mut u8,
-    stack_size: usize,
+    stack: ThreadStack,
     saved_frame: *mut InterruptFrame,
+    /// Address space to run in; `None` runs on the kernel's page tables.
+    address_space: Option<Arc<AddressSpace>>,
+    nice: i8,
+    /// Feedback level, `0..LEVELS`.
+    level: usize,
+    /// Queue the thread is in; above its priority after aging.
+    queue: usize,
+    /// Tick at which it entered `queue`.
+    enqueued: u64,
+    /// Ticks run at the current level, across however many times the
+    /// thread was preempted, so being interrupted doesn't reset its slice.
+    slice_ticks: u32,
+    /// Ticks run and time slices started, in total.
+    ticks: u64,
+    slices: u64,
+    /// Set by `kill_thread` for a thread that exits on its own at its next
+    /// kill point.
+    killed: bool,
+    /// `task::sync` locks held, which a killed thread must let go first.
+    locks: u32,
+}
+
+impl Thread {
+    fn priority(&self) -> usize {
+        priority(self.level, self.nice)
+    }
+
+    /// True if the thread has been killed and can exit without leaving a
+    /// lock held.
+    fn must_exit(&self) -> bool {
+        self.killed && self.locks == 0
+    }
+
+    /// True if the thread can be torn down where it stands, off the CPU:
+    /// it hasn't run yet, or was preempted in ring 3 with nothing held in
+    /// the kernel.
+    fn at_rest(&self) -> bool {
+        // A thread off the CPU always has a valid frame on its stack.
+        self.slices == 0 || unsafe { (*self.saved_frame).cs & 3 == 3 }
+    }
+}
+
+/// Scheduling state of a thread, for `ps`.
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadInfo {
+    pub pid: u64,
+    pub priority: usize,
+    pub nice: i8,
+    pub level: usize,
+    pub ticks: u64,
+    pub slices: u64,
+}
+
+impl ThreadInfo {
+    fn of(thread: &Thread) -> ThreadInfo {
+        ThreadInfo {
+            pid: thread.pid,
+            priority: thread.priority(),
+            nice: thread.nice,
+            level: thread.level,
+            ticks: thread.ticks,
+            slices: thread.slices,
+        }
+    }
 }
 
 // Thread contains raw pointers but is only accessed with the scheduler lock held.
 unsafe impl Send for Thread {}
 
 pub struct Scheduler {
-    threads: VecDeque<Thread>,
+    /// Ready threads, by queue (highest priority first).
+    queues: [VecDeque<Thread>; PRIORITIES],
+    /// Sleeping threads, soonest wake tick first.
+    sleeping: VecDeque<Thread>,
+    /// Parked threads, in the order they blocked.
+    blocked: VecDeque<Thread>,
     current: Option<Thread>,
     idle_frame: *mut InterruptFrame,
-    // Deferred stack deallocation: we can't free a thread's stack while the
-    // ISR is still running on it, so we defer it to the next schedule() call.
-    deferred_dealloc: Option<(*mut u8, usize)>,
+    // Deferred thread teardown: we can't free a thread's stack while the
+    // ISR is still running on it (or its page tables while they're still
+    // loaded), so we defer dropping it to the next schedule() call.
+    // Threads killed off the CPU pass through here too, one at a time.
+    deferred_drop: Option<Thread>,
 }
 
 unsafe impl Send for Scheduler {}
@@ -68,10 +199,12 @@ fn alloc_thread_id() -> u64 {
 /// Initialize the scheduler. Call after process table init.
 pub fn init() {
     *SCHEDULER.lock() = Some(Scheduler {
-        threads: VecDeque::new(),
+        queues: core::array::from_fn(|_| VecDeque::new()),
+        sleeping: VecDeque::new(),
+        blocked: VecDeque::new(),
         current: None,
         idle_frame: core::ptr::null_mut(),
-        deferred_dealloc: None,
+        deferred_drop: None,
     });
     SCHEDULER_ENABLED.store(true, Ordering::Release);
     crate::serial_println!("Preemptive scheduler initialized");
@@ -86,32 +219,71 @@ pub fn is_enabled() -> bool {
 pub fn try_schedule(current_frame: *mut InterruptFrame) -> Option<*mut InterruptFrame> {
     let mut guard = SCHEDULER.try_lock()?;
     let sched = guard.as_mut()?;
-    Some(sched.schedule(current_frame))
+    Some(sched.schedule(current_frame, true))
+}
+
+/// Called from the yield ISR: switch to the next ready thread without
+/// charging the current one a tick. `None` if the lock is held.
+pub fn try_switch(current_frame: *mut InterruptFrame) -> Option<*mut InterruptFrame> {
+    let mut guard = SCHEDULER.try_lock()?;
+    let sched = guard.as_mut()?;
+    Some(sched.schedule(current_frame, false))
 }
 
 impl Scheduler {
-    fn schedule(&mut self, current_frame: *mut InterruptFrame) -> *mut InterruptFrame {
-        // Free any previously-deferred stack (safe: we're now on a different stack)
-        if let Some((ptr, size)) = self.deferred_dealloc.take() {
-            dealloc_stack(ptr, size);
+    /// Save `current_frame` and pick the thread to run next. `tick` is set
+    /// when called from the timer, which charges the running thread and
+    /// may let it keep the CPU; otherwise it is giving the CPU up.
+    fn schedule(&mut self, current_frame: *mut InterruptFrame, tick: bool) -> *mut InterruptFrame {
+        // Free any previously-deferred thread (safe: we're now on a different
+        // stack and a different CR3)
+        drop(self.deferred_drop.take());
+
+        let now = crate::interrupts::TICK_COUNT.load(Ordering::Relaxed);
+        if tick {
+            self.wake_sleepers(now);
+            self.age(now);
         }
 
         // Save context of whoever was running
         match self.current.take() {
             Some(mut thread) => {
                 thread.saved_frame = current_frame;
+                let running = thread.state == ThreadState::Running;
+                if tick && running {
+                    thread.ticks += 1;
+                    thread.slice_ticks += 1;
+                }
+                let expired = thread.slice_ticks >= QUANTUM_TICKS[thread.level];
+                if tick && running && !expired && !self.ready_above(thread.priority()) {
+                    // Keep running
+                    self.current = Some(thread);
+                    self.defer_killed();
+                    return current_frame;
+                }
                 match thread.state {
                     ThreadState::Terminated => {
                         // Defer deallocation — the ISR is still running on this stack
-                        self.deferred_dealloc = Some((thread.stack_bottom, thread.stack_size));
+                        self.deferred_drop = Some(thread);
                     }
-                    ThreadState::Sleeping(_) => {
-                        // Preserve sleep state — don't overwrite to Ready
-                        self.threads.push_back(thread);
+                    ThreadState::Sleeping(_) | ThreadState::Blocked(_) => {
+                        // Gave up the CPU early: treat as interactive.
+                        if !expired {
+                            thread.level = thread.level.saturating_sub(1);
+                        }
+                        thread.slice_ticks = 0;
+                        match thread.state {
+                            ThreadState::Sleeping(wake_tick) => self.sleep(thread, wake_tick),
+                            _ => self.blocked.push_back(thread),
+                        }
                     }
                     _ => {
+                        if expired {
+                            thread.level = (thread.level + 1).min(LEVELS - 1);
+                            thread.slice_ticks = 0;
+                        }
                         thread.state = ThreadState::Ready;
-                        self.threads.push_back(thread);
+                        self.enqueue(thread, now);
                     }
                 }
             }
@@ -121,84 +293,246 @@ impl Scheduler {
             }
         }
 
-        // Clean out terminated threads, wake expired sleepers, find next ready one
-        let current_tick = crate::interrupts::TICK_COUNT.load(core::sync::atomic::Ordering::Relaxed);
-        let len = self.threads.len();
-        for _ in 0..len {
-            if let Some(mut thread) = self.threads.pop_front() {
-                if thread.state == ThreadState::Terminated {
-                    // Threads in the ready queue are not currently executing,
-                    // so their stacks can be freed immediately.
-                    dealloc_stack(thread.stack_bottom, thread.stack_size);
+        self.defer_killed();
+
+        // Pick the first ready thread in the highest-priority queue
+        for queue in self.queues.iter_mut() {
+            let Some(index) = queue.iter().position(|t| t.state == ThreadState::Ready) else {
+                continue;
+            };
+            let mut thread = queue.remove(index).unwrap();
+            thread.state = ThreadState::Running;
+            thread.slices += 1;
+            let frame = thread.saved_frame;
+            switch_address_space(thread.address_space.as_deref());
+            crate::gdt::set_kernel_stack(thread.stack.top());
+            crate::syscall::set_kernel_stack(thread.stack.top());
+            self.current = Some(thread);
+            return frame;
+        }
+
+        // No ready threads — return to idle context
+        switch_address_space(None);
+        self.idle_frame
+    }
+
+    /// Put `thread` at the back of the queue for its priority.
+    fn enqueue(&mut self, mut thread: Thread, now: u64) {
+        thread.queue = thread.priority();
+        thread.enqueued = now;
+        self.queues[thread.queue].push_back(thread);
+    }
+
+    /// Put `thread` in the sleep queue, after any that wake no later.
+    fn sleep(&mut self, thread: Thread, wake_tick: u64) {
+        let index = self.sleeping.partition_point(|t| wake_tick_of(t) <= wake_tick);
+        self.sleeping.insert(index, thread);
+    }
+
+    /// Move sleepers whose wake tick has come to the run queues.
+    fn wake_sleepers(&mut self, now: u64) {
+        while self.sleeping.front().is_some_and(|t| wake_tick_of(t) <= now) {
+            let mut thread = self.sleeping.pop_front().unwrap();
+            thread.state = ThreadState::Ready;
+            self.enqueue(thread, now);
+        }
+    }
+
+    /// Move ready threads that have waited `AGING_TICKS` up a queue,
+    /// leaving the others where they are.
+    fn age(&mut self, now: u64) {
+        // Top down, so a thread moves at most one queue per tick.
+        for index in 1..PRIORITIES {
+            let mut i = 0;
+            while i < self.queues[index].len() {
+                let thread = &self.queues[index][i];
+                if thread.state != ThreadState::Ready || now - thread.enqueued < AGING_TICKS {
+                    i += 1;
                     continue;
                 }
-                // Wake sleeping threads whose time has come
-                if let ThreadState::Sleeping(wake_tick) = thread.state {
-                    if current_tick >= wake_tick {
-                        thread.state = ThreadState::Ready;
-                    }
-                }
-                // Pick first Ready thread
-                if thread.state == ThreadState::Ready {
-                    let frame = thread.saved_frame;
-                    self.current = Some(thread);
-                    self.current.as_mut().unwrap().state = ThreadState::Running;
-                    return frame;
-                }
-                // Still sleeping — put back
-                self.threads.push_back(thread);
+                let mut thread = self.queues[index].remove(i).unwrap();
+                thread.queue = index - 1;
+                thread.enqueued = now;
+                self.queues[index - 1].push_back(thread);
             }
         }
+    }
 
-        // No ready threads — return to idle context
-        self.idle_frame
+    /// Hand a thread killed while off the CPU to `deferred_drop`, if that
+    /// is free, so at most one thread is torn down per schedule.
+    fn defer_killed(&mut self) {
+        if self.deferred_drop.is_some() {
+            return;
+        }
+        for queue in self.queues.iter_mut() {
+            if let Some(index) = queue.iter().position(|t| t.state == ThreadState::Terminated) {
+                self.deferred_drop = queue.remove(index);
+                return;
+            }
+        }
+    }
+
+    /// True if a ready thread waits in a queue before `queue`.
+    fn ready_above(&self, queue: usize) -> bool {
+        self.queues[..queue]
+            .iter()
+            .any(|q| q.iter().any(|t| t.state == ThreadState::Ready))
+    }
+
+    fn threads(&self) -> impl Iterator<Item = &Thread> {
+        self.current
+            .iter()
+            .chain(self.queues.iter().flatten())
+            .chain(&self.sleeping)
+            .chain(&self.blocked)
+    }
+
+    fn threads_mut(&mut self) -> impl Iterator<Item = &mut Thread> {
+        self.current
+            .iter_mut()
+            .chain(self.queues.iter_mut().flatten())
+            .chain(&mut self.sleeping)
+            .chain(&mut self.blocked)
+    }
+
+    /// Set the running thread's state; returns its PID, or `None` in the
+    /// idle context.
+    fn set_current_state(&mut self, state: ThreadState) -> Option<u64> {
+        let thread = self.current.as_mut()?;
+        thread.state = state;
+        Some(thread.pid)
     }
 }
 
-fn dealloc_stack(stack_bottom: *mut u8, stack_size: usize) {
-    if !stack_bottom.is_null() {
-        unsafe {
-            let layout = alloc::alloc::Layout::from_size_align(stack_size, 16).unwrap();
-            alloc::alloc::dealloc(stack_bottom, layout);
-        }
+fn wake_tick_of(thread: &Thread) -> u64 {
+    match thread.state {
+        ThreadState::Sleeping(wake_tick) => wake_tick,
+        _ => 0,
     }
 }
 
-/// Spawn a new preemptible thread. Returns the thread's PID.
+/// Enter the scheduler through the yield interrupt, as the timer would but
+/// without waiting for a tick. Must not be called with `SCHEDULER` held.
+fn switch() {
+    unsafe {
+        core::arch::asm!("int {vector}", vector = const crate::interrupts::YIELD_VECTOR);
+    }
+}
+
+/// Load the page tables for `space` (or the kernel's) if not already active.
+fn switch_address_space(space: Option<&AddressSpace>) {
+    use x86_64::registers::control::Cr3;
+
+    let target = space
+        .map(|s| s.pml4_frame())
+        .unwrap_or_else(crate::memory::kernel_pml4);
+    let (active, flags) = Cr3::read();
+    if active != target {
+        unsafe { Cr3::write(target, flags) };
+    }
+}
+
+/// Spawn a new preemptible thread with the default stack size.
+/// Returns the thread's PID.
 pub fn spawn_thread(
     name: String,
     entry_fn: fn(u64),
     arg: u64,
     parent_pid: Option<u64>,
 ) -> u64 {
-    let pid = alloc_thread_id();
+    spawn_thread_with_stack(name, entry_fn, arg, parent_pid, DEFAULT_STACK_SIZE)
+}
 
-    // Allocate stack
-    let layout = alloc::alloc::Layout::from_size_align(THREAD_STACK_SIZE, 16).unwrap();
-    let stack_bottom = unsafe { alloc::alloc::alloc_zeroed(layout) };
-    if stack_bottom.is_null() {
-        panic!("Failed to allocate thread stack");
+/// Spawn a new preemptible thread that runs with `address_space` loaded.
+/// The thread's kernel stack stays in the shared kernel mappings.
+/// Returns the thread's PID.
+pub fn spawn_thread_in(
+    name: String,
+    entry_fn: fn(u64),
+    arg: u64,
+    parent_pid: Option<u64>,
+    address_space: Arc<AddressSpace>,
+) -> u64 {
+    let space = Some(address_space);
+    spawn(name, parent_pid, DEFAULT_STACK_SIZE, space, kernel_frame(entry_fn, arg))
+}
+
+/// Spawn a thread that starts at CPL 3 in `address_space`, at `entry` with
+/// stack pointer `user_stack` and `arg` in RDI. The caller must already
+/// have mapped the code and stack as user-accessible. Returns the PID.
+pub fn spawn_user_thread(
+    name: String,
+    address_space: Arc<AddressSpace>,
+    entry: VirtAddr,
+    user_stack: VirtAddr,
+    arg: u64,
+    parent_pid: Option<u64>,
+) -> u64 {
+    let frame = move |_kernel_stack_top| InterruptFrame {
+        r15: 0, r14: 0, r13: 0, r12: 0,
+        r11: 0, r10: 0, r9: 0, r8: 0,
+        rbp: 0,
+        rdi: arg,
+        rsi: 0,
+        rdx: 0, rcx: 0, rbx: 0, rax: 0,
+        rip: entry.as_u64(),
+        cs: USER_CS,
+        rflags: RFLAGS_IF,
+        rsp: user_stack.as_u64(),
+        ss: USER_SS,
+    };
+    spawn(name, parent_pid, DEFAULT_STACK_SIZE, Some(address_space), frame)
+}
+
+/// Spawn a new preemptible thread with a stack of `stack_size` bytes
+/// (rounded up to whole pages, at most `stack::MAX_STACK_SIZE`).
+/// Returns the thread's PID.
+pub fn spawn_thread_with_stack(
+    name: String,
+    entry_fn: fn(u64),
+    arg: u64,
+    parent_pid: Option<u64>,
+    stack_size: usize,
+) -> u64 {
+    spawn(name, parent_pid, stack_size, None, kernel_frame(entry_fn, arg))
+}
+
+/// Initial frame for a kernel thread: iretq jumps to thread_entry_wrapper
+/// in ring 0, which calls `entry_fn(arg)`.
+fn kernel_frame(entry_fn: fn(u64), arg: u64) -> impl FnOnce(u64) -> InterruptFrame {
+    move |stack_top| InterruptFrame {
+        r15: 0, r14: 0, r13: 0, r12: 0,
+        r11: 0, r10: 0, r9: 0, r8: 0,
+        rbp: 0,
+        rdi: arg,
+        rsi: entry_fn as u64,
+        rdx: 0, rcx: 0, rbx: 0, rax: 0,
+        rip: thread_entry_wrapper as *const () as u64,
+        cs: KERNEL_CS,
+        rflags: RFLAGS_IF,
+        rsp: stack_top, // thread starts with empty stack
+        ss: KERNEL_SS,
     }
-    let stack_top = unsafe { stack_bottom.add(THREAD_STACK_SIZE) } as u64;
+}
+
+fn spawn(
+    name: String,
+    parent_pid: Option<u64>,
+    stack_size: usize,
+    address_space: Option<Arc<AddressSpace>>,
+    initial_frame: impl FnOnce(u64) -> InterruptFrame,
+) -> u64 {
+    let pid = alloc_thread_id();
+
+    let stack = ThreadStack::new(stack_size).expect("Failed to allocate thread stack");
+    let stack_top = stack.top().as_u64();
 
     // Build a synthetic InterruptFrame at the top of the stack.
     // When the scheduler switches to this thread, the ISR will pop these
-    // registers and iretq will jump to thread_entry_wrapper.
+    // registers and iretq will jump to the thread's entry point.
     let frame_ptr = unsafe {
         let ptr = (stack_top as *mut InterruptFrame).sub(1);
-        core::ptr::write(ptr, InterruptFrame {
-            r15: 0, r14: 0, r13: 0, r12: 0,
-            r11: 0, r10: 0, r9: 0, r8: 0,
-            rbp: 0,
-            rdi: arg,
-            rsi: entry_fn as u64,
-            rdx: 0, rcx: 0, rbx: 0, rax: 0,
-            rip: thread_entry_wrapper as *const () as u64,
-            cs: KERNEL_CS,
-            rflags: RFLAGS_IF,
-            rsp: stack_top, // thread starts with empty stack
-            ss: KERNEL_SS,
-        });
+        core::ptr::write(ptr, initial_frame(stack_top));
         ptr
     };
 
@@ -207,9 +541,18 @@ pub fn spawn_thread(
         name: name.clone(),
         state: ThreadState::Ready,
         parent_pid,
-        stack_bottom,
-        stack_size: THREAD_STACK_SIZE,
+        stack,
         saved_frame: frame_ptr,
+        address_space,
+        nice: 0,
+        level: 0,
+        queue: 0,
+        enqueued: 0,
+        slice_ticks: 0,
+        ticks: 0,
+        slices: 0,
+        killed: false,
+        locks: 0,
     };
 
     // Register in process table (with interrupts disabled to prevent
@@ -225,7 +568,8 @@ pub fn spawn_thread(
     x86_64::instructions::interrupts::without_interrupts(|| {
         let mut sched = SCHEDULER.lock();
         if let Some(sched) = sched.as_mut() {
-            sched.threads.push_back(thread);
+            let now = crate::interrupts::TICK_COUNT.load(Ordering::Relaxed);
+            sched.enqueue(thread, now);
         }
     });
 
@@ -237,73 +581,254 @@ pub fn spawn_thread(
 extern "C" fn thread_entry_wrapper(arg: u64, entry_fn: u64) {
     let f: fn(u64) = unsafe { core::mem::transmute(entry_fn) };
     f(arg);
+    exit_if_killed();
     exit_current_thread();
 }
 
-/// Mark the current thread as terminated and halt until preempted.
+/// Mark the current thread as terminated and switch away for good.
 pub fn exit_current_thread() {
+    terminate_current_thread(0);
+}
+
+/// Mark the current thread as terminated with `exit_code` and switch away
+/// for good. Used by the `exit` syscall.
+pub fn terminate_current_thread(exit_code: i32) -> ! {
     // Acquire SCHEDULER lock with interrupts disabled to prevent preemption
     // while holding the lock. Release it before touching PROCESS_TABLE to
     // avoid nested lock deadlocks.
     let pid = x86_64::instructions::interrupts::without_interrupts(|| {
         let mut sched = SCHEDULER.lock();
-        if let Some(sched) = sched.as_mut() {
-            if let Some(thread) = sched.current.as_mut() {
-                thread.state = ThreadState::Terminated;
-                return Some(thread.pid);
-            }
-        }
-        None
+        sched.as_mut()?.set_current_state(ThreadState::Terminated)
     });
 
     if let Some(pid) = pid {
         x86_64::instructions::interrupts::without_interrupts(|| {
             let mut table = PROCESS_TABLE.lock();
             if let Some(table) = table.as_mut() {
-                table.terminate(pid, 0);
+                table.terminate(pid, exit_code);
             }
         });
     }
 
-    // Halt until the next timer tick preempts us and cleans up
+    // The scheduler drops the thread once it is off its stack. Outside a
+    // thread (or if the lock was busy) there is nothing to switch to yet.
     loop {
+        switch();
         x86_64::instructions::hlt();
     }
 }
 
-/// Kill a thread by PID. Marks it terminated; cleanup happens on next schedule.
+/// PID of the running thread, or `None` in the idle/executor context.
+pub fn current_pid() -> Option<u64> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let sched = SCHEDULER.lock();
+        sched.as_ref()?.current.as_ref().map(|t| t.pid)
+    })
+}
+
+/// Give up the rest of the time slice and switch to the next ready thread
+/// right away. The thread keeps its level and goes to the back of its
+/// queue, so it runs again at once if nothing of equal or higher priority
+/// is ready. A kill point (see `kill_thread`).
+pub fn yield_now() {
+    switch();
+    exit_if_killed();
+}
+
+/// Longest thread name kept in a `FaultedThread`.
+const FAULT_NAME_LEN: usize = 32;
+
+/// Information about the running thread needed to unwind it after a fault.
+/// The name is copied into a fixed buffer: the fault handler runs on its
+/// own IST stack and must not allocate.
+pub struct FaultedThread {
+    pub pid: u64,
+    pub stack_top: u64,
+    name: [u8; FAULT_NAME_LEN],
+    name_len: usize,
+}
+
+impl FaultedThread {
+    /// The thread's name, cut to `FAULT_NAME_LEN` bytes.
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("?")
+    }
+}
+
+/// Called from the page fault handler. Returns the current thread if the
+/// fault happened in thread context, so the handler can redirect it to
+/// `thread_fault_exit` instead of halting the machine.
+pub fn current_thread_for_fault() -> Option<FaultedThread> {
+    let guard = SCHEDULER.try_lock()?;
+    let thread = guard.as_ref()?.current.as_ref()?;
+    let mut name_len = thread.name.len().min(FAULT_NAME_LEN);
+    while !thread.name.is_char_boundary(name_len) {
+        name_len -= 1;
+    }
+    let mut name = [0; FAULT_NAME_LEN];
+    name[..name_len].copy_from_slice(&thread.name.as_bytes()[..name_len]);
+    Some(FaultedThread {
+        pid: thread.pid,
+        stack_top: thread.stack.top().as_u64(),
+        name,
+        name_len,
+    })
+}
+
+/// Address space of the running thread, if it has its own. Called from the
+/// page fault handler, so it only try-locks.
+pub fn current_address_space() -> Option<Arc<AddressSpace>> {
+    let guard = SCHEDULER.try_lock()?;
+    guard.as_ref()?.current.as_ref()?.address_space.clone()
+}
+
+/// Landing point for a thread killed by a fault. The page fault handler
+/// rewrites the faulting frame to resume here on a fresh stack.
+pub extern "C" fn thread_fault_exit() -> ! {
+    terminate_current_thread(FAULT_EXIT_CODE);
+}
+
+/// Exit the current thread after `kill_thread`. Called by a wait that was
+/// cancelled, once the waiter has undone its bookkeeping.
+pub fn exit_killed() -> ! {
+    terminate_current_thread(KILLED_EXIT_CODE);
+}
+
+/// True if the running thread has been killed and holds no lock.
+fn current_must_exit() -> bool {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let sched = SCHEDULER.lock();
+        sched.as_ref().and_then(|s| s.current.as_ref()).is_some_and(Thread::must_exit)
+    })
+}
+
+/// Exit if the running thread has been killed and holds no lock. Called
+/// at the kill points listed at `kill_thread`.
+pub fn exit_if_killed() {
+    if current_must_exit() {
+        exit_killed();
+    }
+}
+
+/// Count a `task::sync` lock taken (`taken`) or let go by the running
+/// thread. Nothing is counted outside a thread.
+pub fn count_lock(taken: bool) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        if let Some(thread) = sched.as_mut().and_then(|s| s.current.as_mut()) {
+            if taken {
+                thread.locks += 1;
+            } else {
+                thread.locks = thread.locks.saturating_sub(1);
+            }
+        }
+    });
+}
+
+/// Kill a thread by PID. Returns false if there is no such thread.
+///
+/// A thread that hasn't run yet or was preempted in ring 3 is marked
+/// terminated and cleaned up on a later schedule. Any other may be inside
+/// a VFS call or holding a spin lock like the ATA channel's, so it is only
+/// marked killed and exits by itself at its next kill point: on the way
+/// back from a syscall, or in `sleep_ms`, `yield_now` or a `task::sync`
+/// wait, once it holds no blocking lock. A sleeper is woken and a parked
+/// waiter has its wait cancelled to get it there. A kernel thread that
+/// loops without reaching a kill point can't be killed.
 pub fn kill_thread(pid: u64) -> bool {
     // Acquire SCHEDULER with interrupts disabled, release before touching
     // PROCESS_TABLE to avoid nested lock deadlocks.
     let found = x86_64::instructions::interrupts::without_interrupts(|| {
         let mut sched = SCHEDULER.lock();
-        if let Some(sched) = sched.as_mut() {
-            if let Some(ref mut current) = sched.current {
-                if current.pid == pid {
-                    current.state = ThreadState::Terminated;
-                    return true;
-                }
-            }
-            for thread in sched.threads.iter_mut() {
-                if thread.pid == pid {
-                    thread.state = ThreadState::Terminated;
-                    return true;
-                }
+        let sched = sched.as_mut()?;
+        if let Some(thread) = sched.queues.iter_mut().flatten().find(|t| t.pid == pid) {
+            if thread.state == ThreadState::Ready && !thread.killed && thread.at_rest() {
+                thread.state = ThreadState::Terminated;
+                return Some(true);
             }
+            thread.killed = true;
+            return Some(false);
         }
-        false
+        let thread = sched.threads_mut().find(|t| t.pid == pid)?;
+        thread.killed = true;
+        let waiting = matches!(thread.state, ThreadState::Sleeping(_) | ThreadState::Blocked(_));
+        if !waiting || !thread.must_exit() {
+            return Some(false);
+        }
+        // Wake it early so it reaches its kill point.
+        let mut thread = match sched.sleeping.iter().position(|t| t.pid == pid) {
+            Some(index) => sched.sleeping.remove(index)?,
+            None => {
+                let index = sched.blocked.iter().position(|t| t.pid == pid)?;
+                sched.blocked.remove(index)?
+            }
+        };
+        thread.state = ThreadState::Ready;
+        let now = crate::interrupts::TICK_COUNT.load(Ordering::Relaxed);
+        sched.enqueue(thread, now);
+        Some(false)
     });
 
-    if found {
+    // A thread that exits by itself updates the process table then.
+    if found == Some(true) {
         x86_64::instructions::interrupts::without_interrupts(|| {
             let mut table = PROCESS_TABLE.lock();
             if let Some(table) = table.as_mut() {
-                table.terminate(pid, 1);
+                table.terminate(pid, KILLED_EXIT_CODE);
             }
         });
     }
 
-    found
+    found.is_some()
+}
+
+/// Set a thread's nice value (clamped to `NICE_MIN..=NICE_MAX`; lower
+/// runs first). Returns false if there is no such thread.
+pub fn set_nice(pid: u64, nice: i8) -> bool {
+    let nice = nice.clamp(NICE_MIN, NICE_MAX);
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        let Some(sched) = sched.as_mut() else {
+            return false;
+        };
+        // Running and waiting threads pick up their queue when next enqueued.
+        let mut waiting = sched
+            .current
+            .iter_mut()
+            .chain(&mut sched.sleeping)
+            .chain(&mut sched.blocked);
+        if let Some(thread) = waiting.find(|t| t.pid == pid) {
+            thread.nice = nice;
+            return true;
+        }
+        for index in 0..PRIORITIES {
+            if let Some(pos) = sched.queues[index].iter().position(|t| t.pid == pid) {
+                let mut thread = sched.queues[index].remove(pos).unwrap();
+                thread.nice = nice;
+                let now = crate::interrupts::TICK_COUNT.load(Ordering::Relaxed);
+                sched.enqueue(thread, now);
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// Scheduling state of thread `pid`.
+pub fn thread_info(pid: u64) -> Option<ThreadInfo> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let sched = SCHEDULER.lock();
+        let info = sched.as_ref()?.threads().find(|t| t.pid == pid).map(ThreadInfo::of);
+        info
+    })
+}
+
+/// Scheduling state of every thread that hasn't been cleaned up.
+pub fn threads() -> alloc::vec::Vec<ThreadInfo> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let sched = SCHEDULER.lock();
+        sched.as_ref().map(|s| s.threads().map(ThreadInfo::of).collect()).unwrap_or_default()
+    })
 }
 
 /// Check if a PID belongs to a preemptible thread.
@@ -322,27 +847,32 @@ pub fn is_thread(pid: u64) -> bool {
 // --- Sleep support ---
 
 /// Put the current thread to sleep for approximately `ms` milliseconds.
-/// Rounds up to 10ms granularity (PIT runs at 100 Hz).
+/// Rounds up to 10ms granularity (PIT runs at 100 Hz). A kill point (see
+/// `kill_thread`).
 pub fn sleep_ms(ms: u64) {
-    let ticks = (ms + 9) / 10; // round up to 10ms granularity
+    exit_if_killed();
+    let ticks = ms.div_ceil(10); // round up to 10ms granularity
     if ticks == 0 {
         return;
     }
 
-    let current_tick = crate::interrupts::TICK_COUNT.load(Ordering::Relaxed);
-    let wake_tick = current_tick.saturating_add(ticks);
-
-    // Mark current thread as sleeping (interrupts disabled to prevent preemption
-    // while holding lock)
+    // Interrupts stay off from marking the thread asleep until it has
+    // switched away, so no tick can run it again in between.
     let pid = x86_64::instructions::interrupts::without_interrupts(|| {
-        let mut sched = SCHEDULER.lock();
-        if let Some(sched) = sched.as_mut() {
-            if let Some(thread) = sched.current.as_mut() {
-                thread.state = ThreadState::Sleeping(wake_tick);
-                return Some(thread.pid);
-            }
+        let current_tick = crate::interrupts::TICK_COUNT.load(Ordering::Relaxed);
+        let wake_tick = current_tick.saturating_add(ticks);
+        let pid = SCHEDULER
+            .lock()
+            .as_mut()?
+            .set_current_state(ThreadState::Sleeping(wake_tick))?;
+        let mut table = PROCESS_TABLE.lock();
+        if let Some(table) = table.as_mut() {
+            table.set_state(pid, crate::task::process::ProcessState::Sleeping);
         }
-        None
+        drop(table);
+        // Returns once the scheduler has woken the thread and picked it.
+        switch();
+        Some(pid)
     });
 
     if pid.is_none() {
@@ -350,32 +880,88 @@ pub fn sleep_ms(ms: u64) {
         return;
     }
 
+    // Restore process table state
     if let Some(pid) = pid {
         x86_64::instructions::interrupts::without_interrupts(|| {
             let mut table = PROCESS_TABLE.lock();
             if let Some(table) = table.as_mut() {
-                table.set_state(pid, crate::task::process::ProcessState::Sleeping);
+                table.set_state(pid, crate::task::process::ProcessState::Ready);
             }
         });
     }
+    exit_if_killed();
+}
 
-    // Halt until wake tick reached
-    loop {
-        x86_64::instructions::hlt();
-        if crate::interrupts::TICK_COUNT.load(Ordering::Relaxed) >= wake_tick {
-            break;
+// --- Wait queues ---
+
+/// How a call to `park` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parked {
+    /// A `wake` made the thread ready.
+    Woken,
+    /// The thread was killed, before parking or while parked.
+    Killed,
+    /// Called outside a thread, where the caller has to poll instead.
+    NoThread,
+}
+
+/// Park the running thread on wait queue `key` until `wake` releases it.
+/// Call with interrupts disabled, right after finding that what the thread
+/// waits for isn't available, so no wake-up can slip in between. A killed
+/// thread doesn't park, and `kill_thread` cancels the wait of one that has.
+pub fn park(key: u64) -> Parked {
+    let parked = SCHEDULER.lock().as_mut().and_then(|s| {
+        let thread = s.current.as_mut()?;
+        if !thread.must_exit() {
+            thread.state = ThreadState::Blocked(key);
         }
+        Some((thread.pid, thread.must_exit()))
+    });
+    let pid = match parked {
+        None => return Parked::NoThread,
+        Some((_, true)) => return Parked::Killed,
+        Some((pid, false)) => pid,
+    };
+    let set_state = |state| {
+        if let Some(table) = PROCESS_TABLE.lock().as_mut() {
+            table.set_state(pid, state);
+        }
+    };
+    set_state(crate::task::process::ProcessState::Blocked);
+    // Returns once a `wake` or `kill_thread` has made the thread ready and
+    // it was picked.
+    switch();
+    set_state(crate::task::process::ProcessState::Ready);
+    if current_must_exit() {
+        Parked::Killed
+    } else {
+        Parked::Woken
     }
+}
 
-    // Restore process table state
-    if let Some(pid) = pid {
-        x86_64::instructions::interrupts::without_interrupts(|| {
-            let mut table = PROCESS_TABLE.lock();
-            if let Some(table) = table.as_mut() {
-                table.set_state(pid, crate::task::process::ProcessState::Ready);
+/// Make up to `count` threads parked on `key` ready, longest-waiting
+/// first. Returns how many were woken.
+pub fn wake(key: u64, count: usize) -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        let Some(sched) = sched.as_mut() else {
+            return 0;
+        };
+        let now = crate::interrupts::TICK_COUNT.load(Ordering::Relaxed);
+        let mut woken = 0;
+        let mut index = 0;
+        while woken < count && index < sched.blocked.len() {
+            if sched.blocked[index].state != ThreadState::Blocked(key) {
+                index += 1;
+                continue;
             }
-        });
-    }
+            let mut thread = sched.blocked.remove(index).unwrap();
+            thread.state = ThreadState::Ready;
+            sched.enqueue(thread, now);
+            woken += 1;
+        }
+        woken
+    })
 }
 
 // --- Demo thread entry functions ---
@@ -419,3 +1005,14 @@ pub fn sleep_thread_entry(arg: u64) {
     crate::serial_println!("[sleep] woke up after {}ms", ms);
     crate::println!("[sleep] woke up after {}ms", ms);
 }
+
+/// A thread that recurses until it hits its stack guard page. Used by the
+/// `overflow` shell command to show that only this thread gets killed.
+#[allow(unconditional_recursion)]
+pub fn overflow_thread_entry(depth: u64) {
+    let mut buf = [0u8; 512];
+    buf[0] = depth as u8;
+    core::hint::black_box(&mut buf);
+    overflow_thread_entry(depth + 1);
+    core::hint::black_box(&buf);
+}
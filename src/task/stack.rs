@@ -0,0 +1,167 @@
+/// Kernel thread stacks with guard pages.
+///
+/// Each thread stack lives in its own slot inside a dedicated virtual
+/// region instead of on the heap. The first page of every slot is left
+/// unmapped as a guard and the stack is mapped directly above it, so
+/// running off the end of the stack faults on the guard page instead of
+/// silently overwriting whatever happens to be next in memory.
+///
+///   slot i:  [ guard page | stack pages ... ]  <- top   [ unmapped ... ]
+///
+/// The page fault handler uses `is_guard_hit` to recognize such faults and
+/// terminates only the offending thread.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+/// Start of the virtual region reserved for thread stacks.
+pub const STACK_REGION_START: u64 = 0x_5555_0000_0000;
+
+/// Virtual space reserved per thread stack, including its guard page.
+const SLOT_SIZE: u64 = 1024 * 1024; // 1 MiB
+
+/// Number of stack slots in the region.
+const MAX_SLOTS: u64 = 4096;
+
+/// Size of the whole thread stack region.
+pub const STACK_REGION_SIZE: u64 = MAX_SLOTS * SLOT_SIZE;
+
+/// Stack size used by `spawn_thread` unless the caller picks one.
+pub const DEFAULT_STACK_SIZE: usize = 16 * 1024; // 16 KiB
+
+/// Largest stack a thread may request (a slot minus its guard page).
+pub const MAX_STACK_SIZE: usize = (SLOT_SIZE - PAGE_SIZE) as usize;
+
+const PAGE_SIZE: u64 = 4096;
+
+struct SlotAllocator {
+    next: u64,
+    free: Vec<u64>,
+}
+
+static SLOTS: Mutex<SlotAllocator> = Mutex::new(SlotAllocator {
+    next: 0,
+    free: Vec::new(),
+});
+
+fn alloc_slot() -> Option<u64> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut slots = SLOTS.lock();
+        if let Some(slot) = slots.free.pop() {
+            return Some(slot);
+        }
+        if slots.next < MAX_SLOTS {
+            slots.next += 1;
+            Some(slots.next - 1)
+        } else {
+            None
+        }
+    })
+}
+
+fn free_slot(slot: u64) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        SLOTS.lock().free.push(slot);
+    });
+}
+
+/// True if `addr` is in the guard page at the bottom of a stack slot,
+/// i.e. a fault there means some thread ran past the end of its stack.
+pub fn is_guard_hit(addr: VirtAddr) -> bool {
+    let addr = addr.as_u64();
+    if !(STACK_REGION_START..STACK_REGION_START + STACK_REGION_SIZE).contains(&addr) {
+        return false;
+    }
+    (addr - STACK_REGION_START) % SLOT_SIZE < PAGE_SIZE
+}
+
+/// Stack slots currently handed out.
+pub fn slots_in_use() -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let slots = SLOTS.lock();
+        slots.next as usize - slots.free.len()
+    })
+}
+
+/// A mapped thread stack. Unmaps its pages and frees the frames on drop.
+pub struct ThreadStack {
+    slot: u64,
+    pages: u64,
+}
+
+impl ThreadStack {
+    /// Map a zeroed stack of at least `size` bytes (rounded up to whole
+    /// pages) with an unmapped guard page below it.
+    pub fn new(size: usize) -> Option<ThreadStack> {
+        if size == 0 || size > MAX_STACK_SIZE {
+            return None;
+        }
+        let pages = (size as u64).div_ceil(PAGE_SIZE);
+        let slot = alloc_slot()?;
+        let stack = ThreadStack { slot, pages };
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let mapped = crate::memory::with_mapper(|mapper, frame_allocator| {
+            for page in stack.page_range() {
+                let frame = frame_allocator.allocate_frame()?;
+                match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+                    Ok(flush) => flush.flush(),
+                    Err(_) => {
+                        unsafe { frame_allocator.deallocate_frame(frame) };
+                        return None;
+                    }
+                }
+                unsafe {
+                    core::ptr::write_bytes(page.start_address().as_mut_ptr::<u8>(), 0, 4096);
+                }
+            }
+            Some(())
+        });
+
+        match mapped {
+            Some(Some(())) => Some(stack),
+            // Dropping `stack` unmaps whatever was mapped before the failure.
+            _ => None,
+        }
+    }
+
+    /// Highest address of the stack (initial stack pointer).
+    pub fn top(&self) -> VirtAddr {
+        self.bottom() + self.pages * PAGE_SIZE
+    }
+
+    /// Lowest mapped address of the stack, just above the guard page.
+    pub fn bottom(&self) -> VirtAddr {
+        VirtAddr::new(STACK_REGION_START + self.slot * SLOT_SIZE + PAGE_SIZE)
+    }
+
+    /// Usable stack size in bytes.
+    pub fn size(&self) -> usize {
+        (self.pages * PAGE_SIZE) as usize
+    }
+
+    fn page_range(&self) -> impl Iterator<Item = Page<Size4KiB>> {
+        let first = Page::containing_address(self.bottom());
+        Page::range(first, first + self.pages)
+    }
+}
+
+impl Drop for ThreadStack {
+    fn drop(&mut self) {
+        crate::memory::with_mapper(|mapper, frame_allocator| {
+            for page in self.page_range() {
+                if let Ok((frame, flush)) = mapper.unmap(page) {
+                    flush.flush();
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+            }
+        });
+        free_slot(self.slot);
+    }
+}
@@ -0,0 +1,440 @@
+/// Synchronization primitives that block preemptible threads.
+///
+/// `spin::Mutex` busy-waits, and a thread preempted while holding one
+/// keeps every other thread spinning for its whole time slice. These
+/// types park waiting threads in the scheduler (see `scheduler::park`)
+/// and wake them when the holder lets go. Killing a parked thread
+/// cancels its wait: the primitive undoes what it did before parking
+/// (like `RwLock`'s count of waiting writers) and the thread exits. The
+/// scheduler counts the locks each thread holds, and a killed thread only
+/// exits once it holds none, so it never leaves one locked behind it.
+///
+/// Waits are closed against lost wake-ups by checking and parking with
+/// interrupts disabled: on one CPU nothing else can run in between, so
+/// no lock is held across the switch. Woken threads retry rather than
+/// being handed ownership, so a running thread may take a lock ahead of
+/// one that was just woken. Outside a thread (the executor's idle
+/// context) there is nothing to park, so waits poll instead.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::scheduler::{self, Parked};
+
+/// A wait ended because the waiting thread was killed. The waiter should
+/// undo anything it did before waiting and call `scheduler::exit_killed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+/// Threads parked until something they wait for changes. The queue's
+/// address is its key in the scheduler; waiters borrow it, so it can't
+/// move while anyone waits.
+pub struct WaitQueue {
+    // Not zero-sized, so every queue has its own address.
+    _key: u8,
+}
+
+impl WaitQueue {
+    pub const fn new() -> WaitQueue {
+        WaitQueue { _key: 0 }
+    }
+
+    fn key(&self) -> u64 {
+        self as *const WaitQueue as u64
+    }
+
+    /// Block until `ready` returns true. `ready` runs with interrupts
+    /// disabled and should take what it finds (e.g. try a lock). Fails if
+    /// the thread is killed before `ready` succeeds.
+    pub fn wait_until(&self, mut ready: impl FnMut() -> bool) -> Result<(), Cancelled> {
+        loop {
+            let done = without_interrupts(|| {
+                if ready() {
+                    return Some(Ok(()));
+                }
+                match self.park() {
+                    Parked::Woken => None,
+                    Parked::Killed => Some(Err(Cancelled)),
+                    Parked::NoThread => {
+                        core::hint::spin_loop();
+                        None
+                    }
+                }
+            });
+            if let Some(result) = done {
+                return result;
+            }
+        }
+    }
+
+    /// Park the current thread until the next wake-up. Call with
+    /// interrupts disabled after checking the condition; returns at once
+    /// outside a thread.
+    fn park(&self) -> Parked {
+        scheduler::park(self.key())
+    }
+
+    /// Wake the longest-waiting thread. Returns true if there was one.
+    pub fn notify_one(&self) -> bool {
+        scheduler::wake(self.key(), 1) == 1
+    }
+
+    /// Wake every waiting thread. Returns how many there were.
+    pub fn notify_all(&self) -> usize {
+        scheduler::wake(self.key(), usize::MAX)
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        WaitQueue::new()
+    }
+}
+
+/// A mutual exclusion lock whose waiters sleep instead of spinning.
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Lock, blocking until the holder unlocks.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self.waiters.wait_until(|| self.acquire()).is_err() {
+            scheduler::exit_killed();
+        }
+        MutexGuard::new(self)
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.acquire().then(|| MutexGuard::new(self))
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    fn acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+        self.waiters.notify_one();
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+        scheduler::count_lock(true);
+        MutexGuard { mutex }
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+        scheduler::count_lock(false);
+    }
+}
+
+/// A counting semaphore.
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Take a permit, blocking until one is free.
+    pub fn acquire(&self) {
+        if self.waiters.wait_until(|| self.try_acquire()).is_err() {
+            scheduler::exit_killed();
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Return a permit, waking a waiter if there is one.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    /// Permits free right now.
+    pub fn permits(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+/// A condition variable, used with a `Mutex`. Wake-ups can be spurious,
+/// so waiters recheck their condition (`wait_while` does this).
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar { waiters: WaitQueue::new() }
+    }
+
+    /// Unlock `guard`'s mutex and block until notified, then lock it again.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        // Unlocking and parking happen with nothing else running, so a
+        // notify sent after the unlock still finds this thread parked.
+        let parked = without_interrupts(|| {
+            drop(guard);
+            self.waiters.park()
+        });
+        if parked == Parked::Killed {
+            scheduler::exit_killed();
+        }
+        mutex.lock()
+    }
+
+    /// Block while `condition` holds for the protected value.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) -> bool {
+        self.waiters.notify_one()
+    }
+
+    pub fn notify_all(&self) -> usize {
+        self.waiters.notify_all()
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
+
+/// Set in `RwLock::state` while a writer holds the lock; the other bits
+/// count readers.
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+/// A reader-writer lock. Once a writer is waiting, new readers wait
+/// behind it so writers aren't starved; a thread that already holds a
+/// read lock must not take another.
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    writers_waiting: AtomicUsize,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> RwLock<T> {
+        RwLock {
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Lock for shared reading, blocking while a writer holds or waits
+    /// for the lock.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        if self.waiters.wait_until(|| self.acquire_read()).is_err() {
+            scheduler::exit_killed();
+        }
+        RwLockReadGuard::new(self)
+    }
+
+    /// Lock for exclusive writing, blocking until all holders let go.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        let waited = self.waiters.wait_until(|| self.acquire_write());
+        self.writers_waiting.fetch_sub(1, Ordering::Relaxed);
+        if waited.is_err() {
+            // Readers held back by this writer may go ahead now.
+            self.waiters.notify_all();
+            scheduler::exit_killed();
+        }
+        RwLockWriteGuard::new(self)
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.acquire_read().then(|| RwLockReadGuard::new(self))
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.acquire_write().then(|| RwLockWriteGuard::new(self))
+    }
+
+    /// Readers holding the lock right now.
+    pub fn readers(&self) -> usize {
+        self.state.load(Ordering::Relaxed) & !WRITER
+    }
+
+    fn acquire_read(&self) -> bool {
+        if self.writers_waiting.load(Ordering::Relaxed) > 0 {
+            return false;
+        }
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                (state & WRITER == 0).then_some(state + 1)
+            })
+            .is_ok()
+    }
+
+    fn acquire_write(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn unlock_read(&self) {
+        // The last reader out lets a waiting writer in.
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.waiters.notify_all();
+        }
+    }
+
+    fn unlock_write(&self) {
+        self.state.store(0, Ordering::Release);
+        self.waiters.notify_all();
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized> RwLockReadGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> RwLockReadGuard<'a, T> {
+        scheduler::count_lock(true);
+        RwLockReadGuard { lock }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_read();
+        scheduler::count_lock(false);
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized> RwLockWriteGuard<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> RwLockWriteGuard<'a, T> {
+        scheduler::count_lock(true);
+        RwLockWriteGuard { lock }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock_write();
+        scheduler::count_lock(false);
+    }
+}
@@ -0,0 +1,285 @@
+/// Loading and starting ring-3 programs.
+///
+/// A user program gets a fresh address space with its code mapped into
+/// the user window and a demand-paged stack ending at `USER_STACK_TOP`.
+/// It then runs as a scheduler thread at CPL 3, so any access to kernel
+/// memory faults and kills only that thread.
+///
+/// Programs are either flat machine code loaded at `USER_CODE_BASE`, or
+/// ELF64 executables read from the filesystem (`exec`). ELF programs
+/// start with the System V initial stack:
+///
+///   rsp ->  argc
+///           argv[0] .. argv[argc - 1], NULL
+///           envp[0] .. envp[n - 1], NULL
+///           AT_NULL auxv entry (two zero words)
+///           argument and environment strings     <- USER_STACK_TOP
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use x86_64::VirtAddr;
+
+use super::elf::{self, ElfError};
+use crate::filesystem::{FsError, VNode, VFS};
+use crate::memory::address_space::{AddressSpace, AddressSpaceError, USER_SPACE_END, USER_SPACE_START};
+use crate::memory::vma::VmaFlags;
+
+/// Where flat (non-ELF) program images are loaded.
+pub const USER_CODE_BASE: u64 = USER_SPACE_START + 0x40_0000;
+
+/// Initial user stack pointer. One page below the end of user space is
+/// left unreserved so running off the top faults.
+pub const USER_STACK_TOP: u64 = USER_SPACE_END - 0x1000;
+
+/// Virtual space reserved for the user stack; pages are mapped on first
+/// touch.
+pub const USER_STACK_SIZE: u64 = 64 * 1024;
+
+/// Most stack space the argument and environment block may take.
+const MAX_ARG_SIZE: u64 = USER_STACK_SIZE / 2;
+
+const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug)]
+pub enum ExecError {
+    Fs(FsError),
+    Elf(ElfError),
+    Memory(AddressSpaceError),
+    ArgumentsTooLong,
+}
+
+impl core::fmt::Display for ExecError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ExecError::Fs(e) => write!(f, "{}", e),
+            ExecError::Elf(e) => write!(f, "{}", e),
+            ExecError::Memory(e) => write!(f, "{}", e),
+            ExecError::ArgumentsTooLong => write!(f, "argument list too long"),
+        }
+    }
+}
+
+impl From<FsError> for ExecError {
+    fn from(e: FsError) -> Self {
+        ExecError::Fs(e)
+    }
+}
+
+impl From<ElfError> for ExecError {
+    fn from(e: ElfError) -> Self {
+        ExecError::Elf(e)
+    }
+}
+
+impl From<AddressSpaceError> for ExecError {
+    fn from(e: AddressSpaceError) -> Self {
+        ExecError::Memory(e)
+    }
+}
+
+/// Create an address space holding `code` at `USER_CODE_BASE` and an
+/// empty user stack.
+pub fn load_flat(code: &[u8]) -> Result<Arc<AddressSpace>, AddressSpaceError> {
+    let space = AddressSpace::new()?;
+    let code_base = VirtAddr::new(USER_CODE_BASE);
+    space.map_user(code_base, code.len() as u64, VmaFlags::READ | VmaFlags::EXEC, "[code]")?;
+    space.write_user(code_base, code)?;
+    reserve_stack(&space)?;
+    Ok(space)
+}
+
+fn reserve_stack(space: &AddressSpace) -> Result<(), AddressSpaceError> {
+    space.reserve(
+        VirtAddr::new(USER_STACK_TOP - USER_STACK_SIZE),
+        USER_STACK_SIZE,
+        VmaFlags::READ | VmaFlags::WRITE,
+        "[stack]",
+    )
+}
+
+/// Load `code` as a flat binary and start it in ring 3 with `arg` in RDI.
+/// Returns the new thread's PID.
+pub fn spawn_flat(
+    name: String,
+    code: &[u8],
+    arg: u64,
+    parent_pid: Option<u64>,
+) -> Result<u64, AddressSpaceError> {
+    let space = load_flat(code)?;
+    Ok(super::scheduler::spawn_user_thread(
+        name,
+        space,
+        VirtAddr::new(USER_CODE_BASE),
+        VirtAddr::new(USER_STACK_TOP),
+        arg,
+        parent_pid,
+    ))
+}
+
+/// Create an address space from an ELF64 executable image, with `argv`
+/// and `envp` on its stack. Returns the space, entry point and initial
+/// stack pointer.
+pub fn load_elf(
+    data: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<(Arc<AddressSpace>, VirtAddr, VirtAddr), ExecError> {
+    let image = elf::parse(data)?;
+    let space = AddressSpace::new()?;
+    for (start, end, flags) in segment_areas(&image.segments)? {
+        space.reserve(VirtAddr::new(start), end - start, flags, "[elf]")?;
+    }
+    for segment in &image.segments {
+        // Pages come in zeroed, which also clears the tail past file_size.
+        let offset = segment.offset as usize;
+        let contents = &data[offset..offset + segment.file_size as usize];
+        if !contents.is_empty() {
+            space.write_user(VirtAddr::new(segment.vaddr), contents)?;
+        }
+    }
+    reserve_stack(&space)?;
+    let rsp = push_arguments(&space, argv, envp)?;
+    let entry = VirtAddr::try_new(image.entry).map_err(|_| ElfError::BadEntry)?;
+    Ok((space, entry, rsp))
+}
+
+/// The page ranges to reserve for `segments`, as `(start, end, flags)`.
+/// Segments may share a page at their edges (e.g. the end of the code and
+/// the start of the data); such neighbours get one area with the flags of
+/// both. Segments whose bytes overlap are rejected.
+fn segment_areas(segments: &[elf::Segment]) -> Result<Vec<(u64, u64, VmaFlags)>, ElfError> {
+    let mut segments: Vec<_> = segments.iter().filter(|s| s.mem_size > 0).collect();
+    segments.sort_by_key(|s| s.vaddr);
+    let mut areas: Vec<(u64, u64, VmaFlags)> = Vec::new();
+    let mut previous_end = 0;
+    for segment in segments {
+        let bytes_end = segment.vaddr.checked_add(segment.mem_size).ok_or(ElfError::BadSegment)?;
+        if segment.vaddr < previous_end {
+            return Err(ElfError::BadSegment);
+        }
+        previous_end = bytes_end;
+        let start = segment.vaddr & !(PAGE_SIZE - 1);
+        let end = bytes_end.checked_next_multiple_of(PAGE_SIZE).ok_or(ElfError::BadSegment)?;
+        match areas.last_mut() {
+            Some(area) if start < area.1 => {
+                area.1 = end;
+                area.2 = area.2 | segment.flags;
+            }
+            _ => areas.push((start, end, segment.flags)),
+        }
+    }
+    Ok(areas)
+}
+
+/// Write the initial System V stack (see the module docs) below
+/// `USER_STACK_TOP` and return the resulting stack pointer.
+fn push_arguments(space: &AddressSpace, argv: &[&str], envp: &[&str]) -> Result<VirtAddr, ExecError> {
+    let strings_size: u64 = argv.iter().chain(envp).map(|s| s.len() as u64 + 1).sum();
+    // argc, argv + NULL, envp + NULL, AT_NULL
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2;
+    if strings_size + words as u64 * 8 + 16 > MAX_ARG_SIZE {
+        return Err(ExecError::ArgumentsTooLong);
+    }
+    let strings_base = USER_STACK_TOP - strings_size;
+    let rsp = (strings_base - words as u64 * 8) & !0xF;
+
+    let mut block = Vec::with_capacity((USER_STACK_TOP - rsp) as usize);
+    block.extend_from_slice(&(argv.len() as u64).to_le_bytes());
+    let mut next = strings_base;
+    for list in [argv, envp] {
+        for s in list {
+            block.extend_from_slice(&next.to_le_bytes());
+            next += s.len() as u64 + 1;
+        }
+        block.extend_from_slice(&0u64.to_le_bytes());
+    }
+    block.extend_from_slice(&[0; 16]); // AT_NULL
+    block.resize((strings_base - rsp) as usize, 0);
+    for s in argv.iter().chain(envp) {
+        block.extend_from_slice(s.as_bytes());
+        block.push(0);
+    }
+
+    space.write_user(VirtAddr::new(rsp), &block)?;
+    Ok(VirtAddr::new(rsp))
+}
+
+/// Load the ELF executable at `path` (relative to `cwd`) and start it in
+/// ring 3 with the given arguments. `argc` is also passed in RDI.
+/// Returns the new thread's PID.
+pub fn exec(
+    path: &str,
+    cwd: VNode,
+    argv: &[&str],
+    envp: &[&str],
+    parent_pid: Option<u64>,
+) -> Result<u64, ExecError> {
+    let data = {
+        let fs = VFS.lock();
+        let fs = fs.as_ref().ok_or(FsError::NotFound)?;
+        let node = fs.resolve_path(path, cwd)?;
+        fs.read_file(node)?
+    };
+    let (space, entry, rsp) = load_elf(&data, argv, envp)?;
+    let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or(path);
+    Ok(super::scheduler::spawn_user_thread(
+        String::from(name),
+        space,
+        entry,
+        rsp,
+        argv.len() as u64,
+        parent_pid,
+    ))
+}
+
+/// Install the demo programs below as ELF executables in `/bin`.
+pub fn install_programs() {
+    let programs = [
+        ("/bin/hello", HELLO_PROGRAM),
+        ("/bin/spin", SPIN_PROGRAM),
+        ("/bin/evil", KERNEL_WRITE_PROGRAM),
+    ];
+    let mut fs = VFS.lock();
+    let Some(fs) = fs.as_mut() else {
+        return;
+    };
+    let root = fs.root();
+    let _ = fs.create_dir("/bin", root);
+    for (path, code) in programs {
+        let _ = fs.write_file(path, &elf::wrap_code(code, USER_CODE_BASE), root);
+    }
+}
+
+// --- Demo programs ---
+
+/// Prints a greeting through the `write` syscall, then calls `exit(0)`:
+///   mov eax, 1; mov edi, 1; lea rsi, [rip + msg]; mov edx, 19; syscall
+///   xor edi, edi; mov eax, 60; syscall
+pub const HELLO_PROGRAM: &[u8] = &[
+    0xB8, 0x01, 0x00, 0x00, 0x00, // mov eax, SYS_WRITE
+    0xBF, 0x01, 0x00, 0x00, 0x00, // mov edi, 1 (stdout)
+    0x48, 0x8D, 0x35, 0x12, 0x00, 0x00, 0x00, // lea rsi, [rip + msg]
+    0xBA, 0x13, 0x00, 0x00, 0x00, // mov edx, 19
+    0x0F, 0x05, // syscall
+    0x31, 0xFF, // xor edi, edi
+    0xB8, 0x3C, 0x00, 0x00, 0x00, // mov eax, SYS_EXIT
+    0x0F, 0x05, // syscall
+    0xEB, 0xFE, // jmp $
+    b'H', b'e', b'l', b'l', b'o', b' ', b'f', b'r', b'o', b'm', b' ', b'r', b'i', b'n', b'g',
+    b' ', b'3', b'!', b'\n',
+];
+
+/// Spins forever, pushing to its stack so the stack gets demand-paged:
+///   1: inc rbx; push rbx; pop rbx; jmp 1b
+pub const SPIN_PROGRAM: &[u8] = &[0x48, 0xFF, 0xC3, 0x53, 0x5B, 0xEB, 0xF9];
+
+/// Tries to overwrite the first word of the kernel heap:
+///   mov rax, 0x4444_4444_0000; mov qword [rax], 1; jmp $
+pub const KERNEL_WRITE_PROGRAM: &[u8] = &[
+    0x48, 0xB8, 0x00, 0x00, 0x44, 0x44, 0x44, 0x44, 0x00, 0x00, // mov rax, imm64
+    0x48, 0xC7, 0x00, 0x01, 0x00, 0x00, 0x00, // mov qword [rax], 1
+    0xEB, 0xFE, // jmp $
+];
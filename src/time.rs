@@ -0,0 +1,170 @@
+/// Wall-clock time.
+///
+/// The CMOS real-time clock is read once at boot (`init`); after that the
+/// time is the boot timestamp plus the PIT ticks counted since then, so
+/// it advances in 10ms steps without touching the RTC again. Timestamps
+/// are seconds since the Unix epoch, UTC (QEMU's RTC runs in UTC).
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+use crate::interrupts::{PIT_TARGET_HZ, TICK_COUNT};
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0A;
+const RTC_STATUS_B: u8 = 0x0B;
+
+/// Status B: values are binary rather than BCD.
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Status B: hours are 0-23 rather than 1-12 with a PM bit.
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+
+/// Unix time at `BOOT_TICK`.
+static BOOT_EPOCH: AtomicU64 = AtomicU64::new(0);
+/// Tick count when the RTC was read.
+static BOOT_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// A broken-down UTC date and time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00 UTC.
+    pub fn to_unix(&self) -> u64 {
+        // Days from civil (Howard Hinnant's algorithm), March-based years.
+        let (y, m) = if self.month <= 2 {
+            (self.year as i64 - 1, self.month as i64 + 9)
+        } else {
+            (self.year as i64, self.month as i64 - 3)
+        };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let doy = (153 * m + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        (days * 86_400) as u64
+            + (self.hour * 3600 + self.minute * 60 + self.second) as u64
+    }
+
+    pub fn from_unix(timestamp: u64) -> DateTime {
+        let days = (timestamp / 86_400) as i64 + 719_468;
+        let secs = (timestamp % 86_400) as u32;
+        let era = days.div_euclid(146_097);
+        let doe = days - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as u32;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+        }
+    }
+}
+
+impl core::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn read_cmos(register: u8) -> u8 {
+    unsafe {
+        // Bit 7 of the address port disables NMIs; keep it clear.
+        Port::<u8>::new(CMOS_ADDRESS).write(register & 0x7F);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+fn update_in_progress() -> bool {
+    read_cmos(RTC_STATUS_A) & 0x80 != 0
+}
+
+fn read_registers() -> [u8; 6] {
+    while update_in_progress() {
+        core::hint::spin_loop();
+    }
+    [RTC_SECONDS, RTC_MINUTES, RTC_HOURS, RTC_DAY, RTC_MONTH, RTC_YEAR].map(read_cmos)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+/// Read the RTC, retrying until two reads agree so we never see a
+/// half-updated value.
+pub fn read_rtc() -> DateTime {
+    let mut values = read_registers();
+    loop {
+        let again = read_registers();
+        if again == values {
+            break;
+        }
+        values = again;
+    }
+
+    let status_b = read_cmos(RTC_STATUS_B);
+    let pm = values[2] & 0x80 != 0;
+    values[2] &= 0x7F;
+    if status_b & STATUS_B_BINARY == 0 {
+        values = values.map(from_bcd);
+    }
+    let mut hour = values[2] as u32;
+    if status_b & STATUS_B_24_HOUR == 0 {
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+    DateTime {
+        year: 2000 + values[5] as u32,
+        month: values[4] as u32,
+        day: values[3] as u32,
+        hour,
+        minute: values[1] as u32,
+        second: values[0] as u32,
+    }
+}
+
+/// Record the current RTC time as the boot timestamp.
+pub fn init() {
+    let now = read_rtc().to_unix();
+    BOOT_TICK.store(TICK_COUNT.load(Ordering::Relaxed), Ordering::Relaxed);
+    BOOT_EPOCH.store(now, Ordering::Relaxed);
+}
+
+/// Milliseconds since the PIT started ticking.
+pub fn uptime_ms() -> u64 {
+    TICK_COUNT.load(Ordering::Relaxed) * 1000 / PIT_TARGET_HZ as u64
+}
+
+/// Current Unix time in seconds. Counts from 0 if `init` wasn't called.
+pub fn now() -> u64 {
+    let ticks = TICK_COUNT.load(Ordering::Relaxed) - BOOT_TICK.load(Ordering::Relaxed);
+    BOOT_EPOCH.load(Ordering::Relaxed) + ticks / PIT_TARGET_HZ as u64
+}
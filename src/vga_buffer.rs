@@ -7,6 +7,7 @@ use core::fmt;
 use spin::Mutex;
 
 use crate::console::CONSOLE;
+use crate::drivers::chardev::CharDevice;
 
 const BUFFER_WIDTH: usize = 80;
 
@@ -54,7 +55,12 @@ impl Writer {
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
+        self.write_bytes(s.as_bytes());
+    }
+
+    /// Write printable ASCII and newlines; anything else shows as `?`.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
             match byte {
                 0x20..=0x7e | b'\n' => self.write_byte(byte),
                 _ => self.write_byte(b'?'),
@@ -90,6 +96,27 @@ pub static WRITER: Mutex<Writer> = Mutex::new(Writer {
     column_position: 0,
 });
 
+/// `/dev/console`: writes go to the screen (and serial) like `print!`.
+/// There is no input; reads return nothing.
+pub struct ConsoleDevice;
+
+impl CharDevice for ConsoleDevice {
+    fn name(&self) -> &str {
+        "console"
+    }
+
+    fn read(&self, _buf: &mut [u8]) -> usize {
+        0
+    }
+
+    fn write(&self, data: &[u8]) -> usize {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            WRITER.lock().write_bytes(data);
+        });
+        data.len()
+    }
+}
+
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::vga_buffer::_print(::core::format_args!($($arg)*)));
@@ -0,0 +1,117 @@
+// Integration test: verify per-process address spaces get private user
+// mappings, share the kernel half, and give their frames back on drop.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use kernel::memory::address_space::{self, AddressSpace, USER_SPACE_START};
+use kernel::memory::vma::{self, VmaFlags};
+use kernel::{allocator, memory};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    vma::init();
+    address_space::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().free_frames()
+}
+
+const USER_ADDR: u64 = USER_SPACE_START + 0x10_0000;
+
+#[test_case]
+fn map_user_sets_user_flags() {
+    let space = AddressSpace::new().unwrap();
+    space
+        .map_user(VirtAddr::new(USER_ADDR), 2 * 4096, VmaFlags::READ | VmaFlags::WRITE, "data")
+        .unwrap();
+    let flags = space.translate_flags(VirtAddr::new(USER_ADDR + 4096)).unwrap();
+    assert!(flags.contains(PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE));
+    assert_eq!(space.usage(), (1, 2));
+}
+
+#[test_case]
+fn kernel_range_is_rejected() {
+    let space = AddressSpace::new().unwrap();
+    let kernel_addr = VirtAddr::new(allocator::HEAP_START as u64);
+    assert!(space.map_user(kernel_addr, 4096, VmaFlags::READ, "bad").is_err());
+}
+
+#[test_case]
+fn spaces_are_isolated() {
+    let a = AddressSpace::new().unwrap();
+    let b = AddressSpace::new().unwrap();
+    let flags = VmaFlags::READ | VmaFlags::WRITE;
+    a.map_user(VirtAddr::new(USER_ADDR), 4096, flags, "a").unwrap();
+    b.map_user(VirtAddr::new(USER_ADDR), 4096, flags, "b").unwrap();
+    let ptr = USER_ADDR as *mut u64;
+
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        a.activate();
+        ptr.write_volatile(0xAAAA);
+        b.activate();
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(0xBBBB);
+        a.activate();
+        assert_eq!(ptr.read_volatile(), 0xAAAA);
+        // The kernel heap is still reachable from inside a user space.
+        let boxed = alloc::boxed::Box::new(7u64);
+        assert_eq!(*boxed, 7);
+        x86_64::registers::control::Cr3::write(
+            memory::kernel_pml4(),
+            x86_64::registers::control::Cr3Flags::empty(),
+        );
+    });
+}
+
+#[test_case]
+fn drop_returns_frames() {
+    let free_before = free_frames();
+    {
+        let space = AddressSpace::new().unwrap();
+        space
+            .map_user(VirtAddr::new(USER_ADDR), 16 * 4096, VmaFlags::READ | VmaFlags::WRITE, "data")
+            .unwrap();
+        assert!(free_frames() < free_before);
+    }
+    assert_eq!(free_frames(), free_before);
+}
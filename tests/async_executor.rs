@@ -31,8 +31,9 @@ fn main(boot_info: &'static mut BootInfo) -> ! {
             .expect("physical_memory_offset not available"),
     );
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator =
-        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
 
     test_main();
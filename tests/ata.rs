@@ -0,0 +1,89 @@
+// Integration test: verify the ATA driver finds the boot disk, reads and
+// writes its sectors, and rejects out-of-range or misaligned transfers.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::drivers::block::{self, BlockDevice, BlockError, MemDisk, SECTOR_SIZE};
+use kernel::{allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    kernel::drivers::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn boot_disk_is_detected() {
+    let disk = block::get("hda").expect("no hda");
+    assert!(disk.sector_count() > 0);
+
+    let mut mbr = [0u8; SECTOR_SIZE];
+    disk.read_sectors(0, &mut mbr).unwrap();
+    assert_eq!(&mbr[510..], &[0x55, 0xAA]);
+}
+
+#[test_case]
+fn write_then_read_back() {
+    let disk = block::get("hda").unwrap();
+    let lba = disk.sector_count() - 2;
+    let mut original = vec![0u8; 2 * SECTOR_SIZE];
+    disk.read_sectors(lba, &mut original).unwrap();
+
+    let pattern: alloc::vec::Vec<u8> = (0..2 * SECTOR_SIZE).map(|i| (i * 7) as u8).collect();
+    disk.write_sectors(lba, &pattern).unwrap();
+    disk.flush().unwrap();
+    let mut back = vec![0u8; 2 * SECTOR_SIZE];
+    disk.read_sectors(lba, &mut back).unwrap();
+    assert_eq!(back, pattern);
+
+    disk.write_sectors(lba, &original).unwrap();
+}
+
+#[test_case]
+fn bad_requests_are_rejected() {
+    let disk = block::get("hda").unwrap();
+    let mut buf = [0u8; SECTOR_SIZE];
+    assert_eq!(disk.read_sectors(disk.sector_count(), &mut buf), Err(BlockError::OutOfRange));
+    assert_eq!(disk.read_sectors(0, &mut buf[..100]), Err(BlockError::Misaligned));
+}
+
+#[test_case]
+fn mem_disk_round_trip() {
+    let disk = MemDisk::new("mem0", 8);
+    assert_eq!(disk.sector_count(), 8);
+    disk.write_sectors(7, &[0xAB; SECTOR_SIZE]).unwrap();
+    let mut buf = [0u8; SECTOR_SIZE];
+    disk.read_sectors(7, &mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 0xAB));
+    assert_eq!(disk.write_sectors(8, &buf), Err(BlockError::OutOfRange));
+}
@@ -0,0 +1,197 @@
+// Integration test: block cache hits and misses, dirty tracking, LRU
+// eviction and write-back, and a FAT32 volume used through the cache.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use kernel::drivers::block::{BlockDevice, BlockError, MemDisk, SECTOR_SIZE};
+use kernel::drivers::cache::{self, BlockCache, CachedDevice};
+use kernel::filesystem::{Fat32, Vfs};
+use kernel::{allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    cache::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// A memory disk that counts the requests it serves.
+struct CountingDisk {
+    disk: MemDisk,
+    reads: AtomicUsize,
+    writes: AtomicUsize,
+}
+
+impl CountingDisk {
+    fn new(sectors: usize) -> Arc<CountingDisk> {
+        Arc::new(CountingDisk {
+            disk: MemDisk::new("count0", sectors),
+            reads: AtomicUsize::new(0),
+            writes: AtomicUsize::new(0),
+        })
+    }
+
+    fn counts(&self) -> (usize, usize) {
+        (self.reads.load(Ordering::Relaxed), self.writes.load(Ordering::Relaxed))
+    }
+}
+
+impl BlockDevice for CountingDisk {
+    fn name(&self) -> &str {
+        self.disk.name()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.disk.sector_count()
+    }
+
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), BlockError> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.disk.read_sectors(lba, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, data: &[u8]) -> Result<(), BlockError> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.disk.write_sectors(lba, data)
+    }
+}
+
+fn raw_sector(device: &Arc<dyn BlockDevice>, lba: u64) -> [u8; SECTOR_SIZE] {
+    let mut buf = [0u8; SECTOR_SIZE];
+    device.read_sectors(lba, &mut buf).unwrap();
+    buf
+}
+
+#[test_case]
+fn repeated_reads_hit() {
+    let disk = CountingDisk::new(64);
+    let device: Arc<dyn BlockDevice> = disk.clone();
+    let mut cache = BlockCache::new(16);
+    let mut buf = [0u8; 8 * SECTOR_SIZE];
+    cache.read(&device, 0, &mut buf).unwrap();
+    // The eight cold sectors came in one request.
+    assert_eq!(disk.counts(), (1, 0));
+    cache.read(&device, 0, &mut buf).unwrap();
+    cache.read(&device, 2, &mut buf[..SECTOR_SIZE]).unwrap();
+    assert_eq!(disk.counts(), (1, 0));
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.cached), (9, 8, 8));
+
+    // A cached sector splits the misses around it into two requests.
+    cache.read(&device, 20, &mut buf[..SECTOR_SIZE]).unwrap();
+    cache.read(&device, 18, &mut buf[..5 * SECTOR_SIZE]).unwrap();
+    assert_eq!(disk.counts(), (4, 0));
+}
+
+#[test_case]
+fn writes_stay_dirty_until_flushed() {
+    let disk = CountingDisk::new(64);
+    let device: Arc<dyn BlockDevice> = disk.clone();
+    let mut cache = BlockCache::new(16);
+    cache.write(&device, 3, &[0xAB; 2 * SECTOR_SIZE]).unwrap();
+    assert_eq!(disk.counts().1, 0);
+    assert_eq!(raw_sector(&device, 3), [0; SECTOR_SIZE]);
+
+    // Reads see the cached data without touching the device.
+    let mut buf = [0u8; SECTOR_SIZE];
+    cache.read(&device, 4, &mut buf).unwrap();
+    assert_eq!(buf, [0xAB; SECTOR_SIZE]);
+    assert_eq!(cache.stats().dirty, 2);
+
+    assert_eq!(cache.flush(None), Ok(2));
+    assert_eq!(raw_sector(&device, 4), [0xAB; SECTOR_SIZE]);
+    assert_eq!(cache.stats().dirty, 0);
+    assert_eq!(cache.flush(None), Ok(0));
+}
+
+#[test_case]
+fn least_recently_used_is_evicted() {
+    let disk = CountingDisk::new(64);
+    let device: Arc<dyn BlockDevice> = disk.clone();
+    let mut cache = BlockCache::new(2);
+    cache.write(&device, 0, &[1; SECTOR_SIZE]).unwrap();
+    cache.write(&device, 1, &[2; SECTOR_SIZE]).unwrap();
+    let mut buf = [0u8; SECTOR_SIZE];
+    cache.read(&device, 0, &mut buf).unwrap();
+
+    // Sector 1 is older, so it goes (and is written back) first.
+    cache.write(&device, 2, &[3; SECTOR_SIZE]).unwrap();
+    assert_eq!(raw_sector(&device, 1), [2; SECTOR_SIZE]);
+    assert_eq!(raw_sector(&device, 0), [0; SECTOR_SIZE]);
+    let stats = cache.stats();
+    assert_eq!((stats.cached, stats.dirty, stats.writebacks), (2, 2, 1));
+    assert_eq!(cache.read(&device, 64, &mut buf), Err(BlockError::OutOfRange));
+}
+
+#[test_case]
+fn large_requests_are_split() {
+    const SECTORS: usize = 4 * cache::MAX_SECTORS_PER_LOCK;
+    let disk = CountingDisk::new(SECTORS * 2);
+    let cached: Arc<dyn BlockDevice> = Arc::new(CachedDevice::new(disk.clone()));
+    let mut buf = vec![0u8; SECTORS * SECTOR_SIZE];
+    cached.read_sectors(0, &mut buf).unwrap();
+    assert_eq!(disk.counts(), (4, 0));
+
+    let data: Vec<u8> = (0..SECTORS * SECTOR_SIZE).map(|i| (i / SECTOR_SIZE) as u8).collect();
+    cached.write_sectors(SECTORS as u64, &data).unwrap();
+    assert_eq!(disk.counts(), (4, 0));
+    // The write-back goes out in batches, but all of it goes.
+    cached.flush().unwrap();
+    assert_eq!(disk.counts(), (4, SECTORS));
+    let mut raw = vec![0u8; SECTORS * SECTOR_SIZE];
+    disk.read_sectors(SECTORS as u64, &mut raw).unwrap();
+    assert_eq!(raw, data);
+    assert_eq!(cached.read_sectors(SECTORS as u64 * 2 - 1, &mut buf), Err(BlockError::OutOfRange));
+}
+
+#[test_case]
+fn filesystems_write_through_the_cache() {
+    let disk: Arc<dyn BlockDevice> = Arc::new(MemDisk::new("mem0", 4096));
+    let cached: Arc<dyn BlockDevice> = Arc::new(CachedDevice::new(disk.clone()));
+    Fat32::format(cached.as_ref(), "CACHE").unwrap();
+    let mut vfs = Vfs::new(Box::new(Fat32::new(cached.clone()).unwrap()));
+    let root = vfs.root();
+    vfs.write_file("/cached.txt", b"written back", root).unwrap();
+    assert!(cache::stats().dirty > 0);
+
+    // Dropping the last handle on the device writes its sectors back.
+    drop(vfs);
+    drop(cached);
+    let vfs = Vfs::new(Box::new(Fat32::new(disk).unwrap()));
+    let file = vfs.resolve_path("/cached.txt", vfs.root()).unwrap();
+    assert_eq!(vfs.read_file(file).unwrap(), b"written back");
+    assert_eq!(cache::sync(), Ok(0));
+}
@@ -0,0 +1,141 @@
+// Integration test: verify anonymous VMAs are backed lazily by the page
+// fault handler, that unmapping returns their frames, and that a kernel
+// thread breaking a VMA's permissions is killed rather than the machine.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use kernel::memory::vma::{self, VmaFlags};
+use kernel::task::process::{ProcessState, PROCESS_TABLE};
+use kernel::task::scheduler;
+use kernel::{allocator, memory};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    vma::init();
+    kernel::interrupts::init_pit();
+    kernel::task::process::init();
+    scheduler::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().free_frames()
+}
+
+fn resident(start: x86_64::VirtAddr) -> usize {
+    vma::kernel_areas()
+        .into_iter()
+        .find(|area| area.start == start)
+        .map(|area| area.resident)
+        .unwrap()
+}
+
+#[test_case]
+fn reserve_maps_nothing() {
+    let free_before = free_frames();
+    let start = vma::map_anonymous(64 * 1024, VmaFlags::READ | VmaFlags::WRITE, "test").unwrap();
+    assert_eq!(resident(start), 0);
+    assert_eq!(free_frames(), free_before);
+    vma::unmap(start).unwrap();
+}
+
+#[test_case]
+fn touching_pages_maps_them_zeroed() {
+    let start = vma::map_anonymous(16 * 4096, VmaFlags::READ | VmaFlags::WRITE, "test").unwrap();
+    let ptr: *mut u8 = start.as_mut_ptr();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.add(5 * 4096).write_volatile(42);
+        assert_eq!(ptr.add(5 * 4096).read_volatile(), 42);
+    }
+    assert_eq!(resident(start), 2);
+    vma::unmap(start).unwrap();
+}
+
+#[test_case]
+fn unmap_returns_frames() {
+    let start = vma::map_anonymous(8 * 4096, VmaFlags::READ | VmaFlags::WRITE, "test").unwrap();
+    let ptr: *mut u8 = start.as_mut_ptr();
+    for page in 0..8 {
+        unsafe { ptr.add(page * 4096).write_volatile(1) };
+    }
+    let free_after_touch = free_frames();
+    vma::unmap(start).unwrap();
+    assert_eq!(free_frames(), free_after_touch + 8);
+}
+
+#[test_case]
+fn overlapping_reservation_is_rejected() {
+    let start = vma::map_anonymous(4096, VmaFlags::READ, "test").unwrap();
+    let result = vma::KERNEL_VMAS.lock().reserve(
+        start,
+        4096,
+        VmaFlags::READ,
+        vma::VmaKind::Anonymous,
+        "overlap",
+    );
+    assert!(result.is_err());
+    vma::unmap(start).unwrap();
+}
+
+#[test_case]
+fn kernel_thread_fault_kills_only_the_thread() {
+    fn writer(addr: u64) {
+        unsafe { core::ptr::write_volatile(addr as *mut u8, 1) };
+    }
+    let process = |pid| {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let table = PROCESS_TABLE.lock();
+            table.as_ref().and_then(|t| t.get(pid)).map(|p| (p.state, p.exit_code))
+        })
+    };
+    let start = vma::map_anonymous(4096, VmaFlags::READ, "test").unwrap();
+    let pid = scheduler::spawn_thread(String::from("writer"), writer, start.as_u64(), None);
+    for _ in 0..200 {
+        if scheduler::thread_info(pid).is_none() {
+            break;
+        }
+        x86_64::instructions::hlt();
+    }
+    let killed = (ProcessState::Terminated, Some(scheduler::FAULT_EXIT_CODE));
+    assert_eq!(process(pid), Some(killed));
+    assert_eq!(resident(start), 0);
+    vma::unmap(start).unwrap();
+}
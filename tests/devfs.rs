@@ -0,0 +1,152 @@
+// Integration test: /dev lists the registered character devices, and reads
+// and writes through paths and handles reach their drivers.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::drivers::chardev::{self, CharDevice};
+use kernel::filesystem::{FileType, FsError, OpenFlags, VFS};
+use kernel::{allocator, memory};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    chardev::init();
+    kernel::filesystem::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// A device that records what is written and reads it back.
+struct Loopback {
+    buffer: Mutex<Vec<u8>>,
+}
+
+impl CharDevice for Loopback {
+    fn name(&self) -> &str {
+        "loop0"
+    }
+
+    fn read(&self, buf: &mut [u8]) -> usize {
+        let mut buffer = self.buffer.lock();
+        let len = buf.len().min(buffer.len());
+        buf[..len].copy_from_slice(&buffer[..len]);
+        buffer.drain(..len);
+        len
+    }
+
+    fn write(&self, data: &[u8]) -> usize {
+        self.buffer.lock().extend_from_slice(data);
+        data.len()
+    }
+}
+
+/// Open `path`, read into a `len`-byte buffer once and return the bytes.
+fn read_once(path: &str, len: usize) -> Vec<u8> {
+    let mut fs = VFS.lock();
+    let fs = fs.as_mut().unwrap();
+    let root = fs.root();
+    let file = fs.open(path, OpenFlags::READ, root).unwrap();
+    let mut buf = alloc::vec![0xAA; len];
+    let n = file.read(fs, &mut buf).unwrap();
+    buf.truncate(n);
+    buf
+}
+
+#[test_case]
+fn lists_registered_devices() {
+    let fs = VFS.lock();
+    let fs = fs.as_ref().unwrap();
+    let dev = fs.resolve_path("/dev", fs.root()).unwrap();
+    let entries = fs.list_dir(dev).unwrap();
+    let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+    for name in ["console", "ttyS0", "null", "zero", "random"] {
+        assert!(names.contains(&name), "missing /dev/{}", name);
+    }
+    assert!(entries.iter().all(|e| e.kind == FileType::CharDevice));
+    let meta = fs.stat(fs.resolve_path("/dev/null", fs.root()).unwrap()).unwrap();
+    assert_eq!((meta.kind, meta.size, meta.mode), (FileType::CharDevice, 0, 0o666));
+    assert_eq!(fs.resolve_path("/dev/nothing", fs.root()), Err(FsError::NotFound));
+}
+
+#[test_case]
+fn null_zero_and_random() {
+    assert!(read_once("/dev/null", 16).is_empty());
+    assert_eq!(read_once("/dev/zero", 16), [0; 16]);
+    let first = read_once("/dev/random", 32);
+    let second = read_once("/dev/random", 32);
+    assert_eq!(first.len(), 32);
+    assert_ne!(first, second);
+
+    let mut fs = VFS.lock();
+    let fs = fs.as_mut().unwrap();
+    let root = fs.root();
+    let null = fs.open("/dev/null", OpenFlags::WRITE, root).unwrap();
+    assert_eq!(null.write(fs, b"discarded"), Ok(9));
+}
+
+#[test_case]
+fn console_and_serial_accept_writes() {
+    let mut fs = VFS.lock();
+    let fs = fs.as_mut().unwrap();
+    let root = fs.root();
+    // Truncating a device is a no-op, so whole-file writes work too.
+    fs.write_file("/dev/console", b"devfs: console write\n", root).unwrap();
+    let serial = fs.open("/dev/ttyS0", OpenFlags::WRITE | OpenFlags::APPEND, root).unwrap();
+    assert_eq!(serial.write(fs, b"devfs: serial write\n"), Ok(20));
+}
+
+#[test_case]
+fn registered_drivers_appear_in_dev() {
+    chardev::register(Arc::new(Loopback { buffer: Mutex::new(Vec::new()) }));
+    let mut fs = VFS.lock();
+    let fs = fs.as_mut().unwrap();
+    let root = fs.root();
+    let file = fs.open("/dev/loop0", OpenFlags::READ | OpenFlags::WRITE, root).unwrap();
+    file.write(fs, b"round trip").unwrap();
+    let mut buf = [0u8; 32];
+    let n = file.read(fs, &mut buf).unwrap();
+    assert_eq!(String::from_utf8_lossy(&buf[..n]), "round trip");
+    assert_eq!(file.read(fs, &mut buf), Ok(0));
+}
+
+#[test_case]
+fn devices_cannot_be_created_or_removed() {
+    let mut fs = VFS.lock();
+    let fs = fs.as_mut().unwrap();
+    let root = fs.root();
+    assert_eq!(fs.create_file("/dev/new", root), Err(FsError::NotSupported));
+    assert_eq!(fs.remove("/dev/zero", root), Err(FsError::NotSupported));
+    let zero = fs.resolve_path("/dev/zero", root).unwrap();
+    assert_eq!(fs.read_file(zero), Err(FsError::NotAFile));
+}
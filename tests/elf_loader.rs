@@ -0,0 +1,197 @@
+// Integration test: verify ELF executables are loaded from the filesystem
+// with the right segment permissions and a System V argument stack.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use kernel::filesystem::{VNode, VFS};
+use kernel::task::elf::{self, ElfError};
+use kernel::task::process::{ProcessState, PROCESS_TABLE};
+use kernel::task::scheduler::FAULT_EXIT_CODE;
+use kernel::task::user::{self, ExecError};
+use kernel::{allocator, memory};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    memory::vma::init();
+    memory::address_space::init();
+    kernel::filesystem::init();
+    kernel::interrupts::init_pit();
+    kernel::task::process::init();
+    kernel::task::scheduler::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// `mov eax, 60; syscall` — exit with the code in RDI.
+const EXIT: [u8; 7] = [0xB8, 0x3C, 0x00, 0x00, 0x00, 0x0F, 0x05];
+
+fn program(code: &[u8]) -> Vec<u8> {
+    let mut code = Vec::from(code);
+    code.extend_from_slice(&EXIT);
+    elf::wrap_code(&code, user::USER_CODE_BASE)
+}
+
+fn install(image: &[u8]) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut fs = VFS.lock();
+        let fs = fs.as_mut().unwrap();
+        let root = fs.root();
+        fs.write_file("/prog", image, root).unwrap();
+    });
+}
+
+fn root() -> VNode {
+    VFS.lock().as_ref().unwrap().root()
+}
+
+/// Run `/prog` and return its exit code.
+fn run(image: &[u8], argv: &[&str], envp: &[&str]) -> i32 {
+    install(image);
+    let pid = user::exec("/prog", root(), argv, envp, None).unwrap();
+    for _ in 0..200 {
+        let state = x86_64::instructions::interrupts::without_interrupts(|| {
+            let table = PROCESS_TABLE.lock();
+            let process = table.as_ref().unwrap().get(pid).unwrap();
+            (process.state, process.exit_code)
+        });
+        if let (ProcessState::Terminated, Some(code)) = state {
+            return code;
+        }
+        x86_64::instructions::hlt();
+    }
+    panic!("program did not exit");
+}
+
+#[test_case]
+fn argc_is_on_the_stack() {
+    // mov rdi, [rsp]
+    let image = program(&[0x48, 0x8B, 0x3C, 0x24]);
+    assert_eq!(run(&image, &["prog", "a", "b"], &[]), 3);
+}
+
+#[test_case]
+fn argv_strings_are_readable() {
+    // mov rax, [rsp + 16]; movzx edi, byte [rax]
+    let image = program(&[0x48, 0x8B, 0x44, 0x24, 0x10, 0x0F, 0xB6, 0x38]);
+    assert_eq!(run(&image, &["prog", "xyz"], &[]), b'x' as i32);
+}
+
+#[test_case]
+fn envp_follows_argv() {
+    // argc = 1: envp[0] is at rsp + 24. mov rax, [rsp + 24]; movzx edi, byte [rax]
+    let image = program(&[0x48, 0x8B, 0x44, 0x24, 0x18, 0x0F, 0xB6, 0x38]);
+    assert_eq!(run(&image, &["prog"], &["K=v"]), b'K' as i32);
+}
+
+#[test_case]
+fn bss_is_zeroed() {
+    // mov rdi, [rip + 0x1000]; add edi, 5
+    let mut image = program(&[0x48, 0x8B, 0x3D, 0x00, 0x10, 0x00, 0x00, 0x83, 0xC7, 0x05]);
+    // Grow the segment's memory size past the file so the read hits bss.
+    let memsz_at = 64 + 40;
+    let memsz = u64::from_le_bytes(image[memsz_at..memsz_at + 8].try_into().unwrap());
+    image[memsz_at..memsz_at + 8].copy_from_slice(&(memsz + 0x2000).to_le_bytes());
+    assert_eq!(run(&image, &["prog"], &[]), 5);
+}
+
+#[test_case]
+fn code_segment_is_not_writable() {
+    // lea rax, [rip]; mov byte [rax], 0
+    let image = program(&[0x48, 0x8D, 0x05, 0x00, 0x00, 0x00, 0x00, 0xC6, 0x00, 0x00]);
+    assert_eq!(run(&image, &["prog"], &[]), FAULT_EXIT_CODE);
+}
+
+#[test_case]
+fn segment_without_read_permission_is_loaded() {
+    // mov edi, 7
+    let mut image = program(&[0xBF, 0x07, 0x00, 0x00, 0x00]);
+    // Execute-only: the loader has to fill pages user code can't read.
+    let flags_at = 64 + 4;
+    image[flags_at..flags_at + 4].copy_from_slice(&1u32.to_le_bytes());
+    assert_eq!(run(&image, &["prog"], &[]), 7);
+}
+
+#[test_case]
+fn segments_sharing_a_page_are_merged() {
+    // lea rax, [rip + 13] (the first byte after the code); mov byte [rax], 9;
+    // movzx edi, byte [rax]
+    let mut code = Vec::from([0x48, 0x8D, 0x05, 0x0D, 0x00, 0x00, 0x00, 0xC6, 0x00, 0x09]);
+    code.extend_from_slice(&[0x0F, 0xB6, 0x38]);
+    code.extend_from_slice(&EXIT);
+    // Read/execute headers and code, then a read/write bss segment that
+    // starts in the same page.
+    let code_at = 64 + 2 * 56;
+    let text_size = (code_at + code.len()) as u64;
+    let mut image = Vec::new();
+    image.extend_from_slice(&program(&[])[..64]);
+    image[24..32].copy_from_slice(&(user::USER_CODE_BASE + code_at as u64).to_le_bytes());
+    image[56..58].copy_from_slice(&2u16.to_le_bytes());
+    for (flags, vaddr, file_size, mem_size) in [
+        (4u32 | 1, user::USER_CODE_BASE, text_size, text_size),
+        (4 | 2, user::USER_CODE_BASE + text_size, 0, 16),
+    ] {
+        image.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        image.extend_from_slice(&flags.to_le_bytes());
+        image.extend_from_slice(&0u64.to_le_bytes());
+        image.extend_from_slice(&vaddr.to_le_bytes());
+        image.extend_from_slice(&vaddr.to_le_bytes());
+        image.extend_from_slice(&file_size.to_le_bytes());
+        image.extend_from_slice(&mem_size.to_le_bytes());
+        image.extend_from_slice(&0x1000u64.to_le_bytes());
+    }
+    image.extend_from_slice(&code);
+    assert_eq!(run(&image, &["prog"], &[]), 9);
+}
+
+#[test_case]
+fn entry_outside_code_is_rejected() {
+    let mut image = program(&[]);
+    let stack = user::USER_STACK_TOP - 8;
+    image[24..32].copy_from_slice(&stack.to_le_bytes());
+    install(&image);
+    let result = user::exec("/prog", root(), &["prog"], &[], None);
+    assert!(matches!(result, Err(ExecError::Elf(ElfError::BadEntry))));
+}
+
+#[test_case]
+fn non_elf_file_is_rejected() {
+    install(b"#!/bin/sh\n");
+    let result = user::exec("/prog", root(), &["prog"], &[], None);
+    assert!(matches!(result, Err(ExecError::Elf(ElfError::BadMagic))));
+}
@@ -0,0 +1,334 @@
+// Integration test: build a small ext2 image in memory (one block group,
+// 1 KiB blocks) and verify the read-only driver through the VFS: listing,
+// file data through indirect blocks and holes, symlinks, and that
+// modifications are refused.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::drivers::block::{BlockDevice, MemDisk, SECTOR_SIZE};
+use kernel::filesystem::{Ext2, FileType, FsError, Vfs};
+use kernel::{allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+const BLOCK: usize = 1024;
+const BLOCKS: usize = 2048;
+const INODES: u32 = 64;
+const INODE_SIZE: usize = 128;
+const INODE_TABLE: u32 = 5;
+const POINTERS_PER_BLOCK: usize = BLOCK / 4;
+
+const ROOT: u32 = 2;
+const HELLO: u32 = 12;
+const DOCS: u32 = 13;
+const LINK: u32 = 14;
+const BIG: u32 = 15;
+
+/// Logical blocks of `big.bin` left as holes: one direct, one reached
+/// through the double-indirect block.
+const HOLES: [usize; 2] = [3, 12 + POINTERS_PER_BLOCK + 4];
+const BIG_BLOCKS: usize = 12 + POINTERS_PER_BLOCK + 10;
+
+/// Just enough of an ext2 writer to lay out a test image.
+struct Image {
+    data: Vec<u8>,
+    next_block: u32,
+}
+
+impl Image {
+    fn new() -> Image {
+        let mut image = Image {
+            data: vec![0; BLOCKS * BLOCK],
+            next_block: INODE_TABLE + (INODES as usize * INODE_SIZE / BLOCK) as u32,
+        };
+        let sb = &mut image.data[1024..2048];
+        put(sb, 0, INODES);
+        put(sb, 4, BLOCKS as u32);
+        put(sb, 20, 1); // first data block
+        put(sb, 24, 0); // 1 KiB blocks
+        put(sb, 32, 8192); // blocks per group
+        put(sb, 40, INODES);
+        sb[56..58].copy_from_slice(&0xEF53u16.to_le_bytes());
+        put(sb, 76, 1); // revision
+        put(sb, 84, 11); // first regular inode
+        sb[88..90].copy_from_slice(&(INODE_SIZE as u16).to_le_bytes());
+        put(sb, 96, 2); // filetype feature
+        let gdt = image.block(2);
+        put(gdt, 0, 3); // block bitmap
+        put(gdt, 4, 4); // inode bitmap
+        put(gdt, 8, INODE_TABLE);
+        image
+    }
+
+    fn block(&mut self, n: u32) -> &mut [u8] {
+        &mut self.data[n as usize * BLOCK..][..BLOCK]
+    }
+
+    fn alloc(&mut self) -> u32 {
+        self.next_block += 1;
+        self.next_block - 1
+    }
+
+    /// The pointer stored at `slot` of block `table`, allocating a
+    /// block for it if it's still empty.
+    fn child(&mut self, table: u32, slot: usize) -> u32 {
+        let existing = u32::from_le_bytes(self.block(table)[slot * 4..][..4].try_into().unwrap());
+        if existing != 0 {
+            return existing;
+        }
+        let block = self.alloc();
+        put(self.block(table), slot * 4, block);
+        block
+    }
+
+    /// Point logical block `n` of a file at `block`.
+    fn map(&mut self, pointers: &mut [u32; 15], n: usize, block: u32) {
+        if n < 12 {
+            pointers[n] = block;
+            return;
+        }
+        let n = n - 12;
+        let (table, slot) = if n < POINTERS_PER_BLOCK {
+            if pointers[12] == 0 {
+                pointers[12] = self.alloc();
+            }
+            (pointers[12], n)
+        } else {
+            let n = n - POINTERS_PER_BLOCK;
+            if pointers[13] == 0 {
+                pointers[13] = self.alloc();
+            }
+            (self.child(pointers[13], n / POINTERS_PER_BLOCK), n % POINTERS_PER_BLOCK)
+        };
+        put(self.block(table), slot * 4, block);
+    }
+
+    fn inode(&mut self, ino: u32, mode: u16, size: usize, links: u16, pointers: &[u32; 15]) {
+        let at = BLOCK * INODE_TABLE as usize + (ino as usize - 1) * INODE_SIZE;
+        let raw = &mut self.data[at..at + INODE_SIZE];
+        raw[0..2].copy_from_slice(&mode.to_le_bytes());
+        raw[2..4].copy_from_slice(&1000u16.to_le_bytes()); // uid
+        put(raw, 4, size as u32);
+        put(raw, 16, 1_700_000_000); // mtime
+        raw[26..28].copy_from_slice(&links.to_le_bytes());
+        let sectors = pointers.iter().filter(|&&p| p != 0).count() * BLOCK / SECTOR_SIZE;
+        put(raw, 28, sectors as u32);
+        for (i, &pointer) in pointers.iter().enumerate() {
+            put(raw, 40 + i * 4, pointer);
+        }
+    }
+
+    /// A regular file whose logical blocks in `holes` are left unmapped.
+    fn file(&mut self, ino: u32, data: &[u8], holes: &[usize]) {
+        let mut pointers = [0u32; 15];
+        for (n, chunk) in data.chunks(BLOCK).enumerate() {
+            if holes.contains(&n) {
+                continue;
+            }
+            let block = self.alloc();
+            self.block(block)[..chunk.len()].copy_from_slice(chunk);
+            self.map(&mut pointers, n, block);
+        }
+        self.inode(ino, 0o100644, data.len(), 1, &pointers);
+    }
+
+    /// A one-block directory; `entries` are `(name, inode, type code)`.
+    fn dir(&mut self, ino: u32, parent: u32, entries: &[(&str, u32, u8)]) {
+        let block = self.alloc();
+        let all: Vec<(&str, u32, u8)> = [(".", ino, 2), ("..", parent, 2)]
+            .into_iter()
+            .chain(entries.iter().copied())
+            .collect();
+        let data = self.block(block);
+        let mut at = 0;
+        for (i, (name, entry_ino, kind)) in all.iter().enumerate() {
+            let rec_len = if i == all.len() - 1 {
+                BLOCK - at
+            } else {
+                (8 + name.len()).next_multiple_of(4)
+            };
+            put(data, at, *entry_ino);
+            data[at + 4..at + 6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+            data[at + 6] = name.len() as u8;
+            data[at + 7] = *kind;
+            data[at + 8..at + 8 + name.len()].copy_from_slice(name.as_bytes());
+            at += rec_len;
+        }
+        let mut pointers = [0u32; 15];
+        pointers[0] = block;
+        let subdirs = entries.iter().filter(|e| e.2 == 2).count() as u16;
+        self.inode(ino, 0o40755, BLOCK, 2 + subdirs, &pointers);
+    }
+
+    /// A symlink short enough to live in the inode's block pointers.
+    fn fast_symlink(&mut self, ino: u32, target: &str) {
+        let mut bytes = [0u8; 60];
+        bytes[..target.len()].copy_from_slice(target.as_bytes());
+        let mut pointers = [0u32; 15];
+        for (pointer, chunk) in pointers.iter_mut().zip(bytes.chunks(4)) {
+            *pointer = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+        let at = BLOCK * INODE_TABLE as usize + (ino as usize - 1) * INODE_SIZE;
+        self.inode(ino, 0o120777, target.len(), 1, &pointers);
+        put(&mut self.data[at..], 28, 0); // no data blocks
+    }
+}
+
+fn put(data: &mut [u8], at: usize, value: u32) {
+    data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn big_contents() -> Vec<u8> {
+    let mut data: Vec<u8> = (0..BIG_BLOCKS * BLOCK).map(|i| (i % 251) as u8).collect();
+    for hole in HOLES {
+        data[hole * BLOCK..(hole + 1) * BLOCK].fill(0);
+    }
+    data
+}
+
+fn disk() -> Arc<MemDisk> {
+    let mut image = Image::new();
+    image.dir(ROOT, ROOT, &[("hello.txt", HELLO, 1), ("docs", DOCS, 2), ("link", LINK, 7)]);
+    image.dir(DOCS, ROOT, &[("big.bin", BIG, 1)]);
+    image.file(HELLO, b"Hello from ext2!\n", &[]);
+    image.file(BIG, &big_contents(), &HOLES);
+    image.fast_symlink(LINK, "docs/big.bin");
+
+    let disk = Arc::new(MemDisk::new("mem0", BLOCKS * BLOCK / SECTOR_SIZE));
+    disk.write_sectors(0, &image.data).unwrap();
+    disk
+}
+
+fn mount() -> Vfs {
+    Vfs::new(Box::new(Ext2::new(disk()).unwrap()))
+}
+
+#[test_case]
+fn lists_directories() {
+    let vfs = mount();
+    let root = vfs.root();
+    let names: Vec<String> = vfs.list_dir(root).unwrap().into_iter().map(|e| e.name).collect();
+    assert_eq!(names, ["hello.txt", "docs", "link"]);
+    let docs = vfs.resolve_path("/docs", root).unwrap();
+    assert!(vfs.is_directory(docs));
+    assert_eq!(vfs.resolve_path("/docs/..", root).unwrap(), root);
+    assert_eq!(vfs.stat(root).unwrap().nlink, 3);
+}
+
+#[test_case]
+fn reads_files_and_metadata() {
+    let vfs = mount();
+    let root = vfs.root();
+    let hello = vfs.resolve_path("/hello.txt", root).unwrap();
+    assert_eq!(vfs.read_file(hello).unwrap(), b"Hello from ext2!\n");
+    let meta = vfs.stat(hello).unwrap();
+    assert_eq!(meta.kind, FileType::File);
+    assert_eq!(meta.mode, 0o644);
+    assert_eq!(meta.uid, 1000);
+    assert_eq!(meta.modified, 1_700_000_000);
+}
+
+#[test_case]
+fn reads_through_indirect_blocks_and_holes() {
+    let vfs = mount();
+    let big = vfs.resolve_path("/docs/big.bin", vfs.root()).unwrap();
+    assert_eq!(vfs.read_file(big).unwrap(), big_contents());
+
+    // A read that straddles the direct/indirect boundary.
+    let mut buf = [0u8; 100];
+    let offset = 12 * BLOCK - 50;
+    assert_eq!(vfs.read(big, offset, &mut buf).unwrap(), 100);
+    assert_eq!(&buf[..], &big_contents()[offset..offset + 100]);
+}
+
+#[test_case]
+fn follows_fast_symlinks() {
+    let vfs = mount();
+    let root = vfs.root();
+    assert_eq!(vfs.read_link("/link", root).unwrap(), "docs/big.bin");
+    let big = vfs.resolve_path("/docs/big.bin", root).unwrap();
+    assert_eq!(vfs.resolve_path("/link", root).unwrap(), big);
+}
+
+#[test_case]
+fn refuses_changes() {
+    let mut vfs = mount();
+    let root = vfs.root();
+    assert_eq!(vfs.write_file("/hello.txt", b"x", root), Err(FsError::ReadOnly));
+    assert_eq!(vfs.create_dir("/new", root), Err(FsError::ReadOnly));
+    assert_eq!(vfs.remove("/hello.txt", root), Err(FsError::ReadOnly));
+    assert_eq!(vfs.rename("/hello.txt", "/bye.txt", root), Err(FsError::ReadOnly));
+}
+
+/// `disk()` with `edit` applied to the raw image first.
+fn edited_disk(edit: impl FnOnce(&mut [u8])) -> Arc<MemDisk> {
+    let disk = disk();
+    let mut data = vec![0; BLOCKS * BLOCK];
+    disk.read_sectors(0, &mut data).unwrap();
+    edit(&mut data);
+    disk.write_sectors(0, &data).unwrap();
+    disk
+}
+
+#[test_case]
+fn rejects_bad_geometry() {
+    // Inodes must tile a block exactly.
+    let odd_inodes = edited_disk(|data| {
+        data[1024 + 88..][..2].copy_from_slice(&200u16.to_le_bytes());
+    });
+    assert!(matches!(Ext2::new(odd_inodes), Err(FsError::Corrupted)));
+    // A volume can't be bigger than its device.
+    let too_big = edited_disk(|data| put(data, 1024 + 4, BLOCKS as u32 * 2));
+    assert!(matches!(Ext2::new(too_big), Err(FsError::Corrupted)));
+}
+
+#[test_case]
+fn rejects_oversized_directories() {
+    let size_at = INODE_TABLE as usize * BLOCK + (ROOT as usize - 1) * INODE_SIZE + 4;
+    let disk = edited_disk(|data| put(data, size_at, 0xFFFF_0000));
+    let vfs = Vfs::new(Box::new(Ext2::new(disk).unwrap()));
+    assert_eq!(vfs.list_dir(vfs.root()).map(|_| ()), Err(FsError::Corrupted));
+}
+
+#[test_case]
+fn rejects_non_ext2_devices() {
+    let blank = Arc::new(MemDisk::new("blank", 64));
+    assert!(matches!(Ext2::new(blank), Err(FsError::Corrupted)));
+}
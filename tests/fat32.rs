@@ -0,0 +1,205 @@
+// Integration test: format an in-memory disk as FAT32 and verify names,
+// file data and free space through the VFS, including after a remount.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::drivers::block::MemDisk;
+use kernel::filesystem::{Fat32, FsError, OpenFlags, SeekFrom, Vfs};
+use kernel::{allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// A freshly formatted 2 MiB disk (512-byte clusters).
+fn formatted() -> Arc<MemDisk> {
+    let disk = Arc::new(MemDisk::new("mem0", 4096));
+    Fat32::format(disk.as_ref(), "TEST").unwrap();
+    disk
+}
+
+fn mount(disk: &Arc<MemDisk>) -> Vfs {
+    Vfs::new(Box::new(Fat32::new(disk.clone()).unwrap()))
+}
+
+fn names(vfs: &Vfs, path: &str) -> Vec<String> {
+    let dir = vfs.resolve_path(path, vfs.root()).unwrap();
+    vfs.list_dir(dir).unwrap().into_iter().map(|e| e.name).collect()
+}
+
+#[test_case]
+fn long_names_survive_remount() {
+    let disk = formatted();
+    let mut vfs = mount(&disk);
+    let root = vfs.root();
+    vfs.create_dir("/docs", root).unwrap();
+    vfs.write_file("/docs/A Long File Name.txt", b"hello fat", root).unwrap();
+    vfs.write_file("/docs/A Long File Name 2.txt", b"second", root).unwrap();
+    vfs.write_file("/SHORT.TXT", b"s", root).unwrap();
+    vfs.write_file("/lower.txt", b"l", root).unwrap();
+    drop(vfs);
+
+    let vfs = mount(&disk);
+    let root = vfs.root();
+    assert_eq!(names(&vfs, "/docs"), ["A Long File Name.txt", "A Long File Name 2.txt"]);
+    assert_eq!(names(&vfs, "/"), ["docs", "SHORT.TXT", "lower.txt"]);
+    // Lookups ignore case and also accept the generated 8.3 alias.
+    let file = vfs.resolve_path("/DOCS/a long file name.TXT", root).unwrap();
+    assert_eq!(vfs.read_file(file).unwrap(), b"hello fat");
+    assert_eq!(vfs.resolve_path("/docs/ALONGF~1.TXT", root).unwrap(), file);
+}
+
+#[test_case]
+fn cluster_chains_are_allocated_and_freed() {
+    let disk = formatted();
+    let mut vfs = mount(&disk);
+    let root = vfs.root();
+    let data: Vec<u8> = (0..10_000u32).map(|i| (i * 13) as u8).collect();
+    vfs.write_file("/big.bin", &data, root).unwrap();
+    drop(vfs);
+
+    let fat = Fat32::new(disk.clone()).unwrap();
+    let free = fat.free_clusters();
+    let mut vfs = Vfs::new(Box::new(fat));
+    let root = vfs.root();
+    let big = vfs.resolve_path("/big.bin", root).unwrap();
+    assert_eq!(vfs.read_file(big).unwrap(), data);
+    vfs.remove("/big.bin", root).unwrap();
+    drop(vfs);
+
+    // 10 000 bytes took 20 clusters of 512 bytes.
+    assert_eq!(Fat32::new(disk).unwrap().free_clusters(), free + 20);
+}
+
+#[test_case]
+fn directories_grow_and_must_be_empty_to_remove() {
+    let disk = formatted();
+    let mut vfs = mount(&disk);
+    let root = vfs.root();
+    vfs.create_dir("/many", root).unwrap();
+    // Far more entries than fit in one cluster.
+    for i in 0..100 {
+        vfs.write_file(&format!("/many/file number {}", i), format!("{}", i).as_bytes(), root)
+            .unwrap();
+    }
+    drop(vfs);
+
+    let mut vfs = mount(&disk);
+    let root = vfs.root();
+    assert_eq!(names(&vfs, "/many").len(), 100);
+    let file = vfs.resolve_path("/many/file number 77", root).unwrap();
+    assert_eq!(vfs.read_file(file).unwrap(), b"77");
+    assert_eq!(vfs.remove("/many", root), Err(FsError::DirectoryNotEmpty));
+    vfs.remove_all("/many", root).unwrap();
+    assert_eq!(vfs.resolve_path("/many", root), Err(FsError::NotFound));
+}
+
+#[test_case]
+fn renames_move_entries() {
+    let disk = formatted();
+    let mut vfs = mount(&disk);
+    let root = vfs.root();
+    vfs.create_dir("/a", root).unwrap();
+    vfs.create_dir("/b", root).unwrap();
+    vfs.create_dir("/a/sub", root).unwrap();
+    vfs.write_file("/a/sub/f", b"moved", root).unwrap();
+    let sub = vfs.resolve_path("/a/sub", root).unwrap();
+
+    vfs.rename("/a/sub", "/b/renamed directory", root).unwrap();
+    assert_eq!(vfs.resolve_path("/b/renamed directory", root).unwrap(), sub);
+    assert_eq!(vfs.get_path(sub).unwrap(), "/b/renamed directory");
+    vfs.write_file("/x", b"x", root).unwrap();
+    vfs.write_file("/y", b"yy", root).unwrap();
+    vfs.rename("/x", "/y", root).unwrap();
+    drop(vfs);
+
+    let vfs = mount(&disk);
+    let root = vfs.root();
+    let moved = vfs.resolve_path("/b/renamed directory/f", root).unwrap();
+    assert_eq!(vfs.read_file(moved).unwrap(), b"moved");
+    let parent = vfs.resolve_path("/b/renamed directory/..", root).unwrap();
+    assert_eq!(vfs.get_path(parent).unwrap(), "/b");
+    assert_eq!(vfs.read_file(vfs.resolve_path("/y", root).unwrap()).unwrap(), b"x");
+    assert_eq!(vfs.resolve_path("/x", root), Err(FsError::NotFound));
+}
+
+#[test_case]
+fn sparse_writes_and_truncation_zero_fill() {
+    let disk = formatted();
+    let mut vfs = mount(&disk);
+    let root = vfs.root();
+    let file = vfs
+        .open("/sparse", OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE, root)
+        .unwrap();
+    file.write(&mut vfs, b"abc").unwrap();
+    file.seek(&vfs, SeekFrom::Start(3000)).unwrap();
+    file.write(&mut vfs, b"end").unwrap();
+    let node = file.vnode();
+    let data = vfs.read_file(node).unwrap();
+    assert_eq!(data.len(), 3003);
+    assert!(data[3..3000].iter().all(|&b| b == 0));
+
+    vfs.truncate(node, 1).unwrap();
+    vfs.truncate(node, 600).unwrap();
+    let data = vfs.read_file(node).unwrap();
+    assert_eq!(data.len(), 600);
+    assert_eq!(data[0], b'a');
+    assert!(data[1..].iter().all(|&b| b == 0));
+
+    // Unlinked but still open: the clusters stay until the handle closes.
+    vfs.remove("/sparse", root).unwrap();
+    let mut buf = [0u8; 1];
+    file.seek(&vfs, SeekFrom::Start(0)).unwrap();
+    assert_eq!(file.read(&vfs, &mut buf).unwrap(), 1);
+    assert_eq!(&buf, b"a");
+}
+
+#[test_case]
+fn full_disk_reports_no_space() {
+    let disk = formatted();
+    let mut vfs = mount(&disk);
+    let root = vfs.root();
+    let file = vfs.open("/fill", OpenFlags::WRITE | OpenFlags::CREATE, root).unwrap();
+    let written = file.write(&mut vfs, &vec![7u8; 3 * 1024 * 1024]).unwrap();
+    assert!(written > 0 && written < 3 * 1024 * 1024);
+    assert_eq!(file.write(&mut vfs, b"more"), Err(FsError::NoSpace));
+    drop(file);
+    drop(vfs);
+    assert_eq!(Fat32::new(disk).unwrap().free_clusters(), 0);
+}
@@ -0,0 +1,107 @@
+// Integration test: verify the bitmap frame allocator allocates, frees and
+// reuses frames, and hands out contiguous runs.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use kernel::memory::BitmapFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    *FRAME_ALLOCATOR.lock() = Some(unsafe {
+        BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    });
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn counts_are_consistent() {
+    let guard = FRAME_ALLOCATOR.lock();
+    let alloc = guard.as_ref().unwrap();
+    assert!(alloc.free_frames() > 0);
+    assert_eq!(alloc.free_frames() + alloc.used_frames(), alloc.total_frames());
+}
+
+#[test_case]
+fn allocate_and_free() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let alloc = guard.as_mut().unwrap();
+    let free_before = alloc.free_frames();
+
+    let a = alloc.allocate_frame().expect("out of frames");
+    let b = alloc.allocate_frame().expect("out of frames");
+    assert_ne!(a, b);
+    assert_eq!(alloc.free_frames(), free_before - 2);
+
+    unsafe {
+        alloc.deallocate_frame(a);
+        alloc.deallocate_frame(b);
+    }
+    assert_eq!(alloc.free_frames(), free_before);
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let alloc = guard.as_mut().unwrap();
+
+    let a = alloc.allocate_frame().expect("out of frames");
+    unsafe { alloc.deallocate_frame(a) };
+    let b = alloc.allocate_frame().expect("out of frames");
+    assert_eq!(a, b);
+    unsafe { alloc.deallocate_frame(b) };
+}
+
+#[test_case]
+fn contiguous_allocation() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let alloc = guard.as_mut().unwrap();
+    let free_before = alloc.free_frames();
+
+    let start = alloc.allocate_contiguous(16).expect("no contiguous run");
+    assert_eq!(alloc.free_frames(), free_before - 16);
+
+    // None of the frames in the run may be handed out again.
+    let single = alloc.allocate_frame().expect("out of frames");
+    let run_start = start.start_address().as_u64();
+    let run_end = run_start + 16 * 4096;
+    let addr = single.start_address().as_u64();
+    assert!(addr < run_start || addr >= run_end);
+
+    unsafe {
+        alloc.deallocate_frame(single);
+        alloc.deallocate_contiguous(start, 16);
+    }
+    assert_eq!(alloc.free_frames(), free_before);
+}
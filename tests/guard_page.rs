@@ -0,0 +1,144 @@
+// Integration test: a thread that overflows its stack into the guard page
+// is the only one killed, its stack slot and frames are given back, and
+// the scheduler carries on running other threads.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use kernel::interrupts::TICK_COUNT;
+use kernel::task::process::{ProcessState, PROCESS_TABLE};
+use kernel::task::{scheduler, stack};
+use kernel::{allocator, memory};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    memory::vma::init();
+    kernel::interrupts::init_pit();
+    kernel::task::process::init();
+    scheduler::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn now() -> u64 {
+    TICK_COUNT.load(Ordering::Relaxed)
+}
+
+fn free_frames() -> usize {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().free_frames()
+    })
+}
+
+fn process_state(pid: u64) -> Option<(ProcessState, Option<i32>)> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let table = PROCESS_TABLE.lock();
+        let process = table.as_ref()?.get(pid)?;
+        Some((process.state, process.exit_code))
+    })
+}
+
+/// Wait until `pid` has terminated and the scheduler has dropped it.
+fn wait_for_exit(pid: u64) {
+    let give_up = now() + 200;
+    while scheduler::thread_info(pid).is_some()
+        || process_state(pid).is_some_and(|(state, _)| state != ProcessState::Terminated)
+    {
+        assert!(now() < give_up, "thread {} did not exit", pid);
+        x86_64::instructions::hlt();
+    }
+    // The last thread out is freed at the next schedule.
+    let tick = now();
+    while now() < tick + 2 {
+        x86_64::instructions::hlt();
+    }
+}
+
+static COUNT: AtomicU64 = AtomicU64::new(0);
+
+fn count_up(rounds: u64) {
+    for _ in 0..rounds {
+        COUNT.fetch_add(1, Ordering::SeqCst);
+        scheduler::sleep_ms(10);
+    }
+}
+
+#[test_case]
+fn guard_addresses() {
+    use x86_64::VirtAddr;
+    let region = stack::STACK_REGION_START;
+    assert!(stack::is_guard_hit(VirtAddr::new(region)));
+    assert!(stack::is_guard_hit(VirtAddr::new(region + 4095)));
+    assert!(!stack::is_guard_hit(VirtAddr::new(region + 4096)));
+    // The unmapped space above a stack is not a guard page.
+    assert!(!stack::is_guard_hit(VirtAddr::new(region + 512 * 1024)));
+    assert!(!stack::is_guard_hit(VirtAddr::new(region - 1)));
+}
+
+#[test_case]
+fn overflow_kills_only_that_thread() {
+    // Warm up with two threads that exit normally, so the page tables
+    // for the two slots used below are already in place.
+    let first = scheduler::spawn_thread(String::from("warmup"), count_up, 1, None);
+    let second = scheduler::spawn_thread(String::from("warmup"), count_up, 1, None);
+    wait_for_exit(first);
+    wait_for_exit(second);
+    let slots = stack::slots_in_use();
+    let frames = free_frames();
+
+    // A neighbour keeps running across the overflow.
+    COUNT.store(0, Ordering::SeqCst);
+    let neighbour = scheduler::spawn_thread(String::from("neighbour"), count_up, 20, None);
+    let victim =
+        scheduler::spawn_thread(String::from("overflow"), scheduler::overflow_thread_entry, 0, None);
+    wait_for_exit(victim);
+    assert_eq!(
+        process_state(victim),
+        Some((ProcessState::Terminated, Some(scheduler::FAULT_EXIT_CODE)))
+    );
+
+    let counted = COUNT.load(Ordering::SeqCst);
+    wait_for_exit(neighbour);
+    assert_eq!(process_state(neighbour), Some((ProcessState::Terminated, Some(0))));
+    assert!(COUNT.load(Ordering::SeqCst) > counted);
+    assert_eq!(COUNT.load(Ordering::SeqCst), 20);
+
+    assert_eq!(stack::slots_in_use(), slots);
+    assert_eq!(free_frames(), frames);
+}
@@ -31,9 +31,11 @@ fn main(boot_info: &'static mut BootInfo) -> ! {
             .expect("physical_memory_offset not available"),
     );
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator =
-        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
 
     test_main();
     kernel::hlt_loop();
@@ -69,3 +71,30 @@ fn many_boxes() {
         assert_eq!(*x, i);
     }
 }
+
+#[test_case]
+fn heap_grows_beyond_initial_size() {
+    let size_before = allocator::stats().size;
+    let big: Vec<u8> = alloc::vec![0xAB; allocator::HEAP_SIZE * 2];
+    assert!(big.iter().all(|&b| b == 0xAB));
+    assert!(allocator::stats().size > size_before);
+}
+
+#[test_case]
+fn small_blocks_are_reused() {
+    let first = Box::new(7u64);
+    let addr = &*first as *const u64 as usize;
+    drop(first);
+    let second = Box::new(9u64);
+    assert_eq!(&*second as *const u64 as usize, addr);
+}
+
+#[test_case]
+fn slab_counters_track_live_blocks() {
+    let class = allocator::SIZE_CLASSES.iter().position(|&s| s == 64).unwrap();
+    let live_before = allocator::slab_stats()[class].live;
+    let boxes: Vec<Box<[u8; 64]>> = (0..10).map(|_| Box::new([0u8; 64])).collect();
+    assert_eq!(allocator::slab_stats()[class].live, live_before + 10);
+    drop(boxes);
+    assert_eq!(allocator::slab_stats()[class].live, live_before);
+}
@@ -0,0 +1,174 @@
+// Integration test: /proc is mounted at boot and generates its files from
+// the process table, allocators and interrupt counters on every read.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::filesystem::{FileType, FsError, OpenFlags, VFS};
+use kernel::task::process::{ProcessState, PROCESS_TABLE};
+use kernel::task::TaskId;
+use kernel::{allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    kernel::filesystem::init();
+    kernel::interrupts::init_pit();
+    kernel::task::process::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn register(pid: u64, name: &str, parent: Option<u64>) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut table = PROCESS_TABLE.lock();
+        table.as_mut().unwrap().register(TaskId::from_u64(pid), String::from(name), parent, false);
+    });
+}
+
+fn read(path: &str) -> Result<String, FsError> {
+    let fs = VFS.lock();
+    let fs = fs.as_ref().unwrap();
+    let node = fs.resolve_path(path, fs.root())?;
+    Ok(String::from_utf8(fs.read_file(node)?).unwrap())
+}
+
+/// The number after `key:` in a `/proc/meminfo`-style listing.
+fn field(text: &str, key: &str) -> u64 {
+    let line = text.lines().find(|l| l.starts_with(key)).unwrap();
+    line[key.len() + 1..].split_whitespace().next().unwrap().parse().unwrap()
+}
+
+/// The count on the timer's line of `/proc/interrupts`.
+fn timer_count(text: &str) -> u64 {
+    let line = text.lines().find(|l| l.ends_with("timer")).unwrap();
+    line.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+/// `/proc/uptime` in hundredths of a second.
+fn uptime_centis() -> u64 {
+    read("/proc/uptime").unwrap().trim().replace('.', "").parse().unwrap()
+}
+
+fn wait_ticks(ticks: u64) {
+    let start = kernel::interrupts::TICK_COUNT.load(core::sync::atomic::Ordering::Relaxed);
+    while kernel::interrupts::TICK_COUNT.load(core::sync::atomic::Ordering::Relaxed) < start + ticks {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn lists_files_and_processes() {
+    register(7, "worker", Some(1));
+    let fs = VFS.lock();
+    let fs = fs.as_ref().unwrap();
+    let proc = fs.resolve_path("/proc", fs.root()).unwrap();
+    let entries = fs.list_dir(proc).unwrap();
+    let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names[..3], ["meminfo", "uptime", "interrupts"]);
+    let worker = entries.iter().find(|e| e.name == "7").unwrap();
+    assert_eq!(worker.kind, FileType::Directory);
+    assert_eq!(fs.resolve_path("/proc/7/..", fs.root()).unwrap(), proc);
+    assert_eq!(fs.resolve_path("/proc/999", fs.root()), Err(FsError::NotFound));
+    assert_eq!(fs.resolve_path("/proc/7/nothing", fs.root()), Err(FsError::NotFound));
+}
+
+#[test_case]
+fn status_follows_the_process_table() {
+    register(8, "status-test", Some(7));
+    let status = read("/proc/8/status").unwrap();
+    assert!(status.contains("Name:\tstatus-test\n"));
+    assert!(status.contains("Pid:\t8\n"));
+    assert!(status.contains("PPid:\t7\n"));
+    assert!(status.contains("State:\tReady\n"));
+    assert!(!status.contains("ExitCode"));
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut table = PROCESS_TABLE.lock();
+        let table = table.as_mut().unwrap();
+        table.set_state(8, ProcessState::Sleeping);
+        table.terminate(8, 3);
+    });
+    let status = read("/proc/8/status").unwrap();
+    assert!(status.contains("State:\tTerminated\n"));
+    assert!(status.contains("ExitCode:\t3\n"));
+}
+
+#[test_case]
+fn handles_read_in_pieces() {
+    register(9, "pieces", None);
+    let whole = read("/proc/9/status").unwrap();
+    let mut fs = VFS.lock();
+    let fs = fs.as_mut().unwrap();
+    let root = fs.root();
+    let file = fs.open("/proc/9/status", OpenFlags::READ, root).unwrap();
+    let mut text = Vec::new();
+    let mut buf = [0u8; 5];
+    loop {
+        let n = file.read(fs, &mut buf).unwrap();
+        if n == 0 {
+            break;
+        }
+        text.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(text, whole.as_bytes());
+    assert_eq!(fs.stat(file.vnode()).unwrap().size, whole.len() as u64);
+}
+
+#[test_case]
+fn meminfo_reports_memory() {
+    let text = read("/proc/meminfo").unwrap();
+    let total = field(&text, "MemTotal");
+    assert!(total > 0);
+    assert!(field(&text, "MemFree") <= total);
+    assert!(field(&text, "HeapUsed") > 0);
+    assert!(field(&text, "HeapSize") <= field(&text, "HeapLimit"));
+}
+
+#[test_case]
+fn uptime_and_interrupts_advance() {
+    let before = uptime_centis();
+    let ticks = timer_count(&read("/proc/interrupts").unwrap());
+    wait_ticks(5);
+    assert!(uptime_centis() >= before + 5);
+    assert!(timer_count(&read("/proc/interrupts").unwrap()) >= ticks + 5);
+}
+
+#[test_case]
+fn proc_is_read_only() {
+    let mut fs = VFS.lock();
+    let fs = fs.as_mut().unwrap();
+    let root = fs.root();
+    assert_eq!(fs.write_file("/proc/uptime", b"0", root), Err(FsError::ReadOnly));
+    assert_eq!(fs.create_dir("/proc/new", root), Err(FsError::ReadOnly));
+    assert_eq!(fs.remove("/proc/meminfo", root), Err(FsError::ReadOnly));
+}
@@ -0,0 +1,166 @@
+// Integration test: verify ustar ramdisk images are unpacked into the
+// filesystem with their directories, contents, modes, owners and times.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use kernel::filesystem::VFS;
+use kernel::ramdisk::{self, RamdiskError};
+use kernel::{allocator, memory};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    kernel::filesystem::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// Write `value` as a zero-padded octal field with a trailing NUL.
+fn put_octal(field: &mut [u8], value: usize) {
+    let digits = field.len() - 1;
+    let mut v = value;
+    for i in (0..digits).rev() {
+        field[i] = b'0' + (v % 8) as u8;
+        v /= 8;
+    }
+    field[digits] = 0;
+}
+
+/// uid/gid and modification time given to every member.
+const OWNER: (u32, u32) = (1000, 100);
+const MTIME: u64 = 1_700_000_000;
+
+/// Append one ustar member to `archive`.
+fn member(archive: &mut Vec<u8>, prefix: &str, name: &str, mode: usize, kind: u8, data: &[u8]) {
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    put_octal(&mut header[100..108], mode);
+    put_octal(&mut header[108..116], OWNER.0 as usize);
+    put_octal(&mut header[116..124], OWNER.1 as usize);
+    put_octal(&mut header[124..136], data.len());
+    put_octal(&mut header[136..148], MTIME as usize);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    header[148..156].fill(b' ');
+    let sum: usize = header.iter().map(|&b| b as usize).sum();
+    put_octal(&mut header[148..155], sum);
+    header[155] = b' ';
+
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    archive.resize(archive.len().next_multiple_of(512), 0);
+}
+
+fn finish(archive: &mut Vec<u8>) {
+    archive.extend_from_slice(&[0; 1024]);
+}
+
+fn file_contents(path: &str) -> Vec<u8> {
+    let fs = VFS.lock();
+    let fs = fs.as_ref().unwrap();
+    let node = fs.resolve_path(path, fs.root()).unwrap();
+    fs.read_file(node).unwrap()
+}
+
+fn mode(path: &str) -> u16 {
+    let fs = VFS.lock();
+    let fs = fs.as_ref().unwrap();
+    fs.stat(fs.resolve_path(path, fs.root()).unwrap()).unwrap().mode
+}
+
+#[test_case]
+fn unpacks_files_and_directories() {
+    let mut archive = Vec::new();
+    member(&mut archive, "", "./", 0o755, b'5', &[]);
+    member(&mut archive, "", "./etc/", 0o700, b'5', &[]);
+    member(&mut archive, "", "./etc/motd", 0o600, b'0', b"hello\n");
+    member(&mut archive, "", "./bin/tool", 0o755, b'0', &[0x7F; 700]);
+    finish(&mut archive);
+
+    let stats = ramdisk::load(&archive).unwrap();
+    assert_eq!((stats.files, stats.directories), (2, 1));
+    assert_eq!(stats.bytes, 706);
+    assert_eq!(file_contents("/etc/motd"), b"hello\n");
+    assert_eq!(file_contents("/bin/tool").len(), 700);
+    assert_eq!(mode("/etc"), 0o700);
+    assert_eq!(mode("/etc/motd"), 0o600);
+    assert_eq!(mode("/bin/tool"), 0o755);
+
+    let fs = VFS.lock();
+    let fs = fs.as_ref().unwrap();
+    let meta = fs.stat(fs.resolve_path("/etc/motd", fs.root()).unwrap()).unwrap();
+    assert_eq!((meta.uid, meta.gid), OWNER);
+    assert_eq!(meta.modified, MTIME);
+}
+
+#[test_case]
+fn prefix_field_is_part_of_the_path() {
+    let mut archive = Vec::new();
+    member(&mut archive, "deep/nested", "file.txt", 0o644, b'0', b"x");
+    finish(&mut archive);
+    ramdisk::load(&archive).unwrap();
+    assert_eq!(file_contents("/deep/nested/file.txt"), b"x");
+}
+
+#[test_case]
+fn links_are_skipped() {
+    let mut archive = Vec::new();
+    member(&mut archive, "", "link", 0o777, b'2', &[]);
+    finish(&mut archive);
+    let stats = ramdisk::load(&archive).unwrap();
+    assert_eq!(stats.skipped, 1);
+}
+
+#[test_case]
+fn corrupt_header_is_rejected() {
+    let mut archive = Vec::new();
+    member(&mut archive, "", "bad", 0o644, b'0', b"data");
+    archive[0] = b'c';
+    finish(&mut archive);
+    assert!(matches!(ramdisk::load(&archive), Err(RamdiskError::BadChecksum { offset: 0 })));
+}
+
+#[test_case]
+fn truncated_member_is_rejected() {
+    let mut archive = Vec::new();
+    member(&mut archive, "", "big", 0o644, b'0', &[1; 2000]);
+    archive.truncate(1024);
+    assert!(matches!(ramdisk::load(&archive), Err(RamdiskError::Truncated { offset: 0 })));
+}
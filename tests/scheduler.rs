@@ -0,0 +1,234 @@
+// Integration test: the multi-level feedback queue gives higher-priority
+// threads more of the CPU without starving the others, keeps threads that
+// sleep ahead of CPU-bound ones, and applies nice values. Yielding switches
+// threads at once, and sleepers wake on their tick without running before.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use kernel::interrupts::TICK_COUNT;
+use kernel::task::scheduler;
+use kernel::{allocator, memory};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    memory::vma::init();
+    kernel::interrupts::init_pit();
+    kernel::task::process::init();
+    scheduler::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// Tick at which the test threads stop.
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+/// Per slot: ticks the thread ran and its feedback level, recorded when
+/// it stops. `u64::MAX` until then.
+static TICKS: [AtomicU64; 2] = [const { AtomicU64::new(u64::MAX) }; 2];
+static LEVELS: [AtomicU64; 2] = [const { AtomicU64::new(u64::MAX) }; 2];
+
+fn now() -> u64 {
+    TICK_COUNT.load(Ordering::Relaxed)
+}
+
+fn record(slot: u64) {
+    let info = scheduler::current_pid().and_then(scheduler::thread_info).unwrap();
+    LEVELS[slot as usize].store(info.level as u64, Ordering::SeqCst);
+    TICKS[slot as usize].store(info.ticks, Ordering::SeqCst);
+}
+
+/// Spin until the deadline.
+fn hog(slot: u64) {
+    while now() < DEADLINE.load(Ordering::Relaxed) {
+        core::hint::spin_loop();
+    }
+    record(slot);
+}
+
+/// Sleep one tick at a time until the deadline.
+fn sleeper(slot: u64) {
+    while now() < DEADLINE.load(Ordering::Relaxed) {
+        scheduler::sleep_ms(10);
+    }
+    record(slot);
+}
+
+/// Start `entries` (with their nice values) in slots 0 and 1, running for
+/// `ticks`, and wait for both to finish. Returns (ticks, level) per slot.
+fn run(entries: [(fn(u64), i8); 2], ticks: u64) -> [(u64, u64); 2] {
+    for slot in 0..2 {
+        TICKS[slot].store(u64::MAX, Ordering::SeqCst);
+        LEVELS[slot].store(u64::MAX, Ordering::SeqCst);
+    }
+    // Set the nice values before either thread gets a tick.
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        DEADLINE.store(now() + ticks, Ordering::SeqCst);
+        for (slot, (entry, nice)) in entries.into_iter().enumerate() {
+            let pid = scheduler::spawn_thread(String::from("sched-test"), entry, slot as u64, None);
+            assert!(scheduler::set_nice(pid, nice));
+        }
+    });
+    let give_up = now() + ticks + 200;
+    while TICKS.iter().any(|t| t.load(Ordering::SeqCst) == u64::MAX) {
+        assert!(now() < give_up, "threads did not finish");
+        x86_64::instructions::hlt();
+    }
+    core::array::from_fn(|slot| {
+        (TICKS[slot].load(Ordering::SeqCst), LEVELS[slot].load(Ordering::SeqCst))
+    })
+}
+
+#[test_case]
+fn higher_priority_gets_more_time() {
+    let [(high, _), (low, _)] = run([(hog, -10), (hog, 10)], 150);
+    assert!(high > 2 * low, "high {} low {}", high, low);
+    // Aging still lets the low-priority thread run.
+    assert!(low > 0);
+}
+
+#[test_case]
+fn sleepers_stay_above_cpu_bound_threads() {
+    let [(_, hog_level), (_, sleeper_level)] = run([(hog, 0), (sleeper, 0)], 60);
+    assert_eq!(hog_level, (scheduler::LEVELS - 1) as u64);
+    assert_eq!(sleeper_level, 0);
+}
+
+#[test_case]
+fn nice_is_clamped_and_sets_priority() {
+    let pid = scheduler::spawn_thread(String::from("nap"), scheduler::sleep_ms, 100, None);
+    assert!(scheduler::set_nice(pid, 100));
+    let info = scheduler::thread_info(pid).unwrap();
+    assert_eq!(info.nice, scheduler::NICE_MAX);
+    assert_eq!(info.priority, info.level + scheduler::PRIORITIES - scheduler::LEVELS);
+    assert!(scheduler::set_nice(pid, -128));
+    let info = scheduler::thread_info(pid).unwrap();
+    assert_eq!((info.nice, info.priority), (scheduler::NICE_MIN, info.level));
+    assert!(!scheduler::set_nice(u64::MAX, 0));
+}
+
+/// Yield `YIELDS` times, then record the tick it finished at.
+const YIELDS: u64 = 200;
+
+fn yielder(slot: u64) {
+    for _ in 0..YIELDS {
+        scheduler::yield_now();
+    }
+    TICKS[slot as usize].store(now(), Ordering::SeqCst);
+}
+
+#[test_case]
+fn yield_switches_without_waiting_for_a_tick() {
+    for ticks in &TICKS {
+        ticks.store(u64::MAX, Ordering::SeqCst);
+    }
+    let start = now();
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        for slot in 0..2 {
+            scheduler::spawn_thread(String::from("yielder"), yielder, slot, None);
+        }
+    });
+    while TICKS.iter().any(|t| t.load(Ordering::SeqCst) == u64::MAX) {
+        assert!(now() < start + 2 * YIELDS, "yields waited for ticks");
+        x86_64::instructions::hlt();
+    }
+    // Waiting a tick per yield would take 2 * YIELDS ticks.
+    let elapsed = TICKS.iter().map(|t| t.load(Ordering::SeqCst)).max().unwrap() - start;
+    assert!(elapsed < YIELDS / 2, "took {} ticks", elapsed);
+}
+
+/// Ticks `nap` spent asleep.
+static SLEPT: AtomicU64 = AtomicU64::new(0);
+
+/// Sleep 50 ms, then record how long that took and the ticks it ran.
+fn nap(slot: u64) {
+    let start = now();
+    scheduler::sleep_ms(50);
+    SLEPT.store(now() - start, Ordering::SeqCst);
+    record(slot);
+}
+
+#[test_case]
+fn sleepers_wake_on_time_without_running() {
+    TICKS[0].store(u64::MAX, Ordering::SeqCst);
+    scheduler::spawn_thread(String::from("nap"), nap, 0, None);
+    let give_up = now() + 100;
+    while TICKS[0].load(Ordering::SeqCst) == u64::MAX {
+        assert!(now() < give_up, "sleeper did not wake");
+        x86_64::instructions::hlt();
+    }
+    let slept = SLEPT.load(Ordering::SeqCst);
+    assert!((5..=6).contains(&slept), "slept {} ticks", slept);
+    // Asleep it was off the run queues, so the timer never charged it.
+    assert!(TICKS[0].load(Ordering::SeqCst) <= 1);
+}
+
+#[test_case]
+fn killed_sleepers_are_all_torn_down() {
+    let pids: [u64; 3] = core::array::from_fn(|_| {
+        scheduler::spawn_thread(String::from("nap"), scheduler::sleep_ms, 10_000, None)
+    });
+    let start = now();
+    while now() < start + 3 {
+        x86_64::instructions::hlt();
+    }
+    for pid in pids {
+        assert!(scheduler::kill_thread(pid));
+    }
+    let start = now();
+    while now() < start + 5 {
+        x86_64::instructions::hlt();
+    }
+    assert!(pids.iter().all(|&pid| scheduler::thread_info(pid).is_none()));
+}
+
+#[test_case]
+fn killing_a_sleeper_removes_it() {
+    let pid = scheduler::spawn_thread(String::from("nap"), scheduler::sleep_ms, 10_000, None);
+    let start = now();
+    while now() < start + 3 {
+        x86_64::instructions::hlt();
+    }
+    assert!(scheduler::thread_info(pid).is_some());
+    assert!(scheduler::kill_thread(pid));
+    let start = now();
+    while now() < start + 2 {
+        x86_64::instructions::hlt();
+    }
+    assert!(scheduler::thread_info(pid).is_none());
+}
@@ -0,0 +1,261 @@
+// Integration test: the root filesystem's tree survives a snapshot round
+// trip (data, metadata, links), restoring merges into an existing tree,
+// and damaged or foreign images are rejected before anything changes.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::drivers::block::{self, BlockDevice, MemDisk, SECTOR_SIZE};
+use kernel::filesystem::snapshot::{self, SnapshotError};
+use kernel::filesystem::{Fat32, FileType, TmpFs, Vfs, VFS};
+use kernel::{allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    kernel::filesystem::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// A tree with nested directories, binary data, a symbolic link, a hard
+/// link and non-default metadata.
+fn sample_tree() -> Vfs {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    vfs.create_dir("/etc", root).unwrap();
+    vfs.create_dir("/home", root).unwrap();
+    vfs.create_dir("/home/user", root).unwrap();
+    vfs.write_file("/etc/motd", b"welcome\n", root).unwrap();
+    let blob: Vec<u8> = (0..3000u32).map(|i| (i * 7) as u8).collect();
+    vfs.write_file("/home/user/blob", &blob, root).unwrap();
+    vfs.create_file("/home/user/empty", root).unwrap();
+    vfs.symlink("../../etc/motd", "/home/user/motd", root).unwrap();
+    vfs.link("/etc/motd", "/home/user/motd.hard", root).unwrap();
+
+    let blob = vfs.resolve_path("/home/user/blob", root).unwrap();
+    vfs.set_mode(blob, 0o600).unwrap();
+    vfs.set_owner(blob, 1000, 100).unwrap();
+    vfs.set_times(blob, 1_700_000_000, 1_600_000_000).unwrap();
+    let user = vfs.resolve_path("/home/user", root).unwrap();
+    vfs.set_mode(user, 0o700).unwrap();
+    vfs.set_times(user, 1_500_000_000, 1_500_000_000).unwrap();
+    vfs
+}
+
+fn encode(vfs: &Vfs) -> Vec<u8> {
+    snapshot::encode(vfs).unwrap().0
+}
+
+#[test_case]
+fn round_trip_keeps_data_and_metadata() {
+    let (image, stats) = snapshot::encode(&sample_tree()).unwrap();
+    assert_eq!(image.len() % SECTOR_SIZE, 0);
+    assert_eq!((stats.files, stats.directories, stats.symlinks, stats.links), (3, 3, 1, 1));
+    assert_eq!(stats.bytes, 3008);
+
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let restored = snapshot::decode(&mut vfs, &image).unwrap();
+    assert_eq!((restored.files, restored.directories, restored.bytes), (3, 3, 3008));
+    let root = vfs.root();
+
+    let blob = vfs.resolve_path("/home/user/blob", root).unwrap();
+    let data = vfs.read_file(blob).unwrap();
+    assert_eq!(data.len(), 3000);
+    assert!(data.iter().enumerate().all(|(i, &b)| b == (i as u32 * 7) as u8));
+    let meta = vfs.stat(blob).unwrap();
+    assert_eq!((meta.mode, meta.uid, meta.gid), (0o600, 1000, 100));
+    assert_eq!(meta.modified, 1_600_000_000);
+
+    let user = vfs.resolve_path("/home/user", root).unwrap();
+    let meta = vfs.stat(user).unwrap();
+    assert_eq!((meta.mode, meta.modified), (0o700, 1_500_000_000));
+    let empty = vfs.resolve_path("/home/user/empty", root).unwrap();
+    assert_eq!(vfs.stat(empty).unwrap().size, 0);
+
+    assert_eq!(vfs.read_link("/home/user/motd", root).unwrap(), "../../etc/motd");
+    let motd = vfs.resolve_path("/home/user/motd", root).unwrap();
+    assert_eq!(vfs.read_file(motd).unwrap(), b"welcome\n");
+    let hard = vfs.resolve_path("/home/user/motd.hard", root).unwrap();
+    assert_eq!(hard, vfs.resolve_path("/etc/motd", root).unwrap());
+    assert_eq!(vfs.stat(hard).unwrap().nlink, 2);
+}
+
+#[test_case]
+fn restore_merges_into_existing_tree() {
+    let image = encode(&sample_tree());
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    vfs.create_dir("/etc", root).unwrap();
+    vfs.write_file("/etc/motd", b"an older, longer message\n", root).unwrap();
+    vfs.write_file("/etc/hostname", b"kept\n", root).unwrap();
+    vfs.create_dir("/home", root).unwrap();
+    vfs.write_file("/home/user", b"a file where a directory goes", root).unwrap();
+
+    snapshot::decode(&mut vfs, &image).unwrap();
+    let motd = vfs.resolve_path("/etc/motd", root).unwrap();
+    assert_eq!(vfs.read_file(motd).unwrap(), b"welcome\n");
+    let hostname = vfs.resolve_path("/etc/hostname", root).unwrap();
+    assert_eq!(vfs.read_file(hostname).unwrap(), b"kept\n");
+    let user = vfs.resolve_path("/home/user", root).unwrap();
+    assert_eq!(vfs.stat(user).unwrap().kind, FileType::Directory);
+
+    // Restoring the same snapshot again reuses what is there.
+    snapshot::decode(&mut vfs, &image).unwrap();
+    let hard = vfs.resolve_path("/home/user/motd.hard", root).unwrap();
+    assert_eq!(hard, vfs.resolve_path("/etc/motd", root).unwrap());
+    assert_eq!(vfs.stat(hard).unwrap().nlink, 2);
+}
+
+#[test_case]
+fn mounted_filesystems_are_not_saved() {
+    let mut vfs = sample_tree();
+    let root = vfs.root();
+    vfs.create_dir("/mnt", root).unwrap();
+    vfs.mount("/mnt", Box::new(TmpFs::new()), root).unwrap();
+    vfs.write_file("/mnt/elsewhere", b"not in the snapshot", root).unwrap();
+
+    let mut restored = Vfs::new(Box::new(TmpFs::new()));
+    snapshot::decode(&mut restored, &encode(&vfs)).unwrap();
+    let root = restored.root();
+    let mnt = restored.resolve_path("/mnt", root).unwrap();
+    assert!(restored.list_dir(mnt).unwrap().is_empty());
+}
+
+#[test_case]
+fn damaged_images_are_rejected() {
+    let image = encode(&sample_tree());
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let try_decode = |vfs: &mut Vfs, image: &[u8]| snapshot::decode(vfs, image).map(|_| ());
+
+    assert!(matches!(try_decode(&mut vfs, &[0; SECTOR_SIZE]), Err(SnapshotError::NoSnapshot)));
+
+    let mut payload_flip = image.clone();
+    payload_flip[SECTOR_SIZE + 10] ^= 0x40;
+    assert!(matches!(try_decode(&mut vfs, &payload_flip), Err(SnapshotError::BadChecksum)));
+
+    let mut header_flip = image.clone();
+    header_flip[13] ^= 0x01;
+    assert!(matches!(try_decode(&mut vfs, &header_flip), Err(SnapshotError::BadChecksum)));
+
+    let mut future = image.clone();
+    future[8..12].copy_from_slice(&(snapshot::VERSION + 1).to_le_bytes());
+    let crc = snapshot::crc32(&future[..36]);
+    future[36..40].copy_from_slice(&crc.to_le_bytes());
+    assert!(matches!(
+        try_decode(&mut vfs, &future),
+        Err(SnapshotError::UnsupportedVersion(v)) if v == snapshot::VERSION + 1
+    ));
+
+    assert!(matches!(try_decode(&mut vfs, &image[..SECTOR_SIZE + 20]), Err(SnapshotError::Truncated)));
+
+    // Nothing was restored by any of the failed attempts.
+    let root = vfs.root();
+    assert!(vfs.list_dir(root).unwrap().is_empty());
+}
+
+#[test_case]
+fn save_and_load_through_a_device() {
+    let disk: Arc<dyn BlockDevice> = Arc::new(MemDisk::new("snap0", 64));
+    block::register(disk.clone());
+    {
+        let mut fs = VFS.lock();
+        let fs = fs.as_mut().unwrap();
+        let root = fs.root();
+        fs.write_file("/saved", b"persisted", root).unwrap();
+    }
+    let saved = snapshot::save(&disk).unwrap();
+    assert!(saved.files >= 1);
+    // An earlier snapshot may be replaced.
+    assert!(snapshot::save(&disk).is_ok());
+    assert!(snapshot::find().is_some_and(|dev| dev.name() == "snap0"));
+
+    {
+        let mut fs = VFS.lock();
+        let fs = fs.as_mut().unwrap();
+        let root = fs.root();
+        fs.remove("/saved", root).unwrap();
+    }
+    snapshot::load(&disk).unwrap();
+    let fs = VFS.lock();
+    let fs = fs.as_ref().unwrap();
+    let node = fs.resolve_path("/saved", fs.root()).unwrap();
+    assert_eq!(fs.read_file(node).unwrap(), b"persisted");
+    // procfs is still mounted over its directory.
+    let proc = fs.resolve_path("/proc", fs.root()).unwrap();
+    assert_ne!(proc.mount, fs.root().mount);
+}
+
+#[test_case]
+fn formatted_and_mounted_devices_are_refused() {
+    let disk: Arc<dyn BlockDevice> = Arc::new(MemDisk::new("fat0", 4096));
+    Fat32::format(disk.as_ref(), "KEEP").unwrap();
+    {
+        let mut fs = VFS.lock();
+        let fs = fs.as_mut().unwrap();
+        let root = fs.root();
+        fs.create_dir("/keep", root).unwrap();
+        fs.mount("/keep", Box::new(Fat32::new(disk.clone()).unwrap()), root).unwrap();
+    }
+    assert!(matches!(snapshot::save(&disk), Err(SnapshotError::Mounted)));
+    {
+        let mut fs = VFS.lock();
+        let fs = fs.as_mut().unwrap();
+        let root = fs.root();
+        fs.unmount("/keep", root).unwrap();
+        fs.remove("/keep", root).unwrap();
+    }
+    assert!(matches!(snapshot::save(&disk), Err(SnapshotError::NotEmpty)));
+    // The volume is untouched.
+    assert!(Fat32::new(disk).is_ok());
+}
+
+#[test_case]
+fn small_devices_are_refused() {
+    let tiny: Arc<dyn BlockDevice> = Arc::new(MemDisk::new("tiny", 1));
+    assert!(matches!(snapshot::save(&tiny), Err(SnapshotError::TooLarge { .. })));
+    assert!(matches!(snapshot::load(&tiny), Err(SnapshotError::NoSnapshot)));
+}
+
+#[test_case]
+fn oversized_payloads_are_rejected() {
+    let disk: Arc<dyn BlockDevice> = Arc::new(MemDisk::new("liar", 8));
+    let mut header = encode(&sample_tree());
+    header.truncate(SECTOR_SIZE);
+    for payload_len in [u64::MAX, u64::MAX - SECTOR_SIZE as u64 + 1, 8 * SECTOR_SIZE as u64] {
+        header[16..24].copy_from_slice(&payload_len.to_le_bytes());
+        let crc = snapshot::crc32(&header[..36]);
+        header[36..40].copy_from_slice(&crc.to_le_bytes());
+        disk.write_sectors(0, &header).unwrap();
+        assert!(matches!(snapshot::load(&disk), Err(SnapshotError::Truncated)));
+    }
+}
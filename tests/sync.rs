@@ -0,0 +1,301 @@
+// Integration test: the blocking Mutex, Semaphore, Condvar and RwLock keep
+// their guarantees while several preemptible threads fight over them, with
+// every holder yielding inside its critical section so others pile up.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use kernel::interrupts::TICK_COUNT;
+use kernel::task::process::{ProcessState, PROCESS_TABLE};
+use kernel::task::scheduler;
+use kernel::task::sync::{Condvar, Mutex, RwLock, Semaphore};
+use kernel::{allocator, memory};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    memory::vma::init();
+    kernel::interrupts::init_pit();
+    kernel::task::process::init();
+    scheduler::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// Passes through each critical section per worker.
+const ROUNDS: u64 = 200;
+/// Workers that have finished.
+static DONE: AtomicU64 = AtomicU64::new(0);
+
+fn now() -> u64 {
+    TICK_COUNT.load(Ordering::Relaxed)
+}
+
+/// A thread entry and how many threads run it.
+type Workers = (fn(u64), u64);
+
+/// Start `count` threads running each entry (with their index as the
+/// argument) and wait for all of them to finish.
+fn run(workers: &[Workers]) {
+    DONE.store(0, Ordering::SeqCst);
+    // Spawn them all before any runs, in order.
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        for &(entry, count) in workers {
+            for index in 0..count {
+                scheduler::spawn_thread(String::from("sync-test"), entry, index, None);
+            }
+        }
+    });
+    let total: u64 = workers.iter().map(|&(_, count)| count).sum();
+    let give_up = now() + 1000;
+    while DONE.load(Ordering::SeqCst) < total {
+        assert!(now() < give_up, "workers did not finish");
+        x86_64::instructions::hlt();
+    }
+}
+
+static COUNTER: Mutex<u64> = Mutex::new(0);
+/// Times a worker found the lock taken.
+static CONTENDED: AtomicU64 = AtomicU64::new(0);
+
+fn mutex_worker(_: u64) {
+    for _ in 0..ROUNDS {
+        let mut count = COUNTER.try_lock().unwrap_or_else(|| {
+            CONTENDED.fetch_add(1, Ordering::Relaxed);
+            COUNTER.lock()
+        });
+        // A lost update shows up if anyone else gets in here.
+        let seen = *count;
+        scheduler::yield_now();
+        *count = seen + 1;
+    }
+    DONE.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn mutex_excludes_under_contention() {
+    run(&[(mutex_worker, 4)]);
+    assert_eq!(*COUNTER.lock(), 4 * ROUNDS);
+    assert!(CONTENDED.load(Ordering::Relaxed) > 0);
+    assert!(!COUNTER.is_locked());
+}
+
+static PERMITS: Semaphore = Semaphore::new(2);
+static HOLDERS: AtomicUsize = AtomicUsize::new(0);
+static MOST_HOLDERS: AtomicUsize = AtomicUsize::new(0);
+
+fn semaphore_worker(_: u64) {
+    for _ in 0..ROUNDS {
+        PERMITS.acquire();
+        let holders = HOLDERS.fetch_add(1, Ordering::SeqCst) + 1;
+        MOST_HOLDERS.fetch_max(holders, Ordering::SeqCst);
+        scheduler::yield_now();
+        HOLDERS.fetch_sub(1, Ordering::SeqCst);
+        PERMITS.release();
+    }
+    DONE.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn semaphore_limits_holders() {
+    run(&[(semaphore_worker, 5)]);
+    assert_eq!(MOST_HOLDERS.load(Ordering::SeqCst), 2);
+    assert_eq!(PERMITS.permits(), 2);
+    assert!(PERMITS.try_acquire());
+    assert!(PERMITS.try_acquire());
+    assert!(!PERMITS.try_acquire());
+    PERMITS.release();
+    PERMITS.release();
+}
+
+/// A bounded queue between producers and consumers.
+const CAPACITY: usize = 4;
+static QUEUE: Mutex<VecDeque<u64>> = Mutex::new(VecDeque::new());
+static NOT_EMPTY: Condvar = Condvar::new();
+static NOT_FULL: Condvar = Condvar::new();
+static CONSUMED: AtomicU64 = AtomicU64::new(0);
+
+fn producer(_: u64) {
+    for n in 1..=ROUNDS {
+        let mut queue = NOT_FULL.wait_while(QUEUE.lock(), |q| q.len() >= CAPACITY);
+        queue.push_back(n);
+        drop(queue);
+        NOT_EMPTY.notify_one();
+        scheduler::yield_now();
+    }
+    DONE.fetch_add(1, Ordering::SeqCst);
+}
+
+fn consumer(_: u64) {
+    for _ in 0..ROUNDS {
+        let mut queue = NOT_EMPTY.wait_while(QUEUE.lock(), |q| q.is_empty());
+        assert!(queue.len() <= CAPACITY);
+        let n = queue.pop_front().unwrap();
+        drop(queue);
+        NOT_FULL.notify_one();
+        CONSUMED.fetch_add(n, Ordering::SeqCst);
+    }
+    DONE.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn condvar_hands_items_between_threads() {
+    run(&[(consumer, 3), (producer, 3)]);
+    assert_eq!(CONSUMED.load(Ordering::SeqCst), 3 * ROUNDS * (ROUNDS + 1) / 2);
+    assert!(QUEUE.lock().is_empty());
+}
+
+/// Writers keep both halves equal; readers check they never see them differ.
+static PAIR: RwLock<(u64, u64)> = RwLock::new((0, 0));
+static MOST_READERS: AtomicUsize = AtomicUsize::new(0);
+
+fn reader(_: u64) {
+    for _ in 0..ROUNDS {
+        let pair = PAIR.read();
+        MOST_READERS.fetch_max(PAIR.readers(), Ordering::SeqCst);
+        scheduler::yield_now();
+        assert_eq!(pair.0, pair.1);
+    }
+    DONE.fetch_add(1, Ordering::SeqCst);
+}
+
+fn writer(_: u64) {
+    for _ in 0..ROUNDS {
+        let mut pair = PAIR.write();
+        pair.0 += 1;
+        scheduler::yield_now();
+        pair.1 += 1;
+    }
+    DONE.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn rwlock_shares_reads_and_excludes_writes() {
+    run(&[(reader, 4), (writer, 2)]);
+    assert_eq!(*PAIR.read(), (2 * ROUNDS, 2 * ROUNDS));
+    assert!(MOST_READERS.load(Ordering::SeqCst) >= 2);
+    assert_eq!(PAIR.readers(), 0);
+    assert!(PAIR.try_write().is_some());
+}
+
+#[test_case]
+fn idle_context_can_wait_on_a_thread() {
+    static HELD: Mutex<u64> = Mutex::new(0);
+    fn holder(_: u64) {
+        let mut held = HELD.lock();
+        scheduler::sleep_ms(50);
+        *held = 1;
+    }
+    scheduler::spawn_thread(String::from("holder"), holder, 0, None);
+    while !HELD.is_locked() {
+        x86_64::instructions::hlt();
+    }
+    // Outside a thread, lock() polls until the holder lets go.
+    assert_eq!(*HELD.lock(), 1);
+}
+
+#[test_case]
+fn killed_holder_lets_go_before_exiting() {
+    static HELD: Mutex<u64> = Mutex::new(0);
+    fn holder(_: u64) {
+        let mut held = HELD.lock();
+        scheduler::sleep_ms(50);
+        *held += 1;
+        drop(held);
+        scheduler::sleep_ms(10_000);
+        *HELD.lock() += 1;
+    }
+    let pid = scheduler::spawn_thread(String::from("holder"), holder, 0, None);
+    while !HELD.is_locked() {
+        x86_64::instructions::hlt();
+    }
+    // Holding the lock, it sleeps on rather than dying with it held.
+    assert!(scheduler::kill_thread(pid));
+    let give_up = now() + 100;
+    while scheduler::thread_info(pid).is_some() {
+        assert!(now() < give_up, "holder did not exit");
+        x86_64::instructions::hlt();
+    }
+    assert_eq!(HELD.try_lock().map(|held| *held), Some(1));
+}
+
+#[test_case]
+fn killed_writer_lets_readers_in() {
+    static LOCK: RwLock<u64> = RwLock::new(0);
+    fn writer(_: u64) {
+        *LOCK.write() += 1;
+    }
+    fn reader(_: u64) {
+        assert_eq!(*LOCK.read(), 0);
+        DONE.fetch_add(1, Ordering::SeqCst);
+    }
+    let process = |pid| {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let table = PROCESS_TABLE.lock();
+            table.as_ref().and_then(|t| t.get(pid)).map(|p| (p.state, p.exit_code))
+        })
+    };
+    let give_up = now() + 100;
+    let wait_blocked = |pid| {
+        while process(pid).map(|p| p.0) != Some(ProcessState::Blocked) {
+            assert!(now() < give_up, "thread did not park");
+            x86_64::instructions::hlt();
+        }
+    };
+
+    DONE.store(0, Ordering::SeqCst);
+    let read = LOCK.read();
+    let writer = scheduler::spawn_thread(String::from("writer"), writer, 0, None);
+    wait_blocked(writer);
+    // The waiting writer holds back new readers.
+    let reader = scheduler::spawn_thread(String::from("reader"), reader, 0, None);
+    wait_blocked(reader);
+    assert!(LOCK.try_read().is_none());
+
+    assert!(scheduler::kill_thread(writer));
+    while DONE.load(Ordering::SeqCst) < 1 || scheduler::thread_info(writer).is_some() {
+        assert!(now() < give_up, "reader did not get in");
+        x86_64::instructions::hlt();
+    }
+    assert!(LOCK.try_read().is_some());
+    let exited = (ProcessState::Terminated, Some(scheduler::KILLED_EXIT_CODE));
+    assert_eq!(process(writer), Some(exited));
+    drop(read);
+    assert_eq!(LOCK.try_write().map(|value| *value), Some(0));
+}
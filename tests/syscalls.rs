@@ -0,0 +1,300 @@
+// Integration test: verify user programs reach the kernel through the
+// syscall interface, that bad arguments come back as -errno, and that
+// filesystem calls wait for the VFS lock instead of hanging the kernel.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use core::sync::atomic::Ordering;
+use kernel::filesystem::VFS;
+use kernel::interrupts::TICK_COUNT;
+use kernel::syscall::{self, errno};
+use kernel::task::process::{ProcessState, PROCESS_TABLE};
+use kernel::task::user;
+use kernel::{allocator, memory};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    memory::vma::init();
+    memory::address_space::init();
+    kernel::filesystem::init();
+    kernel::interrupts::init_pit();
+    kernel::task::process::init();
+    kernel::task::scheduler::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// Offset of the data area within the program image.
+const DATA_OFFSET: usize = 0x800;
+
+/// Tiny assembler for test programs: 64-bit immediate moves and syscalls.
+struct Program {
+    code: Vec<u8>,
+    data: Vec<u8>,
+}
+
+// Register numbers for `mov r64, imm64` (REX.W B8+r).
+const RAX: u8 = 0;
+const RDX: u8 = 2;
+const RSI: u8 = 6;
+const RDI: u8 = 7;
+
+impl Program {
+    fn new() -> Self {
+        Program { code: Vec::new(), data: Vec::new() }
+    }
+
+    fn mov(&mut self, reg: u8, imm: u64) -> &mut Self {
+        self.code.extend_from_slice(&[0x48, 0xB8 + reg]);
+        self.code.extend_from_slice(&imm.to_le_bytes());
+        self
+    }
+
+    fn mov_rdi_rax(&mut self) -> &mut Self {
+        self.code.extend_from_slice(&[0x48, 0x89, 0xC7]);
+        self
+    }
+
+    fn syscall(&mut self) -> &mut Self {
+        self.code.extend_from_slice(&[0x0F, 0x05]);
+        self
+    }
+
+    /// Place `bytes` in the data area and return their user address.
+    fn bytes(&mut self, bytes: &[u8]) -> u64 {
+        let addr = user::USER_CODE_BASE + (DATA_OFFSET + self.data.len()) as u64;
+        self.data.extend_from_slice(bytes);
+        addr
+    }
+
+    /// Append `exit(rax)` and run the program, returning its exit code.
+    fn run(&mut self) -> i32 {
+        let pid = self.spawn();
+        wait_for_exit(pid)
+    }
+
+    /// Append `exit(rax)` and start the program without waiting for it.
+    fn spawn(&mut self) -> u64 {
+        self.mov_rdi_rax().mov(RAX, syscall::SYS_EXIT).syscall();
+        assert!(self.code.len() <= DATA_OFFSET);
+        let mut image = self.code.clone();
+        image.resize(DATA_OFFSET, 0xCC);
+        image.extend_from_slice(&self.data);
+        user::spawn_flat(String::from("test"), &image, 0, None).unwrap()
+    }
+}
+
+fn process_state(pid: u64) -> (ProcessState, Option<i32>) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let table = PROCESS_TABLE.lock();
+        let process = table.as_ref().unwrap().get(pid).unwrap();
+        (process.state, process.exit_code)
+    })
+}
+
+/// Wait for `pid` to exit and return its exit code.
+fn wait_for_exit(pid: u64) -> i32 {
+    for _ in 0..200 {
+        if let (ProcessState::Terminated, Some(code)) = process_state(pid) {
+            return code;
+        }
+        x86_64::instructions::hlt();
+    }
+    panic!("program did not exit");
+}
+
+#[test_case]
+fn write_to_stdout_returns_length() {
+    let mut program = Program::new();
+    let msg = program.bytes(b"hello from a test program\n");
+    let code = program
+        .mov(RAX, syscall::SYS_WRITE)
+        .mov(RDI, 1)
+        .mov(RSI, msg)
+        .mov(RDX, 26)
+        .syscall()
+        .run();
+    assert_eq!(code, 26);
+}
+
+#[test_case]
+fn kernel_pointer_is_rejected() {
+    let code = Program::new()
+        .mov(RAX, syscall::SYS_WRITE)
+        .mov(RDI, 1)
+        .mov(RSI, allocator::HEAP_START as u64)
+        .mov(RDX, 8)
+        .syscall()
+        .run();
+    assert_eq!(code as i64, -errno::EFAULT);
+}
+
+#[test_case]
+fn unknown_syscall_is_enosys() {
+    let code = Program::new().mov(RAX, 999).syscall().run();
+    assert_eq!(code as i64, -errno::ENOSYS);
+}
+
+#[test_case]
+fn huge_sleep_is_rejected() {
+    let code = Program::new()
+        .mov(RAX, syscall::SYS_SLEEP)
+        .mov(RDI, u64::MAX)
+        .syscall()
+        .run();
+    assert_eq!(code as i64, -errno::EINVAL);
+    let code = Program::new()
+        .mov(RAX, syscall::SYS_SLEEP)
+        .mov(RDI, syscall::MAX_SLEEP_MS + 1)
+        .syscall()
+        .run();
+    assert_eq!(code as i64, -errno::EINVAL);
+}
+
+#[test_case]
+fn open_missing_file_is_enoent() {
+    let mut program = Program::new();
+    let path = program.bytes(b"/missing");
+    let code = program
+        .mov(RAX, syscall::SYS_OPEN)
+        .mov(RDI, path)
+        .mov(RSI, 8)
+        .mov(RDX, syscall::O_RDONLY)
+        .syscall()
+        .run();
+    assert_eq!(code as i64, -errno::ENOENT);
+}
+
+#[test_case]
+fn open_and_read_file() {
+    {
+        let mut fs = VFS.lock();
+        let fs = fs.as_mut().unwrap();
+        let root = fs.root();
+        fs.write_file("/greeting", b"hi there", root).unwrap();
+    }
+    let mut program = Program::new();
+    let path = program.bytes(b"/greeting");
+    let code = program
+        .mov(RAX, syscall::SYS_OPEN)
+        .mov(RDI, path)
+        .mov(RSI, 9)
+        .mov(RDX, syscall::O_RDONLY)
+        .syscall()
+        // read(fd, stack buffer, 64)
+        .mov_rdi_rax()
+        .mov(RSI, user::USER_STACK_TOP - 64)
+        .mov(RDX, 64)
+        .mov(RAX, syscall::SYS_READ)
+        .syscall()
+        .run();
+    assert_eq!(code, 8);
+}
+
+#[test_case]
+fn lseek_moves_read_offset() {
+    {
+        let mut fs = VFS.lock();
+        let fs = fs.as_mut().unwrap();
+        let root = fs.root();
+        fs.write_file("/seekable", b"0123456789", root).unwrap();
+    }
+    let mut program = Program::new();
+    let path = program.bytes(b"/seekable");
+    let code = program
+        .mov(RAX, syscall::SYS_OPEN)
+        .mov(RDI, path)
+        .mov(RSI, 9)
+        .mov(RDX, syscall::O_RDONLY)
+        .syscall()
+        // lseek(fd, -3, SEEK_END); RDI keeps the descriptor across calls
+        .mov_rdi_rax()
+        .mov(RSI, -3i64 as u64)
+        .mov(RDX, syscall::SEEK_END)
+        .mov(RAX, syscall::SYS_LSEEK)
+        .syscall()
+        .mov(RSI, user::USER_STACK_TOP - 64)
+        .mov(RDX, 64)
+        .mov(RAX, syscall::SYS_READ)
+        .syscall()
+        .run();
+    assert_eq!(code, 3);
+}
+
+#[test_case]
+fn syscalls_wait_while_the_shell_holds_the_vfs() {
+    const WRITES: usize = 40;
+    let mut program = Program::new();
+    let path = program.bytes(b"/busy");
+    let chunk = program.bytes(b"0123456789abcdef");
+    program
+        .mov(RAX, syscall::SYS_OPEN)
+        .mov(RDI, path)
+        .mov(RSI, 5)
+        .mov(RDX, syscall::O_WRONLY | syscall::O_CREAT | syscall::O_TRUNC)
+        .syscall()
+        .mov_rdi_rax();
+    for _ in 0..WRITES {
+        program
+            .mov(RSI, chunk)
+            .mov(RDX, 16)
+            .mov(RAX, syscall::SYS_WRITE)
+            .syscall();
+    }
+
+    // Hold the lock the way a shell command does, with interrupts on.
+    let held = VFS.lock();
+    let pid = program.spawn();
+    let start = TICK_COUNT.load(Ordering::Relaxed);
+    while TICK_COUNT.load(Ordering::Relaxed) < start + 20 {
+        x86_64::instructions::hlt();
+    }
+    // The program is parked on the lock, and the clock kept running.
+    assert_ne!(process_state(pid).0, ProcessState::Terminated);
+    drop(held);
+
+    assert_eq!(wait_for_exit(pid), 16);
+    let fs = VFS.lock();
+    let fs = fs.as_ref().unwrap();
+    let node = fs.resolve_path("/busy", fs.root()).unwrap();
+    let data = fs.read_file(node).unwrap();
+    assert_eq!(data.len(), WRITES * 16);
+    assert!(data.chunks(16).all(|c| c == b"0123456789abcdef"));
+}
@@ -0,0 +1,59 @@
+// Integration test: verify conversions between Unix timestamps and
+// calendar dates, and that the RTC gives a plausible time.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::time::{self, DateTime};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+fn date(year: u32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> DateTime {
+    DateTime { year, month, day, hour, minute, second }
+}
+
+#[test_case]
+fn epoch_is_zero() {
+    assert_eq!(date(1970, 1, 1, 0, 0, 0).to_unix(), 0);
+    assert_eq!(DateTime::from_unix(0), date(1970, 1, 1, 0, 0, 0));
+}
+
+#[test_case]
+fn known_timestamps() {
+    assert_eq!(date(2000, 3, 1, 0, 0, 0).to_unix(), 951_868_800);
+    assert_eq!(date(2023, 11, 14, 22, 13, 20).to_unix(), 1_700_000_000);
+    assert_eq!(DateTime::from_unix(1_709_210_096), date(2024, 2, 29, 12, 34, 56));
+}
+
+#[test_case]
+fn round_trip_across_leap_days() {
+    let mut t = 946_684_800; // 2000-01-01
+    while t < 1_893_456_000 {
+        assert_eq!(DateTime::from_unix(t).to_unix(), t);
+        t += 86_400 * 37 + 3_661;
+    }
+}
+
+#[test_case]
+fn rtc_reads_a_plausible_date() {
+    let now = time::read_rtc();
+    assert!(now.year >= 2020 && now.year < 2100);
+    assert!((1..=12).contains(&now.month) && (1..=31).contains(&now.day));
+    assert!(now.hour < 24 && now.minute < 60 && now.second < 60);
+}
@@ -0,0 +1,107 @@
+// Integration test: verify programs run in ring 3 with their own address
+// space and that touching kernel memory kills only the offending thread.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use core::panic::PanicInfo;
+use kernel::task::process::{ProcessState, PROCESS_TABLE};
+use kernel::task::{scheduler, user};
+use kernel::{allocator, memory};
+use x86_64::VirtAddr;
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = {
+    let mut config = BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config
+};
+
+entry_point!(main, config = &BOOTLOADER_CONFIG);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    memory::vma::init();
+    memory::address_space::init();
+    kernel::interrupts::init_pit();
+    kernel::task::process::init();
+    scheduler::init();
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// Halt until `done` returns true, failing after ~2 seconds.
+fn wait_for(mut done: impl FnMut() -> bool) {
+    for _ in 0..200 {
+        if done() {
+            return;
+        }
+        x86_64::instructions::hlt();
+    }
+    panic!("timed out");
+}
+
+fn process_state(pid: u64) -> Option<(ProcessState, Option<i32>)> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let table = PROCESS_TABLE.lock();
+        let process = table.as_ref()?.get(pid)?;
+        Some((process.state, process.exit_code))
+    })
+}
+
+#[test_case]
+fn user_program_runs_and_faults_in_its_stack() {
+    let space = user::load_flat(user::SPIN_PROGRAM).unwrap();
+    assert_eq!(space.usage(), (2, 1));
+    let pid = scheduler::spawn_user_thread(
+        String::from("spin"),
+        space.clone(),
+        VirtAddr::new(user::USER_CODE_BASE),
+        VirtAddr::new(user::USER_STACK_TOP),
+        0,
+        None,
+    );
+    // The first push maps a stack page on demand.
+    wait_for(|| space.usage().1 == 2);
+    assert!(scheduler::kill_thread(pid));
+}
+
+#[test_case]
+fn kernel_write_kills_only_the_program() {
+    let pid = user::spawn_flat(String::from("evil"), user::KERNEL_WRITE_PROGRAM, 0, None).unwrap();
+    wait_for(|| matches!(process_state(pid), Some((ProcessState::Terminated, _))));
+    assert_eq!(process_state(pid).unwrap().1, Some(scheduler::FAULT_EXIT_CODE));
+}
+
+#[test_case]
+fn kernel_memory_is_not_user_accessible() {
+    let space = user::load_flat(user::SPIN_PROGRAM).unwrap();
+    let heap = VirtAddr::new(allocator::HEAP_START as u64);
+    let flags = space.translate_flags(heap).unwrap();
+    assert!(!flags.contains(x86_64::structures::paging::PageTableFlags::USER_ACCESSIBLE));
+}
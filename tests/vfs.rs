@@ -0,0 +1,332 @@
+// Integration test: verify the VFS resolves paths across mount points,
+// guards mounted directories, follows symbolic links, counts hard links,
+// and serves reads and writes through open file handles.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader_api::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use kernel::filesystem::{FileType, FsError, OpenFlags, SeekFrom, TmpFs, Vfs};
+use kernel::{allocator, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    kernel::init();
+
+    let phys_mem_offset = x86_64::VirtAddr::new(
+        boot_info
+            .physical_memory_offset
+            .into_option()
+            .expect("physical_memory_offset not available"),
+    );
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        memory::BitmapFrameAllocator::init(&boot_info.memory_regions, phys_mem_offset)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info)
+}
+
+/// A root tmpfs with a second tmpfs mounted on `/mnt`.
+fn vfs_with_mount() -> Vfs {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    vfs.create_dir("/mnt", root).unwrap();
+    vfs.mount("/mnt", Box::new(TmpFs::new()), root).unwrap();
+    vfs
+}
+
+#[test_case]
+fn files_resolve_inside_mount() {
+    let mut vfs = vfs_with_mount();
+    let root = vfs.root();
+    vfs.create_dir("/mnt/dir", root).unwrap();
+    vfs.write_file("/mnt/dir/file", b"mounted", root).unwrap();
+
+    let node = vfs.resolve_path("/mnt/dir/file", root).unwrap();
+    assert_ne!(node.mount, root.mount);
+    assert_eq!(vfs.read_file(node).unwrap(), b"mounted");
+    // Listing the mount point shows the mounted filesystem.
+    let mnt = vfs.resolve_path("/mnt", root).unwrap();
+    assert_eq!(vfs.list_dir(mnt).unwrap()[0].name, "dir");
+}
+
+#[test_case]
+fn dotdot_leaves_mount_root() {
+    let mut vfs = vfs_with_mount();
+    let root = vfs.root();
+    vfs.create_dir("/mnt/dir", root).unwrap();
+    vfs.create_file("/top", root).unwrap();
+
+    let dir = vfs.resolve_path("/mnt/dir", root).unwrap();
+    let top = vfs.resolve_path("../../top", dir).unwrap();
+    assert_eq!(top, vfs.resolve_path("/top", root).unwrap());
+    assert_eq!(vfs.resolve_path("/..", root).unwrap(), root);
+}
+
+#[test_case]
+fn path_of_node_in_mount() {
+    let mut vfs = vfs_with_mount();
+    let root = vfs.root();
+    vfs.create_dir("/mnt/a", root).unwrap();
+    let a = vfs.create_dir("/mnt/a/b", root).unwrap();
+    assert_eq!(vfs.get_path(a).unwrap(), "/mnt/a/b");
+    let mnt = vfs.resolve_path("/mnt", root).unwrap();
+    assert_eq!(vfs.get_path(mnt).unwrap(), "/mnt");
+}
+
+#[test_case]
+fn mount_point_is_busy() {
+    let mut vfs = vfs_with_mount();
+    let root = vfs.root();
+    assert_eq!(vfs.remove("/mnt", root), Err(FsError::Busy));
+    let result = vfs.mount("/mnt", Box::new(TmpFs::new()), root);
+    assert_eq!(result.err(), Some(FsError::Busy));
+    assert!(vfs.unmount("/", root).is_err());
+}
+
+#[test_case]
+fn unmount_uncovers_directory() {
+    let mut vfs = vfs_with_mount();
+    let root = vfs.root();
+    vfs.create_file("/mnt/inside", root).unwrap();
+    assert_eq!(vfs.mounts().len(), 2);
+
+    vfs.unmount("/mnt", root).unwrap();
+    assert_eq!(vfs.mounts().len(), 1);
+    assert_eq!(vfs.resolve_path("/mnt/inside", root), Err(FsError::NotFound));
+    vfs.remove("/mnt", root).unwrap();
+}
+
+#[test_case]
+fn handles_track_offset_and_seek() {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    let flags = OpenFlags::READ | OpenFlags::WRITE | OpenFlags::CREATE;
+    let file = vfs.open("/data", flags, root).unwrap();
+    assert_eq!(file.write(&mut vfs, b"hello world").unwrap(), 11);
+
+    let mut buf = [0; 5];
+    assert_eq!(file.seek(&vfs, SeekFrom::Start(6)).unwrap(), 6);
+    assert_eq!(file.read(&vfs, &mut buf).unwrap(), 5);
+    assert_eq!(&buf, b"world");
+    assert_eq!(file.read(&vfs, &mut buf).unwrap(), 0);
+    assert_eq!(file.seek(&vfs, SeekFrom::End(-5)).unwrap(), 6);
+    assert_eq!(file.seek(&vfs, SeekFrom::Current(-7)), Err(FsError::InvalidSeek));
+
+    let read_only = vfs.open("/data", OpenFlags::READ, root).unwrap();
+    assert_eq!(read_only.write(&mut vfs, b"x"), Err(FsError::AccessMode));
+}
+
+#[test_case]
+fn append_writes_at_end() {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    vfs.write_file("/log", b"one\n", root).unwrap();
+    let file = vfs.open("/log", OpenFlags::WRITE | OpenFlags::APPEND, root).unwrap();
+    file.seek(&vfs, SeekFrom::Start(0)).unwrap();
+    file.write(&mut vfs, b"two\n").unwrap();
+    let node = vfs.resolve_path("/log", root).unwrap();
+    assert_eq!(vfs.read_file(node).unwrap(), b"one\ntwo\n");
+
+    let truncating = OpenFlags::WRITE | OpenFlags::TRUNCATE;
+    drop(vfs.open("/log", truncating, root).unwrap());
+    assert_eq!(vfs.stat(node).unwrap().size, 0);
+}
+
+#[test_case]
+fn unlinked_file_readable_until_closed() {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    vfs.write_file("/tmp", b"still here", root).unwrap();
+    let file = vfs.open("/tmp", OpenFlags::READ, root).unwrap();
+    let node = file.vnode();
+    vfs.remove("/tmp", root).unwrap();
+    assert_eq!(vfs.resolve_path("/tmp", root), Err(FsError::NotFound));
+
+    let mut buf = [0; 16];
+    let len = file.read(&vfs, &mut buf).unwrap();
+    assert_eq!(&buf[..len], b"still here");
+
+    drop(file);
+    vfs.reap();
+    assert_eq!(vfs.stat(node).err(), Some(FsError::NotFound));
+}
+
+#[test_case]
+fn open_files_keep_mount_busy() {
+    let mut vfs = vfs_with_mount();
+    let root = vfs.root();
+    let file = vfs.open("/mnt/f", OpenFlags::WRITE | OpenFlags::CREATE, root).unwrap();
+    assert!(matches!(vfs.unmount("/mnt", root), Err(FsError::Busy)));
+    drop(file);
+    assert!(vfs.unmount("/mnt", root).is_ok());
+}
+
+#[test_case]
+fn stat_reports_links_owner_and_times() {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    let dir = vfs.create_dir("/dir", root).unwrap();
+    vfs.create_dir("/dir/sub", root).unwrap();
+    let file = vfs.create_file("/dir/file", root).unwrap();
+    vfs.write_file("/dir/file", b"12345", root).unwrap();
+
+    let meta = vfs.stat(file).unwrap();
+    assert_eq!((meta.size, meta.mode, meta.nlink), (5, 0o644, 1));
+    assert_eq!((meta.uid, meta.gid), (0, 0));
+    assert!(meta.modified >= meta.created);
+    // `dir`'s own entry, its `.`, and `sub`'s `..`
+    assert_eq!(vfs.stat(dir).unwrap().nlink, 3);
+    vfs.remove("/dir/sub", root).unwrap();
+    assert_eq!(vfs.stat(dir).unwrap().nlink, 2);
+
+    vfs.set_owner(file, 1000, 100).unwrap();
+    vfs.set_times(file, 10, 20).unwrap();
+    let meta = vfs.stat(file).unwrap();
+    assert_eq!((meta.uid, meta.gid, meta.accessed, meta.modified), (1000, 100, 10, 20));
+    let mut buf = [0; 8];
+    vfs.read(file, 0, &mut buf).unwrap();
+    assert!(vfs.stat(file).unwrap().accessed >= meta.created);
+}
+
+#[test_case]
+fn rename_moves_between_directories() {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    vfs.create_dir("/a", root).unwrap();
+    vfs.create_dir("/b", root).unwrap();
+    vfs.write_file("/a/file", b"data", root).unwrap();
+    vfs.write_file("/b/old", b"replaced", root).unwrap();
+
+    vfs.rename("/a/file", "/b/old", root).unwrap();
+    assert_eq!(vfs.resolve_path("/a/file", root), Err(FsError::NotFound));
+    let moved = vfs.resolve_path("/b/old", root).unwrap();
+    assert_eq!(vfs.read_file(moved).unwrap(), b"data");
+
+    vfs.rename("/b", "/a/b", root).unwrap();
+    let b = vfs.resolve_path("/a/b", root).unwrap();
+    assert_eq!(vfs.get_path(b).unwrap(), "/a/b");
+    assert_eq!(vfs.stat(vfs.resolve_path("/a", root).unwrap()).unwrap().nlink, 3);
+}
+
+#[test_case]
+fn rename_refuses_bad_moves() {
+    let mut vfs = vfs_with_mount();
+    let root = vfs.root();
+    vfs.create_dir("/a", root).unwrap();
+    vfs.create_dir("/a/sub", root).unwrap();
+    vfs.create_file("/f", root).unwrap();
+
+    assert_eq!(vfs.rename("/a", "/a/sub/a", root), Err(FsError::InvalidPath));
+    assert_eq!(vfs.rename("/f", "/mnt/f", root), Err(FsError::CrossDevice));
+    assert_eq!(vfs.rename("/mnt", "/elsewhere", root), Err(FsError::Busy));
+    assert_eq!(vfs.rename("/f", "/a", root), Err(FsError::NotAFile));
+}
+
+#[test_case]
+fn remove_all_deletes_tree() {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    vfs.create_dir("/t", root).unwrap();
+    vfs.create_dir("/t/x", root).unwrap();
+    vfs.write_file("/t/x/f", b"1", root).unwrap();
+    vfs.write_file("/t/g", b"2", root).unwrap();
+
+    assert_eq!(vfs.remove("/t", root), Err(FsError::DirectoryNotEmpty));
+    vfs.remove_all("/t", root).unwrap();
+    assert_eq!(vfs.resolve_path("/t", root), Err(FsError::NotFound));
+    assert!(vfs.list_dir(root).unwrap().is_empty());
+}
+
+#[test_case]
+fn copy_duplicates_tree() {
+    let mut vfs = vfs_with_mount();
+    let root = vfs.root();
+    vfs.create_dir("/src", root).unwrap();
+    vfs.create_dir("/src/d", root).unwrap();
+    vfs.write_file("/src/d/f", b"copied", root).unwrap();
+    let f = vfs.resolve_path("/src/d/f", root).unwrap();
+    vfs.set_mode(f, 0o600).unwrap();
+
+    vfs.copy("/src", "/mnt/dst", root).unwrap();
+    let copy = vfs.resolve_path("/mnt/dst/d/f", root).unwrap();
+    assert_ne!(copy, f);
+    assert_eq!(vfs.read_file(copy).unwrap(), b"copied");
+    assert_eq!(vfs.stat(copy).unwrap().mode, 0o600);
+
+    // The original is independent of the copy.
+    vfs.write_file("/mnt/dst/d/f", b"changed", root).unwrap();
+    assert_eq!(vfs.read_file(f).unwrap(), b"copied");
+    assert_eq!(vfs.copy("/src", "/src/d/inner", root), Err(FsError::InvalidPath));
+}
+
+#[test_case]
+fn symlinks_resolve_relative_to_link() {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    vfs.create_dir("/a", root).unwrap();
+    vfs.write_file("/a/target", b"through link", root).unwrap();
+    vfs.symlink("target", "/a/rel", root).unwrap();
+    vfs.symlink("/a", "/dir", root).unwrap();
+
+    let file = vfs.resolve_path("/a/target", root).unwrap();
+    assert_eq!(vfs.resolve_path("/a/rel", root).unwrap(), file);
+    assert_eq!(vfs.resolve_path("/dir/rel", root).unwrap(), file);
+    let link = vfs.resolve_path_nofollow("/a/rel", root).unwrap();
+    assert_ne!(link, file);
+    assert_eq!(vfs.stat(link).unwrap().kind, FileType::Symlink);
+    assert_eq!(vfs.read_link("/a/rel", root).unwrap(), "target");
+
+    // Removing a link leaves its target alone.
+    vfs.remove("/dir", root).unwrap();
+    assert!(vfs.resolve_path("/a", root).is_ok());
+    vfs.remove("/a/target", root).unwrap();
+    assert_eq!(vfs.resolve_path("/a/rel", root), Err(FsError::NotFound));
+}
+
+#[test_case]
+fn symlink_loops_are_detected() {
+    let mut vfs = Vfs::new(Box::new(TmpFs::new()));
+    let root = vfs.root();
+    vfs.symlink("/b", "/a", root).unwrap();
+    vfs.symlink("/a", "/b", root).unwrap();
+
+    assert_eq!(vfs.resolve_path("/a", root), Err(FsError::TooManyLinks));
+    assert!(vfs.resolve_path_nofollow("/a", root).is_ok());
+}
+
+#[test_case]
+fn hard_links_share_data_and_count() {
+    let mut vfs = vfs_with_mount();
+    let root = vfs.root();
+    vfs.create_dir("/d", root).unwrap();
+    vfs.write_file("/one", b"shared", root).unwrap();
+    vfs.link("/one", "/d/two", root).unwrap();
+
+    let node = vfs.resolve_path("/one", root).unwrap();
+    assert_eq!(vfs.resolve_path("/d/two", root).unwrap(), node);
+    assert_eq!(vfs.stat(node).unwrap().nlink, 2);
+
+    vfs.remove("/one", root).unwrap();
+    assert_eq!(vfs.stat(node).unwrap().nlink, 1);
+    assert_eq!(vfs.read_file(node).unwrap(), b"shared");
+
+    assert_eq!(vfs.link("/d/two", "/mnt/three", root), Err(FsError::CrossDevice));
+    assert_eq!(vfs.link("/d", "/e", root), Err(FsError::NotAFile));
+}